@@ -0,0 +1,71 @@
+// Benchmarks for the path audio takes from a source to its listeners. Run
+// with `cargo bench`, and compare against a run from before a change.
+
+#[macro_use]
+extern crate criterion;
+extern crate rustcast;
+
+use std::sync::Arc;
+
+use criterion::Criterion;
+use rustcast::{Channel, Frame, Stream, StreamData};
+
+// about what LAME gives for a packet of a 128 kbps stream:
+const PACKET_SIZE: usize = 418;
+
+fn packet() -> StreamData {
+    Arc::new(vec![0u8; PACKET_SIZE].into_boxed_slice())
+}
+
+// Publishing one packet to every receiver, and each receiving it.
+fn publish(c: &mut Criterion) {
+    c.bench_function_over_inputs("publish to listeners", |b, &&listeners| {
+        let channel = Channel::new(16);
+        let receivers = (0..listeners).map(|_| channel.subscribe()).collect::<Vec<_>>();
+        let data = packet();
+
+        b.iter(|| {
+            channel.publish(Arc::clone(&data));
+
+            for rx in receivers.iter() {
+                rx.recv();
+            }
+        });
+    }, &[1, 100, 1000]);
+}
+
+// Listeners coming and going while audio is published, as when a popular
+// stream starts.
+fn churn(c: &mut Criterion) {
+    c.bench_function("subscribe and publish", |b| {
+        let channel = Channel::new(16);
+        let data = packet();
+
+        b.iter(|| {
+            let rx = channel.subscribe();
+            channel.publish(Arc::clone(&data));
+            rx.recv();
+        });
+    });
+}
+
+// A stream's publish, which also keeps the burst buffer, to a listener.
+fn stream(c: &mut Criterion) {
+    c.bench_function("stream publish", |b| {
+        let stream = Stream::new();
+        let rx = stream.subscribe();
+        let data = packet();
+
+        b.iter(|| {
+            stream.publish(Arc::clone(&data));
+
+            match rx.recv() {
+                Some(Frame::Audio(_)) => (),
+                _ => panic!("expected audio"),
+            }
+        });
+    });
+}
+
+criterion_group!(benches, publish, churn, stream);
+criterion_main!(benches);
@@ -0,0 +1,238 @@
+// AAC-LC and HE-AAC encoding with the Fraunhofer FDK AAC library, for
+// mounts with codec = "aac" or "he-aac". Output is ADTS, which players can
+// pick up from anywhere in the stream like MP3. Only built with the aac
+// feature, as libfdk-aac isn't everywhere.
+
+use config::OutputCodec;
+use encoder::{EncoderError, EncoderSettings};
+
+mod ffi {
+    use libc::{c_int, c_uint, c_void};
+
+    pub enum AacEncoder {}
+
+    pub type HandleAacEncoder = *mut AacEncoder;
+
+    // AACENC_PARAM:
+    pub const AACENC_AOT: c_int = 0x0100;
+    pub const AACENC_BITRATE: c_int = 0x0101;
+    pub const AACENC_SAMPLERATE: c_int = 0x0103;
+    pub const AACENC_CHANNELMODE: c_int = 0x0106;
+    pub const AACENC_AFTERBURNER: c_int = 0x0200;
+    pub const AACENC_TRANSMUX: c_int = 0x0300;
+
+    // AUDIO_OBJECT_TYPE:
+    pub const AOT_AAC_LC: c_uint = 2;
+    pub const AOT_SBR: c_uint = 5;
+
+    pub const TT_MP4_ADTS: c_uint = 2;
+
+    // AACENC_ERROR returned once a flush is done:
+    pub const AACENC_ENCODE_EOF: c_int = 0x0080;
+
+    // AACENC_BufferIdentifier:
+    pub const IN_AUDIO_DATA: c_int = 0;
+    pub const OUT_BITSTREAM_DATA: c_int = 3;
+
+    #[repr(C)]
+    #[allow(non_snake_case)]
+    pub struct AacencBufDesc {
+        pub numBufs: c_int,
+        pub bufs: *mut *mut c_void,
+        pub bufferIdentifiers: *mut c_int,
+        pub bufSizes: *mut c_int,
+        pub bufElSizes: *mut c_int,
+    }
+
+    #[repr(C)]
+    #[allow(non_snake_case)]
+    pub struct AacencInArgs {
+        pub numInSamples: c_int,
+        pub numAncBytes: c_int,
+    }
+
+    #[repr(C)]
+    #[allow(non_snake_case)]
+    pub struct AacencOutArgs {
+        pub numOutBytes: c_int,
+        pub numInSamples: c_int,
+        pub numAncBytes: c_int,
+        pub bitResState: c_int,
+    }
+
+    #[link(name = "fdk-aac")]
+    extern "C" {
+        pub fn aacEncOpen(handle: *mut HandleAacEncoder, enc_modules: c_uint, max_channels: c_uint) -> c_int;
+        pub fn aacEncClose(handle: *mut HandleAacEncoder) -> c_int;
+        pub fn aacEncoder_SetParam(handle: HandleAacEncoder, param: c_int, value: c_uint) -> c_int;
+        pub fn aacEncEncode(handle: HandleAacEncoder,
+            in_buf_desc: *const AacencBufDesc, out_buf_desc: *const AacencBufDesc,
+            in_args: *const AacencInArgs, out_args: *mut AacencOutArgs) -> c_int;
+    }
+}
+
+// big enough for an ADTS frame of the most channels we encode:
+const OUTPUT_BUFFER_SIZE: usize = 8192;
+
+pub struct AacEncoder {
+    handle: ffi::HandleAacEncoder,
+    channels: u8,
+}
+
+// the encoder's state isn't shared with anything else, so can move between
+// threads:
+unsafe impl Send for AacEncoder {}
+
+fn check(setting: &'static str, ret: i32) -> Result<(), EncoderError> {
+    if ret != 0 {
+        Err(EncoderError::Setting(setting))
+    } else {
+        Ok(())
+    }
+}
+
+impl AacEncoder {
+    pub fn new(codec: OutputCodec, settings: &EncoderSettings) -> Result<AacEncoder, EncoderError> {
+        let mut handle = ::std::ptr::null_mut();
+
+        if unsafe { ffi::aacEncOpen(&mut handle, 0, settings.channels as u32) } != 0 || handle.is_null() {
+            return Err(EncoderError::Init);
+        }
+
+        // constructed straight away so the encoder is closed if a setting
+        // fails:
+        let encoder = AacEncoder { handle, channels: settings.channels };
+
+        let aot = match codec {
+            OutputCodec::HeAac => ffi::AOT_SBR,
+            _ => ffi::AOT_AAC_LC,
+        };
+
+        unsafe {
+            check("audio object type", ffi::aacEncoder_SetParam(handle, ffi::AACENC_AOT, aot))?;
+            check("sample rate", ffi::aacEncoder_SetParam(handle, ffi::AACENC_SAMPLERATE, settings.sample_rate))?;
+            // MODE_1 and MODE_2 are mono and stereo:
+            check("channels", ffi::aacEncoder_SetParam(handle, ffi::AACENC_CHANNELMODE, settings.channels as u32))?;
+            check("bitrate", ffi::aacEncoder_SetParam(handle, ffi::AACENC_BITRATE, settings.kilobitrate as u32 * 1000))?;
+            check("transport", ffi::aacEncoder_SetParam(handle, ffi::AACENC_TRANSMUX, ffi::TT_MP4_ADTS))?;
+            check("afterburner", ffi::aacEncoder_SetParam(handle, ffi::AACENC_AFTERBURNER, 1))?;
+
+            // encoding with no buffers applies the parameters:
+            check("parameters", ffi::aacEncEncode(handle,
+                ::std::ptr::null(), ::std::ptr::null(), ::std::ptr::null(), ::std::ptr::null_mut()))?;
+        }
+
+        Ok(encoder)
+    }
+
+    // Encodes a packet of audio, appending any ADTS frames finished to out.
+    // The encoder works in whole frames, so holds on to the rest of the
+    // audio until the next call.
+    pub fn encode(&mut self, left: &[i16], right: &[i16], out: &mut Vec<u8>) -> Result<(), EncoderError> {
+        assert!(left.len() == right.len());
+
+        // the encoder takes its input interleaved:
+        let mut pcm = Vec::with_capacity(left.len() * self.channels as usize);
+
+        for i in 0..left.len() {
+            pcm.push(left[i]);
+
+            if self.channels == 2 {
+                pcm.push(right[i]);
+            }
+        }
+
+        let mut offset = 0;
+
+        while offset < pcm.len() {
+            let (consumed, produced) = self.call(&mut pcm[offset..], false, out)?;
+
+            // nothing taken and nothing given means it's waiting for more:
+            if consumed == 0 && produced == 0 {
+                break;
+            }
+
+            offset += consumed;
+        }
+
+        Ok(())
+    }
+
+    // Encodes whatever audio the encoder is holding on to, appending the
+    // last frames to out.
+    pub fn flush(&mut self, out: &mut Vec<u8>) -> Result<(), EncoderError> {
+        loop {
+            match self.call(&mut [], true, out) {
+                Ok((_, 0)) => return Ok(()),
+                Ok(_) => (),
+                Err(EncoderError::Encode(ffi::AACENC_ENCODE_EOF)) => return Ok(()),
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    // One call to aacEncEncode with pcm as its input, or flushing, returning
+    // how many samples it took and how many bytes it gave.
+    fn call(&mut self, pcm: &mut [i16], flush: bool, out: &mut Vec<u8>) -> Result<(usize, usize), EncoderError> {
+        let mut output = [0u8; OUTPUT_BUFFER_SIZE];
+
+        let mut in_ptr = pcm.as_mut_ptr() as *mut _;
+        let mut in_id = ffi::IN_AUDIO_DATA;
+        let mut in_size = (pcm.len() * 2) as i32;
+        let mut in_el_size = 2;
+
+        let mut out_ptr = output.as_mut_ptr() as *mut _;
+        let mut out_id = ffi::OUT_BITSTREAM_DATA;
+        let mut out_size = output.len() as i32;
+        let mut out_el_size = 1;
+
+        let in_desc = ffi::AacencBufDesc {
+            numBufs: 1,
+            bufs: &mut in_ptr,
+            bufferIdentifiers: &mut in_id,
+            bufSizes: &mut in_size,
+            bufElSizes: &mut in_el_size,
+        };
+
+        let out_desc = ffi::AacencBufDesc {
+            numBufs: 1,
+            bufs: &mut out_ptr,
+            bufferIdentifiers: &mut out_id,
+            bufSizes: &mut out_size,
+            bufElSizes: &mut out_el_size,
+        };
+
+        let in_args = ffi::AacencInArgs {
+            // -1 asks for everything held back:
+            numInSamples: if flush { -1 } else { pcm.len() as i32 },
+            numAncBytes: 0,
+        };
+
+        let mut out_args = ffi::AacencOutArgs {
+            numOutBytes: 0,
+            numInSamples: 0,
+            numAncBytes: 0,
+            bitResState: 0,
+        };
+
+        let ret = unsafe {
+            ffi::aacEncEncode(self.handle, &in_desc, &out_desc, &in_args, &mut out_args)
+        };
+
+        if ret != 0 {
+            return Err(EncoderError::Encode(ret));
+        }
+
+        out.extend_from_slice(&output[..out_args.numOutBytes as usize]);
+
+        Ok((out_args.numInSamples as usize, out_args.numOutBytes as usize))
+    }
+}
+
+impl Drop for AacEncoder {
+    fn drop(&mut self) {
+        unsafe {
+            ffi::aacEncClose(&mut self.handle);
+        }
+    }
+}
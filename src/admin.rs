@@ -0,0 +1,543 @@
+use std::collections::{BTreeMap, HashMap};
+use std::io;
+use std::path::Path;
+
+use reqwest::Url;
+use serde::Serialize;
+use serde_json;
+use tiny_http::{Request, Response, Header, Method};
+
+use audio::Metadata;
+use config::{AdminRole, ApiKey, Config};
+use dashboard;
+use fanout::CloseReason;
+use mountpoint;
+use players::PlayerCounts;
+use royalty;
+use server::{Rustcast, StreamEntry, credentials_from_headers};
+use transfer::MountTransfer;
+
+#[derive(Serialize)]
+struct MountJson {
+    mountpoint: String,
+    uuid: String,
+    artist: Option<String>,
+    title: Option<String>,
+    hidden: bool,
+    listeners: usize,
+    // bytes sent to listeners on this mountpoint since rustcast started:
+    bytes_sent: u64,
+}
+
+#[derive(Serialize)]
+struct StartingJson {
+    mountpoint: String,
+    uuid: String,
+    user: Option<String>,
+    ip: Option<String>,
+    // how long the source has been authenticating for:
+    seconds: u64,
+    // whether it's been long enough that another source may take over:
+    stuck: bool,
+}
+
+#[derive(Serialize)]
+struct KickedJson {
+    // how many listeners were disconnected:
+    kicked: usize,
+}
+
+#[derive(Serialize)]
+struct StatsJson {
+    total: u64,
+    mounts: HashMap<String, MountTransfer>,
+    // listener counts by player, per mountpoint:
+    players: HashMap<String, BTreeMap<&'static str, PlayerCounts>>,
+}
+
+fn respond_json<T: Serialize>(req: Request, status: u16, data: &T) -> io::Result<()> {
+    let content_type = Header::from_bytes(&b"Content-Type"[..], &b"application/json"[..])
+        .expect("valid header");
+
+    req.respond(Response::from_string(serde_json::to_string(data).unwrap())
+        .with_header(content_type)
+        .with_status_code(status))
+}
+
+fn respond_error(req: Request, status: u16, message: &str) -> io::Result<()> {
+    let mut data = HashMap::new();
+    data.insert("error", message);
+    respond_json(req, status, &data)
+}
+
+fn method_not_allowed(req: Request) -> io::Result<()> {
+    let content_type = Header::from_bytes(&b"Content-Type"[..], &b"application/json"[..])
+        .expect("valid header");
+    let allow = Header::from_bytes(&b"Allow"[..], &b"POST"[..])
+        .expect("valid header");
+
+    let mut data = HashMap::new();
+    data.insert("error", "only POST is allowed");
+
+    req.respond(Response::from_string(serde_json::to_string(&data).unwrap())
+        .with_header(content_type)
+        .with_header(allow)
+        .with_status_code(405))
+}
+
+pub fn bearer_token(headers: &[Header]) -> Option<String> {
+    headers.iter()
+        .filter(|header| header.field.equiv("Authorization"))
+        .filter_map(|header| {
+            let mut h = header.value.as_str().split(" ");
+            if let Some("Bearer") = h.next() {
+                h.next().map(str::to_owned)
+            } else {
+                None
+            }
+        })
+        .nth(0)
+}
+
+// compares without bailing out at the first difference, so response timing
+// doesn't leak how much of a guessed key was right:
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+
+    a.iter().zip(b.iter()).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+// Who's making a request, and what they're allowed to do.
+struct Access<'a> {
+    name: &'a str,
+    // the API key they gave, which limits them to its mountpoints. admin
+    // users can act on all of them:
+    key: Option<&'a ApiKey>,
+    role: AdminRole,
+}
+
+impl<'a> Access<'a> {
+    fn allows(&self, mountpoint: &str) -> bool {
+        self.key.map(|key| key.allows(mountpoint)).unwrap_or(true)
+    }
+
+    fn name(&self) -> &str {
+        self.name
+    }
+}
+
+// endpoints that change something, which read-only users can't use:
+const CHANGES: &'static [&'static str] = &[
+    "/admin/metadata",
+    "/admin/kicklisteners",
+    "/admin/killclient",
+    "/admin/killsource",
+    "/admin/reload",
+];
+
+fn admin_user<'a>(config: &'a Config, user: Option<&str>, password: Option<&str>, token: Option<&str>) -> Option<Access<'a>> {
+    let matches = |expected: &Option<String>, given: Option<&str>| match (expected.as_ref(), given) {
+        (Some(expected), Some(given)) => constant_time_eq(expected.as_bytes(), given.as_bytes()),
+        _ => false,
+    };
+
+    config.admin.users.iter()
+        .find(|admin| (user == Some(admin.name.as_str()) && matches(&admin.password, password))
+            || matches(&admin.token, token))
+        .map(|admin| Access {
+            name: &admin.name,
+            key: None,
+            role: admin.role,
+        })
+}
+
+// Admin users give their name and password with HTTP basic auth, or their
+// token as a bearer token. API keys can be given as a bearer token, as the
+// password of HTTP basic auth (which is all most source clients can do for
+// metadata updates), or in the key query parameter.
+fn authenticate<'a>(config: &'a Config, req: &Request, params: &HashMap<String, String>) -> Option<Access<'a>> {
+    let token = bearer_token(req.headers());
+    let credentials = credentials_from_headers(req.headers());
+
+    let (user, password) = match credentials {
+        Some((ref user, ref password)) => (Some(user.as_str()), Some(password.as_str())),
+        None => (None, None),
+    };
+
+    if let Some(access) = admin_user(config, user, password, token.as_ref().map(String::as_str)) {
+        return Some(access);
+    }
+
+    let key = match token.or_else(|| password.map(str::to_owned)).or_else(|| params.get("key").cloned()) {
+        Some(key) => key,
+        None => return None,
+    };
+
+    config.api_keys.iter()
+        .find(|api_key| constant_time_eq(api_key.key.as_bytes(), key.as_bytes()))
+        .map(|api_key| Access {
+            name: api_key.name(),
+            key: Some(api_key),
+            role: api_key.role,
+        })
+}
+
+fn parse_song(song: &str) -> Metadata {
+    let mut parts = song.splitn(2, " - ");
+
+    match (parts.next(), parts.next()) {
+        (Some(artist), Some(title)) => Metadata {
+            artist: Some(artist.to_owned()),
+            title: Some(title.to_owned()),
+            ..Metadata::default()
+        },
+        _ => Metadata {
+            title: Some(song.to_owned()),
+            ..Metadata::default()
+        },
+    }
+}
+
+fn update_metadata(rustcast: &Rustcast, req: Request, access: &Access, params: &HashMap<String, String>) -> io::Result<()> {
+    let mountpoint = match params.get("mount").map(|mountpoint| mountpoint::normalize(mountpoint)) {
+        Some(Ok(mountpoint)) => mountpoint,
+        Some(Err(e)) => return respond_error(req, 400, &format!("{}", e)),
+        None => return respond_error(req, 400, "missing mount parameter"),
+    };
+    let mountpoint = &mountpoint;
+
+    if !access.allows(mountpoint) {
+        return respond_error(req, 403, "key not allowed on this mountpoint");
+    }
+
+    let stream = match rustcast.get_stream(mountpoint) {
+        Some(StreamEntry::Live(stream)) => stream,
+        Some(StreamEntry::Starting(_)) | None => return respond_error(req, 404, "mountpoint not live"),
+    };
+
+    let metadata = match params.get("song") {
+        Some(song) => parse_song(song),
+        None => Metadata {
+            artist: params.get("artist").cloned(),
+            title: params.get("title").cloned(),
+            album: params.get("album").cloned(),
+            genre: params.get("genre").cloned(),
+            date: params.get("date").cloned(),
+            tracknumber: params.get("tracknumber").cloned(),
+            ..Metadata::default()
+        },
+    };
+
+    rustcast.log.info(&format!("Metadata for {} updated by {}: {:?}",
+        mountpoint, access.name(), metadata));
+
+    rustcast.update_metadata(mountpoint, &stream, metadata);
+
+    let mut data = HashMap::new();
+    data.insert("ok", true);
+    respond_json(req, 200, &data)
+}
+
+fn list_mounts(rustcast: &Rustcast, req: Request, access: &Access) -> io::Result<()> {
+    let config = rustcast.config();
+
+    // hidden mountpoints are still listed here, this isn't public:
+    let mounts = rustcast.live_streams().into_iter()
+        .filter(|&(ref mountpoint, _)| access.allows(mountpoint))
+        .map(|(mountpoint, stream)| {
+            let metadata = stream.metadata.read().unwrap();
+            let hidden = config.hidden(&mountpoint);
+            let bytes_sent = rustcast.transfer.mount(&mountpoint).total;
+
+            MountJson {
+                mountpoint,
+                uuid: format!("{}", stream.uuid.hyphenated()),
+                artist: metadata.artist.clone(),
+                title: metadata.title.clone(),
+                hidden,
+                listeners: stream.listeners(),
+                bytes_sent,
+            }
+        })
+        .collect::<Vec<_>>();
+
+    respond_json(req, 200, &mounts)
+}
+
+fn list_starting(rustcast: &Rustcast, req: Request, access: &Access) -> io::Result<()> {
+    let timeout = rustcast.config().source_auth_timeout;
+
+    let starting = rustcast.starting_streams().into_iter()
+        .filter(|&(ref mountpoint, _)| access.allows(mountpoint))
+        .map(|(mountpoint, pending)| {
+            let seconds = pending.since.elapsed().as_secs();
+
+            StartingJson {
+                mountpoint,
+                uuid: format!("{}", pending.uuid.hyphenated()),
+                user: pending.user,
+                ip: pending.client_ip.map(|ip| ip.to_string()),
+                seconds,
+                stuck: seconds >= timeout,
+            }
+        })
+        .collect::<Vec<_>>();
+
+    respond_json(req, 200, &starting)
+}
+
+fn kick_listeners(rustcast: &Rustcast, req: Request, access: &Access, params: &HashMap<String, String>) -> io::Result<()> {
+    let mountpoint = match params.get("mount").map(|mountpoint| mountpoint::normalize(mountpoint)) {
+        Some(Ok(mountpoint)) => mountpoint,
+        Some(Err(e)) => return respond_error(req, 400, &format!("{}", e)),
+        None => return respond_error(req, 400, "missing mount parameter"),
+    };
+    let mountpoint = &mountpoint;
+
+    if !access.allows(mountpoint) {
+        return respond_error(req, 403, "key not allowed on this mountpoint");
+    }
+
+    let stream = match rustcast.get_stream(mountpoint) {
+        Some(StreamEntry::Live(stream)) => stream,
+        Some(StreamEntry::Starting(_)) | None => return respond_error(req, 404, "mountpoint not live"),
+    };
+
+    let kicked = stream.close_listeners(CloseReason::Kicked);
+
+    rustcast.log.info(&format!("Kicked {} listeners from {} (requested by {})",
+        kicked, mountpoint, access.name()));
+
+    respond_json(req, 200, &KickedJson { kicked })
+}
+
+fn list_clients(rustcast: &Rustcast, req: Request, access: &Access, params: &HashMap<String, String>) -> io::Result<()> {
+    let mountpoint = match params.get("mount").map(|mountpoint| mountpoint::normalize(mountpoint)) {
+        Some(Ok(mountpoint)) => mountpoint,
+        Some(Err(e)) => return respond_error(req, 400, &format!("{}", e)),
+        None => return respond_error(req, 400, "missing mount parameter"),
+    };
+
+    if !access.allows(&mountpoint) {
+        return respond_error(req, 403, "key not allowed on this mountpoint");
+    }
+
+    respond_json(req, 200, &rustcast.listeners.list(&mountpoint))
+}
+
+fn kill_client(rustcast: &Rustcast, req: Request, access: &Access, params: &HashMap<String, String>) -> io::Result<()> {
+    let mountpoint = match params.get("mount").map(|mountpoint| mountpoint::normalize(mountpoint)) {
+        Some(Ok(mountpoint)) => mountpoint,
+        Some(Err(e)) => return respond_error(req, 400, &format!("{}", e)),
+        None => return respond_error(req, 400, "missing mount parameter"),
+    };
+    let mountpoint = &mountpoint;
+
+    if !access.allows(mountpoint) {
+        return respond_error(req, 403, "key not allowed on this mountpoint");
+    }
+
+    let id = match params.get("id").and_then(|id| id.parse().ok()) {
+        Some(id) => id,
+        None => return respond_error(req, 400, "missing or invalid id parameter"),
+    };
+
+    if !rustcast.listeners.kick(mountpoint, id) {
+        return respond_error(req, 404, "no such listener on this mountpoint");
+    }
+
+    rustcast.log.info(&format!("Kicked listener {} from {} (requested by {})",
+        id, mountpoint, access.name()));
+
+    respond_json(req, 200, &KickedJson { kicked: 1 })
+}
+
+fn kill_source(rustcast: &Rustcast, req: Request, access: &Access, params: &HashMap<String, String>) -> io::Result<()> {
+    let mountpoint = match params.get("mount").map(|mountpoint| mountpoint::normalize(mountpoint)) {
+        Some(Ok(mountpoint)) => mountpoint,
+        Some(Err(e)) => return respond_error(req, 400, &format!("{}", e)),
+        None => return respond_error(req, 400, "missing mount parameter"),
+    };
+    let mountpoint = &mountpoint;
+
+    if !access.allows(mountpoint) {
+        return respond_error(req, 403, "key not allowed on this mountpoint");
+    }
+
+    let stream = match rustcast.get_stream(mountpoint) {
+        Some(StreamEntry::Live(stream)) => stream,
+        Some(StreamEntry::Starting(_)) | None => return respond_error(req, 404, "mountpoint not live"),
+    };
+
+    // mirrors have no source of their own to stop:
+    if stream.is_mirror() {
+        return respond_error(req, 409, "mountpoint is mirroring another");
+    }
+
+    rustcast.log.info(&format!("Stopping source on {} (requested by {})",
+        mountpoint, access.name()));
+
+    stream.stop();
+
+    let mut data = HashMap::new();
+    data.insert("ok", true);
+    respond_json(req, 200, &data)
+}
+
+fn recent_log(rustcast: &Rustcast, req: Request, access: &Access) -> io::Result<()> {
+    // log lines are about every mountpoint, so need a key scoped to them all:
+    if !access.allows("/") {
+        return respond_error(req, 403, "key not allowed to view the log");
+    }
+
+    respond_json(req, 200, &rustcast.log.recent())
+}
+
+fn stats(rustcast: &Rustcast, req: Request, access: &Access) -> io::Result<()> {
+    // the overall total covers every mountpoint, so needs a key scoped to
+    // them all:
+    if !access.allows("/") {
+        return respond_error(req, 403, "key not allowed to view stats");
+    }
+
+    let transfer = rustcast.transfer.stats();
+
+    respond_json(req, 200, &StatsJson {
+        total: transfer.total,
+        mounts: transfer.mounts,
+        players: rustcast.players.stats(),
+    })
+}
+
+// how many stream sessions /admin/sessions returns unless asked otherwise:
+const DEFAULT_SESSIONS_LIMIT: u32 = 50;
+
+fn sessions(rustcast: &Rustcast, req: Request, access: &Access, params: &HashMap<String, String>) -> io::Result<()> {
+    if !access.allows("/") {
+        return respond_error(req, 403, "key not allowed to view sessions");
+    }
+
+    let config = rustcast.config();
+
+    let path = match config.stats_db {
+        Some(ref path) => Path::new(path),
+        None => return respond_error(req, 404, "no stats database configured"),
+    };
+
+    let limit = params.get("limit")
+        .and_then(|limit| limit.parse().ok())
+        .unwrap_or(DEFAULT_SESSIONS_LIMIT);
+
+    match rustcast.stats.stream_sessions(path, limit) {
+        Ok(sessions) => respond_json(req, 200, &sessions),
+        Err(e) => {
+            rustcast.log.error(&format!("Couldn't read sessions from stats database: {}", e));
+            respond_error(req, 500, "couldn't read stats database")
+        }
+    }
+}
+
+fn royalty_report(rustcast: &Rustcast, req: Request, access: &Access, params: &HashMap<String, String>) -> io::Result<()> {
+    if !access.allows("/") {
+        return respond_error(req, 403, "key not allowed to view royalty reports");
+    }
+
+    let config = rustcast.config();
+
+    let path = match config.stats_db {
+        Some(ref path) => Path::new(path),
+        None => return respond_error(req, 404, "no stats database configured"),
+    };
+
+    let month = match params.get("month").and_then(|month| royalty::parse_month(month)) {
+        Some(month) => month,
+        None => return respond_error(req, 400, "month parameter must be given as YYYY-MM"),
+    };
+
+    match royalty::report(&rustcast.stats, path, month) {
+        Ok(report) => respond_json(req, 200, &report),
+        Err(e) => {
+            rustcast.log.error(&format!("Couldn't make royalty report: {:?}", e));
+            respond_error(req, 500, "couldn't read stats database")
+        }
+    }
+}
+
+fn reload(rustcast: &Rustcast, req: Request, access: &Access) -> io::Result<()> {
+    // reloading affects every mountpoint, so needs a key scoped to them all:
+    if !access.allows("/") {
+        return respond_error(req, 403, "key not allowed to reload config");
+    }
+
+    rustcast.log.info(&format!("Reloading config (requested by {})", access.name()));
+
+    match rustcast.reload_config() {
+        Ok(changes) => respond_json(req, 200, &changes),
+        Err(e) => {
+            rustcast.log.error(&format!("Couldn't reload config: {}", e));
+            respond_error(req, 500, &format!("{}", e))
+        }
+    }
+}
+
+// The page asks for credentials and does everything with it through the API, so
+// is served to anyone.
+fn dashboard(req: Request) -> io::Result<()> {
+    let content_type = Header::from_bytes(&b"Content-Type"[..], dashboard::CONTENT_TYPE.as_bytes())
+        .expect("valid header");
+
+    req.respond(Response::from_string(dashboard::render())
+        .with_header(content_type)
+        .with_status_code(200))
+}
+
+pub fn handle(rustcast: &Rustcast, req: Request) -> io::Result<()> {
+    let url = match Url::parse("http://localhost/").and_then(|base| base.join(req.url())) {
+        Ok(url) => url,
+        Err(_) => return respond_error(req, 400, "bad request url"),
+    };
+
+    if url.path() == "/admin/" {
+        return dashboard(req);
+    }
+
+    // browsers send remembered basic auth credentials along with any
+    // request, even one another site has them make, so nothing's changed
+    // on a GET:
+    if CHANGES.iter().any(|&path| path == url.path()) && *req.method() != Method::Post {
+        return method_not_allowed(req);
+    }
+
+    let params = url.query_pairs().into_owned().collect::<HashMap<String, String>>();
+
+    let config = rustcast.config();
+
+    let access = match authenticate(&config, &req, &params) {
+        Some(access) => access,
+        None => return respond_error(req, 401, "missing or invalid credentials"),
+    };
+    let access = &access;
+
+    if access.role == AdminRole::ReadOnly && CHANGES.iter().any(|&path| path == url.path()) {
+        rustcast.log.info(&format!("Refused {} to {} (read-only)", url.path(), access.name()));
+        return respond_error(req, 403, "read-only access");
+    }
+
+    match url.path() {
+        "/admin/metadata" => update_metadata(rustcast, req, access, &params),
+        "/admin/listmounts" => list_mounts(rustcast, req, access),
+        "/admin/starting" => list_starting(rustcast, req, access),
+        "/admin/kicklisteners" => kick_listeners(rustcast, req, access, &params),
+        "/admin/listclients" => list_clients(rustcast, req, access, &params),
+        "/admin/killclient" => kill_client(rustcast, req, access, &params),
+        "/admin/killsource" => kill_source(rustcast, req, access, &params),
+        "/admin/log" => recent_log(rustcast, req, access),
+        "/admin/reload" => reload(rustcast, req, access),
+        "/admin/stats" => stats(rustcast, req, access),
+        "/admin/sessions" => sessions(rustcast, req, access, &params),
+        "/admin/royalty" => royalty_report(rustcast, req, access, &params),
+        _ => respond_error(req, 404, "no such admin endpoint"),
+    }
+}
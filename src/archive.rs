@@ -0,0 +1,538 @@
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::mem;
+use std::path::{Component, Path};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use chrono::Local;
+use uuid::Uuid;
+
+use audio::Metadata;
+use catchup::Catalog;
+use config::{Config, EncodeMode};
+use hooks::{self, ArchiveSegmentParams, ArchiveErrorParams};
+use log::Log;
+use mp3::{self, FrameCounter};
+use upload;
+
+// how often to check free disk space while dumping:
+const SPACE_CHECK_INTERVAL: Duration = Duration::from_secs(10);
+
+// makes a metadata value safe to use as part of a file name. a leading dot
+// is replaced too, so it can't be . or .., or make a hidden file:
+fn path_component(value: &Option<String>) -> String {
+    let value = match *value {
+        Some(ref value) if !value.is_empty() => value,
+        _ => return "unknown".to_owned(),
+    };
+
+    value.chars()
+        .enumerate()
+        .map(|(i, c)| if c == '/' || c == '\\' || c.is_control() || (i == 0 && c == '.') { '_' } else { c })
+        .collect()
+}
+
+fn dump_path(template: &str, uuid: &Uuid, segment: u64, metadata: &Metadata) -> String {
+    template
+        .replace("{uuid}", &format!("{}", uuid.hyphenated()))
+        .replace("{segment}", &format!("{}", segment))
+        .replace("{timestamp}", &format!("{}", Local::now().format("%Y%m%d%H%M%S")))
+        .replace("{artist}", &path_component(&metadata.artist))
+        .replace("{title}", &path_component(&metadata.title))
+}
+
+// The directory everything made from template goes under: its leading
+// directories, before any placeholder.
+fn dump_root(template: &str) -> &Path {
+    let fixed = match template.find('{') {
+        Some(offset) => &template[..offset],
+        None => template,
+    };
+
+    match fixed.rfind(|c| c == '/' || c == '\\') {
+        Some(offset) => Path::new(&fixed[..offset + 1]),
+        None => Path::new(""),
+    }
+}
+
+// Checks path, made from template, hasn't escaped the template's root
+// directory by way of what was put into its placeholders.
+fn check_dump_path(template: &str, path: &str) -> io::Result<()> {
+    let escaped = match Path::new(path).strip_prefix(dump_root(template)) {
+        Ok(rest) => rest.components().any(|component| match component {
+            Component::Normal(_) => false,
+            _ => true,
+        }),
+        Err(_) => true,
+    };
+
+    if escaped {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput,
+            format!("{} is outside the stream_dump directory", path)));
+    }
+
+    Ok(())
+}
+
+fn dump_dir(path: &str) -> &Path {
+    match Path::new(path).parent() {
+        Some(dir) if dir != Path::new("") => dir,
+        _ => Path::new("."),
+    }
+}
+
+#[cfg(unix)]
+fn free_space(path: &str) -> io::Result<u64> {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+    use libc;
+
+    let c_dir = CString::new(dump_dir(path).as_os_str().as_bytes())
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+
+    unsafe {
+        let mut stat: libc::statvfs = mem::zeroed();
+
+        if libc::statvfs(c_dir.as_ptr(), &mut stat) != 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(stat.f_bavail as u64 * stat.f_frsize as u64)
+    }
+}
+
+#[cfg(windows)]
+fn free_space(path: &str) -> io::Result<u64> {
+    use std::os::windows::ffi::OsStrExt;
+    use std::ptr;
+    use winapi::um::fileapi::GetDiskFreeSpaceExW;
+    use winapi::um::winnt::ULARGE_INTEGER;
+
+    let wide_dir = dump_dir(path).as_os_str().encode_wide()
+        .chain(Some(0))
+        .collect::<Vec<u16>>();
+
+    unsafe {
+        let mut available: ULARGE_INTEGER = mem::zeroed();
+
+        if GetDiskFreeSpaceExW(wide_dir.as_ptr(), &mut available, ptr::null_mut(), ptr::null_mut()) == 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(*available.QuadPart())
+    }
+}
+
+// State shared by both kinds of dump: who the dump belongs to, and whether
+// it's been given up on. Dump failures never take the stream down with them,
+// the dump just stops.
+struct DumpContext {
+    config: Arc<Config>,
+    mountpoint: String,
+    uuid: Uuid,
+    last_space_check: Option<Instant>,
+    failed: bool,
+}
+
+impl DumpContext {
+    fn new(config: Arc<Config>, mountpoint: &str, uuid: Uuid) -> DumpContext {
+        DumpContext {
+            config,
+            mountpoint: mountpoint.to_owned(),
+            uuid,
+            last_space_check: None,
+            failed: false,
+        }
+    }
+
+    fn check_space(&mut self, path: &str) -> io::Result<()> {
+        let min_free = match self.config.stream_dump_min_free {
+            Some(megabytes) => megabytes * 1024 * 1024,
+            None => return Ok(()),
+        };
+
+        if let Some(last_check) = self.last_space_check {
+            if last_check.elapsed() < SPACE_CHECK_INTERVAL {
+                return Ok(());
+            }
+        }
+
+        self.last_space_check = Some(Instant::now());
+
+        let free = free_space(path)?;
+
+        if free < min_free {
+            return Err(io::Error::new(io::ErrorKind::Other,
+                format!("only {} MB of disk space free", free / 1024 / 1024)));
+        }
+
+        Ok(())
+    }
+
+    fn fail(&mut self, path: &str, error: io::Error) {
+        self.failed = true;
+
+        let error = format!("{}", error);
+
+        Log::new().error(&format!("Stopped stream dump {} for {}: {}",
+            path, self.mountpoint, error));
+
+        let config = Arc::clone(&self.config);
+        let mountpoint = self.mountpoint.clone();
+        let uuid = self.uuid;
+        let path = path.to_owned();
+
+        thread::spawn(move || {
+            let params = ArchiveErrorParams {
+                mountpoint: &mountpoint,
+                uuid: &uuid,
+                path: &path,
+                error: &error,
+            };
+
+            if let Err(e) = hooks::archive_error(&config, params) {
+                Log::new().error(&format!("archive_error hook failed for {}: {:?}", path, e));
+            }
+        });
+    }
+
+    fn announce(&self, path: String, index: u64, duration: u64, metadata: Metadata) {
+        let config = Arc::clone(&self.config);
+        let mountpoint = self.mountpoint.clone();
+        let uuid = self.uuid;
+
+        // don't hold up the source thread waiting on the upload or webhook:
+        thread::spawn(move || {
+            let url = match upload::upload(&config, &path) {
+                Ok(url) => url,
+                Err(e) => {
+                    Log::new().error(&format!("Couldn't upload stream dump {}: {:?}", path, e));
+                    None
+                }
+            };
+
+            let params = ArchiveSegmentParams {
+                mountpoint: &mountpoint,
+                uuid: &uuid,
+                path: &path,
+                url: url.as_ref().map(String::as_str),
+                segment: index,
+                duration,
+                artist: metadata.artist.as_ref().map(String::as_str),
+                title: metadata.title.as_ref().map(String::as_str),
+            };
+
+            if let Err(e) = hooks::archive_segment(&config, params) {
+                Log::new().error(&format!("archive_segment hook failed for {}: {:?}", path, e));
+            }
+        });
+    }
+}
+
+// A Xing header at the start of a segment, filled in when it's closed:
+struct XingHeader {
+    counts_offset: u64,
+    counter: FrameCounter,
+}
+
+struct Segment {
+    file: File,
+    path: String,
+    index: u64,
+    started: Instant,
+    metadata: Metadata,
+    // bytes written so far:
+    len: u64,
+    // whether to start the segment with a Xing header:
+    vbr: bool,
+    xing: Option<XingHeader>,
+}
+
+impl Segment {
+    fn write(&mut self, data: &[u8]) -> io::Result<()> {
+        if data.is_empty() {
+            return Ok(());
+        }
+
+        if self.len == 0 && self.vbr {
+            if let Some((frame, counts_offset)) = mp3::xing_frame(data) {
+                self.file.write_all(&frame)?;
+                self.len += frame.len() as u64;
+
+                self.xing = Some(XingHeader {
+                    counts_offset: counts_offset as u64,
+                    counter: FrameCounter::new(),
+                });
+            }
+        }
+
+        self.file.write_all(data)?;
+        self.len += data.len() as u64;
+
+        if let Some(xing) = self.xing.as_mut() {
+            xing.counter.feed(data);
+        }
+
+        Ok(())
+    }
+
+    // fills in the Xing header now the segment's length is known:
+    fn finish(&mut self) -> io::Result<()> {
+        if let Some(ref xing) = self.xing {
+            self.file.seek(SeekFrom::Start(xing.counts_offset))?;
+            self.file.write_all(&mp3::xing_counts(xing.counter.frames, self.len as u32))?;
+        }
+
+        Ok(())
+    }
+}
+
+pub struct Archive {
+    ctx: DumpContext,
+    // where segments are listed for catch-up:
+    catalog: Arc<Catalog>,
+    template: String,
+    rotate: Option<Duration>,
+    split_on_metadata: bool,
+    split_pending: bool,
+    metadata: Metadata,
+    segment: Option<Segment>,
+    next_index: u64,
+    // whether the stream is variable bitrate, so needs Xing headers:
+    vbr: bool,
+}
+
+impl Archive {
+    // returns None if transcoded stream dumps are turned off:
+    pub fn new(config: Arc<Config>, catalog: Arc<Catalog>, mountpoint: &str, uuid: Uuid) -> Option<Archive> {
+        let template = match config.stream_dump {
+            Some(ref template) => template.clone(),
+            None => return None,
+        };
+
+        let rotate = config.stream_dump_rotate.map(Duration::from_secs);
+        let split_on_metadata = config.stream_dump_split_on_metadata;
+
+        let vbr = match config.mount(mountpoint).and_then(|mount| mount.mode) {
+            Some(EncodeMode::Vbr) | Some(EncodeMode::Abr) => true,
+            Some(EncodeMode::Cbr) | None => false,
+        };
+
+        Some(Archive {
+            ctx: DumpContext::new(config, mountpoint, uuid),
+            catalog,
+            template,
+            rotate,
+            split_on_metadata,
+            split_pending: false,
+            metadata: Metadata::default(),
+            segment: None,
+            next_index: 0,
+            vbr,
+        })
+    }
+
+    pub fn set_metadata(&mut self, metadata: &Metadata) {
+        if *metadata == self.metadata {
+            return;
+        }
+
+        self.metadata = metadata.clone();
+
+        if self.split_on_metadata && self.segment.is_some() {
+            self.split_pending = true;
+        }
+    }
+
+    pub fn write(&mut self, data: &[u8]) {
+        if self.ctx.failed {
+            return;
+        }
+
+        if let Err(e) = self.try_write(data) {
+            let path = match self.segment.as_ref() {
+                Some(segment) => segment.path.clone(),
+                None => dump_path(&self.template, &self.ctx.uuid, self.next_index, &self.metadata),
+            };
+
+            self.ctx.fail(&path, e);
+            self.close_segment();
+        }
+    }
+
+    fn try_write(&mut self, data: &[u8]) -> io::Result<()> {
+        let mut data = data;
+
+        if self.split_pending || self.rotation_due() {
+            // only rotate on a frame boundary so that every segment is
+            // independently playable. if there's no frame start in this
+            // buffer we'll try again with the next one:
+            if let Some(offset) = mp3::find_frame(data) {
+                self.split_pending = false;
+
+                if let Some(segment) = self.segment.as_mut() {
+                    segment.write(&data[..offset])?;
+                }
+
+                self.close_segment();
+                data = &data[offset..];
+            }
+        }
+
+        if self.segment.is_none() {
+            let segment = self.open_segment()?;
+            self.segment = Some(segment);
+        }
+
+        if let Some(segment) = self.segment.as_mut() {
+            self.ctx.check_space(&segment.path)?;
+            segment.write(data)?;
+        }
+
+        Ok(())
+    }
+
+    fn rotation_due(&self) -> bool {
+        match (self.rotate, self.segment.as_ref()) {
+            (Some(rotate), Some(segment)) => segment.started.elapsed() >= rotate,
+            _ => false,
+        }
+    }
+
+    fn open_segment(&mut self) -> io::Result<Segment> {
+        let index = self.next_index;
+
+        let path = dump_path(&self.template, &self.ctx.uuid, index, &self.metadata);
+
+        check_dump_path(&self.template, &path)?;
+        self.ctx.check_space(&path)?;
+
+        let file = File::create(&path)?;
+
+        self.next_index += 1;
+
+        self.catalog.opened(&self.ctx.mountpoint, self.ctx.uuid, index, &path,
+            Duration::from_secs(self.ctx.config.catchup_window));
+
+        Ok(Segment {
+            file,
+            path,
+            index,
+            started: Instant::now(),
+            metadata: self.metadata.clone(),
+            len: 0,
+            vbr: self.vbr,
+            xing: None,
+        })
+    }
+
+    fn close_segment(&mut self) {
+        let mut segment = match self.segment.take() {
+            Some(segment) => segment,
+            None => return,
+        };
+
+        if let Err(e) = segment.finish() {
+            Log::new().error(&format!("Couldn't finish stream dump {} for {}: {}",
+                segment.path, self.ctx.mountpoint, e));
+        }
+
+        let Segment { file, path, index, started, metadata, .. } = segment;
+
+        // make sure the file is closed before anyone is told about it:
+        drop(file);
+
+        self.catalog.closed(&self.ctx.mountpoint, self.ctx.uuid, index, started.elapsed());
+
+        // a segment cut short by a write error has been reported to the
+        // archive_error hook instead, and isn't uploaded or announced as if
+        // it were complete:
+        if !self.ctx.failed {
+            self.ctx.announce(path, index, started.elapsed().as_secs(), metadata);
+        }
+    }
+}
+
+impl Drop for Archive {
+    fn drop(&mut self) {
+        self.close_segment();
+    }
+}
+
+// Tees everything read from the source connection into a file, giving an
+// archive of the stream exactly as it was received.
+pub struct RawDump<T: Read> {
+    inner: T,
+    ctx: DumpContext,
+    file: Option<File>,
+    path: String,
+    started: Instant,
+}
+
+impl<T: Read> RawDump<T> {
+    // returns the inner reader untouched if raw dumps are turned off:
+    pub fn wrap(config: Arc<Config>, mountpoint: &str, uuid: Uuid, inner: T) -> Box<Read + Send>
+        where T: Send + 'static
+    {
+        let (path, checked) = match config.stream_dump_raw {
+            Some(ref template) => {
+                let path = dump_path(template, &uuid, 0, &Metadata::default());
+                let checked = check_dump_path(template, &path);
+                (path, checked)
+            }
+            None => return Box::new(inner),
+        };
+
+        let mut ctx = DumpContext::new(config, mountpoint, uuid);
+
+        let file = match checked.and_then(|()| ctx.check_space(&path)).and_then(|()| File::create(&path)) {
+            Ok(file) => Some(file),
+            Err(e) => {
+                ctx.fail(&path, e);
+                None
+            }
+        };
+
+        Box::new(RawDump {
+            inner,
+            ctx,
+            file,
+            path,
+            started: Instant::now(),
+        })
+    }
+
+    fn dump(&mut self, data: &[u8]) {
+        let result = match self.file.as_mut() {
+            Some(file) => self.ctx.check_space(&self.path)
+                .and_then(|()| file.write_all(data)),
+            None => return,
+        };
+
+        if let Err(e) = result {
+            self.ctx.fail(&self.path, e);
+            self.file = None;
+        }
+    }
+}
+
+impl<T: Read> Read for RawDump<T> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let sz = self.inner.read(buf)?;
+        self.dump(&buf[..sz]);
+        Ok(sz)
+    }
+}
+
+impl<T: Read> Drop for RawDump<T> {
+    fn drop(&mut self) {
+        if let Some(file) = self.file.take() {
+            drop(file);
+
+            self.ctx.announce(self.path.clone(), 0, self.started.elapsed().as_secs(),
+                Metadata::default());
+        }
+    }
+}
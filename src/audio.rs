@@ -1,12 +1,25 @@
+use std::collections::BTreeMap;
 use std::io;
+use std::sync::Arc;
 
-#[derive(Debug)]
+use picture::Picture;
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
 pub struct Metadata {
     pub artist: Option<String>,
     pub title: Option<String>,
+    pub album: Option<String>,
+    pub genre: Option<String>,
+    pub date: Option<String>,
+    pub tracknumber: Option<String>,
+    // any other tags, keyed by lowercased name:
+    pub extra: BTreeMap<String, String>,
+    // album art, served separately:
+    #[serde(skip_serializing)]
+    pub picture: Option<Arc<Picture>>,
 }
 
-type PcmData = Vec<Vec<i16>>;
+pub type PcmData = Vec<Vec<i16>>;
 
 pub enum StreamRead {
     Eof,
@@ -19,10 +32,33 @@ pub enum StreamError {
     BadPacket,
 }
 
+// Checks a source's Content-Type says it's sending Ogg, the only format
+// sources can send, or says why it can't be taken. Sources that don't give
+// one are taken to be sending Ogg, as they always have been.
+pub fn check_source_content_type(content_type: Option<&str>) -> Result<(), String> {
+    let mime = content_type
+        .and_then(|content_type| content_type.split(';').next())
+        .map(|mime| mime.trim().to_lowercase());
+
+    match mime.as_ref().map(String::as_str) {
+        None | Some("") | Some("application/octet-stream") => Ok(()),
+        Some("audio/ogg") | Some("application/ogg") | Some("audio/vorbis") => Ok(()),
+        Some("audio/mpeg") | Some("audio/mp3") => Err("MP3 sources aren't supported, send Ogg Vorbis".to_owned()),
+        Some("audio/aac") | Some("audio/aacp") => Err("AAC sources aren't supported, send Ogg Vorbis".to_owned()),
+        Some(mime) => Err(format!("Unsupported Content-Type {}, send Ogg Vorbis", mime)),
+    }
+}
+
 pub trait AudioStream {
     fn codec_name(&self) -> &'static str;
     fn sample_rate(&self) -> u32;
     fn channels(&self) -> u8;
     fn bitrate_nominal(&self) -> i32;
     fn read(&mut self) -> Result<StreamRead, StreamError>;
+
+    // How many bytes the source has sent so far, for sources sent over the
+    // network:
+    fn bytes_read(&self) -> Option<u64> {
+        None
+    }
 }
@@ -0,0 +1,74 @@
+// The most recently published audio on a stream, so new listeners can start
+// with some already buffered instead of waiting on the live edge.
+
+use std::collections::VecDeque;
+
+use server::StreamData;
+
+pub struct BurstBuffer {
+    buffers: VecDeque<StreamData>,
+    // bytes in buffers:
+    len: usize,
+    // bytes to keep:
+    size: usize,
+}
+
+impl BurstBuffer {
+    pub fn new() -> BurstBuffer {
+        BurstBuffer {
+            buffers: VecDeque::new(),
+            len: 0,
+            size: 0,
+        }
+    }
+
+    pub fn set_size(&mut self, size: usize) {
+        self.size = size;
+        self.trim();
+    }
+
+    pub fn clear(&mut self) {
+        self.buffers.clear();
+        self.len = 0;
+    }
+
+    pub fn push(&mut self, data: StreamData) {
+        if self.size == 0 {
+            return;
+        }
+
+        self.len += data.len();
+        self.buffers.push_back(data);
+        self.trim();
+    }
+
+    // drops the oldest buffers that aren't needed to make up size:
+    fn trim(&mut self) {
+        while let Some(oldest) = self.buffers.front().map(|data| data.len()) {
+            if self.len - oldest < self.size {
+                break;
+            }
+
+            self.buffers.pop_front();
+            self.len -= oldest;
+        }
+    }
+
+    // The most recent whole buffers adding up to at most bytes, oldest
+    // first.
+    pub fn tail(&self, bytes: usize) -> Vec<StreamData> {
+        let mut len = 0;
+
+        let mut tail = self.buffers.iter()
+            .rev()
+            .take_while(|data| {
+                len += data.len();
+                len <= bytes
+            })
+            .cloned()
+            .collect::<Vec<_>>();
+
+        tail.reverse();
+        tail
+    }
+}
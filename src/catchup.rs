@@ -0,0 +1,407 @@
+// Catch-up from the stream dumps: /mount/archive?start=...&duration=...
+// stitches together the segments recorded for a mountpoint into one MP3, so
+// listeners can replay a show they missed. Only segments still on this
+// server's disk can be played, so not ones uploaded with delete_local. Like
+// History, the catalog of segments is kept in memory, so starts over on
+// restart. Catch-up listeners are listed, kicked and counted towards the
+// mountpoint's transfer like live ones.
+
+use std::cmp;
+use std::collections::{HashMap, VecDeque};
+use std::fs::File;
+use std::io::{self, BufReader, Read, Write};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use chrono::{self, DateTime, TimeZone, Utc};
+use tiny_http::{Header, Method, Request, Response};
+use uuid::Uuid;
+
+use config;
+use log::Log;
+use mp3::{self, FrameHeader};
+use proxy;
+use query::Query;
+use server::Rustcast;
+use transfer::CountingWriter;
+use useragent;
+
+struct Segment {
+    uuid: Uuid,
+    index: u64,
+    path: String,
+    started: DateTime<Utc>,
+    // None while it's still being recorded:
+    duration: Option<Duration>,
+}
+
+impl Segment {
+    fn ended(&self, now: DateTime<Utc>) -> DateTime<Utc> {
+        match self.duration {
+            Some(duration) => self.started + chrono::Duration::from_std(duration).unwrap_or_else(|_| chrono::Duration::zero()),
+            None => now,
+        }
+    }
+}
+
+pub struct Catalog {
+    mounts: Mutex<HashMap<String, VecDeque<Segment>>>,
+}
+
+impl Catalog {
+    pub fn new() -> Catalog {
+        Catalog {
+            mounts: Mutex::new(HashMap::new()),
+        }
+    }
+
+    // A stream dump segment has started being written. Segments that ended
+    // longer than window ago are forgotten.
+    pub fn opened(&self, mountpoint: &str, uuid: Uuid, index: u64, path: &str, window: Duration) {
+        let now = Utc::now();
+
+        // a window reaching back before time began keeps everything:
+        let cutoff = now.checked_sub_signed(signed(window));
+
+        let mut mounts = self.mounts.lock().expect("lock on catch-up catalog");
+
+        for segments in mounts.values_mut() {
+            while segments.front().map(|segment| Some(segment.ended(now)) < cutoff).unwrap_or(false) {
+                segments.pop_front();
+            }
+        }
+
+        mounts.retain(|_, segments| !segments.is_empty());
+
+        mounts.entry(mountpoint.to_owned())
+            .or_insert_with(VecDeque::new)
+            .push_back(Segment {
+                uuid,
+                index,
+                path: path.to_owned(),
+                started: now,
+                duration: None,
+            });
+    }
+
+    // The segment's been closed, having recorded duration of audio.
+    pub fn closed(&self, mountpoint: &str, uuid: Uuid, index: u64, duration: Duration) {
+        let mut mounts = self.mounts.lock().expect("lock on catch-up catalog");
+
+        let segment = mounts.get_mut(mountpoint)
+            .and_then(|segments| segments.iter_mut()
+                .find(|segment| segment.uuid == uuid && segment.index == index));
+
+        if let Some(segment) = segment {
+            segment.duration = Some(duration);
+        }
+    }
+
+    // The parts of segments recorded between from and to, oldest first.
+    fn pieces(&self, mountpoint: &str, from: DateTime<Utc>, to: DateTime<Utc>) -> VecDeque<Piece> {
+        let now = Utc::now();
+        let mounts = self.mounts.lock().expect("lock on catch-up catalog");
+
+        let segments = match mounts.get(mountpoint) {
+            Some(segments) => segments,
+            None => return VecDeque::new(),
+        };
+
+        segments.iter()
+            .filter(|segment| segment.started < to && segment.ended(now) > from)
+            .map(|segment| Piece {
+                path: segment.path.clone(),
+                skip: nanos(cmp::max(from, segment.started) - segment.started),
+                until: nanos(to - segment.started),
+            })
+            .collect()
+    }
+}
+
+// duration as a chrono::Duration, as long a one as there is if it's longer:
+fn signed(duration: Duration) -> chrono::Duration {
+    chrono::Duration::from_std(duration).unwrap_or_else(|_| chrono::Duration::max_value())
+}
+
+fn nanos(duration: chrono::Duration) -> u64 {
+    duration.num_nanoseconds()
+        .map(|nanos| cmp::max(nanos, 0) as u64)
+        .unwrap_or(u64::max_value())
+}
+
+// What's wanted of one segment, by how far into it the audio is:
+struct Piece {
+    path: String,
+    skip: u64,
+    until: u64,
+}
+
+struct Playing {
+    file: BufReader<File>,
+    piece: Piece,
+    // how much audio's been read from the file:
+    position: u64,
+    first_frame: bool,
+}
+
+// The frames of each piece one after another, read lazily so that hours of
+// catch-up aren't loaded into memory at once.
+struct Stitch<'a> {
+    log: &'a Log,
+    pieces: VecDeque<Piece>,
+    playing: Option<Playing>,
+    frame: Vec<u8>,
+    // how much of frame's been read:
+    offset: usize,
+}
+
+impl<'a> Stitch<'a> {
+    fn new(log: &'a Log, pieces: VecDeque<Piece>) -> Stitch<'a> {
+        Stitch {
+            log,
+            pieces,
+            playing: None,
+            frame: Vec::new(),
+            offset: 0,
+        }
+    }
+
+    // Reads the next frame to send into self.frame. Returns false once
+    // every piece has been played.
+    fn next_frame(&mut self) -> io::Result<bool> {
+        loop {
+            if self.playing.is_none() {
+                let piece = match self.pieces.pop_front() {
+                    Some(piece) => piece,
+                    None => return Ok(false),
+                };
+
+                match File::open(&piece.path) {
+                    Ok(file) => {
+                        self.playing = Some(Playing {
+                            file: BufReader::new(file),
+                            piece,
+                            position: 0,
+                            first_frame: true,
+                        });
+                    }
+                    // uploaded and deleted, or cleaned up some other way:
+                    Err(e) => {
+                        self.log.error(&format!("Skipping stream dump {} in catch-up: {}", piece.path, e));
+                        continue;
+                    }
+                }
+            }
+
+            {
+                let playing = self.playing.as_mut().expect("a piece playing");
+
+                if let Some(header) = read_frame(&mut playing.file, &mut self.frame)? {
+                    // the Xing header describes the whole segment, and isn't
+                    // audio:
+                    if playing.first_frame && mp3::is_xing_frame(&self.frame) {
+                        playing.first_frame = false;
+                        continue;
+                    }
+
+                    playing.first_frame = false;
+
+                    let at = playing.position;
+                    playing.position += header.duration_nanos();
+
+                    if at < playing.piece.skip {
+                        continue;
+                    }
+
+                    if at < playing.piece.until {
+                        self.offset = 0;
+                        return Ok(true);
+                    }
+                }
+            }
+
+            // the end of the file, or of what's wanted from it:
+            self.playing = None;
+        }
+    }
+}
+
+impl<'a> Read for Stitch<'a> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.offset == self.frame.len() {
+            if !self.next_frame()? {
+                return Ok(0);
+            }
+        }
+
+        let len = cmp::min(buf.len(), self.frame.len() - self.offset);
+        buf[..len].copy_from_slice(&self.frame[self.offset..self.offset + len]);
+        self.offset += len;
+
+        Ok(len)
+    }
+}
+
+// Reads the next whole frame of file into frame, skipping anything between
+// frames. None at the end of the file, including if it ends mid frame, as
+// the segment still being written will.
+fn read_frame<R: Read>(file: &mut R, frame: &mut Vec<u8>) -> io::Result<Option<FrameHeader>> {
+    let mut header = [0u8; 4];
+
+    if !read_all(file, &mut header)? {
+        return Ok(None);
+    }
+
+    loop {
+        if let Some(parsed) = FrameHeader::parse(&header) {
+            frame.clear();
+            frame.extend_from_slice(&header);
+            frame.resize(parsed.frame_len(), 0);
+
+            if !read_all(file, &mut frame[4..])? {
+                return Ok(None);
+            }
+
+            return Ok(Some(parsed));
+        }
+
+        header = [header[1], header[2], header[3], 0];
+
+        if !read_all(file, &mut header[3..])? {
+            return Ok(None);
+        }
+    }
+}
+
+// read_exact, but false rather than an error at the end of the file:
+fn read_all<R: Read>(file: &mut R, buf: &mut [u8]) -> io::Result<bool> {
+    match file.read_exact(buf) {
+        Ok(()) => Ok(true),
+        Err(ref e) if e.kind() == io::ErrorKind::UnexpectedEof => Ok(false),
+        Err(e) => Err(e),
+    }
+}
+
+// start is a Unix timestamp or an RFC 3339 date and time:
+fn parse_start(start: &str) -> Option<DateTime<Utc>> {
+    if let Ok(timestamp) = start.parse::<i64>() {
+        return Utc.timestamp_opt(timestamp, 0).single();
+    }
+
+    DateTime::parse_from_rfc3339(start).ok()
+        .map(|start| start.with_timezone(&Utc))
+}
+
+fn respond_error(req: Request, status: u16, message: &str) -> io::Result<()> {
+    req.respond(Response::from_string(format!("{}\n", message))
+        .with_status_code(status))
+}
+
+// Serves duration seconds of mountpoint from start, or everything since
+// start without a duration, from the stream dumps. The listener's already
+// been let in.
+pub fn handle(rustcast: &Rustcast, req: Request, mountpoint: &str, query: &Query) -> io::Result<()> {
+    let config = rustcast.config();
+
+    if config.stream_dump.is_none() || config.catchup_window == 0 {
+        return respond_error(req, 404, "catch-up isn't enabled");
+    }
+
+    let start = match query.get("start").and_then(parse_start) {
+        Some(start) => start,
+        None => return respond_error(req, 400, "start must be a Unix timestamp or RFC 3339 date and time"),
+    };
+
+    let now = Utc::now();
+
+    // nothing's recorded past now, so a duration reaching beyond it, however
+    // far, ends there:
+    let end = match query.get("duration") {
+        Some(duration) => match duration.parse::<u64>() {
+            Ok(duration) => start.checked_add_signed(signed(Duration::from_secs(duration)))
+                .map(|end| cmp::min(end, now))
+                .unwrap_or(now),
+            Err(_) => return respond_error(req, 400, "duration must be a number of seconds"),
+        },
+        None => now,
+    };
+
+    let earliest = now.checked_sub_signed(signed(Duration::from_secs(config.catchup_window)));
+
+    if Some(start) < earliest {
+        return respond_error(req, 404, "start is further back than catchup_window");
+    }
+
+    let pieces = rustcast.catalog.pieces(mountpoint, start, end);
+
+    if pieces.is_empty() {
+        return respond_error(req, 404, "nothing was recorded then");
+    }
+
+    let over_cap = config.mount(mountpoint)
+        .map(|mount| rustcast.transfer.over_cap(mountpoint, mount))
+        .unwrap_or(false);
+
+    if over_cap {
+        rustcast.log.info(&format!("Turned away catch-up listener to {} (transfer cap reached)", mountpoint));
+        return respond_error(req, 503, "transfer cap reached");
+    }
+
+    if *req.method() == Method::Head {
+        // dumps are always MP3, whatever the mountpoint's content_type:
+        let content_type = Header::from_bytes(&b"Content-Type"[..], config::DEFAULT_CONTENT_TYPE.as_bytes())
+            .expect("valid header");
+
+        return req.respond(Response::empty(200)
+            .with_header(content_type));
+    }
+
+    let ip = proxy::client_ip(&config, &req);
+
+    let user_agent = req.headers().iter()
+        .find(|header| header.field.equiv("User-Agent"))
+        .map(|header| header.value.as_str().to_owned());
+
+    let player = useragent::classify(user_agent.as_ref().map(String::as_str));
+
+    rustcast.log.info(&format!("Serving catch-up of {} from {} to {} to {} ({})",
+        mountpoint, start.to_rfc3339(), end.to_rfc3339(), ip, player));
+
+    let mut response = CountingWriter::new(req.into_writer(),
+        Arc::clone(&rustcast.transfer), mountpoint);
+
+    let listener = rustcast.listeners.connect(mountpoint, ip, user_agent, player);
+    rustcast.players.connect(mountpoint, player);
+
+    let result = play(rustcast, mountpoint, &mut response, Stitch::new(&rustcast.log, pieces), listener);
+
+    rustcast.players.disconnect(mountpoint, player);
+    rustcast.listeners.disconnect(listener);
+
+    rustcast.log.info(&format!("Catch-up listener {} disconnected from {}", ip, mountpoint));
+
+    result
+}
+
+// Sends stitch to the listener until it's played out or they're kicked.
+fn play<W: Write>(rustcast: &Rustcast, mountpoint: &str, response: &mut W, mut stitch: Stitch, listener: u64) -> io::Result<()> {
+    write!(response, "HTTP/1.0 200 OK\r\nServer: Rustcast\r\nContent-Type: {}\r\n\r\n",
+        config::DEFAULT_CONTENT_TYPE)?;
+
+    let mut buf = [0u8; 4096];
+
+    loop {
+        if rustcast.listeners.kicked(listener) {
+            rustcast.log.info(&format!("Closing catch-up listener on {} (kicked)", mountpoint));
+            return Ok(());
+        }
+
+        let len = stitch.read(&mut buf)?;
+
+        if len == 0 {
+            return response.flush();
+        }
+
+        response.write_all(&buf[..len])?;
+    }
+}
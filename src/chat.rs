@@ -0,0 +1,113 @@
+// Announces streams starting and ending, and track changes, in Discord or
+// Slack channels through their incoming webhooks, so operators don't need a
+// service of their own translating rustcast's webhooks.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use reqwest::Client;
+
+use audio::Metadata;
+use config::{ChatService, Config, Notification};
+use events::{Event, EventSink};
+use log::Log;
+use mountpoint;
+
+#[derive(Serialize)]
+struct DiscordMessage<'a> {
+    content: &'a str,
+}
+
+#[derive(Serialize)]
+struct SlackMessage<'a> {
+    text: &'a str,
+}
+
+fn song(metadata: &Metadata) -> String {
+    match (metadata.artist.as_ref(), metadata.title.as_ref()) {
+        (Some(artist), Some(title)) => format!("{} - {}", artist, title),
+        (None, Some(title)) => title.clone(),
+        (Some(artist), None) => artist.clone(),
+        (None, None) => "(unknown)".to_owned(),
+    }
+}
+
+// Fills in a message template. {mountpoint}, {url} (the public stream URL,
+// if public_url is set), {artist}, {title} and {song} ("artist - title")
+// are replaced, anything missing with nothing.
+fn render(template: &str, vars: &HashMap<&str, String>) -> String {
+    let mut message = template.to_owned();
+
+    for (name, value) in vars {
+        message = message.replace(&format!("{{{}}}", name), value);
+    }
+
+    message
+}
+
+pub struct ChatSink {
+    config: Arc<Config>,
+    notification: Notification,
+}
+
+impl ChatSink {
+    pub fn new(config: Arc<Config>, notification: Notification) -> ChatSink {
+        ChatSink { config, notification }
+    }
+
+    fn send(&self, mountpoint: &str, message: &str) {
+        let client = Client::new();
+
+        let request = match self.notification.service {
+            ChatService::Discord => client.post(&self.notification.webhook_url)
+                .json(&DiscordMessage { content: message })
+                .send(),
+            ChatService::Slack => client.post(&self.notification.webhook_url)
+                .json(&SlackMessage { text: message })
+                .send(),
+        };
+
+        // reqwest's errors include the URL, which is as good as a password,
+        // so only the status is logged:
+        let failure = match request {
+            Ok(ref response) if response.status().is_success() => return,
+            Ok(response) => format!("status {}", response.status()),
+            Err(ref e) => match e.status() {
+                Some(status) => format!("status {}", status),
+                None => "request failed".to_owned(),
+            },
+        };
+
+        Log::new().error(&format!("Couldn't send {:?} notification for {} ({})",
+            self.notification.service, mountpoint, failure));
+    }
+}
+
+impl EventSink for ChatSink {
+    fn publish(&self, event: &Event) {
+        let (mountpoint, template, metadata) = match *event {
+            Event::StreamStarted { ref mountpoint, .. } => (mountpoint, &self.notification.stream_start, None),
+            Event::StreamEnded { ref mountpoint, .. } => (mountpoint, &self.notification.stream_end, None),
+            Event::MetadataChanged { ref mountpoint, ref metadata, .. } => (mountpoint, &self.notification.track, Some(metadata)),
+            _ => return,
+        };
+
+        let mounts = &self.notification.mounts;
+
+        if template.is_empty() || !(mounts.is_empty() || mounts.iter().any(|prefix| mountpoint::is_within(mountpoint, prefix))) {
+            return;
+        }
+
+        let mut vars = HashMap::new();
+        vars.insert("mountpoint", mountpoint.clone());
+        vars.insert("url", self.config.public_stream_url(mountpoint).unwrap_or_default());
+
+        if let Some(metadata) = metadata {
+            vars.insert("artist", metadata.artist.clone().unwrap_or_default());
+            vars.insert("title", metadata.title.clone().unwrap_or_default());
+            vars.insert("song", song(metadata));
+        }
+
+        self.send(mountpoint, &render(template, &vars));
+    }
+}
@@ -0,0 +1,346 @@
+// A hot standby for stations on cheap servers: nodes listed in each other's
+// [cluster] peers ask each other what's live every poll_interval, and relay
+// whatever's live on a peer but not here, so listeners can be sent to any
+// node. Relays read the peer's stream from /cluster/stream, as it comes from
+// the source, rather than as a listener, who'd get intros, time limits and
+// the like. When a source drops and reconnects to another node the relays
+// follow it: a relay stops once its mountpoint's gone from the peer it came
+// from, and a source connecting to a node takes over from the node's relay.
+
+use std::collections::{HashMap, HashSet};
+use std::io::{self, Read, Write};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use reqwest::{self, Client};
+use reqwest::header::Headers;
+use serde_json;
+use tiny_http::{Header, Request, Response};
+
+use admin;
+use audio::Metadata;
+use config::{self, Config};
+use log::Log;
+use mountpoint;
+use server::{self, Frame, Rustcast, StreamEntry};
+
+const STATE_URL: &'static str = "/cluster/state";
+
+// followed by the mountpoint:
+const STREAM_URL: &'static str = "/cluster/stream";
+
+// how often to check whether clustering's been turned on:
+const IDLE_INTERVAL: Duration = Duration::from_secs(1);
+
+const READ_BUFFER: usize = 8192;
+
+// A mountpoint live on a node from a source of its own:
+#[derive(Serialize, Deserialize, Clone)]
+struct MountState {
+    mountpoint: String,
+    uuid: String,
+    artist: Option<String>,
+    title: Option<String>,
+}
+
+pub struct Cluster {
+    // what each peer last said it had live, by peer URL:
+    peers: Mutex<HashMap<String, Vec<MountState>>>,
+    // mountpoints being relayed from a peer:
+    relays: Mutex<HashSet<String>>,
+}
+
+impl Cluster {
+    pub fn new() -> Cluster {
+        Cluster {
+            peers: Mutex::new(HashMap::new()),
+            relays: Mutex::new(HashSet::new()),
+        }
+    }
+
+    // What's live on mountpoint at peer, as of the last time it was asked.
+    fn peer_mount(&self, peer: &str, mountpoint: &str) -> Option<MountState> {
+        self.peers.lock()
+            .expect("lock on cluster peers")
+            .get(peer)
+            .and_then(|mounts| mounts.iter().find(|mount| mount.mountpoint == mountpoint))
+            .cloned()
+    }
+}
+
+pub fn is_cluster_url(url: &str) -> bool {
+    let path = url.split('?').next().unwrap_or("");
+
+    path == STATE_URL || path.starts_with(&format!("{}/", STREAM_URL))
+}
+
+// Whether req comes from another node, going by the shared secret.
+pub fn is_peer(config: &Config, req: &Request) -> bool {
+    let cluster = match config.cluster {
+        Some(ref cluster) => cluster,
+        None => return false,
+    };
+
+    req.headers().iter()
+        .find(|header| header.field.equiv("Authorization"))
+        .map(|header| header.value.as_str())
+        .filter(|value| value.starts_with("Bearer "))
+        .map(|value| admin::constant_time_eq(value["Bearer ".len()..].as_bytes(), cluster.secret.as_bytes()))
+        .unwrap_or(false)
+}
+
+fn not_found(req: Request) -> io::Result<()> {
+    req.respond(Response::from_string("<h1>Not found</h1>\n")
+        .with_status_code(404))
+}
+
+pub fn handle(rustcast: &Rustcast, req: Request) -> io::Result<()> {
+    if !is_peer(&rustcast.config(), &req) {
+        return not_found(req);
+    }
+
+    let path = req.url().split('?').next().unwrap_or("").to_owned();
+
+    if path == STATE_URL {
+        handle_state(rustcast, req)
+    } else {
+        handle_stream(rustcast, req, &path[STREAM_URL.len()..])
+    }
+}
+
+// Tells a peer what's live here. Relays and mirrors are left out, so nodes
+// never relay each other's relays.
+fn handle_state(rustcast: &Rustcast, req: Request) -> io::Result<()> {
+    let mounts = rustcast.live_streams().into_iter()
+        .filter(|&(_, ref stream)| !stream.is_mirror() && !stream.is_relay())
+        .map(|(mountpoint, stream)| {
+            let metadata = stream.metadata.read().unwrap();
+
+            MountState {
+                mountpoint,
+                uuid: format!("{}", stream.uuid.hyphenated()),
+                artist: metadata.artist.clone(),
+                title: metadata.title.clone(),
+            }
+        })
+        .collect::<Vec<_>>();
+
+    let content_type = Header::from_bytes(&b"Content-Type"[..], &b"application/json"[..])
+        .expect("valid header");
+
+    req.respond(Response::from_string(serde_json::to_string(&mounts).unwrap())
+        .with_header(content_type)
+        .with_status_code(200))
+}
+
+// Sends a peer the stream on path's mountpoint as it comes, with the burst
+// buffer to start, until its source ends. Relays aren't counted or limited
+// as listeners.
+fn handle_stream(rustcast: &Rustcast, req: Request, path: &str) -> io::Result<()> {
+    let mountpoint = match mountpoint::from_url(path) {
+        Ok(mountpoint) => mountpoint,
+        Err(_) => return not_found(req),
+    };
+
+    let stream = match rustcast.get_stream(&mountpoint) {
+        Some(StreamEntry::Live(stream)) => stream,
+        _ => return not_found(req),
+    };
+
+    let (buffered, rx) = stream.subscribe_with_burst(usize::max_value());
+
+    // don't keep the stream alive ourselves, or rx would never see it end:
+    drop(stream);
+
+    let mut response = req.into_writer();
+
+    write!(response, "HTTP/1.0 200 OK\r\nServer: Rustcast\r\nContent-Type: {}\r\n\r\n",
+        rustcast.config().content_type(&mountpoint))?;
+
+    for buffer in buffered {
+        response.write_all(&buffer)?;
+    }
+
+    while let Some(frame) = rx.recv() {
+        match frame {
+            Frame::Audio(buffer) => response.write_all(&buffer)?,
+            Frame::Metadata(_) => continue,
+            Frame::End => break,
+        }
+    }
+
+    response.flush()
+}
+
+fn peer_get(cluster: &config::Cluster, url: &str) -> Result<reqwest::Response, String> {
+    let mut headers = Headers::new();
+    headers.set_raw("Authorization", format!("Bearer {}", cluster.secret));
+
+    let response = Client::new()
+        .get(url)
+        .headers(headers)
+        .send()
+        .map_err(|e| format!("{}", e))?;
+
+    if !response.status().is_success() {
+        return Err(format!("status {}", response.status()));
+    }
+
+    Ok(response)
+}
+
+fn poll(cluster: &config::Cluster, peer: &str) -> Result<Vec<MountState>, String> {
+    peer_get(cluster, &format!("{}{}", peer.trim_end_matches('/'), STATE_URL))?
+        .json()
+        .map_err(|e| format!("{}", e))
+}
+
+// Plays mountpoint from peer here until it's gone from peer, or a source
+// connects here and takes over.
+fn relay(rustcast: &Rustcast, peer: &str, mountpoint: &str) -> Result<(), String> {
+    let config = rustcast.config();
+
+    let cluster = match config.cluster {
+        Some(ref cluster) => cluster,
+        None => return Ok(()),
+    };
+
+    let uuid = match rustcast.cluster.peer_mount(peer, mountpoint) {
+        Some(mount) => mount.uuid,
+        None => return Ok(()),
+    };
+
+    let mut response = peer_get(cluster, &format!("{}{}{}", peer.trim_end_matches('/'), STREAM_URL, mountpoint))?;
+
+    let stream = rustcast.start_in_process(mountpoint)
+        .map_err(|e| format!("{:?}", e))?;
+
+    stream.set_relay();
+
+    let started = Instant::now();
+
+    rustcast.log.info(&format!("Relaying {} from {} (stream {} there)", mountpoint, peer, uuid));
+
+    let mut now_playing = None;
+    let mut buffer = [0u8; READ_BUFFER];
+
+    let result = loop {
+        if rustcast.is_shutdown() || stream.kicked() || stream.stopped() {
+            break Ok(());
+        }
+
+        let mount = match rustcast.cluster.peer_mount(peer, mountpoint) {
+            Some(mount) => mount,
+            None => break Ok(()),
+        };
+
+        let playing = (mount.artist, mount.title);
+
+        if now_playing.as_ref() != Some(&playing) {
+            let metadata = Metadata {
+                artist: playing.0.clone(),
+                title: playing.1.clone(),
+                ..Metadata::default()
+            };
+
+            rustcast.update_metadata(mountpoint, &stream, metadata);
+            now_playing = Some(playing);
+        }
+
+        match response.read(&mut buffer) {
+            // the peer's source went away:
+            Ok(0) => break Ok(()),
+            Ok(n) => {
+                if let Err(e) = stream.producing() {
+                    break Err(format!("{:?}", e));
+                }
+
+                stream.publish(Arc::new(buffer[..n].to_vec().into_boxed_slice()));
+            }
+            Err(e) => break Err(format!("{}", e)),
+        }
+    };
+
+    rustcast.log.info(&format!("Stopped relaying {} from {}", mountpoint, peer));
+
+    server::end_stream(rustcast, &stream, started);
+
+    result
+}
+
+fn start_relay(rustcast: &Arc<Rustcast>, peer: &str, mountpoint: &str) {
+    if !rustcast.cluster.relays.lock().expect("lock on cluster relays").insert(mountpoint.to_owned()) {
+        return;
+    }
+
+    let rustcast = Arc::clone(rustcast);
+    let peer = peer.to_owned();
+    let mountpoint = mountpoint.to_owned();
+
+    thread::spawn(move || {
+        if let Err(e) = relay(&rustcast, &peer, &mountpoint) {
+            rustcast.log.error(&format!("Couldn't relay {} from {}: {}", mountpoint, peer, e));
+        }
+
+        rustcast.cluster.relays.lock()
+            .expect("lock on cluster relays")
+            .remove(&mountpoint);
+    });
+}
+
+pub fn run(rustcast: Arc<Rustcast>) {
+    let log = Log::new();
+
+    // peers that couldn't be reached last time, so it's only logged once:
+    let mut unreachable = HashSet::new();
+
+    while !rustcast.is_shutdown() {
+        let config = rustcast.config();
+
+        let cluster = match config.cluster {
+            Some(ref cluster) => cluster,
+            None => {
+                rustcast.cluster.peers.lock().expect("lock on cluster peers").clear();
+                rustcast.sleep(IDLE_INTERVAL);
+                continue;
+            }
+        };
+
+        for peer in cluster.peers.iter() {
+            // an unreachable peer has nothing live, as far as we know:
+            let mounts = match poll(cluster, peer) {
+                Ok(mounts) => {
+                    if unreachable.remove(peer) {
+                        log.info(&format!("Cluster peer {} is back", peer));
+                    }
+
+                    mounts
+                }
+                Err(e) => {
+                    if unreachable.insert(peer.clone()) {
+                        log.error(&format!("Couldn't reach cluster peer {}: {}", peer, e));
+                    }
+
+                    Vec::new()
+                }
+            };
+
+            rustcast.cluster.peers.lock()
+                .expect("lock on cluster peers")
+                .insert(peer.clone(), mounts.clone());
+
+            for mount in mounts.iter() {
+                if rustcast.get_stream(&mount.mountpoint).is_none() {
+                    start_relay(&rustcast, peer, &mount.mountpoint);
+                }
+            }
+        }
+
+        rustcast.cluster.peers.lock()
+            .expect("lock on cluster peers")
+            .retain(|peer, _| cluster.peers.contains(peer));
+
+        rustcast.sleep(Duration::from_secs(cluster.poll_interval));
+    }
+}
@@ -1,14 +1,44 @@
+use std::collections::HashMap;
 use std::default::Default;
+use std::env;
+use std::error;
+use std::fmt;
 use std::fs::File;
 use std::io::{self, Read};
-use std::path::Path;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
 
+use glob;
+use reqwest::Url;
+use serde_json::{self, Value};
 use toml;
+use toml::value::Table;
 
-#[derive(Deserialize)]
+use mountpoint;
+use schedule;
+use shm;
+
+#[derive(Deserialize, Serialize)]
 pub struct Webhooks {
     pub stream_start: Option<String>,
     pub stream_end: Option<String>,
+    pub metadata: Option<String>,
+    pub duration_warning: Option<String>,
+    pub archive_segment: Option<String>,
+    pub archive_error: Option<String>,
+    pub config_reloaded: Option<String>,
+    pub slot_ending: Option<String>,
+    // called when a source becomes unhealthy, and when it recovers:
+    pub source_health: Option<String>,
+    pub listener_connect: Option<String>,
+    pub listener_disconnect: Option<String>,
+    // called when something goes wrong that an operator would want to know
+    // about:
+    pub error: Option<String>,
+    // posted a snapshot of every live mountpoint every heartbeat_interval
+    // seconds:
+    pub heartbeat: Option<String>,
 }
 
 impl Default for Webhooks {
@@ -16,27 +46,1753 @@ impl Default for Webhooks {
         Webhooks {
             stream_start: None,
             stream_end: None,
+            metadata: None,
+            duration_warning: None,
+            archive_segment: None,
+            archive_error: None,
+            config_reloaded: None,
+            slot_ending: None,
+            source_health: None,
+            listener_connect: None,
+            listener_disconnect: None,
+            error: None,
+            heartbeat: None,
+        }
+    }
+}
+
+// Options for the sockets listeners and sources connect on.
+#[derive(Deserialize, Serialize, Default)]
+pub struct SocketOptions {
+    // turn off Nagle's algorithm, so audio is sent as soon as it's written:
+    #[serde(default)]
+    pub nodelay: bool,
+    // send buffer size in bytes, the system default if None:
+    pub send_buffer: Option<usize>,
+    // seconds a connection can be idle before keepalive probes start, no
+    // keepalive if None:
+    pub keepalive: Option<u64>,
+}
+
+// Where events go besides the webhooks and in-process handlers.
+#[derive(Deserialize, Serialize, Default)]
+pub struct Events {
+    // log every event as a line of JSON:
+    #[serde(default)]
+    pub log: bool,
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ChatService {
+    Discord,
+    Slack,
+}
+
+// A Discord or Slack channel to announce streams and tracks in. Messages are
+// templates, see chat::render, and an empty one isn't sent.
+#[derive(Deserialize, Serialize, Clone)]
+pub struct Notification {
+    pub service: ChatService,
+    // the channel's incoming webhook URL:
+    pub webhook_url: String,
+    // only announce mountpoints starting with one of these, every one if
+    // empty:
+    #[serde(default)]
+    pub mounts: Vec<String>,
+    #[serde(default = "Notification::default_stream_start")]
+    pub stream_start: String,
+    #[serde(default = "Notification::default_stream_end")]
+    pub stream_end: String,
+    #[serde(default = "Notification::default_track")]
+    pub track: String,
+}
+
+impl Notification {
+    fn default_stream_start() -> String {
+        "Live now on {mountpoint}: {url}".to_owned()
+    }
+
+    fn default_stream_end() -> String {
+        "Stream on {mountpoint} has ended".to_owned()
+    }
+
+    fn default_track() -> String {
+        "Now playing on {mountpoint}: {song}".to_owned()
+    }
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ReportFormat {
+    Csv,
+    Json,
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct RoyaltyReports {
+    // directory each month's report is written to:
+    pub dir: String,
+    #[serde(default = "RoyaltyReports::default_format")]
+    pub format: ReportFormat,
+}
+
+impl RoyaltyReports {
+    fn default_format() -> ReportFormat {
+        ReportFormat::Csv
+    }
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct Statsd {
+    // address of the statsd server, like "localhost:8125":
+    pub host: String,
+    // prepended to every metric name:
+    #[serde(default = "Statsd::default_prefix")]
+    pub prefix: String,
+    // seconds between pushes:
+    #[serde(default = "Statsd::default_interval")]
+    pub interval: u64,
+}
+
+impl Statsd {
+    fn default_prefix() -> String {
+        "rustcast".to_owned()
+    }
+
+    fn default_interval() -> u64 {
+        10
+    }
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct Jwt {
+    // shared secret HS256 tokens are signed with:
+    pub secret: Option<String>,
+    // JSON Web Key Set to check RS256 and ES256 tokens against:
+    pub jwks_url: Option<String>,
+    // iss and aud tokens must have, when set:
+    pub issuer: Option<String>,
+    pub audience: Option<String>,
+    // claims listing the mountpoint prefixes a token may listen to, and
+    // broadcast on:
+    #[serde(default = "Jwt::default_listen_claim")]
+    pub listen_claim: String,
+    #[serde(default = "Jwt::default_source_claim")]
+    pub source_claim: String,
+    // query parameter tokens can be given in, for players that can't send
+    // headers:
+    #[serde(default = "Jwt::default_query_param")]
+    pub query_param: String,
+}
+
+impl Jwt {
+    fn default_listen_claim() -> String {
+        "listen".to_owned()
+    }
+
+    fn default_source_claim() -> String {
+        "source".to_owned()
+    }
+
+    fn default_query_param() -> String {
+        "token".to_owned()
+    }
+}
+
+// A weekly time slot in which a mountpoint may be broadcast on.
+#[derive(Deserialize, Serialize, Clone)]
+pub struct Slot {
+    pub mount: String,
+    // source user name allowed to broadcast in the slot, any if None:
+    pub user: Option<String>,
+    // days of the week the slot is on, like ["fri", "sat"], every day if
+    // empty:
+    #[serde(default)]
+    pub days: Vec<String>,
+    // "HH:MM" in UTC. a slot ending at or before its start runs past
+    // midnight into the next day:
+    pub start: String,
+    pub end: String,
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct Schedule {
+    #[serde(default)]
+    pub slots: Vec<Slot>,
+    // URL returning more slots as a JSON list, refetched every refresh
+    // seconds:
+    pub url: Option<String>,
+    #[serde(default = "Schedule::default_refresh")]
+    pub refresh: u64,
+    // seconds before a slot ends to call the slot_ending webhook:
+    #[serde(default = "Schedule::default_warning")]
+    pub warning: u64,
+    // stop sources when their slot ends:
+    #[serde(default)]
+    pub cutoff: bool,
+}
+
+impl Schedule {
+    fn default_refresh() -> u64 {
+        300
+    }
+
+    fn default_warning() -> u64 {
+        300
+    }
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct SessionCookie {
+    #[serde(default = "SessionCookie::default_name")]
+    pub name: String,
+    // domain to share the cookie with, like "example.com" so the station's
+    // website can read it:
+    pub domain: Option<String>,
+    #[serde(default = "SessionCookie::default_path")]
+    pub path: String,
+    // seconds the cookie lasts, otherwise until the browser closes:
+    pub max_age: Option<u64>,
+    // "Strict", "Lax" or "None":
+    pub same_site: Option<String>,
+    #[serde(default)]
+    pub secure: bool,
+    #[serde(default = "SessionCookie::default_http_only")]
+    pub http_only: bool,
+}
+
+impl SessionCookie {
+    fn default_name() -> String {
+        "rustcast_session".to_owned()
+    }
+
+    fn default_path() -> String {
+        "/".to_owned()
+    }
+
+    fn default_http_only() -> bool {
+        true
+    }
+}
+
+#[derive(Deserialize, Serialize, Clone, PartialEq)]
+pub struct Mqtt {
+    // address of the broker, like "localhost:1883":
+    pub broker: String,
+    #[serde(default = "Mqtt::default_client_id")]
+    pub client_id: String,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    // topics are templates, with {mountpoint} replaced by the mountpoint:
+    #[serde(default = "Mqtt::default_metadata_topic")]
+    pub metadata_topic: String,
+    #[serde(default = "Mqtt::default_listeners_topic")]
+    pub listeners_topic: String,
+    // retained messages are sent to new subscribers straight away:
+    #[serde(default = "Mqtt::default_retain")]
+    pub retain: bool,
+}
+
+impl Mqtt {
+    fn default_client_id() -> String {
+        "rustcast".to_owned()
+    }
+
+    fn default_metadata_topic() -> String {
+        "rustcast{mountpoint}/metadata".to_owned()
+    }
+
+    fn default_listeners_topic() -> String {
+        "rustcast{mountpoint}/listeners".to_owned()
+    }
+
+    fn default_retain() -> bool {
+        true
+    }
+}
+
+#[derive(Deserialize, Serialize, Clone)]
+pub struct ShmSource {
+    pub mountpoint: String,
+    pub path: String,
+    pub sample_rate: u32,
+    pub channels: u8,
+    // output bitrate in bits per second:
+    #[serde(default = "ShmSource::default_bitrate")]
+    pub bitrate: i32,
+    #[serde(default = "ShmSource::default_buffer_size")]
+    pub buffer_size: usize,
+    // seconds without new audio before the stream is considered ended:
+    #[serde(default = "ShmSource::default_idle_timeout")]
+    pub idle_timeout: u64,
+}
+
+impl ShmSource {
+    fn default_bitrate() -> i32 {
+        128000
+    }
+
+    fn default_buffer_size() -> usize {
+        1024 * 1024
+    }
+
+    fn default_idle_timeout() -> u64 {
+        5
+    }
+}
+
+#[derive(Deserialize, Serialize, Clone)]
+pub struct PipeConfig {
+    pub mountpoint: String,
+    pub command: Vec<String>,
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct Upload {
+    pub endpoint: String,
+    pub bucket: String,
+    #[serde(default = "Upload::default_region")]
+    pub region: String,
+    pub access_key: String,
+    pub secret_key: String,
+    // prepended to the dump's file name to make the object key:
+    #[serde(default)]
+    pub prefix: String,
+    #[serde(default = "Upload::default_delete_local")]
+    pub delete_local: bool,
+}
+
+impl Upload {
+    fn default_region() -> String {
+        "us-east-1".to_owned()
+    }
+
+    fn default_delete_local() -> bool {
+        true
+    }
+}
+
+// What someone using the admin API can do: look, or change things too.
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum AdminRole {
+    Admin,
+    ReadOnly,
+}
+
+impl Default for AdminRole {
+    fn default() -> Self {
+        AdminRole::Admin
+    }
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct ApiKey {
+    pub name: Option<String>,
+    pub key: String,
+    // mountpoint prefixes this key is allowed to act on:
+    pub mounts: Vec<String>,
+    #[serde(default)]
+    pub role: AdminRole,
+}
+
+impl ApiKey {
+    pub fn allows(&self, mountpoint: &str) -> bool {
+        self.mounts.iter().any(|prefix| mountpoint::is_within(mountpoint, prefix))
+    }
+
+    pub fn name(&self) -> &str {
+        self.name.as_ref().map(String::as_str).unwrap_or("(unnamed)")
+    }
+}
+
+// People allowed to use the admin API on every mountpoint, with a password
+// for HTTP basic auth or a token to send as a bearer token. Separate from
+// source passwords, so a source's password never gets anyone into the admin
+// API.
+#[derive(Deserialize, Serialize, Default)]
+pub struct AdminConfig {
+    #[serde(default)]
+    pub users: Vec<AdminUser>,
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct AdminUser {
+    pub name: String,
+    pub password: Option<String>,
+    pub token: Option<String>,
+    #[serde(default)]
+    pub role: AdminRole,
+}
+
+// Bans addresses that get source passwords wrong too often.
+#[derive(Deserialize, Serialize)]
+pub struct SourceLockout {
+    // wrong passwords an address can give within window before it's banned:
+    #[serde(default = "SourceLockout::default_max_failures")]
+    pub max_failures: u32,
+    // seconds wrong passwords are counted over:
+    #[serde(default = "SourceLockout::default_window")]
+    pub window: u64,
+    // seconds an address is banned for:
+    #[serde(default = "SourceLockout::default_ban")]
+    pub ban: u64,
+}
+
+impl SourceLockout {
+    fn default_max_failures() -> u32 {
+        5
+    }
+
+    fn default_window() -> u64 {
+        300
+    }
+
+    fn default_ban() -> u64 {
+        900
+    }
+}
+
+// Other rustcast nodes to share mountpoints with, so any of them can serve
+// listeners and sources can fail over between them.
+#[derive(Deserialize, Serialize)]
+pub struct Cluster {
+    // the other nodes' base URLs, like "http://b.example.com:8000":
+    pub peers: Vec<String>,
+    // shared by every node, to tell their requests apart from anyone else's:
+    pub secret: String,
+    // seconds between asking peers what they have live:
+    #[serde(default = "Cluster::default_poll_interval")]
+    pub poll_interval: u64,
+}
+
+impl Cluster {
+    fn default_poll_interval() -> u64 {
+        2
+    }
+}
+
+// When a source counts as unhealthy, judged over each window seconds.
+#[derive(Deserialize, Serialize)]
+pub struct SourceHealth {
+    #[serde(default = "SourceHealth::default_window")]
+    pub window: u64,
+    // seconds of audio that must arrive per second, below which the source
+    // is underrunning:
+    #[serde(default = "SourceHealth::default_min_speed")]
+    pub min_speed: f64,
+    // milliseconds without audio, above which the source is stuttering:
+    #[serde(default = "SourceHealth::default_max_gap")]
+    pub max_gap: u64,
+    // the share of packets, from 0 to 1, that may fail to decode:
+    #[serde(default = "SourceHealth::default_max_decode_errors")]
+    pub max_decode_errors: f64,
+    // kbps a source must send at, if any:
+    pub min_bitrate: Option<u32>,
+}
+
+impl SourceHealth {
+    fn default_window() -> u64 {
+        10
+    }
+
+    fn default_min_speed() -> f64 {
+        0.9
+    }
+
+    fn default_max_gap() -> u64 {
+        1000
+    }
+
+    fn default_max_decode_errors() -> f64 {
+        0.01
+    }
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct SourceUser {
+    pub name: Option<String>,
+    pub password: String,
+    // a source can take over from a lower priority source already live on
+    // the mountpoint:
+    #[serde(default)]
+    pub priority: u32,
+}
+
+// Where to check sources' user names and passwords, instead of password and
+// sources.
+#[derive(Deserialize, Serialize)]
+#[serde(tag = "backend", rename_all = "lowercase")]
+pub enum SourceAuth {
+    // an htpasswd file, reread for every source:
+    Htpasswd { file: String },
+    // an LDAP simple bind, with bind_dn a template like
+    // "uid={user},ou=djs,dc=example,dc=org":
+    Ldap { url: String, bind_dn: String },
+}
+
+// A station of its own on one instance, served to requests for its hosts.
+// Its mountpoints are kept apart from everyone else's under /name, so
+// /live on jazz.example.com is /jazz/live in logs, the admin API and
+// webhooks, and can't be reached from any other host.
+#[derive(Deserialize, Serialize)]
+pub struct Station {
+    // Host headers the station answers, without ports:
+    pub hosts: Vec<String>,
+    // the station's public_url, for webhooks and directory listings:
+    pub public_url: Option<String>,
+    // password sources must give on the station's mountpoints without their
+    // own, instead of the top level source_password:
+    pub source_password: Option<String>,
+    // called for the station's streams instead of the top level webhooks:
+    #[serde(default)]
+    pub webhooks: Webhooks,
+    #[serde(default)]
+    pub mounts: HashMap<String, MountConfig>,
+}
+
+#[derive(Deserialize, Serialize, Default)]
+pub struct MountConfig {
+    // seconds a listener may wait on this mountpoint for a source to start,
+    // hearing silence in the meantime:
+    pub wait_for_source: Option<u64>,
+    // seconds to keep listeners connected after the source ends, waiting for
+    // it to come back:
+    pub reconnect_timeout: Option<u64>,
+    // play silence to listeners while waiting for the source to reconnect,
+    // true by default:
+    pub reconnect_silence: Option<bool>,
+    // longest a single stream may run for, in seconds:
+    pub max_duration: Option<u64>,
+    // seconds before max_duration to call the duration_warning hook:
+    pub max_duration_warning: Option<u64>,
+    // seconds to fade out over before the stream is stopped:
+    pub fade_out: Option<u64>,
+    // password sources must give to broadcast on this mountpoint, checked
+    // before the stream_start webhook is called:
+    pub password: Option<String>,
+    // sources allowed on this mountpoint, each with their own password and
+    // priority:
+    #[serde(default)]
+    pub sources: Vec<SourceUser>,
+    // check sources against an htpasswd file or LDAP directory instead:
+    pub source_auth: Option<SourceAuth>,
+    // what listeners are sent, MP3 by default. AAC needs the aac feature:
+    pub codec: Option<OutputCodec>,
+    // output bitrate in kbps, instead of the source's nominal bitrate:
+    pub bitrate: Option<i32>,
+    // LAME encoder settings for transcoded sources. quality runs from 0
+    // (slowest, best) to 9 (fastest), 0 by default:
+    pub quality: Option<u8>,
+    pub mode: Option<EncodeMode>,
+    // VBR quality from 0 (best) to 9 (smallest), 4 by default:
+    pub vbr_quality: Option<u8>,
+    pub joint_stereo: Option<bool>,
+    // lowpass filter frequency in Hz:
+    pub lowpass: Option<u32>,
+    // throttle writes to listeners to about the stream's bitrate:
+    #[serde(default)]
+    pub pace_listeners: bool,
+    // seconds of audio a paced listener may get ahead of real time:
+    pub pace_burst: Option<u64>,
+    // listeners need a JWT allowing this mountpoint:
+    #[serde(default)]
+    pub require_jwt: bool,
+    // minutes within which a listener reconnecting from the same address
+    // and User-Agent is counted as the same listener:
+    pub listener_dedup_window: Option<u64>,
+    // megabytes that may be sent to listeners per day and per calendar
+    // month, after which new listeners are turned away:
+    pub daily_transfer_cap: Option<u64>,
+    pub monthly_transfer_cap: Option<u64>,
+    // bytes of recent audio to keep, to send new listeners so they start
+    // with some buffered:
+    pub burst_size: Option<usize>,
+    // packets of audio a listener can fall behind by before they're
+    // disconnected, DEFAULT_LISTENER_BUFFER by default:
+    pub listener_buffer: Option<usize>,
+    // only encode while someone's listening, or the stream is being dumped:
+    #[serde(default)]
+    pub lazy_encoding: bool,
+    // seconds of the stream to keep in memory, so listeners can listen
+    // behind live with ?offset=N:
+    pub timeshift: Option<u64>,
+    // MP3 file sent to each new listener before the live stream:
+    pub intro: Option<String>,
+    // seconds a listener may stay connected for, for free tiers and
+    // licences that limit listening:
+    pub max_listen_seconds: Option<u64>,
+    // MP3 file played to listeners when their time's up, before they're
+    // disconnected:
+    pub max_listen_message: Option<String>,
+    // Content-Type listeners are sent, audio/mpeg by default:
+    pub content_type: Option<String>,
+    // URL extensions the stream is served at, without the dot, ["mp3"] by
+    // default. The first is used in public URLs and playlists, and the
+    // stream can always be fetched without one:
+    pub extensions: Option<Vec<String>>,
+    // other mountpoints to publish this mountpoint's output on too, while
+    // they don't have a source of their own:
+    #[serde(default)]
+    pub mirror_to: Vec<String>,
+    // leave out of public listings, the mountpoint is still reachable by
+    // anyone who knows its URL:
+    #[serde(default)]
+    pub hidden: bool,
+    // announce to the YP directories in yp_urls:
+    #[serde(default)]
+    pub public: bool,
+    // station details for directory listings:
+    pub name: Option<String>,
+    pub description: Option<String>,
+    pub genre: Option<String>,
+    pub url: Option<String>,
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "kebab-case")]
+pub enum OutputCodec {
+    Mp3,
+    // AAC-LC:
+    Aac,
+    // AAC with spectral band replication, for low bitrates:
+    HeAac,
+}
+
+impl OutputCodec {
+    pub fn name(&self) -> &'static str {
+        match *self {
+            OutputCodec::Mp3 => "MP3",
+            OutputCodec::Aac => "AAC",
+            OutputCodec::HeAac => "HE-AAC",
+        }
+    }
+
+    pub fn content_type(&self) -> &'static str {
+        match *self {
+            OutputCodec::Mp3 => DEFAULT_CONTENT_TYPE,
+            OutputCodec::Aac | OutputCodec::HeAac => "audio/aac",
+        }
+    }
+
+    fn extensions(&self) -> &'static [&'static str] {
+        match *self {
+            OutputCodec::Mp3 => &["mp3"],
+            OutputCodec::Aac | OutputCodec::HeAac => &["aac"],
+        }
+    }
+
+    // Whether the codec can be encoded at kbps.
+    pub fn allows_bitrate(&self, kbps: i32) -> bool {
+        match *self {
+            OutputCodec::Mp3 => MP3_BITRATES.contains(&kbps),
+            OutputCodec::Aac => kbps >= 8 && kbps <= 320,
+            OutputCodec::HeAac => kbps >= 16 && kbps <= 64,
+        }
+    }
+
+    fn bitrates(&self) -> String {
+        match *self {
+            OutputCodec::Mp3 => format!("one of {:?}", MP3_BITRATES),
+            OutputCodec::Aac => "from 8 to 320".to_owned(),
+            OutputCodec::HeAac => "from 16 to 64".to_owned(),
         }
     }
 }
 
-#[derive(Deserialize)]
+impl MountConfig {
+    pub fn codec(&self) -> OutputCodec {
+        self.codec.unwrap_or(OutputCodec::Mp3)
+    }
+
+    pub fn content_type(&self) -> &str {
+        self.content_type.as_ref()
+            .map(String::as_str)
+            .unwrap_or_else(|| self.codec().content_type())
+    }
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum EncodeMode {
+    // constant bitrate:
+    Cbr,
+    // average bitrate, varying around bitrate:
+    Abr,
+    // variable bitrate, at vbr_quality:
+    Vbr,
+}
+
+#[derive(Deserialize, Serialize)]
 pub struct Config {
     pub listen: String,
-    pub stream_dump: String,
+    // the --listen address, which replaces listen, and is kept when the
+    // config is reloaded:
+    #[serde(skip)]
+    pub listen_override: Option<String>,
+    // file to write rustcast's process id to:
+    pub pid_file: Option<String>,
+    // user and group to switch to once listening:
+    pub user: Option<String>,
+    pub group: Option<String>,
+    // tuning for connections (Unix only):
+    #[serde(default)]
+    pub socket: SocketOptions,
+    // base URL listeners reach rustcast on, when that's not the listen
+    // address (eg. behind a reverse proxy):
+    pub public_url: Option<String>,
+    // addresses of reverse proxies whose X-Forwarded-For and
+    // X-Forwarded-Proto headers are believed:
+    #[serde(default)]
+    pub trusted_proxies: Vec<IpAddr>,
+    pub stream_dump: Option<String>,
+    // seconds of audio per stream dump file, or None to write a single file
+    // for the whole stream:
+    pub stream_dump_rotate: Option<u64>,
+    // start a new stream dump file whenever the stream's metadata changes:
+    #[serde(default)]
+    pub stream_dump_split_on_metadata: bool,
+    // path to dump the source's original bytes to, before transcoding:
+    pub stream_dump_raw: Option<String>,
+    // megabytes of disk space to leave free, dumps stop below this:
+    pub stream_dump_min_free: Option<u64>,
+    // seconds back that /mount/archive catch-up URLs reach, 0 to turn them
+    // off:
+    #[serde(default = "Config::default_catchup_window")]
+    pub catchup_window: u64,
+    // object storage to upload finished dumps to:
+    pub upload: Option<Upload>,
+    // number of metadata changes to remember per mountpoint:
+    #[serde(default = "Config::default_history_size")]
+    pub history_size: usize,
+    // origins allowed to fetch the JSON endpoints from a browser, or "*" for
+    // any origin:
+    #[serde(default)]
+    pub cors_origins: Vec<String>,
     #[serde(default)]
     pub webhooks: Webhooks,
+    // seconds between calls to the heartbeat webhook:
+    #[serde(default = "Config::default_heartbeat_interval")]
+    pub heartbeat_interval: u64,
+    // seconds a source may spend being authenticated before its mountpoint
+    // is given up on and freed for another source:
+    #[serde(default = "Config::default_source_auth_timeout")]
+    pub source_auth_timeout: u64,
+    // banning addresses that keep getting source passwords wrong:
+    pub source_lockout: Option<SourceLockout>,
+    // alerting when sources underrun, stutter or send garbage:
+    pub source_health: Option<SourceHealth>,
+    // sharing mountpoints with other nodes, for a hot standby:
+    pub cluster: Option<Cluster>,
+    #[serde(default)]
+    pub events: Events,
+    // broker to publish now playing and listener counts to:
+    pub mqtt: Option<Mqtt>,
+    // statsd server to push metrics to:
+    pub statsd: Option<Statsd>,
+    // SQLite database to keep stream, listener and track history in:
+    pub stats_db: Option<String>,
+    // monthly reports from the stats database:
+    pub royalty_reports: Option<RoyaltyReports>,
+    // time slots sources may broadcast in:
+    pub schedule: Option<Schedule>,
+    // JSON Web Tokens for listener and source auth:
+    pub jwt: Option<Jwt>,
+    // cookie given to listeners to tell their sessions apart:
+    pub session_cookie: Option<SessionCookie>,
+    // chat channels to announce streams and tracks in:
+    #[serde(default)]
+    pub notifications: Vec<Notification>,
+    #[serde(default)]
+    pub mounts: HashMap<String, MountConfig>,
+    // stations served on their own hostnames, by name:
+    #[serde(default)]
+    pub stations: HashMap<String, Station>,
+    // Icecast YP directory servers to announce public mountpoints to:
+    #[serde(default)]
+    pub yp_urls: Vec<String>,
+    // password sources must give on mountpoints without their own:
+    pub source_password: Option<String>,
+    // only accept sources on mountpoints configured in mounts:
+    #[serde(default)]
+    pub strict_mounts: bool,
+    // only report ready on /readyz while at least one source is live:
+    #[serde(default)]
+    pub ready_requires_source: bool,
+    // serve Prometheus metrics at /metrics:
+    #[serde(default)]
+    pub metrics: bool,
+    // mountpoints served as if another mountpoint had been requested:
+    #[serde(default)]
+    pub aliases: HashMap<String, String>,
+    // mountpoints that redirect listeners to another mountpoint or URL:
+    #[serde(default)]
+    pub redirects: HashMap<String, String>,
+    #[serde(default)]
+    pub admin: AdminConfig,
+    #[serde(default)]
+    pub api_keys: Vec<ApiKey>,
+    #[serde(default)]
+    pub shm_sources: Vec<ShmSource>,
+    #[serde(default)]
+    pub pipes: Vec<PipeConfig>,
 }
 
-#[derive(Debug)]
-pub enum ConfigError {
-    Io(io::Error),
-    Toml(toml::de::Error),
-}
+impl Config {
+    fn default_history_size() -> usize {
+        20
+    }
 
-pub fn open(path: &Path) -> Result<Config, ConfigError> {
-    let mut file = File::open(path).map_err(ConfigError::Io)?;
-    let mut buff = String::new();
-    file.read_to_string(&mut buff).map_err(ConfigError::Io)?;
-    toml::from_str(&buff).map_err(ConfigError::Toml)
+    fn default_catchup_window() -> u64 {
+        24 * 60 * 60
+    }
+
+    fn default_heartbeat_interval() -> u64 {
+        60
+    }
+
+    fn default_source_auth_timeout() -> u64 {
+        30
+    }
+
+    // Listens on listen instead of the config file's address, from now on
+    // and after reloads.
+    pub fn override_listen(&mut self, listen: String) {
+        self.listen = listen.clone();
+        self.listen_override = Some(listen);
+    }
+
+    // The station whose hosts include host, which may have a port.
+    pub fn station_for_host(&self, host: &str) -> Option<&str> {
+        let host = match host.rfind(':') {
+            // leave IPv6 addresses in brackets alone:
+            Some(colon) if !host[colon..].contains(']') => &host[..colon],
+            _ => host,
+        };
+
+        self.stations.iter()
+            .find(|&(_, station)| station.hosts.iter().any(|station_host| station_host.eq_ignore_ascii_case(host)))
+            .map(|(name, _)| name.as_str())
+    }
+
+    // The mountpoint a request for path on host is for. None if path is in
+    // a station's mountpoints but the request isn't for that station, since
+    // stations can only be reached on their own hosts.
+    pub fn station_mountpoint(&self, host: Option<&str>, path: &str) -> Option<String> {
+        match host.and_then(|host| self.station_for_host(host)) {
+            Some(station) => Some(format!("/{}{}", station, path)),
+            None if self.in_station(path) => None,
+            None => Some(path.to_owned()),
+        }
+    }
+
+    // Whether path is one of a station's mountpoints, or the station itself.
+    fn in_station(&self, path: &str) -> bool {
+        self.station(path).is_some() || self.stations.contains_key(path.trim_start_matches('/'))
+    }
+
+    // The name of the station mountpoint is in.
+    pub fn station_name(&self, mountpoint: &str) -> Option<&str> {
+        self.stations.keys()
+            .find(|name| mountpoint.starts_with(&format!("/{}/", name)))
+            .map(String::as_str)
+    }
+
+    // The station mountpoint is in, and where it is within the station.
+    pub fn station(&self, mountpoint: &str) -> Option<(&Station, &str)> {
+        self.stations.iter()
+            .filter_map(|(name, station)| {
+                let prefix = format!("/{}/", name);
+
+                if mountpoint.starts_with(&prefix) {
+                    Some((station, &mountpoint[prefix.len() - 1..]))
+                } else {
+                    None
+                }
+            })
+            .next()
+    }
+
+    // Where listeners on the station's hosts find mountpoint. Mountpoints
+    // outside any station are where they are.
+    pub fn public_path<'a>(&self, mountpoint: &'a str) -> &'a str {
+        match self.station(mountpoint) {
+            Some((_, rest)) => &mountpoint[mountpoint.len() - rest.len()..],
+            None => mountpoint,
+        }
+    }
+
+    // target as named by the config or a hook for mountpoint, so a station's
+    // mounts only ever mirror to and reroute within the station.
+    pub fn within_station(&self, mountpoint: &str, target: &str) -> String {
+        match self.station(mountpoint) {
+            Some((_, rest)) => format!("{}{}", &mountpoint[..mountpoint.len() - rest.len()], target),
+            None => target.to_owned(),
+        }
+    }
+
+    pub fn webhooks(&self, mountpoint: &str) -> &Webhooks {
+        match self.station(mountpoint) {
+            Some((station, _)) => &station.webhooks,
+            None => &self.webhooks,
+        }
+    }
+
+    // The password sources must give on mountpoint without one of its own.
+    pub fn source_password(&self, mountpoint: &str) -> Option<&String> {
+        match self.station(mountpoint) {
+            Some((station, _)) => station.source_password.as_ref(),
+            None => self.source_password.as_ref(),
+        }
+    }
+
+    // Settings for a mountpoint. Mounts can be configured by pattern, where
+    // "/dj/*" applies to every mountpoint under /dj/. An exact match wins
+    // over a pattern, and otherwise the longest matching pattern wins.
+    // Station mountpoints only ever get the station's settings.
+    pub fn mount(&self, mountpoint: &str) -> Option<&MountConfig> {
+        let (mounts, mountpoint) = match self.station(mountpoint) {
+            Some((station, rest)) => (&station.mounts, rest),
+            None => (&self.mounts, mountpoint),
+        };
+
+        if let Some(mount) = mounts.get(mountpoint) {
+            return Some(mount);
+        }
+
+        mounts.iter()
+            .filter_map(|(pattern, mount)| {
+                if !pattern.ends_with("/*") {
+                    return None;
+                }
+
+                let prefix = &pattern[..pattern.len() - 1];
+
+                if mountpoint.len() > prefix.len() && mountpoint.starts_with(prefix) {
+                    Some((prefix.len(), mount))
+                } else {
+                    None
+                }
+            })
+            .max_by_key(|&(len, _)| len)
+            .map(|(_, mount)| mount)
+    }
+
+    // The config as TOML, with every default filled in and secrets redacted.
+    pub fn to_toml(&self) -> Result<String, toml::ser::Error> {
+        let mut value = serde_json::to_value(self).expect("config to serialize");
+        redact(&mut value);
+
+        toml::Value::try_from(value).and_then(|value| toml::to_string(&value))
+    }
+
+    pub fn hidden(&self, mountpoint: &str) -> bool {
+        self.mount(mountpoint).map(|mount| mount.hidden).unwrap_or(false)
+    }
+
+    pub fn public_stream_url(&self, mountpoint: &str) -> Option<String> {
+        let public_url = match self.station(mountpoint) {
+            Some((station, _)) => station.public_url.as_ref(),
+            None => self.public_url.as_ref(),
+        };
+
+        public_url
+            .map(|url| format!("{}{}", url.trim_end_matches('/'), self.stream_path(mountpoint)))
+    }
+
+    // The Content-Type of mountpoint's stream.
+    pub fn content_type(&self, mountpoint: &str) -> &str {
+        self.mount(mountpoint)
+            .map(MountConfig::content_type)
+            .unwrap_or(DEFAULT_CONTENT_TYPE)
+    }
+
+    // The codec mountpoint's listeners are sent.
+    pub fn codec(&self, mountpoint: &str) -> OutputCodec {
+        self.mount(mountpoint)
+            .map(MountConfig::codec)
+            .unwrap_or(OutputCodec::Mp3)
+    }
+
+    // The URL extensions mountpoint's stream is served at.
+    pub fn audio_extensions(&self, mountpoint: &str) -> Vec<&str> {
+        match self.mount(mountpoint).and_then(|mount| mount.extensions.as_ref()) {
+            Some(extensions) => extensions.iter().map(String::as_str).collect(),
+            None => self.codec(mountpoint).extensions().to_vec(),
+        }
+    }
+
+    pub fn listener_buffer(&self, mountpoint: &str) -> usize {
+        self.mount(mountpoint)
+            .and_then(|mount| mount.listener_buffer)
+            .unwrap_or(DEFAULT_LISTENER_BUFFER)
+    }
+
+    // The path of mountpoint's stream, with its first extension, as
+    // listeners on its station's hosts see it.
+    pub fn stream_path(&self, mountpoint: &str) -> String {
+        let path = self.public_path(mountpoint);
+
+        match self.audio_extensions(mountpoint).first() {
+            Some(extension) => format!("{}.{}", path, extension),
+            None => path.to_owned(),
+        }
+    }
+}
+
+pub const DEFAULT_CONTENT_TYPE: &'static str = "audio/mpeg";
+
+pub const DEFAULT_LISTENER_BUFFER: usize = 16;
+
+// extensions of the other things served for a mountpoint, which can't be
+// used for its stream:
+const RESERVED_EXTENSIONS: &'static [&'static str] = &["json", "m3u", "pls", "xspf", "jpg"];
+
+// settings that are only read at startup, changing these needs a restart:
+const RESTART_KEYS: &'static [&'static str] = &["listen", "shm_sources", "pipes", "pid_file", "user", "group", "socket"];
+
+// fields whose values are never logged or sent to webhooks:
+const SECRET_FIELDS: &'static [&'static str] = &["key", "access_key", "secret_key", "secret", "password", "source_password", "token", "webhook_url"];
+
+#[derive(Serialize, Debug, Clone)]
+pub struct ConfigChange {
+    pub key: String,
+    pub old: Option<Value>,
+    pub new: Option<Value>,
+    // false if the change won't take effect until rustcast is restarted:
+    pub live: bool,
+}
+
+fn redact(value: &mut Value) {
+    match *value {
+        Value::Object(ref mut fields) => {
+            for (name, value) in fields.iter_mut() {
+                // secrets that aren't set are left unset:
+                if SECRET_FIELDS.contains(&name.as_str()) {
+                    if !value.is_null() {
+                        *value = Value::String("<redacted>".to_owned());
+                    }
+                } else {
+                    redact(value);
+                }
+            }
+        }
+        Value::Array(ref mut values) => {
+            for value in values.iter_mut() {
+                redact(value);
+            }
+        }
+        _ => (),
+    }
+}
+
+fn diff_values(key: &str, name: &str, old: Option<&Value>, new: Option<&Value>, changes: &mut Vec<ConfigChange>) {
+    if let (Some(&Value::Object(ref old)), Some(&Value::Object(ref new))) = (old, new) {
+        let mut names = old.keys().chain(new.keys()).collect::<Vec<_>>();
+        names.sort();
+        names.dedup();
+
+        for name in names {
+            let key = if key.is_empty() {
+                name.clone()
+            } else {
+                format!("{}.{}", key, name)
+            };
+
+            diff_values(&key, name, old.get(name), new.get(name), changes);
+        }
+
+        return;
+    }
+
+    if old == new {
+        return;
+    }
+
+    let secret = SECRET_FIELDS.contains(&name);
+
+    let redacted = |value: Option<&Value>| value.map(|value| {
+        if secret {
+            Value::String("<redacted>".to_owned())
+        } else {
+            let mut value = value.clone();
+            redact(&mut value);
+            value
+        }
+    });
+
+    let top_level = key.split('.').next().unwrap_or(key);
+
+    changes.push(ConfigChange {
+        key: key.to_owned(),
+        old: redacted(old),
+        new: redacted(new),
+        live: !RESTART_KEYS.contains(&top_level),
+    });
+}
+
+pub fn diff(old: &Config, new: &Config) -> Vec<ConfigChange> {
+    let old = serde_json::to_value(old).expect("config to serialize");
+    let new = serde_json::to_value(new).expect("config to serialize");
+
+    let mut changes = Vec::new();
+    diff_values("", "", Some(&old), Some(&new), &mut changes);
+    changes
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Io(io::Error),
+    Toml(toml::de::Error),
+    // an environment variable override that couldn't be parsed:
+    Env(String, String),
+    Glob(String),
+    // an error in an included config file:
+    Include(PathBuf, Box<ConfigError>),
+    // everything found wrong by validate:
+    Invalid(Vec<String>),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ConfigError::Io(ref e) => write!(f, "{}", e),
+            // toml's messages already name the key, what was expected and
+            // where in the file it went wrong:
+            ConfigError::Toml(ref e) => write!(f, "{}", e),
+            ConfigError::Env(ref name, ref value) => write!(f, "couldn't parse {}={:?}", name, value),
+            ConfigError::Glob(ref message) => write!(f, "bad include: {}", message),
+            ConfigError::Include(ref path, ref e) => write!(f, "in {}: {}", path.display(), e),
+            ConfigError::Invalid(ref problems) => {
+                write!(f, "invalid config:")?;
+
+                for problem in problems {
+                    write!(f, "\n  {}", problem)?;
+                }
+
+                Ok(())
+            }
+        }
+    }
+}
+
+impl error::Error for ConfigError {
+    fn description(&self) -> &str {
+        "config error"
+    }
+}
+
+// bitrates in kbps that LAME can encode MP3 at:
+pub const MP3_BITRATES: &'static [i32] = &[8, 16, 24, 32, 40, 48, 56, 64, 80, 96, 112, 128, 160, 192, 224, 256, 320];
+
+// sample rates that LAME can encode MP3 at:
+const MP3_SAMPLE_RATES: &'static [u32] = &[8000, 11025, 12000, 16000, 22050, 24000, 32000, 44100, 48000];
+
+fn check_url(problems: &mut Vec<String>, key: &str, url: &str) {
+    match Url::parse(url) {
+        Ok(ref url) if url.scheme() == "http" || url.scheme() == "https" => (),
+        Ok(_) => problems.push(format!("{}: {:?} must be an http or https URL", key, url)),
+        Err(e) => problems.push(format!("{}: {:?} is not a valid URL ({})", key, url, e)),
+    }
+}
+
+// Mountpoints from requests are always normalized, so one written any other
+// way in the config would never match anything.
+fn check_mountpoint(problems: &mut Vec<String>, key: &str, mountpoint: &str) {
+    // wildcard patterns are checked by their prefix, "/*" having none:
+    let path = match mountpoint {
+        "/*" => return,
+        _ if mountpoint.ends_with("/*") => &mountpoint[..mountpoint.len() - 2],
+        _ => mountpoint,
+    };
+
+    match mountpoint::normalize(path) {
+        Ok(ref normalized) if normalized == path => (),
+        Ok(normalized) => {
+            problems.push(format!("{}: mountpoint {:?} should be written {:?}", key, mountpoint, normalized));
+        }
+        Err(e) => {
+            problems.push(format!("{}: mountpoint {:?} is invalid: {}", key, mountpoint, e));
+        }
+    }
+}
+
+fn check_webhooks(problems: &mut Vec<String>, key: &str, webhooks: &Webhooks) {
+    for (name, url) in [
+        ("stream_start", &webhooks.stream_start),
+        ("stream_end", &webhooks.stream_end),
+        ("metadata", &webhooks.metadata),
+        ("duration_warning", &webhooks.duration_warning),
+        ("archive_segment", &webhooks.archive_segment),
+        ("archive_error", &webhooks.archive_error),
+        ("config_reloaded", &webhooks.config_reloaded),
+        ("slot_ending", &webhooks.slot_ending),
+        ("source_health", &webhooks.source_health),
+        ("listener_connect", &webhooks.listener_connect),
+        ("listener_disconnect", &webhooks.listener_disconnect),
+        ("error", &webhooks.error),
+        ("heartbeat", &webhooks.heartbeat),
+    ].iter() {
+        if let Some(ref url) = **url {
+            check_url(problems, &format!("{}.{}", key, name), url);
+        }
+    }
+}
+
+// Whether value is an HTTP token, as media types and their parameters are
+// made of.
+fn is_token(value: &str) -> bool {
+    !value.is_empty() && value.bytes().all(|byte| byte.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&byte))
+}
+
+// Whether value is a media type like audio/mpeg, with any parameters, that
+// can be sent as a header as it is.
+fn is_media_type(value: &str) -> bool {
+    let mut parts = value.split(';');
+
+    let essence = parts.next().unwrap_or("");
+    let mut names = essence.splitn(2, '/');
+
+    let essence_ok = match (names.next(), names.next()) {
+        (Some(kind), Some(subtype)) => is_token(kind) && is_token(subtype),
+        _ => false,
+    };
+
+    essence_ok && parts.all(|parameter| {
+        let mut pair = parameter.trim_start_matches(' ').splitn(2, '=');
+
+        match (pair.next(), pair.next()) {
+            (Some(name), Some(value)) => is_token(name) && is_token(value),
+            _ => false,
+        }
+    })
+}
+
+fn check_mount(config: &Config, problems: &mut Vec<String>, key: &str, pattern: &str, mount: &MountConfig) {
+    check_mountpoint(problems, key, pattern);
+
+    if pattern.contains('*') && !(pattern.ends_with("/*") && pattern.matches('*').count() == 1) {
+        problems.push(format!("{}: * is only allowed at the end, as in \"/dj/*\"", key));
+    }
+
+    if mount.codec() != OutputCodec::Mp3 && !cfg!(feature = "aac") {
+        problems.push(format!("{}.codec: AAC needs rustcast built with the aac feature", key));
+    }
+
+    if let Some(bitrate) = mount.bitrate {
+        if !mount.codec().allows_bitrate(bitrate) {
+            problems.push(format!("{}.bitrate: {} kbps isn't an {} bitrate, use {}",
+                key, bitrate, mount.codec().name(), mount.codec().bitrates()));
+        }
+    }
+
+    if let Some(SourceAuth::Ldap { ref url, ref bind_dn }) = mount.source_auth {
+        if !url.starts_with("ldap://") && !url.starts_with("ldaps://") {
+            problems.push(format!("{}.source_auth.url: {:?} must be an ldap:// or ldaps:// URL", key, url));
+        }
+
+        if !bind_dn.contains("{user}") {
+            problems.push(format!("{}.source_auth.bind_dn: must contain {{user}}", key));
+        }
+    }
+
+    if let Some(ref content_type) = mount.content_type {
+        if !is_media_type(content_type) {
+            problems.push(format!("{}.content_type: {:?} must be a media type like \"audio/mpeg\"", key, content_type));
+        }
+    }
+
+    for extension in mount.extensions.iter().flat_map(|extensions| extensions.iter()) {
+        if extension.is_empty() || !extension.chars().all(|c| c.is_ascii_alphanumeric()) {
+            problems.push(format!("{}.extensions: {:?} must be letters and digits, without the dot", key, extension));
+        } else if RESERVED_EXTENSIONS.contains(&extension.to_lowercase().as_str()) {
+            problems.push(format!("{}.extensions: .{} is already used for something else", key, extension));
+        }
+    }
+
+    if mount.listener_buffer == Some(0) {
+        problems.push(format!("{}.listener_buffer: must be at least 1 packet", key));
+    }
+
+    if mount.timeshift == Some(0) {
+        problems.push(format!("{}.timeshift: must be at least 1 second", key));
+    }
+
+    if mount.max_listen_seconds == Some(0) {
+        problems.push(format!("{}.max_listen_seconds: must be at least 1 second", key));
+    }
+
+    if mount.require_jwt && config.jwt.is_none() {
+        problems.push(format!("{}.require_jwt: needs [jwt] to check tokens with", key));
+    }
+
+    if let (Some(max_duration), Some(warning)) = (mount.max_duration, mount.max_duration_warning) {
+        if warning >= max_duration {
+            problems.push(format!("{}.max_duration_warning: must be less than max_duration", key));
+        }
+    }
+
+    if let Some(quality) = mount.quality {
+        if quality > 9 {
+            problems.push(format!("{}.quality: must be from 0 (best) to 9 (fastest)", key));
+        }
+    }
+
+    if let Some(vbr_quality) = mount.vbr_quality {
+        if vbr_quality > 9 {
+            problems.push(format!("{}.vbr_quality: must be from 0 (best) to 9 (smallest)", key));
+        }
+    }
+
+    for target in mount.mirror_to.iter() {
+        check_mountpoint(problems, &format!("{}.mirror_to", key), target);
+
+        if target.contains('*') {
+            problems.push(format!("{}.mirror_to: {:?} must be a mountpoint, not a pattern", key, target));
+        }
+
+        if target == pattern {
+            problems.push(format!("{}.mirror_to: a mountpoint can't mirror to itself", key));
+        }
+    }
+}
+
+// Checks the things deserializing can't, so mistakes are reported when the
+// config is loaded rather than when a stream trips over them.
+fn validate(config: &Config) -> Result<(), ConfigError> {
+    let mut problems = Vec::new();
+
+    let port = config.listen.rsplitn(2, ':').next().unwrap_or("");
+
+    if !config.listen.contains(':') || port.parse::<u16>().is_err() {
+        problems.push(format!("listen: {:?} must be an address and port, like \"0.0.0.0:8000\"", config.listen));
+    }
+
+    if let Some(ref url) = config.public_url {
+        check_url(&mut problems, "public_url", url);
+    }
+
+    check_webhooks(&mut problems, "webhooks", &config.webhooks);
+
+    for (index, notification) in config.notifications.iter().enumerate() {
+        check_url(&mut problems, &format!("notifications[{}].webhook_url", index), &notification.webhook_url);
+    }
+
+    if config.royalty_reports.is_some() && config.stats_db.is_none() {
+        problems.push("royalty_reports: needs stats_db to report from".to_owned());
+    }
+
+    let heartbeat = config.webhooks.heartbeat.is_some()
+        || config.stations.values().any(|station| station.webhooks.heartbeat.is_some());
+
+    if heartbeat && config.heartbeat_interval == 0 {
+        problems.push("heartbeat_interval: must be at least 1 second".to_owned());
+    }
+
+    if config.source_auth_timeout == 0 {
+        problems.push("source_auth_timeout: must be at least 1 second".to_owned());
+    }
+
+    if let Some(ref lockout) = config.source_lockout {
+        if lockout.max_failures == 0 {
+            problems.push("source_lockout.max_failures: must be at least 1".to_owned());
+        }
+
+        if lockout.window == 0 || lockout.ban == 0 {
+            problems.push("source_lockout: window and ban must be at least 1 second".to_owned());
+        }
+    }
+
+    if config.socket.send_buffer == Some(0) {
+        problems.push("socket.send_buffer: must be at least 1 byte".to_owned());
+    }
+
+    if config.socket.keepalive == Some(0) {
+        problems.push("socket.keepalive: must be at least 1 second".to_owned());
+    }
+
+    if let Some(ref schedule) = config.schedule {
+        if let Some(ref url) = schedule.url {
+            check_url(&mut problems, "schedule.url", url);
+        }
+
+        for (index, slot) in schedule.slots.iter().enumerate() {
+            for problem in schedule::check_slot(slot) {
+                problems.push(format!("schedule.slots[{}]: {}", index, problem));
+            }
+        }
+    }
+
+    if let Some(ref jwt) = config.jwt {
+        if jwt.secret.is_none() && jwt.jwks_url.is_none() {
+            problems.push("jwt: needs a secret or jwks_url to check tokens with".to_owned());
+        }
+
+        if let Some(ref url) = jwt.jwks_url {
+            check_url(&mut problems, "jwt.jwks_url", url);
+        }
+    }
+
+    if let Some(ref cookie) = config.session_cookie {
+        let valid_name = !cookie.name.is_empty() && cookie.name.chars()
+            .all(|c| c.is_ascii_alphanumeric() || "!#$%&'*+-.^_`|~".contains(c));
+
+        if !valid_name {
+            problems.push(format!("session_cookie.name: {:?} isn't a valid cookie name", cookie.name));
+        }
+
+        if let Some(ref same_site) = cookie.same_site {
+            if !["Strict", "Lax", "None"].contains(&same_site.as_str()) {
+                problems.push(format!("session_cookie.same_site: {:?} must be \"Strict\", \"Lax\" or \"None\"", same_site));
+            }
+        }
+    }
+
+    if let Some(ref statsd) = config.statsd {
+        let port = statsd.host.rsplitn(2, ':').next().unwrap_or("");
+
+        if !statsd.host.contains(':') || port.parse::<u16>().is_err() {
+            problems.push(format!("statsd.host: {:?} must be a host and port, like \"localhost:8125\"", statsd.host));
+        }
+
+        if statsd.interval == 0 {
+            problems.push("statsd.interval: must be at least 1 second".to_owned());
+        }
+    }
+
+    if let Some(ref mqtt) = config.mqtt {
+        let port = mqtt.broker.rsplitn(2, ':').next().unwrap_or("");
+
+        if !mqtt.broker.contains(':') || port.parse::<u16>().is_err() {
+            problems.push(format!("mqtt.broker: {:?} must be a host and port, like \"localhost:1883\"", mqtt.broker));
+        }
+    }
+
+    if let Some(ref upload) = config.upload {
+        check_url(&mut problems, "upload.endpoint", &upload.endpoint);
+    }
+
+    for url in config.yp_urls.iter() {
+        check_url(&mut problems, "yp_urls", url);
+    }
+
+    for (pattern, mount) in config.mounts.iter() {
+        let key = format!("mounts.{:?}", pattern);
+
+        check_mount(config, &mut problems, &key, pattern, mount);
+
+        if config.in_station(pattern) {
+            problems.push(format!("{}: is in a station's mountpoints, configure it under [stations]", key));
+        }
+    }
+
+    if let Some(ref cluster) = config.cluster {
+        if cluster.peers.is_empty() {
+            problems.push("cluster.peers: must list at least one other node".to_owned());
+        }
+
+        for peer in cluster.peers.iter() {
+            check_url(&mut problems, "cluster.peers", peer);
+        }
+
+        if cluster.secret.is_empty() {
+            problems.push("cluster.secret: must not be empty".to_owned());
+        }
+
+        if cluster.poll_interval == 0 {
+            problems.push("cluster.poll_interval: must be at least 1 second".to_owned());
+        }
+    }
+
+    if let Some(ref health) = config.source_health {
+        if health.window == 0 {
+            problems.push("source_health.window: must be at least 1 second".to_owned());
+        }
+
+        if !(health.min_speed >= 0.0) {
+            problems.push("source_health.min_speed: must not be negative".to_owned());
+        }
+
+        if !(health.max_decode_errors >= 0.0 && health.max_decode_errors <= 1.0) {
+            problems.push("source_health.max_decode_errors: must be from 0 to 1".to_owned());
+        }
+    }
+
+    for (name, station) in config.stations.iter() {
+        let key = format!("stations.{:?}", name);
+
+        if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_') {
+            problems.push(format!("{}: station names must be letters, digits, - and _", key));
+        }
+
+        if station.hosts.is_empty() {
+            problems.push(format!("{}.hosts: must list at least one host", key));
+        }
+
+        for host in station.hosts.iter() {
+            let elsewhere = config.stations.iter()
+                .any(|(other, station)| other != name && station.hosts.iter().any(|other_host| other_host.eq_ignore_ascii_case(host)));
+
+            if elsewhere {
+                problems.push(format!("{}.hosts: {} is another station's host too", key, host));
+            }
+        }
+
+        if let Some(ref url) = station.public_url {
+            check_url(&mut problems, &format!("{}.public_url", key), url);
+        }
+
+        check_webhooks(&mut problems, &format!("{}.webhooks", key), &station.webhooks);
+
+        for (pattern, mount) in station.mounts.iter() {
+            check_mount(config, &mut problems, &format!("{}.mounts.{:?}", key, pattern), pattern, mount);
+        }
+    }
+
+    for (from, to) in config.aliases.iter() {
+        check_mountpoint(&mut problems, "aliases", from);
+        check_mountpoint(&mut problems, "aliases", to);
+
+        if config.in_station(from) || config.in_station(to) {
+            problems.push(format!("aliases: {} to {} is in a station's mountpoints, which can't be aliased", from, to));
+        }
+    }
+
+    for (from, to) in config.redirects.iter() {
+        check_mountpoint(&mut problems, "redirects", from);
+
+        if config.in_station(from) {
+            problems.push(format!("redirects: {} is in a station's mountpoints, which can't be redirected", from));
+        }
+
+        // redirects can go to other mountpoints or anywhere on the web:
+        if !to.starts_with("/") {
+            check_url(&mut problems, "redirects", to);
+        }
+    }
+
+    for (i, user) in config.admin.users.iter().enumerate() {
+        if user.name.is_empty() {
+            problems.push("admin.users: name must not be empty".to_owned());
+        }
+
+        if config.admin.users[..i].iter().any(|other| other.name == user.name) {
+            problems.push(format!("admin.users: {} is listed more than once", user.name));
+        }
+
+        if user.password.is_none() && user.token.is_none() {
+            problems.push(format!("admin.users: {} needs a password or token", user.name));
+        }
+
+        if user.password.as_ref().map(String::is_empty).unwrap_or(false)
+            || user.token.as_ref().map(String::is_empty).unwrap_or(false) {
+            problems.push(format!("admin.users: password and token for {} must not be empty", user.name));
+        }
+    }
+
+    for api_key in config.api_keys.iter() {
+        if api_key.key.is_empty() {
+            problems.push(format!("api_keys: key for {} must not be empty", api_key.name()));
+        }
+    }
+
+    for shm in config.shm_sources.iter() {
+        let key = format!("shm_sources {:?}", shm.mountpoint);
+
+        check_mountpoint(&mut problems, "shm_sources", &shm.mountpoint);
+
+        if !MP3_SAMPLE_RATES.contains(&shm.sample_rate) {
+            problems.push(format!("{}: sample_rate {} isn't supported by MP3, use one of {:?}",
+                key, shm.sample_rate, MP3_SAMPLE_RATES));
+        }
+
+        if shm.channels != 1 && shm.channels != 2 {
+            problems.push(format!("{}: channels must be 1 or 2", key));
+        } else if shm.buffer_size < shm::min_buffer_size(shm.channels) {
+            problems.push(format!("{}: buffer_size must be at least {} bytes, a chunk of {} channel audio",
+                key, shm::min_buffer_size(shm.channels), shm.channels));
+        }
+
+        if !MP3_BITRATES.contains(&(shm.bitrate / 1000)) || shm.bitrate % 1000 != 0 {
+            problems.push(format!("{}: bitrate {} bps isn't an MP3 bitrate", key, shm.bitrate));
+        }
+    }
+
+    for pipe in config.pipes.iter() {
+        check_mountpoint(&mut problems, "pipes", &pipe.mountpoint);
+
+        if pipe.command.is_empty() {
+            problems.push(format!("pipes {:?}: command must not be empty", pipe.mountpoint));
+        }
+    }
+
+    if problems.is_empty() {
+        Ok(())
+    } else {
+        Err(ConfigError::Invalid(problems))
+    }
+}
+
+fn env_var(name: &str) -> Option<String> {
+    env::var(name).ok()
+}
+
+fn env_parse<T: FromStr>(name: &str) -> Result<Option<T>, ConfigError> {
+    match env_var(name) {
+        Some(value) => value.parse().map(Some)
+            .map_err(|_| ConfigError::Env(name.to_owned(), value)),
+        None => Ok(None),
+    }
+}
+
+// Settings from RUSTCAST_* environment variables take precedence over the
+// config file, so containers can be configured without templating it.
+fn apply_env(config: &mut Config) -> Result<(), ConfigError> {
+    if let Some(listen) = env_var("RUSTCAST_LISTEN") {
+        config.listen = listen;
+    }
+
+    if let Some(public_url) = env_var("RUSTCAST_PUBLIC_URL") {
+        config.public_url = Some(public_url);
+    }
+
+    // stations don't fall back to the top level source_password, so it's
+    // given to each of them too:
+    if let Some(password) = env_var("RUSTCAST_SOURCE_PASSWORD") {
+        for station in config.stations.values_mut() {
+            station.source_password = Some(password.clone());
+        }
+
+        config.source_password = Some(password);
+    }
+
+    if let Some(stream_dump) = env_var("RUSTCAST_STREAM_DUMP") {
+        config.stream_dump = Some(stream_dump);
+    }
+
+    if let Some(history_size) = env_parse("RUSTCAST_HISTORY_SIZE")? {
+        config.history_size = history_size;
+    }
+
+    if let Some(url) = env_var("RUSTCAST_WEBHOOK_STREAM_START") {
+        config.webhooks.stream_start = Some(url);
+    }
+
+    if let Some(url) = env_var("RUSTCAST_WEBHOOK_STREAM_END") {
+        config.webhooks.stream_end = Some(url);
+    }
+
+    if let Some(url) = env_var("RUSTCAST_WEBHOOK_METADATA") {
+        config.webhooks.metadata = Some(url);
+    }
+
+    if let Some(upload) = config.upload.as_mut() {
+        if let Some(access_key) = env_var("RUSTCAST_UPLOAD_ACCESS_KEY") {
+            upload.access_key = access_key;
+        }
+
+        if let Some(secret_key) = env_var("RUSTCAST_UPLOAD_SECRET_KEY") {
+            upload.secret_key = secret_key;
+        }
+    }
+
+    Ok(())
+}
+
+fn read_file(path: &Path) -> Result<String, ConfigError> {
+    let mut file = File::open(path).map_err(ConfigError::Io)?;
+    let mut buff = String::new();
+    file.read_to_string(&mut buff).map_err(ConfigError::Io)?;
+    Ok(buff)
+}
+
+fn read_table(path: &Path) -> Result<Table, ConfigError> {
+    toml::from_str(&read_file(path)?).map_err(ConfigError::Toml)
+}
+
+// Merges an included file into the config: tables are merged key by key,
+// arrays are appended to, and anything else is replaced.
+fn merge(into: &mut Table, from: Table) {
+    for (key, value) in from {
+        let value = match (into.get_mut(&key), value) {
+            (Some(&mut toml::Value::Table(ref mut into)), toml::Value::Table(from)) => {
+                merge(into, from);
+                None
+            }
+            (Some(&mut toml::Value::Array(ref mut into)), toml::Value::Array(from)) => {
+                into.extend(from);
+                None
+            }
+            (_, value) => Some(value),
+        };
+
+        if let Some(value) = value {
+            into.insert(key, value);
+        }
+    }
+}
+
+fn include_patterns(table: &mut Table) -> Result<Vec<String>, ConfigError> {
+    let patterns = match table.remove("include") {
+        None => return Ok(Vec::new()),
+        Some(toml::Value::String(pattern)) => vec![toml::Value::String(pattern)],
+        Some(toml::Value::Array(patterns)) => patterns,
+        Some(_) => return Err(ConfigError::Glob("include must be a string or array of strings".to_owned())),
+    };
+
+    patterns.into_iter()
+        .map(|pattern| match pattern {
+            toml::Value::String(pattern) => Ok(pattern),
+            _ => Err(ConfigError::Glob("include must be a string or array of strings".to_owned())),
+        })
+        .collect()
+}
+
+// Paths matching an include pattern, relative to the including file.
+fn include_paths(base: &Path, pattern: &str) -> Result<Vec<PathBuf>, ConfigError> {
+    let pattern = base.join(pattern);
+    let pattern = pattern.to_string_lossy();
+
+    let mut paths = glob::glob(&pattern)
+        .map_err(|e| ConfigError::Glob(format!("{}: {}", pattern, e)))?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| ConfigError::Glob(format!("{}: {}", pattern, e)))?;
+
+    // merge in a predictable order:
+    paths.sort();
+
+    Ok(paths)
+}
+
+pub fn open(path: &Path) -> Result<Config, ConfigError> {
+    let buff = read_file(path)?;
+    let mut table: Table = toml::from_str(&buff).map_err(ConfigError::Toml)?;
+
+    let base = match path.parent() {
+        Some(dir) if dir != Path::new("") => dir,
+        _ => Path::new("."),
+    };
+
+    let patterns = include_patterns(&mut table)?;
+
+    let mut config: Config = if patterns.is_empty() {
+        // deserializing straight from the file keeps line numbers in errors:
+        toml::from_str(&buff).map_err(ConfigError::Toml)?
+    } else {
+        for pattern in patterns {
+            for included in include_paths(base, &pattern)? {
+                let included_table = read_table(&included)
+                    .map_err(|e| ConfigError::Include(included.clone(), Box::new(e)))?;
+
+                merge(&mut table, included_table);
+            }
+        }
+
+        toml::Value::Table(table).try_into().map_err(ConfigError::Toml)?
+    };
+
+    apply_env(&mut config)?;
+    validate(&config)?;
+    Ok(config)
 }
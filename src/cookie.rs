@@ -0,0 +1,371 @@
+// Cookies sent by listeners, and the session cookie rustcast gives them, so
+// listener sessions can be matched up with the station's website.
+
+use std::fmt;
+use std::time;
+
+use chrono::{DateTime, Duration, Utc};
+use tiny_http::Request;
+use uuid::Uuid;
+
+use config::SessionCookie;
+
+pub struct Cookies {
+    pairs: Vec<(String, String)>,
+}
+
+impl Cookies {
+    // Parses a Cookie header leniently, the way browsers and other clients
+    // actually send them: pairs are separated by ; with or without
+    // whitespace, values may be quoted (and then hold ; or other
+    // separators), and anything after the first = is the value, so the
+    // commas and = of things like Expires dates copied into a value are
+    // kept as they are.
+    pub fn parse(header: &str) -> Cookies {
+        let mut pairs = Vec::new();
+        let mut chars = header.chars().peekable();
+
+        loop {
+            let mut name = String::new();
+
+            while let Some(&c) = chars.peek() {
+                if c == '=' || c == ';' {
+                    break;
+                }
+
+                name.push(c);
+                chars.next();
+            }
+
+            let has_value = chars.peek() == Some(&'=');
+
+            if has_value {
+                chars.next();
+            }
+
+            while chars.peek().map(|c| c.is_whitespace()).unwrap_or(false) {
+                chars.next();
+            }
+
+            let mut value = String::new();
+
+            if has_value && chars.peek() == Some(&'"') {
+                chars.next();
+
+                while let Some(c) = chars.next() {
+                    match c {
+                        '"' => break,
+                        '\\' => value.extend(chars.next()),
+                        c => value.push(c),
+                    }
+                }
+
+                // anything between the closing quote and the next ; is junk:
+                while chars.peek().map(|&c| c != ';').unwrap_or(false) {
+                    chars.next();
+                }
+            } else {
+                while let Some(&c) = chars.peek() {
+                    if c == ';' {
+                        break;
+                    }
+
+                    value.push(c);
+                    chars.next();
+                }
+
+                let trimmed = value.trim_end().len();
+                value.truncate(trimmed);
+            }
+
+            let name = name.trim();
+
+            // a bare word with no = isn't a cookie:
+            if has_value && !name.is_empty() {
+                pairs.push((name.to_owned(), value));
+            }
+
+            if chars.next().is_none() {
+                break;
+            }
+        }
+
+        Cookies { pairs }
+    }
+
+    // The cookies from every Cookie header on req.
+    pub fn from_request(req: &Request) -> Cookies {
+        let header = req.headers().iter()
+            .filter(|header| header.field.equiv("Cookie"))
+            .map(|header| header.value.as_str())
+            .collect::<Vec<_>>()
+            .join("; ");
+
+        Cookies::parse(&header)
+    }
+
+    // The value of the first cookie called name, ignoring case, as cookies
+    // set by a website's framework don't always match the configured name
+    // exactly.
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.pairs.iter()
+            .find(|&&(ref pair_name, _)| pair_name.eq_ignore_ascii_case(name))
+            .map(|&(_, ref value)| value.as_str())
+    }
+}
+
+// A Set-Cookie header value, built up from its attributes:
+//
+//     SetCookie::new("session", "abc").with_path("/").with_http_only()
+//
+pub struct SetCookie {
+    name: String,
+    value: String,
+    domain: Option<String>,
+    path: Option<String>,
+    max_age: Option<u64>,
+    expires: Option<DateTime<Utc>>,
+    same_site: Option<String>,
+    secure: bool,
+    http_only: bool,
+}
+
+impl SetCookie {
+    pub fn new(name: &str, value: &str) -> SetCookie {
+        SetCookie {
+            name: name.to_owned(),
+            value: value.to_owned(),
+            domain: None,
+            path: None,
+            max_age: None,
+            expires: None,
+            same_site: None,
+            secure: false,
+            http_only: false,
+        }
+    }
+
+    pub fn with_domain(mut self, domain: &str) -> SetCookie {
+        self.domain = Some(domain.to_owned());
+        self
+    }
+
+    pub fn with_path(mut self, path: &str) -> SetCookie {
+        self.path = Some(path.to_owned());
+        self
+    }
+
+    pub fn with_max_age(mut self, seconds: u64) -> SetCookie {
+        self.max_age = Some(seconds);
+        self
+    }
+
+    pub fn with_expires(mut self, expires: DateTime<Utc>) -> SetCookie {
+        self.expires = Some(expires);
+        self
+    }
+
+    pub fn with_same_site(mut self, same_site: &str) -> SetCookie {
+        self.same_site = Some(same_site.to_owned());
+        self
+    }
+
+    pub fn with_secure(mut self) -> SetCookie {
+        self.secure = true;
+        self
+    }
+
+    pub fn with_http_only(mut self) -> SetCookie {
+        self.http_only = true;
+        self
+    }
+}
+
+// characters a cookie value may hold without being quoted, per RFC 6265:
+fn is_cookie_octet(c: char) -> bool {
+    c.is_ascii() && !c.is_ascii_control() && !" \",;\\".contains(c)
+}
+
+impl fmt::Display for SetCookie {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.value.chars().all(is_cookie_octet) {
+            write!(f, "{}={}", self.name, self.value)?;
+        } else {
+            write!(f, "{}=\"{}\"", self.name, self.value.replace('\\', "\\\\").replace('"', "\\\""))?;
+        }
+
+        if let Some(ref domain) = self.domain {
+            write!(f, "; Domain={}", domain)?;
+        }
+
+        if let Some(ref path) = self.path {
+            write!(f, "; Path={}", path)?;
+        }
+
+        if let Some(max_age) = self.max_age {
+            write!(f, "; Max-Age={}", max_age)?;
+        }
+
+        if let Some(expires) = self.expires {
+            write!(f, "; Expires={}", expires.format("%a, %d %b %Y %H:%M:%S GMT"))?;
+        }
+
+        if let Some(ref same_site) = self.same_site {
+            write!(f, "; SameSite={}", same_site)?;
+        }
+
+        if self.secure {
+            f.write_str("; Secure")?;
+        }
+
+        if self.http_only {
+            f.write_str("; HttpOnly")?;
+        }
+
+        Ok(())
+    }
+}
+
+// A new session id, for listeners who don't have one yet.
+pub fn new_session() -> String {
+    Uuid::new_v4().simple().to_string()
+}
+
+// The Set-Cookie header value giving a listener their session.
+pub fn set_session(config: &SessionCookie, session: &str) -> String {
+    let mut cookie = SetCookie::new(&config.name, session)
+        .with_path(&config.path);
+
+    if let Some(ref domain) = config.domain {
+        cookie = cookie.with_domain(domain);
+    }
+
+    // Expires as well as Max-Age, for old clients that only know Expires,
+    // unless it's too far off to be a date:
+    if let Some(max_age) = config.max_age {
+        cookie = cookie.with_max_age(max_age);
+
+        let expires = Duration::from_std(time::Duration::from_secs(max_age)).ok()
+            .and_then(|max_age| Utc::now().checked_add_signed(max_age));
+
+        if let Some(expires) = expires {
+            cookie = cookie.with_expires(expires);
+        }
+    }
+
+    if let Some(ref same_site) = config.same_site {
+        cookie = cookie.with_same_site(same_site);
+    }
+
+    if config.secure {
+        cookie = cookie.with_secure();
+    }
+
+    if config.http_only {
+        cookie = cookie.with_http_only();
+    }
+
+    cookie.to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{TimeZone, Utc};
+
+    use super::{Cookies, SetCookie};
+
+    #[test]
+    fn parses_pairs() {
+        let cookies = Cookies::parse("a=1; b=2");
+
+        assert_eq!(cookies.get("a"), Some("1"));
+        assert_eq!(cookies.get("b"), Some("2"));
+        assert_eq!(cookies.get("c"), None);
+    }
+
+    #[test]
+    fn parses_whitespace_variants() {
+        for header in &["a=1;b=2", "a=1; b=2", "  a = 1 ;\tb=2  ", "a=1 ;  b=2;"] {
+            let cookies = Cookies::parse(header);
+
+            assert_eq!(cookies.get("a"), Some("1"), "in {:?}", header);
+            assert_eq!(cookies.get("b"), Some("2"), "in {:?}", header);
+        }
+    }
+
+    #[test]
+    fn parses_quoted_values() {
+        let cookies = Cookies::parse(r#"a="x; y=z, w"; b=2"#);
+
+        assert_eq!(cookies.get("a"), Some("x; y=z, w"));
+        assert_eq!(cookies.get("b"), Some("2"));
+    }
+
+    #[test]
+    fn parses_escaped_quotes() {
+        let cookies = Cookies::parse(r#"a="say \"hi\" \\ bye"; b=2"#);
+
+        assert_eq!(cookies.get("a"), Some(r#"say "hi" \ bye"#));
+        assert_eq!(cookies.get("b"), Some("2"));
+    }
+
+    #[test]
+    fn drops_junk_after_closing_quote() {
+        let cookies = Cookies::parse(r#"a="x"junk; b=2"#);
+
+        assert_eq!(cookies.get("a"), Some("x"));
+        assert_eq!(cookies.get("b"), Some("2"));
+    }
+
+    #[test]
+    fn keeps_separators_in_unquoted_values() {
+        let cookies = Cookies::parse("expires=Wed, 21 Oct 2015 07:28:00 GMT; token=abc==");
+
+        assert_eq!(cookies.get("expires"), Some("Wed, 21 Oct 2015 07:28:00 GMT"));
+        assert_eq!(cookies.get("token"), Some("abc=="));
+    }
+
+    #[test]
+    fn skips_bare_words() {
+        let cookies = Cookies::parse("flag; a=; b=2");
+
+        assert_eq!(cookies.get("flag"), None);
+        assert_eq!(cookies.get("a"), Some(""));
+        assert_eq!(cookies.get("b"), Some("2"));
+        assert_eq!(Cookies::parse("").get(""), None);
+    }
+
+    #[test]
+    fn looks_up_names_ignoring_case() {
+        let cookies = Cookies::parse("Session=abc; SESSION=def");
+
+        assert_eq!(cookies.get("session"), Some("abc"));
+        assert_eq!(cookies.get("SESSION"), Some("abc"));
+    }
+
+    #[test]
+    fn builds_set_cookie() {
+        let cookie = SetCookie::new("session", "abc")
+            .with_domain("example.com")
+            .with_path("/")
+            .with_max_age(3600)
+            .with_expires(Utc.ymd(2015, 10, 21).and_hms(7, 28, 0))
+            .with_same_site("Lax")
+            .with_secure()
+            .with_http_only();
+
+        assert_eq!(cookie.to_string(), "session=abc; Domain=example.com; Path=/; Max-Age=3600; \
+            Expires=Wed, 21 Oct 2015 07:28:00 GMT; SameSite=Lax; Secure; HttpOnly");
+
+        assert_eq!(SetCookie::new("a", "b").to_string(), "a=b");
+    }
+
+    #[test]
+    fn quotes_set_cookie_values_that_need_it() {
+        let value = r#"x; "y" \ z"#;
+        let cookie = SetCookie::new("a", value).to_string();
+
+        assert_eq!(cookie, r#"a="x; \"y\" \\ z""#);
+        assert_eq!(Cookies::parse(&cookie).get("a"), Some(value));
+    }
+}
@@ -0,0 +1,56 @@
+use tiny_http::{Request, Header};
+
+use config::Config;
+
+fn header(field: &str, value: &str) -> Header {
+    Header::from_bytes(field.as_bytes(), value.as_bytes())
+        .expect("valid header")
+}
+
+// CORS response headers for req, according to the configured origins. Empty
+// if the request didn't come from an allowed origin.
+pub fn headers(config: &Config, req: &Request) -> Vec<Header> {
+    if config.cors_origins.iter().any(|origin| origin == "*") {
+        return vec![header("Access-Control-Allow-Origin", "*")];
+    }
+
+    let origin = req.headers().iter()
+        .find(|header| header.field.equiv("Origin"))
+        .map(|header| header.value.as_str());
+
+    match origin {
+        Some(origin) if config.cors_origins.iter().any(|allowed| allowed == origin) => {
+            vec![
+                header("Access-Control-Allow-Origin", origin),
+                header("Vary", "Origin"),
+            ]
+        }
+        _ => Vec::new(),
+    }
+}
+
+// Response headers for a CORS preflight (OPTIONS) request from an allowed
+// origin, letting browsers go on to make the request with any of methods.
+// Empty if the origin isn't allowed.
+pub fn preflight_headers(config: &Config, req: &Request, methods: &str) -> Vec<Header> {
+    let mut headers = headers(config, req);
+
+    if headers.is_empty() {
+        return headers;
+    }
+
+    headers.push(header("Access-Control-Allow-Methods", methods));
+
+    // whatever headers the browser wants to send are fine by us:
+    let request_headers = req.headers().iter()
+        .find(|header| header.field.equiv("Access-Control-Request-Headers"))
+        .map(|header| header.value.as_str().to_owned());
+
+    if let Some(request_headers) = request_headers {
+        headers.push(header("Access-Control-Allow-Headers", &request_headers));
+    }
+
+    headers.push(header("Access-Control-Max-Age", "86400"));
+
+    headers
+}
@@ -0,0 +1,161 @@
+// Support for running as a classic Unix daemon: detaching from the
+// terminal, writing a PID file, and giving up root once the listen socket
+// is bound.
+
+use std::ffi::CString;
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::os::unix::io::AsRawFd;
+use std::process;
+
+use libc;
+
+fn check(ret: libc::c_int) -> io::Result<()> {
+    if ret == -1 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(())
+    }
+}
+
+// forks, exiting the parent:
+fn fork_and_exit_parent() -> io::Result<()> {
+    match unsafe { libc::fork() } {
+        -1 => Err(io::Error::last_os_error()),
+        0 => Ok(()),
+        _ => unsafe { libc::_exit(0) },
+    }
+}
+
+// Detaches from the controlling terminal and carries on in the background.
+// This has to happen before any threads are started, since only the calling
+// thread survives a fork. Stdout and stderr are left alone so logs can be
+// redirected wherever the init script likes.
+pub fn daemonize() -> io::Result<()> {
+    fork_and_exit_parent()?;
+
+    check(unsafe { libc::setsid() })?;
+
+    // fork again so that we're not a session leader, and can never acquire
+    // a controlling terminal:
+    fork_and_exit_parent()?;
+
+    let dev_null = CString::new("/dev/null").expect("no nul bytes");
+
+    unsafe {
+        let fd = libc::open(dev_null.as_ptr(), libc::O_RDWR);
+        check(fd)?;
+        check(libc::dup2(fd, libc::STDIN_FILENO))?;
+
+        if fd > libc::STDERR_FILENO {
+            libc::close(fd);
+        }
+    }
+
+    Ok(())
+}
+
+// Writes the PID file, owned by the user and group drop_privileges will
+// switch to, so it can still be cleaned up after giving up root.
+pub fn write_pid_file(path: &str, user: Option<&str>, group: Option<&str>) -> io::Result<()> {
+    let mut file = File::create(path)?;
+    writeln!(file, "{}", process::id())?;
+
+    let user = match user {
+        Some(name) => Some(lookup_user(name)?),
+        None => None,
+    };
+
+    let gid = match group {
+        Some(name) => Some(lookup_group(name)?),
+        None => user.map(|(_, gid)| gid),
+    };
+
+    if user.is_some() || gid.is_some() {
+        // -1 leaves the owner or group as it is:
+        let uid = user.map(|(uid, _)| uid).unwrap_or(!0);
+        let gid = gid.unwrap_or(!0);
+
+        check(unsafe { libc::fchown(file.as_raw_fd(), uid, gid) })?;
+    }
+
+    Ok(())
+}
+
+// Removes the PID file, or empties it if its directory isn't writable by
+// the user rustcast switched to, so it doesn't name a process that's gone.
+pub fn remove_pid_file(path: &str) -> io::Result<()> {
+    match fs::remove_file(path) {
+        Err(ref e) if e.kind() == io::ErrorKind::PermissionDenied => File::create(path).map(|_| ()),
+        result => result,
+    }
+}
+
+fn lookup_user(name: &str) -> io::Result<(libc::uid_t, libc::gid_t)> {
+    let c_name = CString::new(name)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+
+    let passwd = unsafe { libc::getpwnam(c_name.as_ptr()) };
+
+    if passwd.is_null() {
+        return Err(io::Error::new(io::ErrorKind::NotFound, format!("no such user {}", name)));
+    }
+
+    unsafe { Ok(((*passwd).pw_uid, (*passwd).pw_gid)) }
+}
+
+fn lookup_group(name: &str) -> io::Result<libc::gid_t> {
+    let c_name = CString::new(name)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+
+    let group = unsafe { libc::getgrnam(c_name.as_ptr()) };
+
+    if group.is_null() {
+        return Err(io::Error::new(io::ErrorKind::NotFound, format!("no such group {}", name)));
+    }
+
+    unsafe { Ok((*group).gr_gid) }
+}
+
+// Switches to user and group, so rustcast can bind a privileged port as root
+// and then run without root. The group defaults to the user's primary group,
+// and the supplementary groups are the user's, or just the group without a
+// user, rather than root's.
+pub fn drop_privileges(user: Option<&str>, group: Option<&str>) -> io::Result<()> {
+    let user = match user {
+        Some(name) => Some((name, lookup_user(name)?)),
+        None => None,
+    };
+
+    let gid = match group {
+        Some(name) => Some(lookup_group(name)?),
+        None => user.map(|(_, (_, gid))| gid),
+    };
+
+    // the groups have to go first, we won't be allowed to change them once
+    // we've given up root:
+    if let Some(gid) = gid {
+        match user {
+            Some((name, _)) => {
+                let c_name = CString::new(name)
+                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+
+                check(unsafe { libc::initgroups(c_name.as_ptr(), gid as _) })?;
+            }
+            None => check(unsafe { libc::setgroups(1, &gid) })?,
+        }
+
+        check(unsafe { libc::setgid(gid) })?;
+    }
+
+    if let Some((_, (uid, _))) = user {
+        check(unsafe { libc::setuid(uid) })?;
+
+        // make sure there's no way back:
+        if uid != 0 && unsafe { libc::setuid(0) } != -1 {
+            return Err(io::Error::new(io::ErrorKind::Other, "still able to become root after switching user"));
+        }
+    }
+
+    Ok(())
+}
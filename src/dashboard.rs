@@ -0,0 +1,201 @@
+// The admin page at /admin/, for running rustcast from a browser: live
+// mountpoints and who's listening, recent log lines, and buttons to kick
+// sources and listeners and set metadata. It's static; everything it shows
+// and does goes through the admin API with credentials typed into the page.
+
+// how often the page refreshes what it shows, in milliseconds:
+const POLL_INTERVAL: u32 = 5000;
+
+pub const CONTENT_TYPE: &'static str = "text/html; charset=utf-8";
+
+pub fn render() -> String {
+    format!(r##"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<meta name="viewport" content="width=device-width, initial-scale=1">
+<title>rustcast admin</title>
+<style>
+body {{ font-family: sans-serif; max-width: 60em; margin: 2em auto; padding: 0 1em; }}
+table {{ border-collapse: collapse; width: 100%; margin-bottom: 1em; }}
+th, td {{ text-align: left; padding: 0.25em 0.5em; border-bottom: 1px solid #ddd; }}
+.mount {{ border: 1px solid #ccc; border-radius: 4px; padding: 0 1em 1em; margin-bottom: 1em; }}
+.muted {{ color: #666; }}
+#error {{ color: #b00; }}
+#log {{ background: #f6f6f6; padding: 0.5em; max-height: 20em; overflow: auto; font-size: 0.85em; }}
+.ERROR {{ color: #b00; }}
+</style>
+</head>
+<body>
+<h1>rustcast</h1>
+<form id="login">
+<p><label>User <input id="user" autocomplete="username"></label>
+<span class="muted">(leave blank for an API key or token)</span></p>
+<p><label>Password, API key or token <input type="password" id="key" autocomplete="current-password"></label></p>
+<button>Sign in</button>
+</form>
+<div id="admin" hidden>
+<p><button id="logout">Sign out</button> <span id="error"></span></p>
+<h2>Live mountpoints</h2>
+<div id="mounts"></div>
+<h2>Log</h2>
+<pre id="log"></pre>
+</div>
+<script>
+(function() {{
+  // the Authorization header to send:
+  var auth = sessionStorage.getItem("rustcast-auth");
+
+  function el(tag, text, className) {{
+    var node = document.createElement(tag);
+    if (text !== undefined) {{ node.textContent = text; }}
+    if (className) {{ node.className = className; }}
+    return node;
+  }}
+
+  function button(text, action) {{
+    var node = el("button", text);
+    node.onclick = action;
+    return node;
+  }}
+
+  function api(method, path, params) {{
+    var query = Object.keys(params || {{}}).map(function(name) {{
+      return encodeURIComponent(name) + "=" + encodeURIComponent(params[name]);
+    }}).join("&");
+
+    return fetch("/admin/" + path + (query ? "?" + query : ""), {{
+      method: method,
+      cache: "no-store",
+      headers: {{ "Authorization": auth }}
+    }}).then(function(response) {{
+      return response.json().then(function(data) {{
+        if (response.status === 401) {{ signOut(); }}
+        if (!response.ok) {{ throw new Error(data.error || response.statusText); }}
+        return data;
+      }});
+    }});
+  }}
+
+  function showError(e) {{
+    document.getElementById("error").textContent = e ? e.message : "";
+  }}
+
+  function act(method, path, params, confirmation) {{
+    if (confirmation && !confirm(confirmation)) {{ return; }}
+    api(method, path, params).then(refresh).catch(showError);
+  }}
+
+  function renderListeners(mount, listeners) {{
+    var table = el("table");
+    var head = table.insertRow();
+    ["Listener", "IP", "Player", "User agent", "Connected for", ""].forEach(function(name) {{
+      head.appendChild(el("th", name));
+    }});
+
+    listeners.forEach(function(listener) {{
+      var row = table.insertRow();
+      row.appendChild(el("td", "#" + listener.id));
+      row.appendChild(el("td", listener.ip));
+      row.appendChild(el("td", listener.player));
+      row.appendChild(el("td", listener.user_agent || "", "muted"));
+      row.appendChild(el("td", Math.floor(listener.seconds / 60) + "m " + listener.seconds % 60 + "s"));
+      row.appendChild(el("td")).appendChild(button("Kick", function() {{
+        act("POST", "killclient", {{ mount: mount, id: listener.id }});
+      }}));
+    }});
+
+    return table;
+  }}
+
+  function renderMount(mount) {{
+    var box = el("div", undefined, "mount");
+    var nowPlaying = [mount.artist, mount.title].filter(Boolean).join(" - ");
+
+    box.appendChild(el("h3", mount.mountpoint + (mount.hidden ? " (hidden)" : "")));
+    box.appendChild(el("p", (nowPlaying || "No metadata") + " · " + mount.listeners + " listening"));
+
+    var song = el("input");
+    song.placeholder = "Artist - Title";
+    box.appendChild(song);
+    box.appendChild(button("Update metadata", function() {{
+      act("POST", "metadata", {{ mount: mount.mountpoint, song: song.value }});
+    }}));
+    box.appendChild(button("Kick source", function() {{
+      act("POST", "killsource", {{ mount: mount.mountpoint }}, "Disconnect the source on " + mount.mountpoint + "?");
+    }}));
+    box.appendChild(button("Kick all listeners", function() {{
+      act("POST", "kicklisteners", {{ mount: mount.mountpoint }}, "Disconnect everyone listening to " + mount.mountpoint + "?");
+    }}));
+
+    var listeners = el("div");
+    box.appendChild(listeners);
+
+    api("GET", "listclients", {{ mount: mount.mountpoint }}).then(function(list) {{
+      listeners.appendChild(renderListeners(mount.mountpoint, list));
+    }}).catch(showError);
+
+    return box;
+  }}
+
+  function refresh() {{
+    api("GET", "listmounts").then(function(mounts) {{
+      var container = document.getElementById("mounts");
+
+      // leave the page alone while someone's typing metadata:
+      if (container.contains(document.activeElement)) {{ return; }}
+
+      container.textContent = "";
+      if (!mounts.length) {{ container.appendChild(el("p", "Nothing's live.", "muted")); }}
+      mounts.forEach(function(mount) {{ container.appendChild(renderMount(mount)); }});
+      showError(null);
+    }}).catch(showError);
+
+    // only users and keys with every mountpoint can see the log:
+    api("GET", "log").then(function(lines) {{
+      var log = document.getElementById("log");
+      log.textContent = "";
+      lines.forEach(function(line) {{
+        log.appendChild(el("div", line.time + " " + line.level + " " + line.message, line.level));
+      }});
+      log.scrollTop = log.scrollHeight;
+    }}).catch(function() {{
+      document.getElementById("log").textContent = "Not available to you.";
+    }});
+  }}
+
+  function signIn() {{
+    document.getElementById("login").hidden = true;
+    document.getElementById("admin").hidden = false;
+    refresh();
+  }}
+
+  function signOut() {{
+    auth = null;
+    sessionStorage.removeItem("rustcast-auth");
+    document.getElementById("login").hidden = false;
+    document.getElementById("admin").hidden = true;
+  }}
+
+  document.getElementById("login").onsubmit = function(e) {{
+    e.preventDefault();
+    var user = document.getElementById("user").value;
+    var key = document.getElementById("key").value;
+
+    auth = user ? "Basic " + btoa(user + ":" + key) : "Bearer " + key;
+    sessionStorage.setItem("rustcast-auth", auth);
+    signIn();
+  }};
+
+  document.getElementById("logout").onclick = signOut;
+
+  if (auth) {{ signIn(); }}
+
+  setInterval(function() {{ if (auth) {{ refresh(); }} }}, {interval});
+}})();
+</script>
+</body>
+</html>
+"##,
+        interval = POLL_INTERVAL)
+}
@@ -0,0 +1,129 @@
+// Counts a listener who drops and reconnects soon after, as flaky mobile
+// connections do, as a single listener. When a listener disconnects from a
+// mountpoint with a dedup window, its listener_left event is held back for
+// the window. If the same address and User-Agent comes back in that time,
+// with the same session cookie if it has one, the event is dropped and the
+// new connection carries on the old session, so stats and hooks only see one
+// join and one leave. Events still held back at shutdown are let go then.
+
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use events::Event;
+use server::Rustcast;
+
+// how often held back events are checked for having run out their window:
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+type Key = (String, IpAddr, Option<String>);
+
+struct Held {
+    until: Instant,
+    // the listener's session, which a reconnecting listener with a session
+    // cookie must give back:
+    session: Option<String>,
+    event: Event,
+}
+
+pub struct Dedup {
+    held: Mutex<HashMap<Key, Vec<Held>>>,
+}
+
+impl Dedup {
+    pub fn new() -> Dedup {
+        Dedup {
+            held: Mutex::new(HashMap::new()),
+        }
+    }
+
+    // Holds back a listener's listener_left event for window, in case they
+    // reconnect.
+    pub fn hold(&self, mountpoint: &str, ip: IpAddr, user_agent: Option<&str>, session: Option<&str>, window: Duration, event: Event) {
+        let key = (mountpoint.to_owned(), ip, user_agent.map(str::to_owned));
+
+        self.held.lock().expect("lock on held listener events")
+            .entry(key)
+            .or_insert_with(Vec::new)
+            .push(Held { until: Instant::now() + window, session: session.map(str::to_owned), event });
+    }
+
+    // Whether a connecting listener is one that disconnected within the
+    // window, in which case their held back listener_left is dropped. The
+    // session is the one the listener brought with them, if any, so
+    // listeners sharing an address and player are told apart by cookie.
+    pub fn reconnect(&self, mountpoint: &str, ip: IpAddr, user_agent: Option<&str>, session: Option<&str>) -> bool {
+        let key = (mountpoint.to_owned(), ip, user_agent.map(str::to_owned));
+        let mut held = self.held.lock().expect("lock on held listener events");
+
+        let reconnected = match held.get_mut(&key) {
+            Some(events) => {
+                let now = Instant::now();
+
+                // the most recent disconnect still in its window:
+                let same_session = |held: &Held| match (held.session.as_ref(), session) {
+                    (Some(held), Some(session)) => held == session,
+                    _ => true,
+                };
+
+                match events.iter().rposition(|held| held.until > now && same_session(held)) {
+                    Some(index) => {
+                        events.remove(index);
+                        true
+                    }
+                    None => false,
+                }
+            }
+            None => false,
+        };
+
+        if held.get(&key).map(Vec::is_empty).unwrap_or(false) {
+            held.remove(&key);
+        }
+
+        reconnected
+    }
+
+    // Takes the held back events whose window has run out.
+    fn expired(&self) -> Vec<Event> {
+        let now = Instant::now();
+        let mut held = self.held.lock().expect("lock on held listener events");
+        let mut expired = Vec::new();
+
+        for events in held.values_mut() {
+            let mut i = 0;
+
+            while i < events.len() {
+                if events[i].until <= now {
+                    expired.push(events.remove(i).event);
+                } else {
+                    i += 1;
+                }
+            }
+        }
+
+        held.retain(|_, events| !events.is_empty());
+
+        expired
+    }
+
+    // Takes every held back event, window or not, for shutdown.
+    pub fn flush(&self) -> Vec<Event> {
+        self.held.lock().expect("lock on held listener events")
+            .drain()
+            .flat_map(|(_, events)| events.into_iter().map(|held| held.event))
+            .collect()
+    }
+}
+
+pub fn run(rustcast: Arc<Rustcast>) {
+    while !rustcast.is_shutdown() {
+        for event in rustcast.dedup.expired() {
+            rustcast.publish_event(event);
+        }
+
+        thread::sleep(POLL_INTERVAL);
+    }
+}
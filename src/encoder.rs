@@ -0,0 +1,262 @@
+// MP3 encoding for sources that need transcoding. The lame crate only
+// exposes constant bitrate encoding at a fixed quality, so this talks to
+// libmp3lame directly to get at the rest of its settings.
+
+#[cfg(feature = "aac")]
+use aac::AacEncoder;
+use config::{EncodeMode, MountConfig, OutputCodec};
+
+mod ffi {
+    use libc::{c_int, c_short, c_uchar};
+
+    pub enum LameGlobalFlags {}
+
+    pub type LameT = *mut LameGlobalFlags;
+
+    // vbr_mode:
+    pub const VBR_OFF: c_int = 0;
+    pub const VBR_ABR: c_int = 3;
+    pub const VBR_MTRH: c_int = 4;
+
+    // MPEG_mode:
+    pub const STEREO: c_int = 0;
+    pub const JOINT_STEREO: c_int = 1;
+
+    #[link(name = "mp3lame")]
+    #[allow(non_snake_case)]
+    extern "C" {
+        pub fn lame_init() -> LameT;
+        pub fn lame_close(gfp: LameT) -> c_int;
+        pub fn lame_set_in_samplerate(gfp: LameT, rate: c_int) -> c_int;
+        pub fn lame_set_num_channels(gfp: LameT, channels: c_int) -> c_int;
+        pub fn lame_set_quality(gfp: LameT, quality: c_int) -> c_int;
+        pub fn lame_set_brate(gfp: LameT, kbps: c_int) -> c_int;
+        pub fn lame_set_VBR(gfp: LameT, mode: c_int) -> c_int;
+        pub fn lame_set_VBR_mean_bitrate_kbps(gfp: LameT, kbps: c_int) -> c_int;
+        pub fn lame_set_VBR_q(gfp: LameT, quality: c_int) -> c_int;
+        pub fn lame_set_bWriteVbrTag(gfp: LameT, write: c_int) -> c_int;
+        pub fn lame_set_mode(gfp: LameT, mode: c_int) -> c_int;
+        pub fn lame_set_lowpassfreq(gfp: LameT, freq: c_int) -> c_int;
+        pub fn lame_init_params(gfp: LameT) -> c_int;
+        pub fn lame_encode_buffer(gfp: LameT,
+            buffer_l: *const c_short, buffer_r: *const c_short, nsamples: c_int,
+            mp3buf: *mut c_uchar, mp3buf_size: c_int) -> c_int;
+        pub fn lame_encode_flush(gfp: LameT, mp3buf: *mut c_uchar, size: c_int) -> c_int;
+    }
+}
+
+// quality used when the mount doesn't say, LAME's slowest and best:
+const DEFAULT_QUALITY: u8 = 0;
+
+// LAME's default VBR quality:
+const DEFAULT_VBR_QUALITY: u8 = 4;
+
+#[derive(Debug)]
+pub enum EncoderError {
+    // LAME couldn't allocate its state:
+    Init,
+    // LAME rejected a setting, named:
+    Setting(&'static str),
+    // a negative return from lame_encode_buffer:
+    Encode(i32),
+}
+
+// Everything needed to set up an encoder for one stream.
+pub struct EncoderSettings {
+    pub sample_rate: u32,
+    pub channels: u8,
+    pub kilobitrate: i32,
+    pub quality: u8,
+    pub mode: EncodeMode,
+    pub vbr_quality: u8,
+    // None leaves the choice to LAME:
+    pub joint_stereo: Option<bool>,
+    // lowpass filter frequency in Hz, None for LAME's default:
+    pub lowpass: Option<u32>,
+}
+
+impl EncoderSettings {
+    pub fn new(sample_rate: u32, channels: u8, kilobitrate: i32, mount: Option<&MountConfig>) -> EncoderSettings {
+        EncoderSettings {
+            sample_rate,
+            channels,
+            kilobitrate,
+            quality: mount.and_then(|mount| mount.quality).unwrap_or(DEFAULT_QUALITY),
+            mode: mount.and_then(|mount| mount.mode).unwrap_or(EncodeMode::Cbr),
+            vbr_quality: mount.and_then(|mount| mount.vbr_quality).unwrap_or(DEFAULT_VBR_QUALITY),
+            joint_stereo: mount.and_then(|mount| mount.joint_stereo),
+            lowpass: mount.and_then(|mount| mount.lowpass),
+        }
+    }
+}
+
+pub struct Encoder {
+    lame: ffi::LameT,
+}
+
+// LAME's state isn't shared with anything else, so can move between threads:
+unsafe impl Send for Encoder {}
+
+fn check(setting: &'static str, ret: i32) -> Result<(), EncoderError> {
+    if ret < 0 {
+        Err(EncoderError::Setting(setting))
+    } else {
+        Ok(())
+    }
+}
+
+impl Encoder {
+    pub fn new(settings: &EncoderSettings) -> Result<Encoder, EncoderError> {
+        let lame = unsafe { ffi::lame_init() };
+
+        if lame.is_null() {
+            return Err(EncoderError::Init);
+        }
+
+        // constructed straight away so LAME is closed if a setting fails:
+        let encoder = Encoder { lame };
+
+        unsafe {
+            check("sample rate", ffi::lame_set_in_samplerate(lame, settings.sample_rate as i32))?;
+            check("channels", ffi::lame_set_num_channels(lame, settings.channels as i32))?;
+            check("quality", ffi::lame_set_quality(lame, settings.quality as i32))?;
+
+            match settings.mode {
+                EncodeMode::Cbr => {
+                    check("mode", ffi::lame_set_VBR(lame, ffi::VBR_OFF))?;
+                    check("bitrate", ffi::lame_set_brate(lame, settings.kilobitrate))?;
+                }
+                EncodeMode::Abr => {
+                    check("mode", ffi::lame_set_VBR(lame, ffi::VBR_ABR))?;
+                    check("bitrate", ffi::lame_set_VBR_mean_bitrate_kbps(lame, settings.kilobitrate))?;
+                }
+                EncodeMode::Vbr => {
+                    check("mode", ffi::lame_set_VBR(lame, ffi::VBR_MTRH))?;
+                    check("VBR quality", ffi::lame_set_VBR_q(lame, settings.vbr_quality as i32))?;
+                }
+            }
+
+            // LAME's tag describes the whole stream, which listeners joining
+            // partway through would be misled by. stream dumps get their own
+            // per file instead:
+            check("VBR tag", ffi::lame_set_bWriteVbrTag(lame, 0))?;
+
+            // mono is always encoded as mono:
+            if settings.channels == 2 {
+                match settings.joint_stereo {
+                    Some(true) => check("joint stereo", ffi::lame_set_mode(lame, ffi::JOINT_STEREO))?,
+                    Some(false) => check("joint stereo", ffi::lame_set_mode(lame, ffi::STEREO))?,
+                    None => (),
+                }
+            }
+
+            if let Some(lowpass) = settings.lowpass {
+                check("lowpass", ffi::lame_set_lowpassfreq(lame, lowpass as i32))?;
+            }
+
+            check("parameters", ffi::lame_init_params(lame))?;
+        }
+
+        Ok(encoder)
+    }
+
+    // Encodes a packet of audio into mp3_buffer, returning how many bytes of
+    // MP3 were written. LAME holds on to some audio between calls, so this
+    // may well be none.
+    pub fn encode(&mut self, left: &[i16], right: &[i16], mp3_buffer: &mut [u8]) -> Result<usize, EncoderError> {
+        assert!(left.len() == right.len());
+
+        let ret = unsafe {
+            ffi::lame_encode_buffer(self.lame,
+                left.as_ptr(), right.as_ptr(), left.len() as i32,
+                mp3_buffer.as_mut_ptr(), mp3_buffer.len() as i32)
+        };
+
+        if ret < 0 {
+            Err(EncoderError::Encode(ret))
+        } else {
+            Ok(ret as usize)
+        }
+    }
+
+    // Encodes whatever audio LAME is holding on to, returning how many
+    // bytes of MP3 were written to mp3_buffer, which should be at least
+    // 7200 bytes.
+    pub fn flush(&mut self, mp3_buffer: &mut [u8]) -> Result<usize, EncoderError> {
+        let ret = unsafe {
+            ffi::lame_encode_flush(self.lame, mp3_buffer.as_mut_ptr(), mp3_buffer.len() as i32)
+        };
+
+        if ret < 0 {
+            Err(EncoderError::Encode(ret))
+        } else {
+            Ok(ret as usize)
+        }
+    }
+}
+
+impl Drop for Encoder {
+    fn drop(&mut self) {
+        unsafe {
+            ffi::lame_close(self.lame);
+        }
+    }
+}
+
+// The encoder for a mount's output codec.
+pub enum OutputEncoder {
+    Mp3(Encoder),
+    #[cfg(feature = "aac")]
+    Aac(AacEncoder),
+}
+
+impl OutputEncoder {
+    pub fn new(codec: OutputCodec, settings: &EncoderSettings) -> Result<OutputEncoder, EncoderError> {
+        match codec {
+            OutputCodec::Mp3 => Encoder::new(settings).map(OutputEncoder::Mp3),
+            #[cfg(feature = "aac")]
+            OutputCodec::Aac | OutputCodec::HeAac => AacEncoder::new(codec, settings).map(OutputEncoder::Aac),
+            // config validation turns these away without the aac feature:
+            #[cfg(not(feature = "aac"))]
+            OutputCodec::Aac | OutputCodec::HeAac => Err(EncoderError::Init),
+        }
+    }
+
+    // Encodes a packet of audio, returning whatever output is ready.
+    pub fn encode(&mut self, left: &[i16], right: &[i16]) -> Result<Vec<u8>, EncoderError> {
+        match *self {
+            OutputEncoder::Mp3(ref mut encoder) => {
+                // buffer size calculation is a suggestion from lame/lame.h:
+                let mut buffer = vec![0; (left.len() * 5) / 4 + 7200];
+                let len = encoder.encode(left, right, &mut buffer)?;
+                buffer.truncate(len);
+                Ok(buffer)
+            }
+            #[cfg(feature = "aac")]
+            OutputEncoder::Aac(ref mut encoder) => {
+                let mut buffer = Vec::new();
+                encoder.encode(left, right, &mut buffer)?;
+                Ok(buffer)
+            }
+        }
+    }
+
+    // Finishes off the audio the encoder is holding on to, before it's
+    // replaced.
+    pub fn flush(&mut self) -> Result<Vec<u8>, EncoderError> {
+        match *self {
+            OutputEncoder::Mp3(ref mut encoder) => {
+                let mut buffer = vec![0; 7200];
+                let len = encoder.flush(&mut buffer)?;
+                buffer.truncate(len);
+                Ok(buffer)
+            }
+            #[cfg(feature = "aac")]
+            OutputEncoder::Aac(ref mut encoder) => {
+                let mut buffer = Vec::new();
+                encoder.flush(&mut buffer)?;
+                Ok(buffer)
+            }
+        }
+    }
+}
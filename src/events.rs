@@ -0,0 +1,249 @@
+// The event bus. Whatever part of rustcast notices something happening to a
+// stream or listener publishes it here, and each kind of sink has its own
+// worker thread and queue, so a slow webhook or unreachable broker only holds
+// up its own events. Publishing never waits on a sink, and each sink sees
+// events in the order they happened. A sink that falls more than QUEUE_SIZE
+// events behind has new ones dropped until it catches up.
+//
+// Sinks are set up from the config for each event, so reloading it applies
+// straight away.
+
+use std::mem;
+use std::net::IpAddr;
+use std::sync::{mpsc, Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+
+use chrono::{DateTime, Utc};
+use serde_json;
+use uuid::Uuid;
+
+use audio::Metadata;
+use config::{Config, ConfigChange};
+use hooks::{self, ConfigReloadedParams, DurationWarningParams, ErrorParams, Handlers, ListenerParams, MetadataParams, SlotEndingParams, SourceHealthParams, StreamEndParams};
+use log::Log;
+use sourcehealth::SourceStats;
+
+#[derive(Serialize, Clone, Debug)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event {
+    // a source has gone live:
+    StreamStarted { mountpoint: String, uuid: Uuid },
+    StreamEnded { mountpoint: String, uuid: Uuid, duration: u64 },
+    MetadataChanged { mountpoint: String, uuid: Uuid, metadata: Metadata },
+    DurationWarning { mountpoint: String, uuid: Uuid, remaining: u64 },
+    SlotEnding { mountpoint: String, uuid: Uuid, user: Option<String>, remaining: u64 },
+    // a source has become unhealthy, or recovered:
+    SourceHealth { mountpoint: String, uuid: Uuid, healthy: bool, problems: Vec<String>, stats: SourceStats },
+    // player is the User-Agent classified, see useragent::classify, and
+    // session the listener's session cookie, when session_cookie is set:
+    ListenerJoined { mountpoint: String, ip: IpAddr, user_agent: Option<String>, player: &'static str, session: Option<String> },
+    // peak_buffer and dropped are how far behind the listener got, in
+    // packets, and left is when they went, which is a while before the event
+    // is published if listener_dedup_window held it back:
+    ListenerLeft { mountpoint: String, ip: IpAddr, user_agent: Option<String>, player: &'static str, session: Option<String>, peak_buffer: usize, dropped: usize, left: DateTime<Utc> },
+    ConfigReloaded { changes: Vec<ConfigChange> },
+    // something went wrong that an operator would want to know about:
+    Error { mountpoint: Option<String>, message: String },
+}
+
+pub trait EventSink {
+    fn publish(&self, event: &Event);
+}
+
+// events a sink can fall behind by before new ones are dropped for it:
+const QUEUE_SIZE: usize = 1024;
+
+struct Worker {
+    name: &'static str,
+    sender: mpsc::SyncSender<Event>,
+    thread: thread::JoinHandle<()>,
+    // whether events are being dropped, so it's only logged once:
+    overflowing: AtomicBool,
+}
+
+pub struct EventBus {
+    workers: Mutex<Vec<Worker>>,
+}
+
+impl EventBus {
+    pub fn new() -> EventBus {
+        EventBus {
+            workers: Mutex::new(Vec::new()),
+        }
+    }
+
+    // Starts a worker that hands each event to the sinks sinks returns for
+    // it, named for the log.
+    pub fn add_worker<F>(&self, name: &'static str, sinks: F) where F: Fn() -> Vec<Box<EventSink>> + Send + 'static {
+        let (sender, receiver) = mpsc::sync_channel::<Event>(QUEUE_SIZE);
+
+        let thread = thread::spawn(move || {
+            for event in receiver {
+                for sink in sinks() {
+                    sink.publish(&event);
+                }
+            }
+        });
+
+        self.workers.lock()
+            .expect("lock on event workers")
+            .push(Worker {
+                name,
+                sender,
+                thread,
+                overflowing: AtomicBool::new(false),
+            });
+    }
+
+    pub fn publish(&self, event: Event) {
+        let workers = self.workers.lock().expect("lock on event workers");
+
+        for worker in workers.iter() {
+            match worker.sender.try_send(event.clone()) {
+                Ok(()) => {
+                    if worker.overflowing.swap(false, Ordering::SeqCst) {
+                        Log::new().info(&format!("Event sink {} caught up", worker.name));
+                    }
+                }
+                Err(mpsc::TrySendError::Full(_)) => {
+                    if !worker.overflowing.swap(true, Ordering::SeqCst) {
+                        Log::new().error(&format!("Event sink {} is falling behind, dropping events", worker.name));
+                    }
+                }
+                // only once the bus is closed:
+                Err(mpsc::TrySendError::Disconnected(_)) => (),
+            }
+        }
+    }
+
+    // Stops taking events, and waits for every worker to finish handing on
+    // the ones already queued. Events published after this are dropped.
+    pub fn close(&self) {
+        let workers = mem::replace(&mut *self.workers.lock().expect("lock on event workers"), Vec::new());
+
+        for worker in workers {
+            drop(worker.sender);
+
+            if worker.thread.join().is_err() {
+                Log::new().error(&format!("Event sink {} panicked", worker.name));
+            }
+        }
+    }
+}
+
+// Passes events on to the StreamLifecycleHandlers, which includes the
+// configured webhooks.
+pub struct HandlerSink {
+    config: Arc<Config>,
+    handlers: Handlers,
+}
+
+impl HandlerSink {
+    pub fn new(config: Arc<Config>, handlers: Handlers) -> HandlerSink {
+        HandlerSink { config, handlers }
+    }
+}
+
+impl EventSink for HandlerSink {
+    fn publish(&self, event: &Event) {
+        match *event {
+            Event::StreamStarted { .. } => (),
+            Event::StreamEnded { ref mountpoint, ref uuid, duration } => {
+                let params = StreamEndParams { mountpoint, uuid, duration };
+
+                hooks::notify(&self.handlers, &format!("stream_end for {}", mountpoint),
+                    |handler| handler.stream_end(&params));
+            }
+            Event::MetadataChanged { ref mountpoint, ref uuid, ref metadata } => {
+                let url = self.config.public_stream_url(mountpoint);
+
+                let params = MetadataParams {
+                    mountpoint,
+                    uuid,
+                    url: url.as_ref().map(String::as_str),
+                    metadata,
+                };
+
+                hooks::notify(&self.handlers, &format!("metadata for {}", mountpoint),
+                    |handler| handler.metadata(&params));
+            }
+            Event::DurationWarning { ref mountpoint, ref uuid, remaining } => {
+                let params = DurationWarningParams { mountpoint, uuid, remaining };
+
+                hooks::notify(&self.handlers, &format!("duration_warning for {}", mountpoint),
+                    |handler| handler.duration_warning(&params));
+            }
+            Event::SlotEnding { ref mountpoint, ref uuid, ref user, remaining } => {
+                let params = SlotEndingParams {
+                    mountpoint,
+                    uuid,
+                    user: user.as_ref().map(String::as_str),
+                    remaining,
+                };
+
+                hooks::notify(&self.handlers, &format!("slot_ending for {}", mountpoint),
+                    |handler| handler.slot_ending(&params));
+            }
+            Event::SourceHealth { ref mountpoint, ref uuid, healthy, ref problems, ref stats } => {
+                let params = SourceHealthParams { mountpoint, uuid, healthy, problems, stats };
+
+                hooks::notify(&self.handlers, &format!("source_health for {}", mountpoint),
+                    |handler| handler.source_health(&params));
+            }
+            Event::ListenerJoined { ref mountpoint, ip, ref user_agent, player, ref session } => {
+                let params = ListenerParams {
+                    mountpoint,
+                    ip,
+                    user_agent: user_agent.as_ref().map(String::as_str),
+                    player,
+                    session: session.as_ref().map(String::as_str),
+                };
+
+                hooks::notify(&self.handlers, &format!("listener_connect for {}", mountpoint),
+                    |handler| handler.listener_connect(&params));
+            }
+            Event::ListenerLeft { ref mountpoint, ip, ref user_agent, player, ref session, .. } => {
+                let params = ListenerParams {
+                    mountpoint,
+                    ip,
+                    user_agent: user_agent.as_ref().map(String::as_str),
+                    player,
+                    session: session.as_ref().map(String::as_str),
+                };
+
+                hooks::notify(&self.handlers, &format!("listener_disconnect for {}", mountpoint),
+                    |handler| handler.listener_disconnect(&params));
+            }
+            Event::ConfigReloaded { ref changes } => {
+                // only ever a webhook, in-process handlers can't see config:
+                let params = ConfigReloadedParams { changes };
+
+                if let Err(e) = hooks::config_reloaded(&self.config, params) {
+                    Log::new().error(&format!("config_reloaded hook failed: {:?}", e));
+                }
+            }
+            Event::Error { ref mountpoint, ref message } => {
+                let params = ErrorParams {
+                    mountpoint: mountpoint.as_ref().map(String::as_str),
+                    message,
+                };
+
+                hooks::notify(&self.handlers, "error",
+                    |handler| handler.error(&params));
+            }
+        }
+    }
+}
+
+// Logs every event as a line of JSON, for log pipelines to pick up.
+pub struct LogSink;
+
+impl EventSink for LogSink {
+    fn publish(&self, event: &Event) {
+        match serde_json::to_string(event) {
+            Ok(json) => Log::new().info(&format!("event {}", json)),
+            Err(e) => Log::new().error(&format!("Couldn't serialize event {:?}: {}", event, e)),
+        }
+    }
+}
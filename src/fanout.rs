@@ -1,60 +1,199 @@
-use std::sync::{mpsc, RwLock, Mutex};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::mem;
+use std::sync::{mpsc, Arc, Mutex};
 
+// New receivers are queued up in joining and picked up by the next publish,
+// so subscribing only ever waits on other subscribers, never on a publish
+// sending to everyone already listening.
 pub struct Channel<T> {
-    buffer_size: usize,
-    txs: RwLock<Vec<Mutex<mpsc::SyncSender<T>>>>,
+    buffer_size: AtomicUsize,
+    joining: Mutex<Vec<Subscriber<T>>>,
+    subscribers: Mutex<Vec<Subscriber<T>>>,
+    // subscribers and joining together, so counting doesn't wait either:
+    count: AtomicUsize,
+}
+
+struct Subscriber<T> {
+    tx: mpsc::SyncSender<T>,
+    lag: Arc<Lag>,
+}
+
+// Why a channel stopped sending to its receivers early.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CloseReason {
+    Shutdown,
+    Kicked,
+}
+
+// How far behind a receiver is, and whether it's been closed, shared
+// between it and the channel:
+#[derive(Default)]
+struct Lag {
+    // published but not yet received:
+    queued: AtomicUsize,
+    // published while the buffer was full:
+    dropped: AtomicUsize,
+    closed: Mutex<Option<CloseReason>>,
 }
 
 pub struct Receiver<T> {
     rx: mpsc::Receiver<T>,
+    buffer_size: usize,
+    lag: Arc<Lag>,
+}
+
+// Closes a single receiver from elsewhere. It stops receiving once whatever
+// it's waiting on arrives.
+#[derive(Clone)]
+pub struct Closer {
+    lag: Arc<Lag>,
+}
+
+impl Closer {
+    pub fn close(&self, reason: CloseReason) {
+        *self.lag.closed.lock().expect("lock on closed") = Some(reason);
+    }
 }
 
 impl<T> Channel<T> where T: Clone {
     pub fn new(buffer_size: usize) -> Channel<T> {
         Channel {
-            buffer_size: buffer_size,
-            txs: RwLock::new(Vec::new())
+            buffer_size: AtomicUsize::new(buffer_size),
+            joining: Mutex::new(Vec::new()),
+            subscribers: Mutex::new(Vec::new()),
+            count: AtomicUsize::new(0),
         }
     }
 
+    // only applies to receivers subscribed from now on:
+    pub fn set_buffer_size(&self, buffer_size: usize) {
+        self.buffer_size.store(buffer_size, Ordering::SeqCst);
+    }
+
     pub fn publish(&self, data: T) {
-        let mut dead_txs = Vec::new();
+        let mut subscribers = self.subscribers.lock()
+            .expect("lock on subscribers");
 
-        {
-            let txs = self.txs.read()
-                .expect("reader lock on txs");
+        subscribers.extend(self.take_joining());
 
-            for (index, tx) in txs.iter().enumerate() {
-                let tx = tx.lock().expect("lock on tx");
-                if let Err(_) = tx.try_send(data.clone()) {
-                    dead_txs.push(index);
-                }
-            }
-        }
+        let before = subscribers.len();
 
-        if dead_txs.len() > 0 {
-            let mut txs = self.txs.write()
-                .expect("writer lock on txs");
+        // dead receivers are dropped as they're found, a subscriber that's
+        // fallen behind included:
+        subscribers.retain(|subscriber| subscriber.send(data.clone()));
 
-            for dead_tx_index in dead_txs {
-                txs.swap_remove(dead_tx_index);
-            }
+        self.count.fetch_sub(before - subscribers.len(), Ordering::SeqCst);
+    }
+
+    fn take_joining(&self) -> Vec<Subscriber<T>> {
+        mem::replace(&mut *self.joining.lock().expect("lock on joining"), Vec::new())
+    }
+
+    // Ends every current receiver, without waiting for them to get through
+    // what's buffered. Receivers subscribed afterwards aren't affected.
+    // Returns how many were closed.
+    pub fn close(&self, reason: CloseReason) -> usize {
+        let mut subscribers = self.subscribers.lock()
+            .expect("lock on subscribers");
+
+        subscribers.extend(self.take_joining());
+
+        for subscriber in subscribers.iter() {
+            *subscriber.lag.closed.lock().expect("lock on closed") = Some(reason);
         }
+
+        // dropping the senders wakes up receivers waiting on them:
+        let closed = subscribers.len();
+        subscribers.clear();
+        self.count.fetch_sub(closed, Ordering::SeqCst);
+        closed
+    }
+
+    // receivers that haven't been found dead by a publish yet:
+    pub fn subscriber_count(&self) -> usize {
+        self.count.load(Ordering::SeqCst)
     }
 
     pub fn subscribe(&self) -> Receiver<T> {
-        let (tx, rx) = mpsc::sync_channel(self.buffer_size);
+        let buffer_size = self.buffer_size.load(Ordering::SeqCst);
+        let (tx, rx) = mpsc::sync_channel(buffer_size);
+        let lag = Arc::new(Lag::default());
 
-        self.txs.write()
-            .expect("writer lock on txs")
-            .push(Mutex::new(tx));
+        // counted first, so a publish or close can't take it off first:
+        self.count.fetch_add(1, Ordering::SeqCst);
 
-        Receiver { rx: rx }
+        self.joining.lock()
+            .expect("lock on joining")
+            .push(Subscriber { tx, lag: Arc::clone(&lag) });
+
+        Receiver { rx: rx, buffer_size, lag }
+    }
+}
+
+impl<T> Subscriber<T> {
+    // Returns whether the receiver is still there and keeping up.
+    fn send(&self, data: T) -> bool {
+        // counted before sending, so the receiver can't take it off first:
+        self.lag.queued.fetch_add(1, Ordering::SeqCst);
+
+        match self.tx.try_send(data) {
+            Ok(()) => true,
+            Err(e) => {
+                self.lag.queued.fetch_sub(1, Ordering::SeqCst);
+
+                if let mpsc::TrySendError::Full(_) = e {
+                    self.lag.dropped.fetch_add(1, Ordering::SeqCst);
+                }
+
+                false
+            }
+        }
     }
 }
 
 impl<T> Receiver<T> where T: Clone {
     pub fn recv(&self) -> Option<T> {
-        self.rx.recv().ok()
+        if self.close_reason().is_some() {
+            return None;
+        }
+
+        let data = self.rx.recv().ok();
+
+        if data.is_some() {
+            self.lag.queued.fetch_sub(1, Ordering::SeqCst);
+        }
+
+        // closed by a Closer while waiting:
+        if self.close_reason().is_some() {
+            return None;
+        }
+
+        data
+    }
+
+    // Why the channel was closed, if it was, rather than ending because the
+    // channel itself went away.
+    pub fn close_reason(&self) -> Option<CloseReason> {
+        *self.lag.closed.lock().expect("lock on closed")
+    }
+
+    pub fn closer(&self) -> Closer {
+        Closer { lag: Arc::clone(&self.lag) }
+    }
+
+    // How many packets are waiting to be received.
+    pub fn queued(&self) -> usize {
+        self.lag.queued.load(Ordering::SeqCst)
+    }
+
+    // How many packets can wait before the receiver starts missing them.
+    pub fn buffer_size(&self) -> usize {
+        self.buffer_size
+    }
+
+    // How many packets the receiver has missed by falling behind. For now
+    // the first one missed ends the subscription.
+    pub fn dropped(&self) -> usize {
+        self.lag.dropped.load(Ordering::SeqCst)
     }
 }
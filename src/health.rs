@@ -0,0 +1,68 @@
+// Healthcheck endpoints for orchestrators and load balancers. /healthz says
+// whether the process is alive at all, and /readyz whether it should be sent
+// listeners right now.
+
+use std::io;
+
+use serde_json;
+use tiny_http::{Header, Request, Response};
+
+use server::Rustcast;
+
+#[derive(Serialize)]
+struct HealthJson {
+    status: &'static str,
+    // why rustcast isn't ready, if it isn't:
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reason: Option<&'static str>,
+}
+
+fn respond(req: Request, reason: Option<&'static str>) -> io::Result<()> {
+    let content_type = Header::from_bytes(&b"Content-Type"[..], &b"application/json"[..])
+        .expect("valid header");
+
+    let (status_code, status) = match reason {
+        None => (200, "ok"),
+        Some(_) => (503, "unavailable"),
+    };
+
+    let data = HealthJson { status, reason };
+
+    req.respond(Response::from_string(serde_json::to_string(&data).unwrap())
+        .with_header(content_type)
+        .with_status_code(status_code))
+}
+
+// Why rustcast shouldn't be sent listeners, if anything. Being able to
+// answer at all means the listen socket is up and a config has been loaded.
+fn not_ready_reason(rustcast: &Rustcast) -> Option<&'static str> {
+    if rustcast.is_shutdown() {
+        return Some("shutting down");
+    }
+
+    if rustcast.config().ready_requires_source && rustcast.live_streams().is_empty() {
+        return Some("no sources live");
+    }
+
+    None
+}
+
+fn path(url: &str) -> &str {
+    url.split('?').next().unwrap_or(url)
+}
+
+pub fn is_health_url(url: &str) -> bool {
+    match path(url) {
+        "/healthz" | "/readyz" => true,
+        _ => false,
+    }
+}
+
+pub fn handle(rustcast: &Rustcast, req: Request) -> io::Result<()> {
+    let reason = match path(req.url()) {
+        "/readyz" => not_ready_reason(rustcast),
+        _ => None,
+    };
+
+    respond(req, reason)
+}
@@ -0,0 +1,97 @@
+// Posts a snapshot of every live mountpoint to the heartbeat webhook every
+// so often, so dashboards can stay up to date from the one webhook, without
+// also polling or handling events. Each station's mountpoints go to its own
+// heartbeat webhook, and the rest to the top level one.
+
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use config::Config;
+use hooks::{self, HeartbeatMount, HeartbeatParams};
+use server::Rustcast;
+
+// how often to check whether it's time to post, and whether the webhook has
+// been configured or changed:
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+fn snapshot(rustcast: &Rustcast) -> Vec<HeartbeatMount> {
+    let config = rustcast.config();
+
+    rustcast.live_streams().into_iter()
+        .map(|(mountpoint, stream)| {
+            HeartbeatMount {
+                url: config.public_stream_url(&mountpoint),
+                hidden: config.hidden(&mountpoint),
+                uuid: stream.uuid,
+                listeners: stream.listeners(),
+                bitrate: stream.bitrate(),
+                metadata: stream.metadata.read().unwrap().clone(),
+                mountpoint,
+            }
+        })
+        .collect()
+}
+
+// Whether there's a heartbeat webhook anywhere to post to.
+fn configured(config: &Config) -> bool {
+    config.webhooks.heartbeat.is_some()
+        || config.stations.values().any(|station| station.webhooks.heartbeat.is_some())
+}
+
+pub fn run(rustcast: Arc<Rustcast>) {
+    let mut last_post: Option<Instant> = None;
+
+    while !rustcast.is_shutdown() {
+        thread::sleep(POLL_INTERVAL);
+
+        let config = rustcast.config();
+
+        if !configured(&config) {
+            continue;
+        }
+
+        let due = last_post.map(|at| at.elapsed() >= Duration::from_secs(config.heartbeat_interval))
+            .unwrap_or(true);
+
+        if !due {
+            continue;
+        }
+
+        last_post = Some(Instant::now());
+
+        // by station name, None for mountpoints outside any station. every
+        // station is posted to, even with nothing live:
+        let mut groups = BTreeMap::new();
+
+        groups.insert(None, Vec::new());
+
+        for name in config.stations.keys() {
+            groups.insert(Some(name.as_str()), Vec::new());
+        }
+
+        for mount in snapshot(&rustcast) {
+            groups.get_mut(&config.station_name(&mount.mountpoint))
+                .expect("a group for every station")
+                .push(mount);
+        }
+
+        for (station, mounts) in groups {
+            let webhooks = match station {
+                Some(name) => &config.stations[name].webhooks,
+                None => &config.webhooks,
+            };
+
+            let params = HeartbeatParams {
+                listeners: mounts.iter().map(|mount| mount.listeners).sum(),
+                mounts: &mounts,
+            };
+
+            if let Err(e) = hooks::heartbeat(webhooks, &params) {
+                rustcast.log.error(&format!("heartbeat hook for {} failed: {:?}",
+                    station.unwrap_or("mountpoints outside stations"), e));
+            }
+        }
+    }
+}
@@ -0,0 +1,53 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+
+use chrono::Utc;
+
+use audio::Metadata;
+
+#[derive(Serialize, Clone)]
+pub struct HistoryEntry {
+    pub artist: Option<String>,
+    pub title: Option<String>,
+    pub started_at: String,
+}
+
+// Recently played metadata per mountpoint. This outlives individual streams
+// so a source reconnecting doesn't wipe the list.
+pub struct History {
+    mounts: Mutex<HashMap<String, VecDeque<HistoryEntry>>>,
+}
+
+impl History {
+    pub fn new() -> History {
+        History {
+            mounts: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn record(&self, mountpoint: &str, metadata: &Metadata, size: usize) {
+        if metadata.artist.is_none() && metadata.title.is_none() {
+            return;
+        }
+
+        let mut mounts = self.mounts.lock().expect("lock on history");
+
+        let entries = mounts.entry(mountpoint.to_owned())
+            .or_insert_with(VecDeque::new);
+
+        entries.push_front(HistoryEntry {
+            artist: metadata.artist.clone(),
+            title: metadata.title.clone(),
+            started_at: Utc::now().to_rfc3339(),
+        });
+
+        entries.truncate(size);
+    }
+
+    // most recent first:
+    pub fn get(&self, mountpoint: &str) -> Option<Vec<HistoryEntry>> {
+        self.mounts.lock().expect("lock on history")
+            .get(mountpoint)
+            .map(|entries| entries.iter().cloned().collect())
+    }
+}
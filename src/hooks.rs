@@ -1,17 +1,47 @@
+use std::net::IpAddr;
+use std::sync::{Arc, RwLock};
+use std::time::Instant;
+
 use reqwest::{self, Client};
 use serde::Serialize;
 use serde::de::DeserializeOwned;
 use uuid::Uuid;
 
-use config::Config;
+use audio::Metadata;
+use config::{Config, ConfigChange, Webhooks};
+use log::Log;
+use metrics;
+use sourcehealth::SourceStats;
 
 #[derive(Debug)]
 pub enum HookError {
     Http(reqwest::Error),
     Status(reqwest::StatusCode),
+    // returned by in-process handlers:
+    Handler(String),
+}
+
+// Why a hook failed, for metrics.
+fn failure_reason(error: &HookError) -> &'static str {
+    match *error {
+        HookError::Http(ref e) if e.is_serialization() => "response",
+        HookError::Http(_) => "http",
+        HookError::Status(_) => "status",
+        HookError::Handler(_) => "handler",
+    }
+}
+
+fn call_hook<Params: Serialize, Resp: DeserializeOwned>(hook: &'static str, url: &str, params: Params) -> Result<Resp, HookError> {
+    let started = Instant::now();
+    let result = post_hook(url, params);
+
+    metrics::record_hook(hook, started.elapsed(),
+        result.as_ref().err().map(failure_reason));
+
+    result
 }
 
-fn call_hook<Params: Serialize, Resp: DeserializeOwned>(url: &str, params: Params) -> Result<Resp, HookError> {
+fn post_hook<Params: Serialize, Resp: DeserializeOwned>(url: &str, params: Params) -> Result<Resp, HookError> {
     let mut response = Client::new()
         .post(url)
         .json(&params)
@@ -29,29 +59,82 @@ fn call_hook<Params: Serialize, Resp: DeserializeOwned>(url: &str, params: Param
 pub struct StreamStartParams<'a> {
     pub mountpoint: &'a str,
     pub uuid: &'a Uuid,
+    pub url: Option<&'a str>,
+    // address of the source client, None for sources not connecting over
+    // HTTP:
+    pub ip: Option<IpAddr>,
+    // the basic auth user name the source gave, if any:
+    pub user: Option<&'a str>,
     pub password: Option<&'a str>,
 }
 
 pub enum StreamStart {
-    Ok,
+    Ok { priority: Option<u32>, overrides: StreamOverrides },
     Reject,
 }
 
+// Settings a stream_start hook can override for a single stream, so stations
+// can have per-DJ policies. Anything left as None comes from the config.
+#[derive(Deserialize, Serialize, Clone, Default, Debug)]
+pub struct StreamOverrides {
+    // output bitrate in kbps:
+    pub bitrate: Option<i32>,
+    // most MP3 listeners the stream may have at once:
+    pub max_listeners: Option<usize>,
+    // whether to write stream dumps, when they're configured:
+    pub dump: Option<bool>,
+    // put before the title of every metadata update, like "DJ Alice: ":
+    pub metadata_prefix: Option<String>,
+    // publish the stream on this mountpoint instead of the one the source
+    // asked for, like "/main" for a DJ during their slot:
+    pub mountpoint: Option<String>,
+}
+
+impl StreamOverrides {
+    // Takes any settings other gives, keeping the rest.
+    fn merge(&mut self, other: StreamOverrides) {
+        if other.bitrate.is_some() {
+            self.bitrate = other.bitrate;
+        }
+
+        if other.max_listeners.is_some() {
+            self.max_listeners = other.max_listeners;
+        }
+
+        if other.dump.is_some() {
+            self.dump = other.dump;
+        }
+
+        if other.metadata_prefix.is_some() {
+            self.metadata_prefix = other.metadata_prefix;
+        }
+
+        if other.mountpoint.is_some() {
+            self.mountpoint = other.mountpoint;
+        }
+    }
+}
+
 #[derive(Deserialize)]
 struct StreamStartResponse {
     ok: bool,
+    // lets the source take over from a lower priority source already live
+    // on the mountpoint:
+    priority: Option<u32>,
+    #[serde(flatten)]
+    overrides: StreamOverrides,
 }
 
-pub fn stream_start<'a>(config: &Config, params: StreamStartParams<'a>) -> Result<StreamStart, HookError> {
-    let url = match config.webhooks.stream_start.as_ref() {
+pub fn stream_start<'a>(config: &Config, params: &StreamStartParams<'a>) -> Result<StreamStart, HookError> {
+    let url = match config.webhooks(params.mountpoint).stream_start.as_ref() {
         Some(url) => url,
-        None => return Ok(StreamStart::Ok),
+        None => return Ok(StreamStart::Ok { priority: None, overrides: StreamOverrides::default() }),
     };
 
-    let response = call_hook::<_, StreamStartResponse>(url, params)?;
+    let response = call_hook::<_, StreamStartResponse>("stream_start", url, params)?;
 
     if response.ok {
-        Ok(StreamStart::Ok)
+        Ok(StreamStart::Ok { priority: response.priority, overrides: response.overrides })
     } else {
         Ok(StreamStart::Reject)
     }
@@ -61,18 +144,418 @@ pub fn stream_start<'a>(config: &Config, params: StreamStartParams<'a>) -> Resul
 pub struct StreamEndParams<'a> {
     pub mountpoint: &'a str,
     pub uuid: &'a Uuid,
+    // seconds of audio the source sent:
+    pub duration: u64,
 }
 
 #[derive(Deserialize)]
 struct StreamEndResponse {}
 
-pub fn stream_end<'a>(config: &Config, params: StreamEndParams<'a>) -> Result<(), HookError> {
-    let url = match config.webhooks.stream_end.as_ref() {
+pub fn stream_end<'a>(config: &Config, params: &StreamEndParams<'a>) -> Result<(), HookError> {
+    let url = match config.webhooks(params.mountpoint).stream_end.as_ref() {
+        Some(url) => url,
+        None => return Ok(()),
+    };
+
+    call_hook::<_, StreamEndResponse>("stream_end", url, params)?;
+
+    Ok(())
+}
+
+#[derive(Serialize)]
+pub struct MetadataParams<'a> {
+    pub mountpoint: &'a str,
+    pub uuid: &'a Uuid,
+    pub url: Option<&'a str>,
+    pub metadata: &'a Metadata,
+}
+
+#[derive(Deserialize)]
+struct MetadataResponse {}
+
+pub fn metadata<'a>(config: &Config, params: &MetadataParams<'a>) -> Result<(), HookError> {
+    let url = match config.webhooks(params.mountpoint).metadata.as_ref() {
+        Some(url) => url,
+        None => return Ok(()),
+    };
+
+    call_hook::<_, MetadataResponse>("metadata", url, params)?;
+
+    Ok(())
+}
+
+#[derive(Serialize)]
+pub struct DurationWarningParams<'a> {
+    pub mountpoint: &'a str,
+    pub uuid: &'a Uuid,
+    pub remaining: u64,
+}
+
+#[derive(Deserialize)]
+struct DurationWarningResponse {}
+
+pub fn duration_warning<'a>(config: &Config, params: &DurationWarningParams<'a>) -> Result<(), HookError> {
+    let url = match config.webhooks(params.mountpoint).duration_warning.as_ref() {
+        Some(url) => url,
+        None => return Ok(()),
+    };
+
+    call_hook::<_, DurationWarningResponse>("duration_warning", url, params)?;
+
+    Ok(())
+}
+
+#[derive(Serialize)]
+pub struct ArchiveSegmentParams<'a> {
+    pub mountpoint: &'a str,
+    pub uuid: &'a Uuid,
+    pub path: &'a str,
+    pub url: Option<&'a str>,
+    pub segment: u64,
+    pub duration: u64,
+    pub artist: Option<&'a str>,
+    pub title: Option<&'a str>,
+}
+
+#[derive(Deserialize)]
+struct ArchiveSegmentResponse {}
+
+pub fn archive_segment<'a>(config: &Config, params: ArchiveSegmentParams<'a>) -> Result<(), HookError> {
+    let url = match config.webhooks(params.mountpoint).archive_segment.as_ref() {
+        Some(url) => url,
+        None => return Ok(()),
+    };
+
+    call_hook::<_, ArchiveSegmentResponse>("archive_segment", url, params)?;
+
+    Ok(())
+}
+
+#[derive(Serialize)]
+pub struct ArchiveErrorParams<'a> {
+    pub mountpoint: &'a str,
+    pub uuid: &'a Uuid,
+    pub path: &'a str,
+    pub error: &'a str,
+}
+
+#[derive(Deserialize)]
+struct ArchiveErrorResponse {}
+
+pub fn archive_error<'a>(config: &Config, params: ArchiveErrorParams<'a>) -> Result<(), HookError> {
+    let url = match config.webhooks(params.mountpoint).archive_error.as_ref() {
+        Some(url) => url,
+        None => return Ok(()),
+    };
+
+    call_hook::<_, ArchiveErrorResponse>("archive_error", url, params)?;
+
+    Ok(())
+}
+
+#[derive(Serialize)]
+pub struct ConfigReloadedParams<'a> {
+    pub changes: &'a [ConfigChange],
+}
+
+#[derive(Deserialize)]
+struct ConfigReloadedResponse {}
+
+pub fn config_reloaded<'a>(config: &Config, params: ConfigReloadedParams<'a>) -> Result<(), HookError> {
+    let url = match config.webhooks.config_reloaded.as_ref() {
+        Some(url) => url,
+        None => return Ok(()),
+    };
+
+    call_hook::<_, ConfigReloadedResponse>("config_reloaded", url, params)?;
+
+    Ok(())
+}
+
+#[derive(Serialize)]
+pub struct ListenerParams<'a> {
+    pub mountpoint: &'a str,
+    pub ip: IpAddr,
+    pub user_agent: Option<&'a str>,
+    // the player the user agent belongs to, like "vlc" or "chrome":
+    pub player: &'a str,
+    // the listener's session cookie, when session_cookie is configured:
+    pub session: Option<&'a str>,
+}
+
+#[derive(Deserialize)]
+struct ListenerResponse {}
+
+pub fn listener_connect<'a>(config: &Config, params: &ListenerParams<'a>) -> Result<(), HookError> {
+    let url = match config.webhooks(params.mountpoint).listener_connect.as_ref() {
+        Some(url) => url,
+        None => return Ok(()),
+    };
+
+    call_hook::<_, ListenerResponse>("listener_connect", url, params)?;
+
+    Ok(())
+}
+
+pub fn listener_disconnect<'a>(config: &Config, params: &ListenerParams<'a>) -> Result<(), HookError> {
+    let url = match config.webhooks(params.mountpoint).listener_disconnect.as_ref() {
+        Some(url) => url,
+        None => return Ok(()),
+    };
+
+    call_hook::<_, ListenerResponse>("listener_disconnect", url, params)?;
+
+    Ok(())
+}
+
+#[derive(Serialize)]
+pub struct SlotEndingParams<'a> {
+    pub mountpoint: &'a str,
+    pub uuid: &'a Uuid,
+    // the source's user name, if it gave one:
+    pub user: Option<&'a str>,
+    // seconds until the slot ends:
+    pub remaining: u64,
+}
+
+#[derive(Deserialize)]
+struct SlotEndingResponse {}
+
+pub fn slot_ending<'a>(config: &Config, params: &SlotEndingParams<'a>) -> Result<(), HookError> {
+    let url = match config.webhooks(params.mountpoint).slot_ending.as_ref() {
+        Some(url) => url,
+        None => return Ok(()),
+    };
+
+    call_hook::<_, SlotEndingResponse>("slot_ending", url, params)?;
+
+    Ok(())
+}
+
+#[derive(Serialize)]
+pub struct SourceHealthParams<'a> {
+    pub mountpoint: &'a str,
+    pub uuid: &'a Uuid,
+    // false when the source's become unhealthy, true when it's recovered:
+    pub healthy: bool,
+    // what's wrong, empty once healthy:
+    pub problems: &'a [String],
+    pub stats: &'a SourceStats,
+}
+
+#[derive(Deserialize)]
+struct SourceHealthResponse {}
+
+pub fn source_health<'a>(config: &Config, params: &SourceHealthParams<'a>) -> Result<(), HookError> {
+    let url = match config.webhooks(params.mountpoint).source_health.as_ref() {
+        Some(url) => url,
+        None => return Ok(()),
+    };
+
+    call_hook::<_, SourceHealthResponse>("source_health", url, params)?;
+
+    Ok(())
+}
+
+#[derive(Serialize)]
+pub struct HeartbeatMount {
+    pub mountpoint: String,
+    pub uuid: Uuid,
+    pub url: Option<String>,
+    pub hidden: bool,
+    pub listeners: usize,
+    // output bitrate in kbps, when known:
+    pub bitrate: Option<u32>,
+    pub metadata: Metadata,
+}
+
+#[derive(Serialize)]
+pub struct HeartbeatParams<'a> {
+    // across every mountpoint:
+    pub listeners: usize,
+    pub mounts: &'a [HeartbeatMount],
+}
+
+#[derive(Deserialize)]
+struct HeartbeatResponse {}
+
+// Heartbeats are per station, so take the station's webhooks (or the top
+// level ones for mountpoints outside any station) rather than a mountpoint.
+pub fn heartbeat<'a>(webhooks: &Webhooks, params: &HeartbeatParams<'a>) -> Result<(), HookError> {
+    let url = match webhooks.heartbeat.as_ref() {
+        Some(url) => url,
+        None => return Ok(()),
+    };
+
+    call_hook::<_, HeartbeatResponse>("heartbeat", url, params)?;
+
+    Ok(())
+}
+
+#[derive(Serialize)]
+pub struct ErrorParams<'a> {
+    // None for errors that aren't about any one mountpoint:
+    pub mountpoint: Option<&'a str>,
+    pub message: &'a str,
+}
+
+#[derive(Deserialize)]
+struct ErrorResponse {}
+
+// Errors about a mountpoint go to its station's webhook, others to the top
+// level one.
+pub fn error<'a>(config: &Config, params: &ErrorParams<'a>) -> Result<(), HookError> {
+    let webhooks = match params.mountpoint {
+        Some(mountpoint) => config.webhooks(mountpoint),
+        None => &config.webhooks,
+    };
+
+    let url = match webhooks.error.as_ref() {
         Some(url) => url,
         None => return Ok(()),
     };
 
-    call_hook::<_, StreamEndResponse>(url, params)?;
+    call_hook::<_, ErrorResponse>("error", url, params)?;
 
     Ok(())
 }
+
+// Receives stream and listener events. The webhooks configured under
+// [webhooks] are one implementation, and embedding applications can add
+// their own with Rustcast::add_handler. Every method does nothing by
+// default.
+pub trait StreamLifecycleHandler: Send + Sync {
+    // Called before a source goes live, any handler can reject it.
+    fn stream_start(&self, _params: &StreamStartParams) -> Result<StreamStart, HookError> {
+        Ok(StreamStart::Ok { priority: None, overrides: StreamOverrides::default() })
+    }
+
+    fn stream_end(&self, _params: &StreamEndParams) -> Result<(), HookError> {
+        Ok(())
+    }
+
+    fn metadata(&self, _params: &MetadataParams) -> Result<(), HookError> {
+        Ok(())
+    }
+
+    fn duration_warning(&self, _params: &DurationWarningParams) -> Result<(), HookError> {
+        Ok(())
+    }
+
+    // Called as a source's slot in the schedule comes to an end.
+    fn slot_ending(&self, _params: &SlotEndingParams) -> Result<(), HookError> {
+        Ok(())
+    }
+
+    // Called when a source becomes unhealthy, and when it recovers.
+    fn source_health(&self, _params: &SourceHealthParams) -> Result<(), HookError> {
+        Ok(())
+    }
+
+    fn listener_connect(&self, _params: &ListenerParams) -> Result<(), HookError> {
+        Ok(())
+    }
+
+    fn listener_disconnect(&self, _params: &ListenerParams) -> Result<(), HookError> {
+        Ok(())
+    }
+
+    // Called when something goes wrong with a stream that an operator would
+    // want to know about, like a source crashing.
+    fn error(&self, _params: &ErrorParams) -> Result<(), HookError> {
+        Ok(())
+    }
+}
+
+// Made once, and given the new config whenever it's reloaded.
+pub struct HttpWebhooks {
+    config: RwLock<Arc<Config>>,
+}
+
+impl HttpWebhooks {
+    pub fn new(config: Arc<Config>) -> HttpWebhooks {
+        HttpWebhooks { config: RwLock::new(config) }
+    }
+
+    pub fn set_config(&self, config: Arc<Config>) {
+        *self.config.write().expect("writer lock on webhooks config") = config;
+    }
+
+    fn config(&self) -> Arc<Config> {
+        Arc::clone(&self.config.read().expect("reader lock on webhooks config"))
+    }
+}
+
+impl StreamLifecycleHandler for HttpWebhooks {
+    fn stream_start(&self, params: &StreamStartParams) -> Result<StreamStart, HookError> {
+        stream_start(&self.config(), params)
+    }
+
+    fn stream_end(&self, params: &StreamEndParams) -> Result<(), HookError> {
+        stream_end(&self.config(), params)
+    }
+
+    fn metadata(&self, params: &MetadataParams) -> Result<(), HookError> {
+        metadata(&self.config(), params)
+    }
+
+    fn duration_warning(&self, params: &DurationWarningParams) -> Result<(), HookError> {
+        duration_warning(&self.config(), params)
+    }
+
+    fn slot_ending(&self, params: &SlotEndingParams) -> Result<(), HookError> {
+        slot_ending(&self.config(), params)
+    }
+
+    fn source_health(&self, params: &SourceHealthParams) -> Result<(), HookError> {
+        source_health(&self.config(), params)
+    }
+
+    fn listener_connect(&self, params: &ListenerParams) -> Result<(), HookError> {
+        listener_connect(&self.config(), params)
+    }
+
+    fn listener_disconnect(&self, params: &ListenerParams) -> Result<(), HookError> {
+        listener_disconnect(&self.config(), params)
+    }
+
+    fn error(&self, params: &ErrorParams) -> Result<(), HookError> {
+        error(&self.config(), params)
+    }
+}
+
+pub type Handlers = Vec<Arc<StreamLifecycleHandler>>;
+
+// Asks every handler whether a source may start. Any rejection or error
+// stops it, and the highest priority given by any handler is used.
+pub fn start_stream(handlers: &Handlers, params: &StreamStartParams) -> Result<StreamStart, HookError> {
+    let mut priority = None;
+    let mut overrides = StreamOverrides::default();
+
+    // later handlers' overrides win:
+    for handler in handlers {
+        match handler.stream_start(params)? {
+            StreamStart::Ok { priority: handler_priority, overrides: handler_overrides } => {
+                if let Some(p) = handler_priority {
+                    priority = Some(priority.map_or(p, |current: u32| current.max(p)));
+                }
+
+                overrides.merge(handler_overrides);
+            }
+            StreamStart::Reject => return Ok(StreamStart::Reject),
+        }
+    }
+
+    Ok(StreamStart::Ok { priority, overrides })
+}
+
+// Passes an event to every handler, logging any that fail.
+pub fn notify<F>(handlers: &Handlers, event: &str, f: F)
+    where F: Fn(&StreamLifecycleHandler) -> Result<(), HookError>
+{
+    for handler in handlers {
+        if let Err(e) = f(&**handler) {
+            Log::new().error(&format!("{} hook failed: {:?}", event, e));
+        }
+    }
+}
@@ -0,0 +1,196 @@
+// Source auth against an htpasswd file, as made by Apache's htpasswd tool,
+// for stations that already keep their DJs' accounts in one. bcrypt ($2y$),
+// Apache MD5 ($apr1$) and SHA-1 ({SHA}) hashes are understood. The file is
+// read for every source, so accounts can be changed without a reload.
+
+use std::fs;
+use std::io;
+
+use base64;
+use bcrypt;
+use md5;
+use ring::digest;
+
+use admin;
+
+#[derive(Debug)]
+pub enum HtpasswdError {
+    Io(io::Error),
+    // the user's hash is in a format we can't check, like crypt():
+    UnsupportedHash(String),
+}
+
+const APR1_MAGIC: &'static str = "$apr1$";
+
+const ITOA64: &'static [u8] = b"./0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz";
+
+fn to64(out: &mut String, mut value: u32, chars: usize) {
+    for _ in 0..chars {
+        out.push(ITOA64[(value & 0x3f) as usize] as char);
+        value >>= 6;
+    }
+}
+
+// Apache's MD5 crypt, the htpasswd tool's default format.
+fn apr1(password: &[u8], salt: &[u8]) -> String {
+    let salt = &salt[..salt.len().min(8)];
+
+    let mut alt = md5::Context::new();
+    alt.consume(password);
+    alt.consume(salt);
+    alt.consume(password);
+    let alt = alt.compute();
+
+    let mut ctx = md5::Context::new();
+    ctx.consume(password);
+    ctx.consume(APR1_MAGIC.as_bytes());
+    ctx.consume(salt);
+
+    let mut remaining = password.len();
+
+    while remaining > 0 {
+        let n = remaining.min(16);
+        ctx.consume(&alt[..n]);
+        remaining -= n;
+    }
+
+    let mut bits = password.len();
+
+    while bits > 0 {
+        if bits & 1 == 1 {
+            ctx.consume(&[0]);
+        } else {
+            ctx.consume(&password[..1]);
+        }
+
+        bits >>= 1;
+    }
+
+    let mut digest = ctx.compute();
+
+    for i in 0..1000 {
+        let mut round = md5::Context::new();
+
+        if i & 1 == 1 {
+            round.consume(password);
+        } else {
+            round.consume(&digest[..]);
+        }
+
+        if i % 3 != 0 {
+            round.consume(salt);
+        }
+
+        if i % 7 != 0 {
+            round.consume(password);
+        }
+
+        if i & 1 == 1 {
+            round.consume(&digest[..]);
+        } else {
+            round.consume(password);
+        }
+
+        digest = round.compute();
+    }
+
+    let d = |i: usize| digest[i] as u32;
+
+    let mut out = format!("{}{}$", APR1_MAGIC, String::from_utf8_lossy(salt));
+    to64(&mut out, (d(0) << 16) | (d(6) << 8) | d(12), 4);
+    to64(&mut out, (d(1) << 16) | (d(7) << 8) | d(13), 4);
+    to64(&mut out, (d(2) << 16) | (d(8) << 8) | d(14), 4);
+    to64(&mut out, (d(3) << 16) | (d(9) << 8) | d(15), 4);
+    to64(&mut out, (d(4) << 16) | (d(10) << 8) | d(5), 4);
+    to64(&mut out, d(11), 2);
+    out
+}
+
+fn verify_hash(hash: &str, password: &str) -> Result<bool, HtpasswdError> {
+    if hash.starts_with("$2y$") || hash.starts_with("$2a$") || hash.starts_with("$2b$") {
+        Ok(bcrypt::verify(password, hash).unwrap_or(false))
+    } else if hash.starts_with(APR1_MAGIC) {
+        let salt = hash[APR1_MAGIC.len()..].split('$').next().unwrap_or("");
+        let expected = apr1(password.as_bytes(), salt.as_bytes());
+        Ok(admin::constant_time_eq(expected.as_bytes(), hash.as_bytes()))
+    } else if hash.starts_with("{SHA}") {
+        let digest = digest::digest(&digest::SHA1_FOR_LEGACY_USE_ONLY, password.as_bytes());
+        let expected = format!("{{SHA}}{}", base64::encode(digest.as_ref()));
+        Ok(admin::constant_time_eq(expected.as_bytes(), hash.as_bytes()))
+    } else {
+        // only say what kind of hash it is, not the hash itself:
+        Err(HtpasswdError::UnsupportedHash(hash.chars().take(4).collect()))
+    }
+}
+
+// Whether user's password is right according to the htpasswd file at path.
+pub fn check(path: &str, user: &str, password: &str) -> Result<bool, HtpasswdError> {
+    let contents = fs::read_to_string(path).map_err(HtpasswdError::Io)?;
+
+    let hash = contents.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .filter_map(|line| {
+            let mut parts = line.splitn(2, ':');
+            match (parts.next(), parts.next()) {
+                (Some(name), Some(hash)) if name == user => Some(hash),
+                _ => None,
+            }
+        })
+        .next();
+
+    match hash {
+        Some(hash) => verify_hash(hash, password),
+        None => Ok(false),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{apr1, verify_hash, HtpasswdError};
+
+    // made with `openssl passwd -apr1`, the same MD5 crypt as `htpasswd -m`:
+    #[test]
+    fn apr1_matches_htpasswd() {
+        assert_eq!(apr1(b"hackme", b"r31...."), "$apr1$r31....$0CvtbaJxv3SVfI4hUsLQI/");
+        assert_eq!(apr1(b"a", b"x"), "$apr1$x$16j9.5e7KiXmuYFAYpPJM/");
+
+        // longer than one MD5 digest, so the alternate sum is taken twice:
+        assert_eq!(apr1(b"correct horse battery staple", b"abcdefgh"),
+            "$apr1$abcdefgh$5MT.bUwCwhCf2blpqTwiG/");
+    }
+
+    #[test]
+    fn verifies_apr1() {
+        let hash = "$apr1$abcdefgh$5MT.bUwCwhCf2blpqTwiG/";
+
+        assert!(verify_hash(hash, "correct horse battery staple").unwrap());
+        assert!(!verify_hash(hash, "correct horse battery stapler").unwrap());
+        assert!(!verify_hash(hash, "").unwrap());
+    }
+
+    #[test]
+    fn verifies_sha() {
+        let hash = "{SHA}zJWX0x8FA73tXfMQ618o+01J+w8=";
+
+        assert!(verify_hash(hash, "hackme").unwrap());
+        assert!(!verify_hash(hash, "hackm").unwrap());
+    }
+
+    // from PHP's password_verify documentation, as htpasswd -B makes $2y$:
+    #[test]
+    fn verifies_bcrypt() {
+        let hash = "$2y$07$BCryptRequires22Chrcte/VlQH0piJtjXl.0t1XkA8pw9dMXTpOq";
+
+        assert!(verify_hash(hash, "rasmuslerdorf").unwrap());
+        assert!(!verify_hash(hash, "rasmuslerdorF").unwrap());
+    }
+
+    #[test]
+    fn rejects_crypt() {
+        match verify_hash("rl0uy1dzmBMTs", "hackme") {
+            Err(HtpasswdError::UnsupportedHash(kind)) => assert_eq!(kind, "rl0u"),
+            other => panic!("expected an unsupported hash, got {:?}", other),
+        }
+    }
+}
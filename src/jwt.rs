@@ -0,0 +1,296 @@
+// JSON Web Tokens for listener and source auth, so a station's website or
+// auth service can hand out tokens and rustcast can check them by itself,
+// rather than calling a webhook for every connection.
+//
+// Tokens are signed with HS256 using the configured shared secret, or with
+// RS256 or ES256 using a key from the configured JWKS URL. The mountpoints
+// a token may listen to or broadcast on are listed in its claims, as
+// mountpoint prefixes like API key mounts. HS256 uses the same HMAC as
+// upload signing, ring is only needed for the public key algorithms.
+
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use base64;
+use chrono::Utc;
+use reqwest::{self, Client};
+use ring::signature;
+use serde::de::DeserializeOwned;
+use serde_json::{self, Map, Value};
+use tiny_http::Request;
+
+use admin;
+use config::Jwt;
+use mountpoint;
+use query::Query;
+use upload;
+
+// how long keys fetched from the JWKS URL are used for:
+const JWKS_MAX_AGE: Duration = Duration::from_secs(3600);
+
+// a token signed with a key we don't have refetches the keys, in case
+// they've been rotated, but no more often than this:
+const JWKS_MIN_REFETCH: Duration = Duration::from_secs(60);
+
+// seconds of clock skew allowed on exp and nbf:
+const LEEWAY: i64 = 60;
+
+#[derive(Debug)]
+pub enum JwtError {
+    Malformed,
+    // alg isn't supported, or there's no secret or JWKS URL to check it with:
+    Algorithm(String),
+    UnknownKey,
+    Jwks(String),
+    Signature,
+    Expired,
+    NotYetValid,
+    Issuer,
+    Audience,
+}
+
+#[derive(Deserialize)]
+struct JoseHeader {
+    alg: String,
+    kid: Option<String>,
+}
+
+#[derive(Deserialize, Clone)]
+struct Jwk {
+    kty: String,
+    kid: Option<String>,
+    // RSA keys:
+    n: Option<String>,
+    e: Option<String>,
+    // EC keys:
+    crv: Option<String>,
+    x: Option<String>,
+    y: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct JwkSet {
+    keys: Vec<Jwk>,
+}
+
+struct CachedKeys {
+    url: String,
+    fetched: Instant,
+    keys: Vec<Jwk>,
+}
+
+pub struct Claims {
+    claims: Map<String, Value>,
+}
+
+impl Claims {
+    // Whether the mountpoint prefixes in claim cover mountpoint. The claim
+    // can be a single prefix or a list of them.
+    pub fn allows(&self, claim: &str, mountpoint: &str) -> bool {
+        match self.claims.get(claim) {
+            Some(&Value::String(ref prefix)) => mountpoint::is_within(mountpoint, prefix),
+            Some(&Value::Array(ref prefixes)) => prefixes.iter()
+                .filter_map(Value::as_str)
+                .any(|prefix| mountpoint::is_within(mountpoint, prefix)),
+            _ => false,
+        }
+    }
+
+    // The priority a source with this token broadcasts at, see SourceUser.
+    pub fn priority(&self) -> Option<u32> {
+        self.claims.get("priority")
+            .and_then(Value::as_u64)
+            .map(|priority| priority as u32)
+    }
+}
+
+fn decode(part: &str) -> Result<Vec<u8>, JwtError> {
+    base64::decode_config(part, base64::URL_SAFE_NO_PAD)
+        .map_err(|_| JwtError::Malformed)
+}
+
+fn decode_json<T: DeserializeOwned>(part: &str) -> Result<T, JwtError> {
+    serde_json::from_slice(&decode(part)?)
+        .map_err(|_| JwtError::Malformed)
+}
+
+// The token on a request, as a bearer token or in the configured query
+// parameter.
+pub fn token_from_request(config: &Jwt, req: &Request, query: &Query) -> Option<String> {
+    admin::bearer_token(req.headers())
+        .or_else(|| query.get(&config.query_param).map(str::to_owned))
+}
+
+fn verify_with_jwk(jwk: &Jwk, alg: &str, message: &[u8], sig: &[u8]) -> Result<(), JwtError> {
+    let result = match (alg, jwk.kty.as_str()) {
+        ("RS256", "RSA") => {
+            let (n, e) = match (jwk.n.as_ref(), jwk.e.as_ref()) {
+                (Some(n), Some(e)) => (decode(n)?, decode(e)?),
+                _ => return Err(JwtError::Jwks("RSA key without n and e".to_owned())),
+            };
+
+            signature::RsaPublicKeyComponents { n: &n[..], e: &e[..] }
+                .verify(&signature::RSA_PKCS1_2048_8192_SHA256, message, sig)
+        }
+        ("ES256", "EC") => {
+            if jwk.crv.as_ref().map(String::as_str) != Some("P-256") {
+                return Err(JwtError::Algorithm(alg.to_owned()));
+            }
+
+            let (x, y) = match (jwk.x.as_ref(), jwk.y.as_ref()) {
+                (Some(x), Some(y)) => (decode(x)?, decode(y)?),
+                _ => return Err(JwtError::Jwks("EC key without x and y".to_owned())),
+            };
+
+            // uncompressed point:
+            let mut point = vec![0x04];
+            point.extend(x);
+            point.extend(y);
+
+            signature::UnparsedPublicKey::new(&signature::ECDSA_P256_SHA256_FIXED, point)
+                .verify(message, sig)
+        }
+        _ => return Err(JwtError::Algorithm(alg.to_owned())),
+    };
+
+    result.map_err(|_| JwtError::Signature)
+}
+
+fn fetch_keys(url: &str) -> Result<CachedKeys, JwtError> {
+    let mut response = Client::new()
+        .get(url)
+        .send()
+        .map_err(|e| JwtError::Jwks(format!("{}", e)))?;
+
+    if !response.status().is_success() {
+        return Err(JwtError::Jwks(format!("{}", response.status())));
+    }
+
+    let set = response.json::<JwkSet>()
+        .map_err(|e: reqwest::Error| JwtError::Jwks(format!("{}", e)))?;
+
+    Ok(CachedKeys {
+        url: url.to_owned(),
+        fetched: Instant::now(),
+        keys: set.keys,
+    })
+}
+
+fn find_key(keys: &[Jwk], kid: Option<&str>, kty: &str) -> Option<Jwk> {
+    keys.iter()
+        .filter(|key| key.kty == kty)
+        .find(|key| kid.is_none() || key.kid.as_ref().map(String::as_str) == kid)
+        .cloned()
+}
+
+pub struct Validator {
+    jwks: Mutex<Option<CachedKeys>>,
+}
+
+impl Validator {
+    pub fn new() -> Validator {
+        Validator {
+            jwks: Mutex::new(None),
+        }
+    }
+
+    fn key(&self, url: &str, kid: Option<&str>, kty: &str) -> Result<Jwk, JwtError> {
+        {
+            let cache = self.jwks.lock().expect("lock on jwks");
+
+            if let Some(ref cached) = *cache {
+                if cached.url == url && cached.fetched.elapsed() < JWKS_MAX_AGE {
+                    if let Some(key) = find_key(&cached.keys, kid, kty) {
+                        return Ok(key);
+                    }
+
+                    if cached.fetched.elapsed() < JWKS_MIN_REFETCH {
+                        return Err(JwtError::UnknownKey);
+                    }
+                }
+            }
+        }
+
+        // fetched without the lock, so a slow JWKS URL only holds up tokens
+        // that need it fetched, not ones the cached keys can check:
+        let fetched = fetch_keys(url)?;
+        let key = find_key(&fetched.keys, kid, kty);
+
+        *self.jwks.lock().expect("lock on jwks") = Some(fetched);
+
+        key.ok_or(JwtError::UnknownKey)
+    }
+
+    // Checks token's signature, expiry, issuer and audience, returning its
+    // claims.
+    pub fn validate(&self, config: &Jwt, token: &str) -> Result<Claims, JwtError> {
+        let parts = token.split('.').collect::<Vec<_>>();
+
+        if parts.len() != 3 {
+            return Err(JwtError::Malformed);
+        }
+
+        let header = decode_json::<JoseHeader>(parts[0])?;
+        let signed = &token[..parts[0].len() + 1 + parts[1].len()];
+        let sig = decode(parts[2])?;
+
+        match header.alg.as_str() {
+            "HS256" => {
+                let secret = config.secret.as_ref()
+                    .ok_or_else(|| JwtError::Algorithm(header.alg.clone()))?;
+
+                let expected = upload::hmac_sha256(secret.as_bytes(), signed);
+
+                if !admin::constant_time_eq(&expected, &sig) {
+                    return Err(JwtError::Signature);
+                }
+            }
+            "RS256" | "ES256" => {
+                let url = config.jwks_url.as_ref()
+                    .ok_or_else(|| JwtError::Algorithm(header.alg.clone()))?;
+
+                let kty = if header.alg == "RS256" { "RSA" } else { "EC" };
+                let key = self.key(url, header.kid.as_ref().map(String::as_str), kty)?;
+
+                verify_with_jwk(&key, &header.alg, signed.as_bytes(), &sig)?;
+            }
+            // including "none":
+            _ => return Err(JwtError::Algorithm(header.alg)),
+        }
+
+        let claims = Claims { claims: decode_json(parts[1])? };
+        let now = Utc::now().timestamp();
+
+        if let Some(exp) = claims.claims.get("exp").and_then(Value::as_i64) {
+            if now > exp + LEEWAY {
+                return Err(JwtError::Expired);
+            }
+        }
+
+        if let Some(nbf) = claims.claims.get("nbf").and_then(Value::as_i64) {
+            if now + LEEWAY < nbf {
+                return Err(JwtError::NotYetValid);
+            }
+        }
+
+        if let Some(ref issuer) = config.issuer {
+            if claims.claims.get("iss").and_then(Value::as_str) != Some(issuer.as_str()) {
+                return Err(JwtError::Issuer);
+            }
+        }
+
+        if let Some(ref audience) = config.audience {
+            let matches = match claims.claims.get("aud") {
+                Some(&Value::String(ref aud)) => aud == audience,
+                Some(&Value::Array(ref auds)) => auds.iter().any(|aud| aud.as_str() == Some(audience.as_str())),
+                _ => false,
+            };
+
+            if !matches {
+                return Err(JwtError::Audience);
+            }
+        }
+
+        Ok(claims)
+    }
+}
@@ -0,0 +1,259 @@
+// Source auth by binding to an LDAP directory as the source's user, for
+// stations that already keep their DJs' accounts in one. A simple bind is all
+// that's needed, so it's done here in a few BER messages rather than with a
+// full LDAP client, and every step times out after TIMEOUT so a directory
+// that's gone quiet can't hold sources up for long.
+
+use std::io::{self, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::time::Duration;
+
+use native_tls::TlsConnector;
+
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+// the longest bind response read, which only holds a result code and a
+// message:
+const MAX_RESPONSE_LENGTH: usize = 64 * 1024;
+
+// BER tags of the parts of a bind the client sends and reads:
+const INTEGER: u8 = 0x02;
+const OCTET_STRING: u8 = 0x04;
+const ENUMERATED: u8 = 0x0a;
+const SEQUENCE: u8 = 0x30;
+const UNBIND_REQUEST: u8 = 0x42;
+const BIND_REQUEST: u8 = 0x60;
+const BIND_RESPONSE: u8 = 0x61;
+const SIMPLE_AUTH: u8 = 0x80;
+
+// result codes:
+const SUCCESS: u32 = 0;
+const INVALID_CREDENTIALS: u32 = 49;
+
+// Escapes a value for use in a DN, per RFC 4514, so a user name can't
+// change which entry is bound to.
+fn escape_dn_value(value: &str) -> String {
+    let mut escaped = String::new();
+
+    for (i, c) in value.chars().enumerate() {
+        let special = match c {
+            ',' | '+' | '"' | '\\' | '<' | '>' | ';' | '=' => true,
+            '#' | ' ' if i == 0 => true,
+            ' ' if i == value.chars().count() - 1 => true,
+            _ => false,
+        };
+
+        if c == '\0' {
+            escaped.push_str("\\00");
+        } else {
+            if special {
+                escaped.push('\\');
+            }
+
+            escaped.push(c);
+        }
+    }
+
+    escaped
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("ldap: {}", message))
+}
+
+fn encode(tag: u8, contents: &[u8]) -> Vec<u8> {
+    let mut buf = vec![tag];
+
+    if contents.len() < 0x80 {
+        buf.push(contents.len() as u8);
+    } else {
+        let mut length = Vec::new();
+        let mut rest = contents.len();
+
+        while rest > 0 {
+            length.insert(0, rest as u8);
+            rest >>= 8;
+        }
+
+        buf.push(0x80 | length.len() as u8);
+        buf.extend_from_slice(&length);
+    }
+
+    buf.extend_from_slice(contents);
+    buf
+}
+
+fn message(id: u8, op: &[u8]) -> Vec<u8> {
+    let mut body = encode(INTEGER, &[id]);
+    body.extend_from_slice(op);
+    encode(SEQUENCE, &body)
+}
+
+fn bind_request(dn: &str, password: &str) -> Vec<u8> {
+    let mut bind = encode(INTEGER, &[3]); // protocol version
+    bind.extend_from_slice(&encode(OCTET_STRING, dn.as_bytes()));
+    bind.extend_from_slice(&encode(SIMPLE_AUTH, password.as_bytes()));
+
+    message(1, &encode(BIND_REQUEST, &bind))
+}
+
+fn read_byte<R: Read>(reader: &mut R) -> io::Result<u8> {
+    let mut byte = [0u8];
+    reader.read_exact(&mut byte)?;
+    Ok(byte[0])
+}
+
+// Reads one BER element, returning its tag and contents.
+fn read_element<R: Read>(reader: &mut R) -> io::Result<(u8, Vec<u8>)> {
+    let tag = read_byte(reader)?;
+    let first = read_byte(reader)?;
+
+    let length = if first & 0x80 == 0 {
+        first as usize
+    } else {
+        let count = first & 0x7f;
+
+        if count == 0 || count > 4 {
+            return Err(invalid("unsupported length encoding"));
+        }
+
+        let mut length = 0;
+
+        for _ in 0..count {
+            length = length << 8 | read_byte(reader)? as usize;
+        }
+
+        length
+    };
+
+    if length > MAX_RESPONSE_LENGTH {
+        return Err(invalid("response too long"));
+    }
+
+    let mut contents = vec![0; length];
+    reader.read_exact(&mut contents)?;
+
+    Ok((tag, contents))
+}
+
+// Reads the bind response, returning its result code and diagnostic
+// message.
+fn read_bind_response<R: Read>(reader: &mut R) -> io::Result<(u32, String)> {
+    let (tag, message) = read_element(reader)?;
+
+    if tag != SEQUENCE {
+        return Err(invalid("expected an LDAP message"));
+    }
+
+    let mut message = &message[..];
+
+    match read_element(&mut message)? {
+        (INTEGER, ref id) if id[..] == [1] => (),
+        _ => return Err(invalid("expected the bind's message id")),
+    }
+
+    let (tag, response) = read_element(&mut message)?;
+
+    if tag != BIND_RESPONSE {
+        return Err(invalid("expected a bind response"));
+    }
+
+    let mut response = &response[..];
+
+    let code = match read_element(&mut response)? {
+        (ENUMERATED, ref code) if !code.is_empty() && code.len() <= 4 =>
+            code.iter().fold(0, |code, &byte| code << 8 | byte as u32),
+        _ => return Err(invalid("expected a result code")),
+    };
+
+    // the matched DN, then the diagnostic message:
+    read_element(&mut response)?;
+
+    let text = match read_element(&mut response)? {
+        (OCTET_STRING, ref text) => String::from_utf8_lossy(text).into_owned(),
+        _ => String::new(),
+    };
+
+    Ok((code, text))
+}
+
+fn bind<S: Read + Write>(mut stream: S, dn: &str, password: &str) -> io::Result<(u32, String)> {
+    stream.write_all(&bind_request(dn, password))?;
+    let result = read_bind_response(&mut stream)?;
+
+    // the server closes the connection itself if this doesn't get there:
+    let _ = stream.write_all(&message(2, &encode(UNBIND_REQUEST, &[])));
+
+    Ok(result)
+}
+
+// The host and port an ldap:// or ldaps:// URL points to, and whether it's
+// ldaps://.
+fn parse_url(url: &str) -> io::Result<(String, u16, bool)> {
+    let (rest, tls, default_port) = if url.starts_with("ldaps://") {
+        (&url["ldaps://".len()..], true, 636)
+    } else if url.starts_with("ldap://") {
+        (&url["ldap://".len()..], false, 389)
+    } else {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "not an ldap:// or ldaps:// URL"));
+    };
+
+    let authority = rest.split('/').next().unwrap_or("");
+
+    // IPv6 addresses are bracketed, so their colons aren't taken for a port:
+    let (host, port) = match authority.rfind(':') {
+        Some(colon) if !authority[colon..].contains(']') => {
+            let port = authority[colon + 1..].parse()
+                .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "invalid port"))?;
+            (&authority[..colon], port)
+        }
+        _ => (authority, default_port),
+    };
+
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+
+    Ok((host.to_owned(), port, tls))
+}
+
+// Whether user can bind to the directory at url with password, where
+// bind_dn is a template like "uid={user},ou=djs,dc=example,dc=org".
+pub fn check(url: &str, bind_dn: &str, user: &str, password: &str) -> io::Result<bool> {
+    // an empty password makes an unauthenticated bind, which servers let
+    // anyone do:
+    if user.is_empty() || password.is_empty() {
+        return Ok(false);
+    }
+
+    let dn = bind_dn.replace("{user}", &escape_dn_value(user));
+
+    let (host, port, tls) = parse_url(url)?;
+
+    let addr = (host.as_str(), port).to_socket_addrs()?.next()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::Other, "directory address didn't resolve"))?;
+
+    let stream = TcpStream::connect_timeout(&addr, TIMEOUT)?;
+    stream.set_read_timeout(Some(TIMEOUT))?;
+    stream.set_write_timeout(Some(TIMEOUT))?;
+
+    let (code, text) = if tls {
+        let connector = TlsConnector::builder()
+            .and_then(|builder| builder.build())
+            .map_err(|e| io::Error::new(io::ErrorKind::Other, format!("ldaps: {}", e)))?;
+
+        let stream = connector.connect(&host, stream)
+            .map_err(|e| io::Error::new(io::ErrorKind::Other, format!("ldaps: {}", e)))?;
+
+        bind(stream, &dn, password)?
+    } else {
+        bind(stream, &dn, password)?
+    };
+
+    // anything but success or wrong credentials is the server having
+    // trouble:
+    match code {
+        SUCCESS => Ok(true),
+        INVALID_CREDENTIALS => Ok(false),
+        code => Err(io::Error::new(io::ErrorKind::Other,
+            format!("ldap bind failed with result code {}: {}", code, text))),
+    }
+}
@@ -0,0 +1,101 @@
+extern crate base64;
+extern crate bcrypt;
+extern crate chrono;
+extern crate glob;
+extern crate lame;
+extern crate lewton;
+extern crate libc;
+extern crate md5;
+extern crate native_tls;
+extern crate reqwest;
+extern crate ring;
+extern crate rusqlite;
+extern crate serde;
+extern crate serde_json;
+extern crate tiny_http;
+extern crate toml;
+extern crate uuid;
+
+#[cfg(windows)]
+extern crate winapi;
+#[cfg(windows)]
+#[macro_use]
+extern crate windows_service;
+
+#[macro_use]
+extern crate serde_derive;
+
+#[cfg(feature = "aac")]
+mod aac;
+mod admin;
+mod archive;
+mod audio;
+mod burst;
+mod catchup;
+mod chat;
+mod cluster;
+pub mod config;
+mod cookie;
+mod cors;
+#[cfg(unix)]
+pub mod daemon;
+mod dashboard;
+mod dedup;
+mod encoder;
+mod events;
+mod fanout;
+mod health;
+mod heartbeat;
+mod history;
+mod hooks;
+mod htpasswd;
+mod jwt;
+mod ldap;
+mod limit;
+mod listeners;
+mod lockout;
+pub mod loadtest;
+mod log;
+mod metrics;
+mod mountpoint;
+mod mp3;
+mod mqtt;
+mod oembed;
+mod ogg;
+mod pace;
+mod picture;
+mod pipe;
+mod player;
+mod players;
+mod playlist;
+mod proxy;
+mod query;
+mod royalty;
+mod schedule;
+mod server;
+#[cfg(windows)]
+pub mod service;
+mod shm;
+#[cfg(unix)]
+pub mod signal;
+mod silence;
+#[cfg(unix)]
+mod sockopt;
+mod source;
+mod sourcehealth;
+mod stats;
+mod statsd;
+mod tee;
+mod timeshift;
+mod transfer;
+mod upload;
+mod useragent;
+mod yp;
+
+pub use audio::{Metadata, PcmData};
+pub use config::Config;
+pub use fanout::{Channel, CloseReason, Receiver};
+pub use hooks::{ErrorParams, HookError, ListenerParams, MetadataParams, DurationWarningParams, StreamEndParams, SlotEndingParams, SourceHealthParams, StreamLifecycleHandler, StreamOverrides, StreamStart, StreamStartParams};
+pub use server::{start, Frame, Handle, InputFormat, Pending, Rustcast, Stream, StreamData, StreamEntry, StreamSource, StartStreamError};
+pub use source::{Mp3Source, PcmFormat, PcmSource};
+pub use sourcehealth::SourceStats;
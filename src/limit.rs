@@ -0,0 +1,68 @@
+use audio::PcmData;
+use config::MountConfig;
+
+const DEFAULT_WARNING: u64 = 300;
+const DEFAULT_FADE_OUT: u64 = 5;
+
+pub enum LimitAction {
+    Continue,
+    // seconds remaining until the stream is stopped:
+    Warn(u64),
+    Stop,
+}
+
+// Enforces a mountpoint's max_duration: warns ahead of time, then fades the
+// audio out and stops the stream. Positions are in seconds of audio rather
+// than wall clock time, so stalls on the source connection don't eat into
+// the fade.
+pub struct DurationLimit {
+    max: f64,
+    warn_at: f64,
+    fade_from: f64,
+    warned: bool,
+}
+
+impl DurationLimit {
+    pub fn new(mount: &MountConfig) -> Option<DurationLimit> {
+        let max = match mount.max_duration {
+            Some(max) => max as f64,
+            None => return None,
+        };
+
+        let warning = mount.max_duration_warning.unwrap_or(DEFAULT_WARNING) as f64;
+        let fade_out = mount.fade_out.unwrap_or(DEFAULT_FADE_OUT) as f64;
+
+        Some(DurationLimit {
+            max,
+            warn_at: (max - warning).max(0.0),
+            fade_from: (max - fade_out).max(0.0),
+            warned: false,
+        })
+    }
+
+    // position is the time in seconds at which packet starts
+    pub fn process(&mut self, position: f64, sample_rate: u32, packet: &mut PcmData) -> LimitAction {
+        if position >= self.max {
+            return LimitAction::Stop;
+        }
+
+        if position >= self.fade_from {
+            let fade_len = self.max - self.fade_from;
+
+            for channel in packet.iter_mut() {
+                for (index, sample) in channel.iter_mut().enumerate() {
+                    let time = position + index as f64 / sample_rate as f64;
+                    let gain = ((self.max - time) / fade_len).max(0.0).min(1.0);
+                    *sample = (*sample as f64 * gain) as i16;
+                }
+            }
+        }
+
+        if !self.warned && position >= self.warn_at {
+            self.warned = true;
+            return LimitAction::Warn((self.max - position).ceil() as u64);
+        }
+
+        LimitAction::Continue
+    }
+}
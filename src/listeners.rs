@@ -0,0 +1,140 @@
+// Who's listening to what right now, for the admin API to list and kick
+// listeners one at a time. Listeners are numbered from 1 in the order they
+// connect, starting over on restart.
+
+use std::collections::BTreeMap;
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::time::Instant;
+
+use fanout::{CloseReason, Closer};
+
+struct Listener {
+    mountpoint: String,
+    ip: IpAddr,
+    user_agent: Option<String>,
+    player: &'static str,
+    connected: Instant,
+    // ends the listener's subscription to the stream it's on, if it's on one
+    // rather than waiting for a source:
+    closer: Option<Closer>,
+    kicked: bool,
+}
+
+#[derive(Serialize)]
+pub struct ListenerJson {
+    pub id: u64,
+    pub ip: String,
+    pub user_agent: Option<String>,
+    pub player: &'static str,
+    pub seconds: u64,
+}
+
+pub struct Listeners {
+    next_id: Mutex<u64>,
+    listeners: Mutex<BTreeMap<u64, Listener>>,
+}
+
+impl Listeners {
+    pub fn new() -> Listeners {
+        Listeners {
+            next_id: Mutex::new(1),
+            listeners: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    // Returns the listener's id.
+    pub fn connect(&self, mountpoint: &str, ip: IpAddr, user_agent: Option<String>, player: &'static str) -> u64 {
+        let id = {
+            let mut next_id = self.next_id.lock().expect("lock on next listener id");
+            let id = *next_id;
+            *next_id += 1;
+            id
+        };
+
+        self.listeners.lock()
+            .expect("lock on listeners")
+            .insert(id, Listener {
+                mountpoint: mountpoint.to_owned(),
+                ip,
+                user_agent,
+                player,
+                connected: Instant::now(),
+                closer: None,
+                kicked: false,
+            });
+
+        id
+    }
+
+    // The listener has subscribed to a stream, which closer ends. Returns
+    // false if it's been kicked in the meantime, and shouldn't carry on.
+    pub fn listening(&self, id: u64, closer: Closer) -> bool {
+        match self.listeners.lock().expect("lock on listeners").get_mut(&id) {
+            Some(listener) => {
+                listener.closer = Some(closer);
+                !listener.kicked
+            }
+            None => false,
+        }
+    }
+
+    // Whether the listener's been kicked, for listeners that aren't
+    // subscribed to a stream to be closed through.
+    pub fn kicked(&self, id: u64) -> bool {
+        self.listeners.lock()
+            .expect("lock on listeners")
+            .get(&id)
+            .map(|listener| listener.kicked)
+            .unwrap_or(false)
+    }
+
+    pub fn disconnect(&self, id: u64) {
+        self.listeners.lock()
+            .expect("lock on listeners")
+            .remove(&id);
+    }
+
+    pub fn list(&self, mountpoint: &str) -> Vec<ListenerJson> {
+        self.listeners.lock()
+            .expect("lock on listeners")
+            .iter()
+            .filter(|&(_, listener)| listener.mountpoint == mountpoint && !listener.kicked)
+            .map(|(&id, listener)| ListenerJson {
+                id,
+                ip: listener.ip.to_string(),
+                user_agent: listener.user_agent.clone(),
+                player: listener.player,
+                seconds: listener.connected.elapsed().as_secs(),
+            })
+            .collect()
+    }
+
+    // How many listeners mountpoint has, not counting any being kicked.
+    pub fn count(&self, mountpoint: &str) -> usize {
+        self.listeners.lock()
+            .expect("lock on listeners")
+            .values()
+            .filter(|listener| listener.mountpoint == mountpoint && !listener.kicked)
+            .count()
+    }
+
+    // Disconnects a listener to mountpoint. Returns whether there was one
+    // with that id.
+    pub fn kick(&self, mountpoint: &str, id: u64) -> bool {
+        let mut listeners = self.listeners.lock().expect("lock on listeners");
+
+        let listener = match listeners.get_mut(&id) {
+            Some(listener) if listener.mountpoint == mountpoint && !listener.kicked => listener,
+            _ => return false,
+        };
+
+        listener.kicked = true;
+
+        if let Some(ref closer) = listener.closer {
+            closer.close(CloseReason::Kicked);
+        }
+
+        true
+    }
+}
@@ -0,0 +1,142 @@
+// Simulates many listeners on a running rustcast, for `rustcast loadtest`,
+// so changes to the fanout and listener write path can be measured against
+// a real server rather than guessed at.
+
+use std::io::Read;
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use reqwest::Client;
+
+// listeners connect this far apart, so the server isn't measured on how
+// fast it accepts a stampede:
+const CONNECT_INTERVAL: Duration = Duration::from_millis(5);
+
+const READ_BUFFER: usize = 8192;
+
+struct ListenerResult {
+    // how long until the first audio arrived, if any did:
+    first_byte: Option<Duration>,
+    bytes: u64,
+    // whether it was still connected when the test ended:
+    stayed: bool,
+    error: Option<String>,
+}
+
+pub struct Report {
+    pub listeners: usize,
+    pub duration: Duration,
+    // listeners that couldn't connect at all:
+    pub failed: usize,
+    // listeners disconnected before the end:
+    pub dropped: usize,
+    pub bytes: u64,
+    pub first_byte_median: Option<Duration>,
+    pub first_byte_max: Option<Duration>,
+    // one of the errors listeners hit, to go on:
+    pub example_error: Option<String>,
+}
+
+impl Report {
+    // Average rate each listener received audio at.
+    pub fn kilobits_per_listener(&self) -> f64 {
+        let secs = secs(self.duration);
+
+        if self.listeners == 0 || secs == 0.0 {
+            return 0.0;
+        }
+
+        self.bytes as f64 * 8.0 / 1000.0 / secs / self.listeners as f64
+    }
+}
+
+fn secs(duration: Duration) -> f64 {
+    duration.as_secs() as f64 + duration.subsec_nanos() as f64 / 1e9
+}
+
+fn listen(client: &Client, url: &str, until: Instant) -> ListenerResult {
+    let started = Instant::now();
+
+    let mut result = ListenerResult {
+        first_byte: None,
+        bytes: 0,
+        stayed: false,
+        error: None,
+    };
+
+    let mut response = match client.get(url).send() {
+        Ok(response) => response,
+        Err(e) => {
+            result.error = Some(format!("{}", e));
+            return result;
+        }
+    };
+
+    if !response.status().is_success() {
+        result.error = Some(format!("status {}", response.status()));
+        return result;
+    }
+
+    let mut buffer = [0u8; READ_BUFFER];
+
+    while Instant::now() < until {
+        match response.read(&mut buffer) {
+            Ok(0) => return result,
+            Ok(n) => {
+                if result.first_byte.is_none() {
+                    result.first_byte = Some(started.elapsed());
+                }
+
+                result.bytes += n as u64;
+            }
+            Err(e) => {
+                result.error = Some(format!("{}", e));
+                return result;
+            }
+        }
+    }
+
+    result.stayed = true;
+    result
+}
+
+// Connects listeners to url, one after another, and has each of them
+// listen until duration after the first connected.
+pub fn run(url: &str, listeners: usize, duration: Duration) -> Report {
+    let until = Instant::now() + duration;
+    let (tx, rx) = mpsc::channel();
+
+    for _ in 0..listeners {
+        let tx = tx.clone();
+        let url = url.to_owned();
+
+        thread::spawn(move || {
+            let client = Client::new();
+            let _ = tx.send(listen(&client, &url, until));
+        });
+
+        thread::sleep(CONNECT_INTERVAL);
+    }
+
+    drop(tx);
+
+    let results = rx.iter().collect::<Vec<_>>();
+
+    let mut first_bytes = results.iter()
+        .filter_map(|result| result.first_byte)
+        .collect::<Vec<_>>();
+
+    first_bytes.sort();
+
+    Report {
+        listeners,
+        duration,
+        failed: results.iter().filter(|result| result.first_byte.is_none()).count(),
+        dropped: results.iter().filter(|result| result.first_byte.is_some() && !result.stayed).count(),
+        bytes: results.iter().map(|result| result.bytes).sum(),
+        first_byte_median: first_bytes.get(first_bytes.len() / 2).cloned(),
+        first_byte_max: first_bytes.last().cloned(),
+        example_error: results.into_iter().filter_map(|result| result.error).next(),
+    }
+}
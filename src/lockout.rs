@@ -0,0 +1,77 @@
+// Bans addresses that keep getting source passwords wrong for a while, as
+// set by source_lockout, since anything listening on an Icecast port soon
+// gets brute-forced. Like Dedup this is only kept in memory, so starts over
+// on restart.
+
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use config::SourceLockout;
+
+struct Failures {
+    // wrong passwords in the window starting at since:
+    count: u32,
+    since: Instant,
+    banned_until: Option<Instant>,
+}
+
+pub struct Lockout {
+    addresses: Mutex<HashMap<IpAddr, Failures>>,
+}
+
+impl Lockout {
+    pub fn new() -> Lockout {
+        Lockout {
+            addresses: Mutex::new(HashMap::new()),
+        }
+    }
+
+    // Whether ip is banned right now.
+    pub fn banned(&self, ip: IpAddr) -> bool {
+        let now = Instant::now();
+
+        self.addresses.lock()
+            .expect("lock on source lockout")
+            .get(&ip)
+            .and_then(|failures| failures.banned_until)
+            .map(|until| until > now)
+            .unwrap_or(false)
+    }
+
+    // Counts a wrong password from ip. Returns whether that's got it banned.
+    pub fn fail(&self, ip: IpAddr, config: &SourceLockout) -> bool {
+        let now = Instant::now();
+        let window = Duration::from_secs(config.window);
+        let mut addresses = self.addresses.lock().expect("lock on source lockout");
+
+        // forget anyone who's given up, so this doesn't grow forever:
+        addresses.retain(|_, failures| match failures.banned_until {
+            Some(until) => until > now,
+            None => now.duration_since(failures.since) < window,
+        });
+
+        let failures = addresses.entry(ip).or_insert(Failures {
+            count: 0,
+            since: now,
+            banned_until: None,
+        });
+
+        failures.count += 1;
+
+        if failures.count < config.max_failures || failures.banned_until.is_some() {
+            return false;
+        }
+
+        failures.banned_until = Some(now + Duration::from_secs(config.ban));
+        true
+    }
+
+    // A source from ip got its password right, so it starts over.
+    pub fn succeed(&self, ip: IpAddr) {
+        self.addresses.lock()
+            .expect("lock on source lockout")
+            .remove(&ip);
+    }
+}
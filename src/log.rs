@@ -1,14 +1,50 @@
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+
 use chrono::Local;
 
-pub struct Log;
+pub struct Log {
+    // the last lines logged, for logs that keep them:
+    recent: Option<Arc<Mutex<VecDeque<LogLine>>>>,
+    // how many of them are kept:
+    capacity: usize,
+}
+
+// A line logged, as kept for the admin page.
+#[derive(Serialize, Clone)]
+pub struct LogLine {
+    pub level: &'static str,
+    pub time: String,
+    pub message: String,
+}
 
 impl Log {
     pub fn new() -> Self {
-        Log
+        Log { recent: None, capacity: 0 }
     }
 
-    fn emit(&self, level: &str, msg: &str) {
-        println!("{:5} [{}] {}", level, Local::now(), msg);
+    // A log that keeps the last lines logged through it, for recent to
+    // return.
+    pub fn keeping_recent(lines: usize) -> Self {
+        Log {
+            recent: Some(Arc::new(Mutex::new(VecDeque::with_capacity(lines)))),
+            capacity: lines,
+        }
+    }
+
+    fn emit(&self, level: &'static str, msg: &str) {
+        let now = Local::now();
+
+        println!("{:5} [{}] {}", level, now, msg);
+
+        self.remember(LogLine {
+            level,
+            time: now.to_rfc3339(),
+            message: msg.to_owned(),
+        });
+
+        #[cfg(windows)]
+        event_log::report(level == "ERROR", msg);
     }
 
     pub fn info(&self, msg: &str) {
@@ -18,4 +54,78 @@ impl Log {
     pub fn error(&self, msg: &str) {
         self.emit("ERROR", msg);
     }
+
+    fn remember(&self, line: LogLine) {
+        let recent = match self.recent {
+            Some(ref recent) => recent,
+            None => return,
+        };
+
+        let mut lines = recent.lock().expect("lock on recent log lines");
+
+        if lines.len() >= self.capacity {
+            lines.pop_front();
+        }
+
+        lines.push_back(line);
+    }
+
+    // The last lines logged, oldest first, as many as the log keeps. Logs
+    // that don't keep them have none.
+    pub fn recent(&self) -> Vec<LogLine> {
+        self.recent.as_ref()
+            .map(|recent| recent.lock()
+                .expect("lock on recent log lines")
+                .iter().cloned().collect())
+            .unwrap_or_default()
+    }
+}
+
+// Sends log messages to the Windows event log as well as stdout from now on,
+// under the given source name. Used when running as a service, where nobody
+// is watching stdout.
+#[cfg(windows)]
+pub fn open_event_log(source: &str) {
+    event_log::open(source);
+}
+
+#[cfg(windows)]
+mod event_log {
+    use std::ffi::OsStr;
+    use std::os::windows::ffi::OsStrExt;
+    use std::ptr;
+    use std::sync::atomic::{AtomicPtr, Ordering};
+
+    use winapi::ctypes::c_void;
+    use winapi::um::winbase::{RegisterEventSourceW, ReportEventW};
+    use winapi::um::winnt::{EVENTLOG_ERROR_TYPE, EVENTLOG_INFORMATION_TYPE};
+
+    // null until open is called:
+    static SOURCE: AtomicPtr<c_void> = AtomicPtr::new(ptr::null_mut());
+
+    fn wide(s: &str) -> Vec<u16> {
+        OsStr::new(s).encode_wide().chain(Some(0)).collect()
+    }
+
+    pub fn open(source: &str) {
+        let handle = unsafe { RegisterEventSourceW(ptr::null(), wide(source).as_ptr()) };
+        SOURCE.store(handle, Ordering::SeqCst);
+    }
+
+    pub fn report(error: bool, msg: &str) {
+        let handle = SOURCE.load(Ordering::SeqCst);
+
+        if handle.is_null() {
+            return;
+        }
+
+        let event_type = if error { EVENTLOG_ERROR_TYPE } else { EVENTLOG_INFORMATION_TYPE };
+        let msg = wide(msg);
+        let mut strings = [msg.as_ptr()];
+
+        unsafe {
+            ReportEventW(handle, event_type, 0, 0, ptr::null_mut(),
+                1, 0, strings.as_mut_ptr(), ptr::null_mut());
+        }
+    }
 }
@@ -1,49 +1,191 @@
-extern crate base64;
-extern crate chrono;
-extern crate lame;
-extern crate lewton;
-extern crate reqwest;
-extern crate serde;
-extern crate serde_json;
-extern crate tiny_http;
-extern crate toml;
-extern crate uuid;
-
-#[macro_use]
-extern crate serde_derive;
-
-mod audio;
-mod config;
-mod fanout;
-mod hooks;
-mod log;
-mod ogg;
-mod server;
-
-use std::env;
+extern crate clap;
+extern crate rustcast;
+
 use std::path::PathBuf;
 use std::process;
+use std::time::Duration;
+
+use clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
+
+use rustcast::config;
+use rustcast::loadtest;
+#[cfg(unix)]
+use rustcast::daemon;
+#[cfg(unix)]
+use rustcast::signal;
+#[cfg(windows)]
+use rustcast::service;
+
+fn main() {
+    let matches = App::new("rustcast")
+        .about("Streaming audio server")
+        .setting(AppSettings::SubcommandsNegateReqs)
+        .arg(Arg::with_name("config")
+            .help("Path to the config file")
+            .required(true))
+        .arg(Arg::with_name("check-config")
+            .long("check-config")
+            .help("Checks the config file and prints the effective config, then exits"))
+        .arg(Arg::with_name("daemon")
+            .long("daemon")
+            .help("Detaches from the terminal and runs in the background (Unix only)"))
+        .arg(Arg::with_name("listen")
+            .long("listen")
+            .value_name("ADDRESS")
+            .help("Address to listen on, overriding the config file")
+            .takes_value(true))
+        .arg(Arg::with_name("service")
+            .long("service")
+            .help("Runs as a Windows service (Windows only)"))
+        .subcommand(SubCommand::with_name("loadtest")
+            .about("Connects many listeners to a running server and reports how it kept up")
+            .arg(Arg::with_name("url")
+                .help("Stream URL to listen to, like http://localhost:8000/live")
+                .required(true))
+            .arg(Arg::with_name("listeners")
+                .long("listeners")
+                .value_name("N")
+                .help("How many listeners to connect, 100 by default")
+                .takes_value(true))
+            .arg(Arg::with_name("seconds")
+                .long("seconds")
+                .value_name("SECONDS")
+                .help("How long to listen for, 30 by default")
+                .takes_value(true)))
+        .get_matches();
+
+    if let Some(matches) = matches.subcommand_matches("loadtest") {
+        run_loadtest(matches);
+        return;
+    }
 
-fn config_path() -> PathBuf {
-    match env::args_os().nth(1) {
-        Some(path) => path.into(),
-        None => {
-            eprintln!("usage: rustcast <config file>");
+    let config_path = PathBuf::from(matches.value_of_os("config").expect("config is required"));
+
+    let listen = matches.value_of("listen").map(str::to_owned);
+
+    let mut config = match config::open(&config_path) {
+        Ok(config) => config,
+        Err(err) => {
+            eprintln!("Couldn't open config file: {}", err);
             process::exit(1);
         }
+    };
+
+    if let Some(ref listen) = listen {
+        config.override_listen(listen.clone());
     }
-}
 
-fn main() {
-    let config_path = config_path();
+    if matches.is_present("check-config") {
+        match config.to_toml() {
+            Ok(toml) => {
+                print!("{}", toml);
+                process::exit(0);
+            }
+            Err(err) => {
+                eprintln!("Couldn't print config: {:?}", err);
+                process::exit(1);
+            }
+        }
+    }
 
-    let config = match config::open(&config_path) {
-        Ok(config) => config,
+    #[cfg(windows)]
+    {
+        if matches.is_present("service") {
+            if let Err(err) = service::run(config_path, listen) {
+                eprintln!("Couldn't run as a service: {:?}", err);
+                process::exit(1);
+            }
+
+            return;
+        }
+    }
+
+    #[cfg(unix)]
+    {
+        if matches.is_present("daemon") {
+            if let Err(err) = daemon::daemonize() {
+                eprintln!("Couldn't daemonize: {}", err);
+                process::exit(1);
+            }
+        }
+
+        // written before starting, since that may give up the permissions
+        // needed to write it, and owned by whoever rustcast will run as:
+        if let Some(ref pid_file) = config.pid_file {
+            let user = config.user.as_ref().map(String::as_str);
+            let group = config.group.as_ref().map(String::as_str);
+
+            if let Err(err) = daemon::write_pid_file(pid_file, user, group) {
+                eprintln!("Couldn't write pid file {}: {}", pid_file, err);
+                process::exit(1);
+            }
+        }
+
+        signal::catch_sighup();
+    }
+
+    #[cfg(unix)]
+    let pid_file = config.pid_file.clone();
+
+    match rustcast::start(config_path, config) {
+        Ok(handle) => handle.join(),
         Err(err) => {
-            eprintln!("Couldn't open config file: {:?}", err);
+            eprintln!("Couldn't start server: {}", err);
+            process::exit(1);
+        }
+    }
+
+    #[cfg(unix)]
+    {
+        if let Some(ref pid_file) = pid_file {
+            let _ = daemon::remove_pid_file(pid_file);
+        }
+    }
+}
+
+fn run_loadtest(matches: &ArgMatches) {
+    let url = matches.value_of("url").expect("url is required");
+
+    let listeners = match matches.value_of("listeners").unwrap_or("100").parse() {
+        Ok(listeners) => listeners,
+        Err(_) => {
+            eprintln!("--listeners must be a number");
+            process::exit(1);
+        }
+    };
+
+    let seconds = match matches.value_of("seconds").unwrap_or("30").parse() {
+        Ok(seconds) => seconds,
+        Err(_) => {
+            eprintln!("--seconds must be a number");
             process::exit(1);
         }
     };
 
-    server::run(config);
+    println!("Connecting {} listeners to {} for {} seconds", listeners, url, seconds);
+
+    let report = loadtest::run(url, listeners, Duration::from_secs(seconds));
+
+    println!("listeners:         {}", report.listeners);
+    println!("failed:            {}", report.failed);
+    println!("dropped:           {}", report.dropped);
+    println!("bytes received:    {}", report.bytes);
+    println!("kbps per listener: {:.1}", report.kilobits_per_listener());
+
+    if let (Some(median), Some(max)) = (report.first_byte_median, report.first_byte_max) {
+        println!("first byte:        {} ms median, {} ms max",
+            millis(median), millis(max));
+    }
+
+    if let Some(error) = report.example_error {
+        println!("example error:     {}", error);
+    }
+
+    if report.failed > 0 || report.dropped > 0 {
+        process::exit(1);
+    }
+}
+
+fn millis(duration: Duration) -> u64 {
+    duration.as_secs() * 1000 + (duration.subsec_nanos() / 1_000_000) as u64
 }
@@ -0,0 +1,131 @@
+// Prometheus metrics, served at /metrics in the text exposition format when
+// metrics = true. Hooks are called from all over with nothing but a config to
+// hand, so their metrics are kept process wide, like the event log.
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::io;
+use std::ptr;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicPtr, Ordering};
+use std::time::Duration;
+
+use tiny_http::{Header, Request, Response};
+
+use server::Rustcast;
+
+// upper bounds of the hook latency histogram buckets, in seconds:
+const HOOK_BUCKETS: [f64; 9] = [0.01, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0];
+
+#[derive(Default)]
+struct HookMetrics {
+    calls: u64,
+    // by reason, see hooks::failure_reason:
+    failures: BTreeMap<&'static str, u64>,
+    // calls that took at most each of HOOK_BUCKETS, not cumulative:
+    buckets: [u64; 9],
+    seconds: f64,
+}
+
+type Hooks = Mutex<BTreeMap<&'static str, HookMetrics>>;
+
+// null until the first hook is recorded:
+static HOOKS: AtomicPtr<Hooks> = AtomicPtr::new(ptr::null_mut());
+
+fn hooks() -> &'static Hooks {
+    let mut hooks = HOOKS.load(Ordering::SeqCst);
+
+    if hooks.is_null() {
+        let new = Box::into_raw(Box::new(Mutex::new(BTreeMap::new())));
+
+        // another thread may have got there first, in which case theirs is
+        // used and ours thrown away:
+        hooks = match HOOKS.compare_exchange(ptr::null_mut(), new, Ordering::SeqCst, Ordering::SeqCst) {
+            Ok(_) => new,
+            Err(existing) => {
+                drop(unsafe { Box::from_raw(new) });
+                existing
+            }
+        };
+    }
+
+    unsafe { &*hooks }
+}
+
+fn seconds(duration: Duration) -> f64 {
+    duration.as_secs() as f64 + duration.subsec_nanos() as f64 / 1e9
+}
+
+// Records a call to a webhook, how long it took, and why it failed if it did.
+pub fn record_hook(hook: &'static str, elapsed: Duration, failure: Option<&'static str>) {
+    let mut hooks = hooks().lock().expect("lock on hook metrics");
+    let metrics = hooks.entry(hook).or_insert_with(HookMetrics::default);
+    let elapsed = seconds(elapsed);
+
+    metrics.calls += 1;
+    metrics.seconds += elapsed;
+
+    if let Some(bucket) = HOOK_BUCKETS.iter().position(|&le| elapsed <= le) {
+        metrics.buckets[bucket] += 1;
+    }
+
+    if let Some(reason) = failure {
+        *metrics.failures.entry(reason).or_insert(0) += 1;
+    }
+}
+
+fn render_hooks(out: &mut String) {
+    let hooks = hooks().lock().expect("lock on hook metrics");
+
+    // writing to a String can't fail, so the results are ignored throughout:
+    let _ = writeln!(out, "# HELP rustcast_hook_calls_total Webhook calls made.");
+    let _ = writeln!(out, "# TYPE rustcast_hook_calls_total counter");
+
+    for (hook, metrics) in hooks.iter() {
+        let _ = writeln!(out, "rustcast_hook_calls_total{{hook=\"{}\"}} {}", hook, metrics.calls);
+    }
+
+    let _ = writeln!(out, "# HELP rustcast_hook_failures_total Webhook calls that failed, by reason.");
+    let _ = writeln!(out, "# TYPE rustcast_hook_failures_total counter");
+
+    for (hook, metrics) in hooks.iter() {
+        for (reason, count) in &metrics.failures {
+            let _ = writeln!(out, "rustcast_hook_failures_total{{hook=\"{}\",reason=\"{}\"}} {}",
+                hook, reason, count);
+        }
+    }
+
+    let _ = writeln!(out, "# HELP rustcast_hook_duration_seconds How long webhook calls took.");
+    let _ = writeln!(out, "# TYPE rustcast_hook_duration_seconds histogram");
+
+    for (hook, metrics) in hooks.iter() {
+        let mut cumulative = 0;
+
+        for (le, count) in HOOK_BUCKETS.iter().zip(metrics.buckets.iter()) {
+            cumulative += count;
+            let _ = writeln!(out, "rustcast_hook_duration_seconds_bucket{{hook=\"{}\",le=\"{}\"}} {}",
+                hook, le, cumulative);
+        }
+
+        let _ = writeln!(out, "rustcast_hook_duration_seconds_bucket{{hook=\"{}\",le=\"+Inf\"}} {}",
+            hook, metrics.calls);
+        let _ = writeln!(out, "rustcast_hook_duration_seconds_sum{{hook=\"{}\"}} {}", hook, metrics.seconds);
+        let _ = writeln!(out, "rustcast_hook_duration_seconds_count{{hook=\"{}\"}} {}", hook, metrics.calls);
+    }
+}
+
+pub fn is_metrics_url(url: &str) -> bool {
+    url.split('?').next() == Some("/metrics")
+}
+
+pub fn handle(_rustcast: &Rustcast, req: Request) -> io::Result<()> {
+    let mut out = String::new();
+
+    render_hooks(&mut out);
+
+    let content_type = Header::from_bytes(&b"Content-Type"[..], &b"text/plain; version=0.0.4"[..])
+        .expect("valid header");
+
+    req.respond(Response::from_string(out)
+        .with_header(content_type))
+}
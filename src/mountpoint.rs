@@ -0,0 +1,101 @@
+// Mountpoint names, as taken from request URLs. The same stream can be asked
+// for in several ways (/live/, /live?x=1, /%6Cive), so every handler
+// normalizes the URL the same way before using it as a mountpoint.
+
+use std::fmt;
+
+#[derive(Debug, PartialEq)]
+pub enum InvalidMountpoint {
+    NoLeadingSlash,
+    BadEscape,
+    BadCharacter(char),
+    DotSegment,
+}
+
+impl fmt::Display for InvalidMountpoint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            InvalidMountpoint::NoLeadingSlash => write!(f, "mountpoint must start with /"),
+            InvalidMountpoint::BadEscape => write!(f, "bad percent escape in mountpoint"),
+            InvalidMountpoint::BadCharacter(c) => write!(f, "{:?} isn't allowed in mountpoints", c),
+            InvalidMountpoint::DotSegment => write!(f, ". and .. aren't allowed in mountpoints"),
+        }
+    }
+}
+
+// characters that would be ambiguous in a mountpoint, or in the URLs and
+// file names made from it:
+const RESERVED: &'static str = "\\\"<>%*?#";
+
+fn hex_value(byte: u8) -> Option<u8> {
+    match byte {
+        b'0'..=b'9' => Some(byte - b'0'),
+        b'a'..=b'f' => Some(byte - b'a' + 10),
+        b'A'..=b'F' => Some(byte - b'A' + 10),
+        _ => None,
+    }
+}
+
+fn percent_decode(path: &str) -> Result<String, InvalidMountpoint> {
+    let bytes = path.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            let high = bytes.get(i + 1).cloned().and_then(hex_value);
+            let low = bytes.get(i + 2).cloned().and_then(hex_value);
+
+            match (high, low) {
+                (Some(high), Some(low)) => decoded.push(high << 4 | low),
+                _ => return Err(InvalidMountpoint::BadEscape),
+            }
+
+            i += 3;
+        } else {
+            decoded.push(bytes[i]);
+            i += 1;
+        }
+    }
+
+    String::from_utf8(decoded).map_err(|_| InvalidMountpoint::BadEscape)
+}
+
+// Checks an already decoded mountpoint, and normalizes its slashes: doubled
+// slashes are collapsed and trailing slashes dropped, so "/live/" is "/live".
+pub fn normalize(path: &str) -> Result<String, InvalidMountpoint> {
+    if !path.starts_with('/') {
+        return Err(InvalidMountpoint::NoLeadingSlash);
+    }
+
+    if let Some(c) = path.chars().find(|&c| c.is_control() || c.is_whitespace() || RESERVED.contains(c)) {
+        return Err(InvalidMountpoint::BadCharacter(c));
+    }
+
+    let segments = path.split('/')
+        .filter(|segment| !segment.is_empty())
+        .collect::<Vec<_>>();
+
+    if segments.iter().any(|&segment| segment == "." || segment == "..") {
+        return Err(InvalidMountpoint::DotSegment);
+    }
+
+    Ok(format!("/{}", segments.join("/")))
+}
+
+// The normalized path of a request URL, without its query string.
+pub fn from_url(url: &str) -> Result<String, InvalidMountpoint> {
+    let path = url.split('?').next().unwrap_or("");
+    normalize(&percent_decode(path)?)
+}
+
+// Whether mountpoint is prefix or under it, as mount scopes are matched: a
+// prefix of /live covers /live and /live/hd, but not /live2. A prefix ending
+// in a slash only covers what's under it.
+pub fn is_within(mountpoint: &str, prefix: &str) -> bool {
+    if !mountpoint.starts_with(prefix) {
+        return false;
+    }
+
+    prefix.ends_with('/') || mountpoint.len() == prefix.len() || mountpoint[prefix.len()..].starts_with('/')
+}
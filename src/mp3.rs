@@ -0,0 +1,268 @@
+// MPEG audio frame header parsing, just enough to find frame boundaries in
+// LAME's output.
+
+const BITRATES_V1_L3: [u32; 16] = [
+    0, 32, 40, 48, 56, 64, 80, 96, 112, 128, 160, 192, 224, 256, 320, 0,
+];
+
+const BITRATES_V2_L3: [u32; 16] = [
+    0, 8, 16, 24, 32, 40, 48, 56, 64, 80, 96, 112, 128, 144, 160, 0,
+];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Version {
+    Mpeg1,
+    Mpeg2,
+    Mpeg25,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct FrameHeader {
+    pub version: Version,
+    pub kilobitrate: u32,
+    pub sample_rate: u32,
+    pub padding: bool,
+}
+
+impl FrameHeader {
+    pub fn parse(bytes: &[u8]) -> Option<FrameHeader> {
+        if bytes.len() < 4 {
+            return None;
+        }
+
+        // 11 bit frame sync:
+        if bytes[0] != 0xff || (bytes[1] & 0xe0) != 0xe0 {
+            return None;
+        }
+
+        let version = match (bytes[1] >> 3) & 0b11 {
+            0b00 => Version::Mpeg25,
+            0b10 => Version::Mpeg2,
+            0b11 => Version::Mpeg1,
+            _ => return None,
+        };
+
+        // we only ever produce layer III:
+        if (bytes[1] >> 1) & 0b11 != 0b01 {
+            return None;
+        }
+
+        let bitrate_index = (bytes[2] >> 4) as usize;
+
+        let kilobitrate = match version {
+            Version::Mpeg1 => BITRATES_V1_L3[bitrate_index],
+            Version::Mpeg2 | Version::Mpeg25 => BITRATES_V2_L3[bitrate_index],
+        };
+
+        // free format and bad bitrate indices:
+        if kilobitrate == 0 {
+            return None;
+        }
+
+        let base_sample_rate = match (bytes[2] >> 2) & 0b11 {
+            0b00 => 44100,
+            0b01 => 48000,
+            0b10 => 32000,
+            _ => return None,
+        };
+
+        let sample_rate = match version {
+            Version::Mpeg1 => base_sample_rate,
+            Version::Mpeg2 => base_sample_rate / 2,
+            Version::Mpeg25 => base_sample_rate / 4,
+        };
+
+        Some(FrameHeader {
+            version,
+            kilobitrate,
+            sample_rate,
+            padding: (bytes[2] >> 1) & 1 == 1,
+        })
+    }
+
+    pub fn frame_len(&self) -> usize {
+        let coefficient = match self.version {
+            Version::Mpeg1 => 144,
+            Version::Mpeg2 | Version::Mpeg25 => 72,
+        };
+
+        let padding = if self.padding { 1 } else { 0 };
+
+        (coefficient * self.kilobitrate * 1000 / self.sample_rate) as usize + padding
+    }
+
+    // How long the frame plays for, in nanoseconds.
+    pub fn duration_nanos(&self) -> u64 {
+        let samples = match self.version {
+            Version::Mpeg1 => 1152,
+            Version::Mpeg2 | Version::Mpeg25 => 576,
+        };
+
+        samples * 1_000_000_000 / self.sample_rate as u64
+    }
+}
+
+// Returns the offset of the first frame header in buf. A candidate header
+// is only accepted if it is followed by another header or the end of the
+// buffer, which rules out most false syncs inside frame data.
+pub fn find_frame(buf: &[u8]) -> Option<usize> {
+    for offset in 0..buf.len() {
+        let header = match FrameHeader::parse(&buf[offset..]) {
+            Some(header) => header,
+            None => continue,
+        };
+
+        let next = offset + header.frame_len();
+
+        if next >= buf.len() || FrameHeader::parse(&buf[next..]).is_some() {
+            return Some(offset);
+        }
+    }
+
+    None
+}
+
+// Counts the frames in MP3 data fed to it in arbitrary pieces, for writing
+// into a Xing header.
+pub struct FrameCounter {
+    pub frames: u32,
+    // bytes left of the frame being passed over:
+    remaining: usize,
+    // the start of a header split across pieces:
+    partial: Vec<u8>,
+    // whether we're at a frame boundary, rather than looking for one:
+    synced: bool,
+}
+
+impl FrameCounter {
+    pub fn new() -> FrameCounter {
+        FrameCounter {
+            frames: 0,
+            remaining: 0,
+            partial: Vec::new(),
+            synced: false,
+        }
+    }
+
+    pub fn feed(&mut self, mut data: &[u8]) {
+        loop {
+            if !self.synced {
+                match find_frame(data) {
+                    Some(offset) => {
+                        data = &data[offset..];
+                        self.synced = true;
+                    }
+                    None => return,
+                }
+            }
+
+            if self.remaining >= data.len() {
+                self.remaining -= data.len();
+                return;
+            }
+
+            data = &data[self.remaining..];
+            self.remaining = 0;
+
+            let needed = 4 - self.partial.len();
+
+            if data.len() < needed {
+                self.partial.extend_from_slice(data);
+                return;
+            }
+
+            self.partial.extend_from_slice(&data[..needed]);
+            data = &data[needed..];
+
+            match FrameHeader::parse(&self.partial) {
+                Some(header) => {
+                    self.frames += 1;
+                    self.remaining = header.frame_len() - 4;
+                }
+                None => self.synced = false,
+            }
+
+            self.partial.clear();
+        }
+    }
+}
+
+// Builds an empty frame carrying a Xing header, to go at the start of a VBR
+// file so that players can work out its duration and seek in it. The frame
+// matches the version, sample rate and channels of first_frame. Returns the
+// frame, and the offset within it of the frame and byte counts, which are
+// filled in from xing_counts once they're known.
+pub fn xing_frame(first_frame: &[u8]) -> Option<(Vec<u8>, usize)> {
+    let header = FrameHeader::parse(first_frame)?;
+
+    let tag_offset = xing_tag_offset(&header, first_frame);
+
+    // "Xing", flags, frame count and byte count:
+    let needed = tag_offset + 16;
+
+    let bitrates = match header.version {
+        Version::Mpeg1 => &BITRATES_V1_L3,
+        Version::Mpeg2 | Version::Mpeg25 => &BITRATES_V2_L3,
+    };
+
+    // the smallest bitrate whose frames have room for the tag:
+    let (bitrate_index, xing_header) = (1..15)
+        .map(|index| (index, FrameHeader {
+            kilobitrate: bitrates[index],
+            padding: false,
+            ..header
+        }))
+        .find(|&(_, ref xing_header)| xing_header.frame_len() >= needed)?;
+
+    let mut frame = vec![0u8; xing_header.frame_len()];
+
+    frame[0] = first_frame[0];
+    // no CRC:
+    frame[1] = first_frame[1] | 0b1;
+    // keep the sample rate, clear padding and the private bit:
+    frame[2] = ((bitrate_index as u8) << 4) | (first_frame[2] & 0b0000_1100);
+    frame[3] = first_frame[3];
+
+    frame[tag_offset..tag_offset + 4].copy_from_slice(b"Xing");
+    // frame and byte counts present:
+    frame[tag_offset + 7] = 0b11;
+
+    Some((frame, tag_offset + 8))
+}
+
+// Where a Xing header goes in a frame: straight after the side info.
+fn xing_tag_offset(header: &FrameHeader, frame: &[u8]) -> usize {
+    let mono = frame[3] >> 6 == 0b11;
+
+    let side_info_len = match (header.version, mono) {
+        (Version::Mpeg1, false) => 32,
+        (Version::Mpeg1, true) => 17,
+        (_, false) => 17,
+        (_, true) => 9,
+    };
+
+    4 + side_info_len
+}
+
+// Whether frame is a Xing (or LAME's "Info") header rather than audio.
+pub fn is_xing_frame(frame: &[u8]) -> bool {
+    let header = match FrameHeader::parse(frame) {
+        Some(header) => header,
+        None => return false,
+    };
+
+    let tag_offset = xing_tag_offset(&header, frame);
+
+    match frame.get(tag_offset..tag_offset + 4) {
+        Some(tag) => tag == b"Xing" || tag == b"Info",
+        None => false,
+    }
+}
+
+// The frame and byte counts for a Xing header, big endian.
+pub fn xing_counts(frames: u32, bytes: u32) -> [u8; 8] {
+    [
+        (frames >> 24) as u8, (frames >> 16) as u8, (frames >> 8) as u8, frames as u8,
+        (bytes >> 24) as u8, (bytes >> 16) as u8, (bytes >> 8) as u8, bytes as u8,
+    ]
+}
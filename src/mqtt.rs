@@ -0,0 +1,231 @@
+// Publishes now playing metadata and listener counts to an MQTT broker, for
+// studio dashboards and home automation to subscribe to. Only as much of
+// MQTT 3.1.1 as publishing at QoS 0 needs is implemented: CONNECT, then
+// PUBLISH. Keep alive is turned off, so there's no pinging to do between
+// events. Listener counts are taken from who's listening when a listener
+// joins or leaves, rather than counted from the events, so they can't drift.
+
+use std::io::{self, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use serde_json;
+
+use config::Mqtt;
+use events::{Event, EventSink};
+use listeners::Listeners;
+use log::Log;
+
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+// how long to wait after failing to connect before trying again, dropping
+// anything published in the meantime:
+const RETRY_INTERVAL: Duration = Duration::from_secs(30);
+
+// the longest string MQTT can encode, its length being two bytes:
+const MAX_STRING_LENGTH: usize = 0xffff;
+
+// packet types, already shifted into the first byte's high nibble:
+const CONNECT: u8 = 0x10;
+const CONNACK: u8 = 0x20;
+const PUBLISH: u8 = 0x30;
+
+fn encode_length(buf: &mut Vec<u8>, mut len: usize) {
+    loop {
+        let mut byte = (len % 128) as u8;
+        len /= 128;
+
+        if len > 0 {
+            byte |= 0x80;
+        }
+
+        buf.push(byte);
+
+        if len == 0 {
+            break;
+        }
+    }
+}
+
+fn encode_string(buf: &mut Vec<u8>, string: &str) -> io::Result<()> {
+    if string.len() > MAX_STRING_LENGTH {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput,
+            format!("{} bytes is too long for an MQTT string", string.len())));
+    }
+
+    buf.push((string.len() >> 8) as u8);
+    buf.push(string.len() as u8);
+    buf.extend_from_slice(string.as_bytes());
+
+    Ok(())
+}
+
+fn packet(header: u8, body: &[u8]) -> Vec<u8> {
+    let mut packet = vec![header];
+    encode_length(&mut packet, body.len());
+    packet.extend_from_slice(body);
+    packet
+}
+
+fn connect(config: &Mqtt) -> io::Result<TcpStream> {
+    let addr = config.broker.to_socket_addrs()?.next()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::Other, "broker address didn't resolve"))?;
+
+    let mut stream = TcpStream::connect_timeout(&addr, TIMEOUT)?;
+    stream.set_read_timeout(Some(TIMEOUT))?;
+    stream.set_write_timeout(Some(TIMEOUT))?;
+
+    let mut flags = 0x02; // clean session
+
+    if config.username.is_some() {
+        flags |= 0x80;
+    }
+
+    if config.password.is_some() {
+        flags |= 0x40;
+    }
+
+    let mut body = Vec::new();
+    encode_string(&mut body, "MQTT")?;
+    body.push(4); // protocol level, 3.1.1
+    body.push(flags);
+    body.extend_from_slice(&[0, 0]); // keep alive off
+    encode_string(&mut body, &config.client_id)?;
+
+    if let Some(ref username) = config.username {
+        encode_string(&mut body, username)?;
+    }
+
+    if let Some(ref password) = config.password {
+        encode_string(&mut body, password)?;
+    }
+
+    stream.write_all(&packet(CONNECT, &body))?;
+
+    let mut connack = [0u8; 4];
+    stream.read_exact(&mut connack)?;
+
+    if connack[0] != CONNACK || connack[1] != 2 {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "expected CONNACK"));
+    }
+
+    if connack[3] != 0 {
+        return Err(io::Error::new(io::ErrorKind::ConnectionRefused,
+            format!("broker refused connection (return code {})", connack[3])));
+    }
+
+    Ok(stream)
+}
+
+struct Connection {
+    config: Mqtt,
+    stream: TcpStream,
+}
+
+pub struct MqttClient {
+    // None until the first publish, and after anything goes wrong:
+    connection: Mutex<Option<Connection>>,
+    // the config that last failed to connect, and when:
+    failed: Mutex<Option<(Mqtt, Instant)>>,
+}
+
+impl MqttClient {
+    pub fn new() -> MqttClient {
+        MqttClient {
+            connection: Mutex::new(None),
+            failed: Mutex::new(None),
+        }
+    }
+
+    // Publishes payload to topic, connecting first if need be. The
+    // connection is made again if the config has changed since, and dropped
+    // if publishing fails so the next publish starts afresh. For
+    // RETRY_INTERVAL after failing to connect, publishing with the same
+    // config does nothing, so an unreachable broker doesn't hold up every
+    // event by TIMEOUT.
+    fn publish(&self, config: &Mqtt, topic: &str, payload: &[u8]) -> io::Result<()> {
+        let mut body = Vec::new();
+        encode_string(&mut body, topic)?;
+        body.extend_from_slice(payload);
+
+        let mut connection = self.connection.lock().expect("lock on mqtt connection");
+
+        let stale = match *connection {
+            Some(ref connection) => connection.config != *config,
+            None => true,
+        };
+
+        if stale {
+            *connection = None;
+
+            let mut failed = self.failed.lock().expect("lock on mqtt connect failure");
+
+            if let Some((ref failed_config, at)) = *failed {
+                if failed_config == config && at.elapsed() < RETRY_INTERVAL {
+                    return Ok(());
+                }
+            }
+
+            match connect(config) {
+                Ok(stream) => {
+                    *failed = None;
+                    *connection = Some(Connection { config: config.clone(), stream });
+                }
+                Err(e) => {
+                    *failed = Some((config.clone(), Instant::now()));
+                    return Err(e);
+                }
+            }
+        }
+
+        let header = if config.retain { PUBLISH | 0x01 } else { PUBLISH };
+
+        let result = connection.as_mut()
+            .expect("connection just made")
+            .stream.write_all(&packet(header, &body));
+
+        if result.is_err() {
+            *connection = None;
+        }
+
+        result
+    }
+}
+
+fn topic(template: &str, mountpoint: &str) -> String {
+    template.replace("{mountpoint}", mountpoint)
+}
+
+pub struct MqttSink {
+    client: Arc<MqttClient>,
+    config: Mqtt,
+    listeners: Arc<Listeners>,
+}
+
+impl MqttSink {
+    pub fn new(client: Arc<MqttClient>, config: Mqtt, listeners: Arc<Listeners>) -> MqttSink {
+        MqttSink { client, config, listeners }
+    }
+}
+
+impl EventSink for MqttSink {
+    fn publish(&self, event: &Event) {
+        let (mountpoint, topic, payload) = match *event {
+            Event::MetadataChanged { ref mountpoint, ref metadata, .. } => {
+                let payload = serde_json::to_vec(metadata).expect("metadata to serialize");
+                (mountpoint, topic(&self.config.metadata_topic, mountpoint), payload)
+            }
+            Event::ListenerJoined { ref mountpoint, .. } | Event::ListenerLeft { ref mountpoint, .. } => {
+                let count = self.listeners.count(mountpoint);
+                (mountpoint, topic(&self.config.listeners_topic, mountpoint), count.to_string().into_bytes())
+            }
+            _ => return,
+        };
+
+        if let Err(e) = self.client.publish(&self.config, &topic, &payload) {
+            Log::new().error(&format!("Couldn't publish to MQTT topic {} for {}: {}",
+                topic, mountpoint, e));
+        }
+    }
+}
@@ -0,0 +1,126 @@
+// oEmbed for mountpoints, so pasting a link to a mountpoint's player into a
+// CMS that supports oEmbed embeds a small player. The embed is an iframe of
+// /mount/embed. JSON-P is there for sites that can't fetch cross-origin.
+
+use std::cmp;
+use std::collections::HashMap;
+use std::io;
+
+use reqwest::Url;
+use serde_json;
+use tiny_http::{Header, Request, Response};
+
+use config::Config;
+use mountpoint;
+use server::{self, Rustcast, StreamEntry};
+
+// size of the embed when the consumer doesn't ask for smaller:
+const WIDTH: u32 = 400;
+const HEIGHT: u32 = 120;
+
+#[derive(Serialize)]
+struct OembedJson {
+    version: &'static str,
+    #[serde(rename = "type")]
+    kind: &'static str,
+    provider_name: &'static str,
+    title: String,
+    html: String,
+    width: u32,
+    height: u32,
+}
+
+fn path(url: &str) -> &str {
+    url.split('?').next().unwrap_or(url)
+}
+
+pub fn is_oembed_url(url: &str) -> bool {
+    path(url) == "/oembed"
+}
+
+fn respond_error(req: Request, status: u16, message: &str) -> io::Result<()> {
+    req.respond(Response::from_string(format!("{}\n", message))
+        .with_status_code(status))
+}
+
+// The mountpoint a link is for: its player, embed, stream or anything else
+// served for it, on the station its host is for.
+fn mountpoint_for(config: &Config, url: &str) -> Option<String> {
+    let url = Url::parse(url).ok()?;
+    let path = mountpoint::normalize(url.path()).ok()?;
+    let path = config.station_mountpoint(url.host_str(), &path)?;
+    Some(server::request_mountpoint(config, &path))
+}
+
+// callbacks are put straight into a script, so are kept to names:
+fn valid_callback(callback: &str) -> bool {
+    !callback.is_empty() && callback.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.' || c == '$')
+}
+
+pub fn handle(rustcast: &Rustcast, req: Request) -> io::Result<()> {
+    let params = Url::parse("http://localhost/")
+        .and_then(|base| base.join(req.url()))
+        .map(|url| url.query_pairs().into_owned().collect::<HashMap<String, String>>())
+        .unwrap_or_default();
+
+    if params.get("format").map(String::as_str).unwrap_or("json") != "json" {
+        return respond_error(req, 501, "only json is supported");
+    }
+
+    let config = rustcast.config();
+
+    let mountpoint = match params.get("url").and_then(|url| mountpoint_for(&config, url)) {
+        Some(mountpoint) => mountpoint,
+        None => return respond_error(req, 404, "no url, or not a mountpoint"),
+    };
+
+    let live = match rustcast.get_stream(&mountpoint) {
+        Some(StreamEntry::Live(_)) => true,
+        _ => false,
+    };
+
+    // the same mountpoints as get a player page:
+    if !live && config.mount(&mountpoint).is_none() {
+        return respond_error(req, 404, "no such mountpoint");
+    }
+
+    let max = |name: &str, default: u32| params.get(name)
+        .and_then(|max| max.parse().ok())
+        .map(|max| cmp::min(max, default))
+        .unwrap_or(default);
+
+    let width = max("maxwidth", WIDTH);
+    let height = max("maxheight", HEIGHT);
+
+    let title = config.mount(&mountpoint)
+        .and_then(|mount| mount.name.clone())
+        .unwrap_or_else(|| mountpoint.clone());
+
+    let src = format!("{}{}/embed", server::base_url(rustcast, &req), config.public_path(&mountpoint));
+
+    let data = OembedJson {
+        version: "1.0",
+        kind: "rich",
+        provider_name: "rustcast",
+        html: format!("<iframe src=\"{}\" width=\"{}\" height=\"{}\" frameborder=\"0\" allow=\"autoplay\"></iframe>",
+            src.replace('"', "%22"), width, height),
+        title,
+        width,
+        height,
+    };
+
+    let json = serde_json::to_string(&data).unwrap();
+
+    let (body, content_type) = match params.get("callback") {
+        Some(callback) if valid_callback(callback) => (format!("{}({});", callback, json), "application/javascript"),
+        Some(_) => return respond_error(req, 400, "callback must be a function name"),
+        None => (json, "application/json"),
+    };
+
+    let content_type = Header::from_bytes(&b"Content-Type"[..], content_type.as_bytes())
+        .expect("valid header");
+
+    req.respond(Response::from_string(body)
+        .with_header(content_type)
+        .with_status_code(200))
+}
@@ -1,23 +1,31 @@
 extern crate ogg;
 
 use std::io;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
 
 use self::ogg::{PacketReader, OggReadError};
 use lewton::VorbisError;
 use lewton::inside_ogg::read_headers;
 use lewton::audio::{read_audio_packet, PreviousWindowRight, AudioReadError};
-use lewton::header::{read_header_comment, IdentHeader, CommentHeader, SetupHeader};
+use lewton::header::{read_header_comment, read_header_ident, read_header_setup, IdentHeader, CommentHeader, SetupHeader};
 
 use audio::{AudioStream, StreamRead, StreamError, Metadata};
+use picture::{Picture, FRONT_COVER};
 
 struct NonSeekStream<T: io::Read> {
     stream: T,
+    // bytes read so far, shared with the OggStream reading from this:
+    bytes_read: Arc<AtomicUsize>,
 }
 
 impl<T> io::Read for NonSeekStream<T> where T: io::Read {
     fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
         match self.stream.read(buf) {
-            Ok(sz) => Ok(sz),
+            Ok(sz) => {
+                self.bytes_read.fetch_add(sz, Ordering::Relaxed);
+                Ok(sz)
+            }
             Err(e) => Err(e),
         }
     }
@@ -30,31 +38,62 @@ impl<T> io::Seek for NonSeekStream<T> where T: io::Read {
 }
 
 impl<T> NonSeekStream<T> where T: io::Read {
-    pub fn new(stream: T) -> NonSeekStream<T> {
-        NonSeekStream { stream: stream }
+    pub fn new(stream: T, bytes_read: Arc<AtomicUsize>) -> NonSeekStream<T> {
+        NonSeekStream { stream: stream, bytes_read: bytes_read }
     }
 }
 
 impl From<CommentHeader> for Metadata {
     fn from(header: CommentHeader) -> Metadata {
-        let mut artist = None;
-        let mut title = None;
+        let mut metadata = Metadata::default();
 
         for (name, value) in header.comment_list {
-            match name.as_ref() {
-                "ARTIST" => artist = Some(value),
-                "TITLE" => title = Some(value),
-                _ => (),
+            // vorbis comment field names are case insensitive:
+            match name.to_uppercase().as_ref() {
+                "ARTIST" => metadata.artist = Some(value),
+                "TITLE" => metadata.title = Some(value),
+                "ALBUM" => metadata.album = Some(value),
+                "GENRE" => metadata.genre = Some(value),
+                "DATE" => metadata.date = Some(value),
+                "TRACKNUMBER" => metadata.tracknumber = Some(value),
+                "METADATA_BLOCK_PICTURE" => {
+                    // there can be several pictures, prefer the front cover:
+                    let picture = match Picture::from_vorbis_comment(&value) {
+                        Some(picture) => picture,
+                        None => continue,
+                    };
+
+                    let have_cover = metadata.picture.as_ref()
+                        .map(|picture| picture.picture_type == FRONT_COVER)
+                        .unwrap_or(false);
+
+                    if !have_cover {
+                        metadata.picture = Some(Arc::new(picture));
+                    }
+                }
+                _ => {
+                    metadata.extra.insert(name.to_lowercase(), value);
+                }
             }
         }
 
-        Metadata { artist, title }
+        metadata
+    }
+}
+
+fn copy_comment(header: &CommentHeader) -> CommentHeader {
+    CommentHeader {
+        vendor: header.vendor.clone(),
+        comment_list: header.comment_list.clone(),
     }
 }
 
 pub struct OggStream<T: io::Read> {
     rdr: PacketReader<NonSeekStream<T>>,
     pwr: PreviousWindowRight,
+    // metadata from headers read, to be returned by the next read:
+    pending_metadata: Option<Metadata>,
+    bytes_read: Arc<AtomicUsize>,
 
     pub ident_hdr: IdentHeader,
     pub comment_hdr: CommentHeader,
@@ -63,18 +102,61 @@ pub struct OggStream<T: io::Read> {
 
 impl<T: io::Read> OggStream<T> {
     pub fn new(io: T) -> Result<Self, VorbisError> {
-        let mut rdr = PacketReader::new(NonSeekStream::new(io));
+        let bytes_read = Arc::new(AtomicUsize::new(0));
+        let mut rdr = PacketReader::new(NonSeekStream::new(io, Arc::clone(&bytes_read)));
 
         let (ident_hdr, comment_hdr, setup_hdr) = read_headers(&mut rdr)?;
 
+        // the first track's tags are in its comment header:
+        let metadata = Metadata::from(copy_comment(&comment_hdr));
+
         Ok(OggStream {
             rdr,
             pwr: PreviousWindowRight::new(),
+            pending_metadata: if metadata == Metadata::default() { None } else { Some(metadata) },
+            bytes_read,
             ident_hdr,
             comment_hdr,
             setup_hdr,
         })
     }
+
+    fn read_header_packet(&mut self) -> Result<Vec<u8>, StreamError> {
+        match self.rdr.read_packet() {
+            Ok(Some(packet)) => Ok(packet.data),
+            Ok(None) => Err(StreamError::IoError(io::Error::new(io::ErrorKind::UnexpectedEof, "stream ended in headers"))),
+            Err(OggReadError::ReadError(e)) => Err(StreamError::IoError(e)),
+            Err(_) => Err(StreamError::BadPacket),
+        }
+    }
+
+    // Sources like liquidsoap start a new logical bitstream for each track,
+    // chained on to the last, each with its own headers. The new headers
+    // replace the old ones and decoding carries on from them, with the new
+    // comment header as the track's metadata.
+    fn read_chained_headers(&mut self, ident_packet: &[u8]) -> Result<StreamRead, StreamError> {
+        let ident_hdr = read_header_ident(ident_packet)
+            .map_err(|_| StreamError::BadPacket)?;
+
+        let comment_hdr = read_header_comment(&self.read_header_packet()?)
+            .map_err(|_| StreamError::BadPacket)?;
+
+        let setup_hdr = read_header_setup(&self.read_header_packet()?,
+            ident_hdr.audio_channels, (ident_hdr.blocksize_0, ident_hdr.blocksize_1))
+            .map_err(|_| StreamError::BadPacket)?;
+
+        let metadata = Metadata::from(copy_comment(&comment_hdr));
+
+        self.ident_hdr = ident_hdr;
+        self.comment_hdr = comment_hdr;
+        self.setup_hdr = setup_hdr;
+
+        // the previous window was from the last track, which doesn't
+        // overlap with the new one:
+        self.pwr = PreviousWindowRight::new();
+
+        Ok(StreamRead::Metadata(metadata))
+    }
 }
 
 impl<T: io::Read> AudioStream for OggStream<T> {
@@ -94,7 +176,15 @@ impl<T: io::Read> AudioStream for OggStream<T> {
         self.ident_hdr.bitrate_nominal
     }
 
+    fn bytes_read(&self) -> Option<u64> {
+        Some(self.bytes_read.load(Ordering::Relaxed) as u64)
+    }
+
     fn read(&mut self) -> Result<StreamRead, StreamError> {
+        if let Some(metadata) = self.pending_metadata.take() {
+            return Ok(StreamRead::Metadata(metadata));
+        }
+
         let packet = match self.rdr.read_packet() {
             Ok(Some(packet)) => packet,
             Ok(None) => return Ok(StreamRead::Eof),
@@ -105,6 +195,10 @@ impl<T: io::Read> AudioStream for OggStream<T> {
             Err(OggReadError::InvalidData) => return Err(StreamError::BadPacket),
         };
 
+        if packet.first_in_stream() {
+            return self.read_chained_headers(&packet.data);
+        }
+
         let decoded_packet = read_audio_packet(&self.ident_hdr,
             &self.setup_hdr, &packet.data, &mut self.pwr);
 
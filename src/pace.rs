@@ -0,0 +1,49 @@
+// Throttles writes to a listener to roughly the stream's bitrate, so a burst
+// of buffered audio doesn't overrun clients on poor connections.
+
+use std::thread;
+use std::time::{Duration, Instant};
+
+// how far ahead of real time a paced listener may get, when the mount
+// doesn't say:
+pub const DEFAULT_BURST: Duration = Duration::from_secs(2);
+
+pub struct Pacer {
+    bytes_per_sec: f64,
+    // bytes a listener may be sent ahead of real time:
+    burst: f64,
+    started: Instant,
+    sent: u64,
+}
+
+fn secs(duration: Duration) -> f64 {
+    duration.as_secs() as f64 + duration.subsec_nanos() as f64 / 1_000_000_000.0
+}
+
+impl Pacer {
+    pub fn new(kilobitrate: u32, burst: Duration) -> Pacer {
+        let bytes_per_sec = kilobitrate as f64 * 1000.0 / 8.0;
+
+        Pacer {
+            bytes_per_sec,
+            burst: bytes_per_sec * secs(burst),
+            started: Instant::now(),
+            sent: 0,
+        }
+    }
+
+    // Blocks until len more bytes can be sent without getting further ahead
+    // than the burst allowance.
+    pub fn wait(&mut self, len: usize) {
+        self.sent += len as u64;
+
+        let allowed = secs(self.started.elapsed()) * self.bytes_per_sec + self.burst;
+        let excess = self.sent as f64 - allowed;
+
+        if excess > 0.0 {
+            let delay = excess / self.bytes_per_sec;
+
+            thread::sleep(Duration::new(delay as u64, (delay.fract() * 1_000_000_000.0) as u32));
+        }
+    }
+}
@@ -0,0 +1,99 @@
+use std::collections::hash_map::DefaultHasher;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+
+use base64;
+
+// picture type for "Cover (front)", as used by ID3v2 APIC frames and FLAC
+// picture blocks:
+pub const FRONT_COVER: u32 = 3;
+
+#[derive(PartialEq)]
+pub struct Picture {
+    pub picture_type: u32,
+    pub mime_type: String,
+    pub data: Vec<u8>,
+    pub etag: String,
+}
+
+impl fmt::Debug for Picture {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Picture({}, {} bytes)", self.mime_type, self.data.len())
+    }
+}
+
+fn read_u32(buf: &[u8], offset: &mut usize) -> Option<u32> {
+    let bytes = buf.get(*offset..(*offset + 4))?;
+    *offset += 4;
+
+    Some((bytes[0] as u32) << 24 | (bytes[1] as u32) << 16 | (bytes[2] as u32) << 8 | bytes[3] as u32)
+}
+
+fn read_bytes<'a>(buf: &'a [u8], offset: &mut usize) -> Option<&'a [u8]> {
+    let len = read_u32(buf, offset)? as usize;
+    let bytes = buf.get(*offset..(*offset + len))?;
+    *offset += len;
+
+    Some(bytes)
+}
+
+// what a picture whose MIME type can't be sent as a header is served as:
+const UNKNOWN_MIME_TYPE: &'static str = "application/octet-stream";
+
+fn is_token(part: &str) -> bool {
+    !part.is_empty() && part.bytes().all(|byte| byte.is_ascii_alphanumeric() || b"!#$&^_.+-".contains(&byte))
+}
+
+// Whether mime_type is a plain type/subtype, safe to send as Content-Type.
+// It comes from the source, so can be anything, like the "-->" FLAC uses
+// for linked pictures.
+fn is_mime_type(mime_type: &str) -> bool {
+    let mut parts = mime_type.splitn(2, '/');
+
+    match (parts.next(), parts.next()) {
+        (Some(kind), Some(subtype)) => is_token(kind) && is_token(subtype),
+        _ => false,
+    }
+}
+
+impl Picture {
+    pub fn new(picture_type: u32, mime_type: String, data: Vec<u8>) -> Picture {
+        let mut hasher = DefaultHasher::new();
+        data.hash(&mut hasher);
+
+        let mime_type = if is_mime_type(&mime_type) {
+            mime_type
+        } else {
+            UNKNOWN_MIME_TYPE.to_owned()
+        };
+
+        Picture {
+            picture_type,
+            mime_type,
+            data,
+            etag: format!("\"{:016x}\"", hasher.finish()),
+        }
+    }
+
+    // Parses a FLAC METADATA_BLOCK_PICTURE, which is also how pictures are
+    // embedded in Vorbis comments (base64 encoded).
+    pub fn from_flac_block(block: &[u8]) -> Option<Picture> {
+        let mut offset = 0;
+
+        let picture_type = read_u32(block, &mut offset)?;
+        let mime_type = String::from_utf8(read_bytes(block, &mut offset)?.to_vec()).ok()?;
+        let _description = read_bytes(block, &mut offset)?;
+
+        // width, height, colour depth, palette size:
+        offset += 16;
+
+        let data = read_bytes(block, &mut offset)?.to_vec();
+
+        Some(Picture::new(picture_type, mime_type, data))
+    }
+
+    pub fn from_vorbis_comment(value: &str) -> Option<Picture> {
+        base64::decode(value).ok()
+            .and_then(|block| Picture::from_flac_block(&block))
+    }
+}
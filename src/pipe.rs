@@ -0,0 +1,178 @@
+use std::io::{self, Write};
+use std::process::{Command, Child, ChildStdin, Stdio};
+use std::sync::mpsc::{self, SyncSender, TrySendError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use config::PipeConfig;
+use fanout::Receiver;
+use log::Log;
+use server::{Frame, StreamData};
+
+const MIN_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+// a command that stays up this long is considered healthy again:
+const STABLE_AFTER: Duration = Duration::from_secs(60);
+
+// buffers kept for a command that's behind on reading, and how long it can
+// go without making room for more before it's taken to have stopped:
+const QUEUE_LENGTH: usize = 256;
+const WRITE_TIMEOUT: Duration = Duration::from_secs(10);
+const FULL_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+// Tees a mountpoint's encoded output into the stdin of a supervised child
+// process, restarting it with exponential backoff when it falls over.
+pub struct Pipe {
+    config: PipeConfig,
+    backoff: Option<Duration>,
+}
+
+impl Pipe {
+    pub fn new(config: PipeConfig) -> Pipe {
+        Pipe {
+            config,
+            backoff: None,
+        }
+    }
+
+    pub fn mountpoint(&self) -> &str {
+        &self.config.mountpoint
+    }
+
+    // how long to wait before running the command again, after it failed:
+    pub fn backoff(&self) -> Option<Duration> {
+        self.backoff
+    }
+
+    fn failed(&mut self) {
+        self.backoff = Some(match self.backoff {
+            Some(backoff) => (backoff * 2).min(MAX_BACKOFF),
+            None => MIN_BACKOFF,
+        });
+    }
+
+    fn spawn(&self) -> io::Result<Child> {
+        let (program, args) = match self.config.command.split_first() {
+            Some(command) => command,
+            None => return Err(io::Error::new(io::ErrorKind::InvalidInput, "empty command")),
+        };
+
+        Command::new(program)
+            .args(args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .spawn()
+    }
+
+    // runs the command for as long as rx keeps producing audio:
+    pub fn run(&mut self, log: &Log, rx: &Receiver<Frame>) {
+        let mut child = match self.spawn() {
+            Ok(child) => child,
+            Err(e) => {
+                log.error(&format!("Couldn't spawn pipe command {:?} for {}: {}",
+                    self.config.command, self.config.mountpoint, e));
+                self.failed();
+                return;
+            }
+        };
+
+        log.info(&format!("Started pipe command {:?} for {} (pid {})",
+            self.config.command, self.config.mountpoint, child.id()));
+
+        let started = Instant::now();
+        let mut died = false;
+
+        // written from a thread of its own, so a command that stops reading
+        // is noticed here rather than holding us up. how that went comes
+        // back on done:
+        let stdin = child.stdin.take().expect("piped stdin on child");
+        let (queue, queued) = mpsc::sync_channel(QUEUE_LENGTH);
+        let (done_tx, done) = mpsc::channel();
+
+        thread::spawn(move || {
+            let _ = done_tx.send(feed(stdin, queued));
+        });
+
+        while let Some(frame) = rx.recv() {
+            let buffer = match frame {
+                Frame::Audio(buffer) => buffer,
+                Frame::Metadata(_) => continue,
+                Frame::End => break,
+            };
+
+            if !enqueue(&queue, buffer) {
+                log.error(&format!("Pipe command for {} stopped reading, stopping it",
+                    self.config.mountpoint));
+                died = true;
+                break;
+            }
+        }
+
+        // closing the queue closes stdin once everything's written, which
+        // tells the command the stream is over:
+        drop(queue);
+
+        // a killed command can't hold up the writer either:
+        if died {
+            let _ = child.kill();
+        }
+
+        match done.recv_timeout(WRITE_TIMEOUT) {
+            Ok(Ok(())) => (),
+            Ok(Err(e)) => {
+                if !died {
+                    log.error(&format!("Pipe command for {} stopped accepting data: {:?}",
+                        self.config.mountpoint, e));
+                    died = true;
+                }
+            }
+            Err(_) => {
+                log.error(&format!("Pipe command for {} stopped reading, stopping it",
+                    self.config.mountpoint));
+                let _ = child.kill();
+                died = true;
+            }
+        }
+
+        match child.wait() {
+            Ok(status) => log.info(&format!("Pipe command for {} exited with {}",
+                self.config.mountpoint, status)),
+            Err(e) => log.error(&format!("Couldn't wait on pipe command for {}: {:?}",
+                self.config.mountpoint, e)),
+        }
+
+        if died {
+            self.failed();
+        } else if started.elapsed() >= STABLE_AFTER {
+            self.backoff = None;
+        }
+    }
+}
+
+// Writes everything queued to the command, until the queue's closed.
+fn feed(mut stdin: ChildStdin, queued: mpsc::Receiver<StreamData>) -> io::Result<()> {
+    for buffer in queued {
+        stdin.write_all(&buffer)?;
+    }
+
+    Ok(())
+}
+
+// Queues buffer for the command, waiting up to WRITE_TIMEOUT for it to make
+// room. Returns false if it didn't, or writing to it has failed.
+fn enqueue(queue: &SyncSender<StreamData>, buffer: StreamData) -> bool {
+    let started = Instant::now();
+    let mut buffer = buffer;
+
+    loop {
+        match queue.try_send(buffer) {
+            Ok(()) => return true,
+            Err(TrySendError::Full(unsent)) if started.elapsed() < WRITE_TIMEOUT => {
+                buffer = unsent;
+                thread::sleep(FULL_POLL_INTERVAL);
+            }
+            Err(_) => return false,
+        }
+    }
+}
@@ -0,0 +1,95 @@
+// A page with a player for a mountpoint, at /mount/player, so a station can
+// share a link to listen in the browser without building a website. It
+// keeps the now playing details and artwork up to date from /mount.json.
+// /mount/embed is a smaller version for other sites to put in an iframe.
+
+use playlist::xml_escape;
+
+// how often the page checks for new metadata, in milliseconds:
+const POLL_INTERVAL: u32 = 10000;
+
+pub const CONTENT_TYPE: &'static str = "text/html; charset=utf-8";
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Layout {
+    Page,
+    Embed,
+}
+
+pub struct Paths {
+    pub stream: String,
+    pub json: String,
+    pub cover: String,
+    // where the page's oEmbed is, for sites to discover:
+    pub oembed: String,
+}
+
+pub fn render(layout: Layout, title: &str, paths: &Paths) -> String {
+    format!(r#"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<meta name="viewport" content="width=device-width, initial-scale=1">
+<title>{title}</title>
+<link rel="alternate" type="application/json+oembed" href="{oembed}" title="{title}">
+<style>
+body {{ font-family: sans-serif; max-width: 24em; margin: 2em auto; padding: 0 1em; text-align: center; }}
+img {{ width: 100%; border-radius: 4px; }}
+audio {{ width: 100%; margin-top: 1em; }}
+#artist {{ color: #666; }}
+body.embed {{ max-width: none; margin: 0; padding: 0.5em; text-align: left; }}
+body.embed h1 {{ display: none; }}
+body.embed img {{ float: left; width: 3.5em; margin-right: 0.5em; }}
+body.embed p {{ margin: 0; }}
+body.embed audio {{ margin-top: 0.5em; }}
+</style>
+</head>
+<body class="{class}" data-json="{json}" data-cover="{cover}">
+<h1>{title}</h1>
+<img id="cover" alt="" hidden>
+<p><strong id="title"></strong><br><span id="artist"></span></p>
+<audio controls preload="none" src="{stream}"></audio>
+<script>
+(function() {{
+  var body = document.body;
+  var cover = document.getElementById("cover");
+  var last = null;
+
+  cover.onerror = function() {{ cover.hidden = true; }};
+  cover.onload = function() {{ cover.hidden = false; }};
+
+  function update() {{
+    fetch(body.dataset.json, {{ cache: "no-store" }})
+      .then(function(response) {{ return response.ok ? response.json() : null; }})
+      .then(function(mount) {{
+        var title = mount ? mount.title || "" : "Off air";
+        var artist = mount ? mount.artist || "" : "";
+
+        document.getElementById("title").textContent = title;
+        document.getElementById("artist").textContent = artist;
+
+        // artwork goes with the track, so is only fetched again when it
+        // changes:
+        if (title + artist !== last) {{
+          last = title + artist;
+          cover.src = body.dataset.cover + "?" + encodeURIComponent(last);
+        }}
+      }})
+      .catch(function() {{}});
+  }}
+
+  update();
+  setInterval(update, {interval});
+}})();
+</script>
+</body>
+</html>
+"#,
+        title = xml_escape(title),
+        class = if layout == Layout::Embed { "embed" } else { "page" },
+        stream = xml_escape(&paths.stream),
+        json = xml_escape(&paths.json),
+        cover = xml_escape(&paths.cover),
+        oembed = xml_escape(&paths.oembed),
+        interval = POLL_INTERVAL)
+}
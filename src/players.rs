@@ -0,0 +1,62 @@
+// Listener counts by player, per mountpoint, for the stats API. Like
+// Transfer this is only kept in memory, so starts over on restart.
+
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Mutex;
+
+#[derive(Serialize, Clone, Default)]
+pub struct PlayerCounts {
+    // listeners that have connected since rustcast started:
+    pub connections: u64,
+    // listeners connected right now:
+    pub listening: u64,
+}
+
+pub struct Players {
+    // by mountpoint, then player:
+    mounts: Mutex<HashMap<String, BTreeMap<&'static str, PlayerCounts>>>,
+}
+
+impl Players {
+    pub fn new() -> Players {
+        Players {
+            mounts: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn connect(&self, mountpoint: &str, player: &'static str) {
+        let mut mounts = self.mounts.lock().expect("lock on player counts");
+
+        let counts = mounts.entry(mountpoint.to_owned())
+            .or_insert_with(BTreeMap::new)
+            .entry(player)
+            .or_insert_with(PlayerCounts::default);
+
+        counts.connections += 1;
+        counts.listening += 1;
+    }
+
+    // A listener back within the dedup window, who isn't counted as a new
+    // connection.
+    pub fn reconnect(&self, mountpoint: &str, player: &'static str) {
+        let mut mounts = self.mounts.lock().expect("lock on player counts");
+
+        mounts.entry(mountpoint.to_owned())
+            .or_insert_with(BTreeMap::new)
+            .entry(player)
+            .or_insert_with(PlayerCounts::default)
+            .listening += 1;
+    }
+
+    pub fn disconnect(&self, mountpoint: &str, player: &'static str) {
+        let mut mounts = self.mounts.lock().expect("lock on player counts");
+
+        if let Some(counts) = mounts.get_mut(mountpoint).and_then(|players| players.get_mut(player)) {
+            counts.listening = counts.listening.saturating_sub(1);
+        }
+    }
+
+    pub fn stats(&self) -> HashMap<String, BTreeMap<&'static str, PlayerCounts>> {
+        self.mounts.lock().expect("lock on player counts").clone()
+    }
+}
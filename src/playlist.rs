@@ -0,0 +1,52 @@
+// Small playlist files pointing at a stream, for "click to listen" links
+// that open in desktop players.
+
+#[derive(Clone, Copy)]
+pub enum PlaylistFormat {
+    M3u,
+    Pls,
+    Xspf,
+}
+
+impl PlaylistFormat {
+    pub fn content_type(&self) -> &'static str {
+        match *self {
+            PlaylistFormat::M3u => "audio/x-mpegurl",
+            PlaylistFormat::Pls => "audio/x-scpls",
+            PlaylistFormat::Xspf => "application/xspf+xml",
+        }
+    }
+}
+
+pub fn xml_escape(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+pub fn render(format: PlaylistFormat, stream_url: &str, title: &str) -> String {
+    match format {
+        PlaylistFormat::M3u => {
+            format!("#EXTM3U\n#EXTINF:-1,{}\n{}\n", title, stream_url)
+        }
+        PlaylistFormat::Pls => {
+            format!("[playlist]\nNumberOfEntries=1\nFile1={}\nTitle1={}\nLength1=-1\nVersion=2\n",
+                stream_url, title)
+        }
+        PlaylistFormat::Xspf => {
+            format!(concat!(
+                "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n",
+                "<playlist version=\"1\" xmlns=\"http://xspf.org/ns/0/\">\n",
+                "  <trackList>\n",
+                "    <track>\n",
+                "      <location>{}</location>\n",
+                "      <title>{}</title>\n",
+                "    </track>\n",
+                "  </trackList>\n",
+                "</playlist>\n"),
+                xml_escape(stream_url), xml_escape(title))
+        }
+    }
+}
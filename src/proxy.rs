@@ -0,0 +1,59 @@
+use std::net::IpAddr;
+
+use tiny_http::Request;
+
+use config::Config;
+
+fn header<'a>(req: &'a Request, field: &str) -> Option<&'a str> {
+    req.headers().iter()
+        .find(|header| header.field.equiv(field))
+        .map(|header| header.value.as_str())
+}
+
+fn from_trusted_proxy(config: &Config, req: &Request) -> bool {
+    config.trusted_proxies.contains(&req.remote_addr().ip())
+}
+
+// The address of the client that made req. Forwarding headers are only
+// believed when the connection comes from a trusted proxy, and then the
+// client is the last address in X-Forwarded-For not added by one of our own
+// proxies.
+pub fn client_ip(config: &Config, req: &Request) -> IpAddr {
+    let peer = req.remote_addr().ip();
+
+    if !from_trusted_proxy(config, req) {
+        return peer;
+    }
+
+    if let Some(forwarded_for) = header(req, "X-Forwarded-For") {
+        let mut addrs = forwarded_for.split(',')
+            .map(|addr| addr.trim().parse::<IpAddr>())
+            .collect::<Vec<_>>();
+
+        while let Some(addr) = addrs.pop() {
+            match addr {
+                Ok(addr) if config.trusted_proxies.contains(&addr) => continue,
+                Ok(addr) => return addr,
+                // a malformed entry means anything before it is unreliable:
+                Err(_) => break,
+            }
+        }
+    }
+
+    header(req, "X-Real-IP")
+        .and_then(|addr| addr.trim().parse().ok())
+        .unwrap_or(peer)
+}
+
+// The scheme the client used to reach us, which a TLS terminating proxy
+// reports in X-Forwarded-Proto.
+pub fn client_scheme(config: &Config, req: &Request) -> &'static str {
+    if !from_trusted_proxy(config, req) {
+        return "http";
+    }
+
+    match header(req, "X-Forwarded-Proto").map(str::trim) {
+        Some(proto) if proto.eq_ignore_ascii_case("https") => "https",
+        _ => "http",
+    }
+}
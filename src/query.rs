@@ -0,0 +1,44 @@
+// Query string parameters of listener requests, like ?burst=0. The query is
+// never part of the mountpoint, so it's parsed once up front and handed to
+// whichever handler serves the request.
+
+use std::str::FromStr;
+
+use reqwest::Url;
+
+#[derive(Default)]
+pub struct Query {
+    params: Vec<(String, String)>,
+}
+
+impl Query {
+    // Parses the query string of a request URL, decoding percent escapes and
+    // + as a space. A URL without one has no parameters.
+    pub fn from_url(url: &str) -> Query {
+        let query = match url.find('?') {
+            Some(offset) => &url[offset..],
+            None => return Query::default(),
+        };
+
+        let params = Url::parse("http://localhost/")
+            .and_then(|base| base.join(query))
+            .map(|url| url.query_pairs().into_owned().collect())
+            .unwrap_or_default();
+
+        Query { params }
+    }
+
+    // The value of the first parameter named name. A parameter given without
+    // a value (?name) is the empty string.
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.params.iter()
+            .find(|&&(ref key, _)| key == name)
+            .map(|&(_, ref value)| value.as_str())
+    }
+
+    // The first parameter named name parsed as a T, or None if it's missing
+    // or doesn't parse.
+    pub fn parse<T: FromStr>(&self, name: &str) -> Option<T> {
+        self.get(name).and_then(|value| value.parse().ok())
+    }
+}
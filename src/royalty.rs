@@ -0,0 +1,182 @@
+// Monthly royalty reports from the stats database: every track played with
+// its actual total performances, and listener hours per mountpoint, which
+// between them cover what SoundExchange and PPL style reporting asks for.
+// Once a month has passed its report is written to the configured directory,
+// and any month can be fetched through the admin API.
+
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use chrono::{Datelike, NaiveDate, Utc};
+use rusqlite;
+use serde_json;
+
+use config::{ReportFormat, RoyaltyReports};
+use log::Log;
+use server::Rustcast;
+use stats::{ListenerHours, StatsDb, TrackPlay};
+
+// how often to check whether last month's report needs writing:
+const POLL_INTERVAL: Duration = Duration::from_secs(3600);
+
+#[derive(Debug)]
+pub enum ReportError {
+    Db(rusqlite::Error),
+    Io(io::Error),
+}
+
+#[derive(Serialize)]
+pub struct RoyaltyReport {
+    // "2026-09":
+    pub month: String,
+    pub plays: Vec<TrackPlay>,
+    pub listener_hours: Vec<ListenerHours>,
+}
+
+// Parses a month given as "YYYY-MM" into its first day.
+pub fn parse_month(month: &str) -> Option<NaiveDate> {
+    NaiveDate::parse_from_str(&format!("{}-01", month), "%Y-%m-%d").ok()
+}
+
+fn next_month(month: NaiveDate) -> NaiveDate {
+    if month.month() == 12 {
+        NaiveDate::from_ymd(month.year() + 1, 1, 1)
+    } else {
+        NaiveDate::from_ymd(month.year(), month.month() + 1, 1)
+    }
+}
+
+fn previous_month(month: NaiveDate) -> NaiveDate {
+    if month.month() == 1 {
+        NaiveDate::from_ymd(month.year() - 1, 12, 1)
+    } else {
+        NaiveDate::from_ymd(month.year(), month.month() - 1, 1)
+    }
+}
+
+// The report for the month starting on month, in UTC like the database.
+pub fn report(stats: &StatsDb, db: &Path, month: NaiveDate) -> Result<RoyaltyReport, ReportError> {
+    let from = format!("{} 00:00:00", month.format("%Y-%m-%d"));
+    let to = format!("{} 00:00:00", next_month(month).format("%Y-%m-%d"));
+
+    Ok(RoyaltyReport {
+        month: month.format("%Y-%m").to_string(),
+        plays: stats.track_plays(db, &from, &to).map_err(ReportError::Db)?,
+        listener_hours: stats.listener_hours(db, &from, &to).map_err(ReportError::Db)?,
+    })
+}
+
+fn csv_field(value: &str) -> String {
+    if value.contains(|c| c == ',' || c == '"' || c == '\n' || c == '\r') {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_owned()
+    }
+}
+
+fn write_csv_row<W: Write>(out: &mut W, fields: &[&str]) -> io::Result<()> {
+    let row = fields.iter().map(|field| csv_field(field)).collect::<Vec<_>>();
+    write!(out, "{}\r\n", row.join(","))
+}
+
+fn write_plays_csv<W: Write>(out: &mut W, report: &RoyaltyReport) -> io::Result<()> {
+    write_csv_row(out, &["mountpoint", "artist", "title", "album", "started_at", "ended_at", "performances"])?;
+
+    for play in &report.plays {
+        write_csv_row(out, &[
+            &play.mountpoint,
+            play.artist.as_ref().map(String::as_str).unwrap_or(""),
+            play.title.as_ref().map(String::as_str).unwrap_or(""),
+            play.album.as_ref().map(String::as_str).unwrap_or(""),
+            &play.started_at,
+            &play.ended_at,
+            &play.performances.to_string(),
+        ])?;
+    }
+
+    Ok(())
+}
+
+fn write_listener_hours_csv<W: Write>(out: &mut W, report: &RoyaltyReport) -> io::Result<()> {
+    write_csv_row(out, &["mountpoint", "listener_hours"])?;
+
+    for hours in &report.listener_hours {
+        write_csv_row(out, &[&hours.mountpoint, &format!("{:.3}", hours.listener_hours)])?;
+    }
+
+    Ok(())
+}
+
+// The files a month's report is written to, the first being the one whose
+// existence says the report has been written.
+fn report_paths(config: &RoyaltyReports, month: NaiveDate) -> Vec<PathBuf> {
+    let name = format!("royalty-{}", month.format("%Y-%m"));
+    let dir = Path::new(&config.dir);
+
+    match config.format {
+        ReportFormat::Json => vec![dir.join(format!("{}.json", name))],
+        ReportFormat::Csv => vec![
+            dir.join(format!("{}-plays.csv", name)),
+            dir.join(format!("{}-listener-hours.csv", name)),
+        ],
+    }
+}
+
+fn write_report(config: &RoyaltyReports, report: &RoyaltyReport, month: NaiveDate) -> io::Result<()> {
+    fs::create_dir_all(&config.dir)?;
+
+    let paths = report_paths(config, month);
+
+    match config.format {
+        ReportFormat::Json => {
+            let mut file = File::create(&paths[0])?;
+            serde_json::to_writer_pretty(&mut file, report)
+                .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+        }
+        ReportFormat::Csv => {
+            // listener hours first, so a crash partway through is retried:
+            write_listener_hours_csv(&mut File::create(&paths[1])?, report)?;
+            write_plays_csv(&mut File::create(&paths[0])?, report)?;
+        }
+    }
+
+    Ok(())
+}
+
+pub fn run(rustcast: Arc<Rustcast>) {
+    let log = Log::new();
+
+    while !rustcast.is_shutdown() {
+        let config = rustcast.config();
+
+        if let (Some(reports), Some(db)) = (config.royalty_reports.as_ref(), config.stats_db.as_ref()) {
+            let today = Utc::today().naive_utc();
+            let month = previous_month(NaiveDate::from_ymd(today.year(), today.month(), 1));
+
+            if !report_paths(reports, month)[0].exists() {
+                let result = report(&rustcast.stats, Path::new(db), month)
+                    .and_then(|report| write_report(reports, &report, month).map_err(ReportError::Io));
+
+                match result {
+                    Ok(()) => log.info(&format!("Wrote royalty report for {} to {}",
+                        month.format("%Y-%m"), reports.dir)),
+                    Err(e) => log.error(&format!("Couldn't write royalty report for {}: {:?}",
+                        month.format("%Y-%m"), e)),
+                }
+            }
+        }
+
+        // sleep in short steps so shutting down isn't held up:
+        for _ in 0..POLL_INTERVAL.as_secs() {
+            if rustcast.is_shutdown() {
+                return;
+            }
+
+            thread::sleep(Duration::from_secs(1));
+        }
+    }
+}
@@ -0,0 +1,260 @@
+// The broadcast schedule: weekly time slots saying who may broadcast on a
+// mountpoint when. Sources on a scheduled mountpoint are only let on during
+// one of its slots, the slot_ending webhook is called as a slot comes to an
+// end, and with cutoff on, sources are stopped once it has.
+//
+// Mountpoints without any slots aren't scheduled, and anyone with the
+// password may broadcast on them at any time. Slots are always matched
+// against the mountpoint a source ends up live on, after any stream_start
+// hook has sent it elsewhere, and sources started in-process (shm, pipes,
+// relays and embedders) aren't held to the schedule at all.
+
+use std::collections::HashSet;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use chrono::{self, DateTime, Datelike, NaiveTime, Utc, Weekday};
+use reqwest::Client;
+use uuid::Uuid;
+
+use config::{Schedule, Slot};
+use events::Event;
+use log::Log;
+use mountpoint;
+use server::Rustcast;
+
+// how often live streams are checked against the schedule:
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+fn parse_time(time: &str) -> Option<NaiveTime> {
+    NaiveTime::parse_from_str(time, "%H:%M").ok()
+}
+
+fn parse_day(day: &str) -> Option<Weekday> {
+    Weekday::from_str(day).ok()
+}
+
+// What's wrong with slot, if anything, for config validation.
+pub fn check_slot(slot: &Slot) -> Vec<String> {
+    let mut problems = Vec::new();
+
+    if let Err(e) = mountpoint::normalize(&slot.mount) {
+        problems.push(format!("mount: {:?} {}", slot.mount, e));
+    }
+
+    for &(name, time) in [("start", &slot.start), ("end", &slot.end)].iter() {
+        if parse_time(time).is_none() {
+            problems.push(format!("{}: {:?} must be a time like \"20:00\"", name, time));
+        }
+    }
+
+    for day in slot.days.iter() {
+        if parse_day(day).is_none() {
+            problems.push(format!("days: {:?} isn't a day of the week, use \"mon\" to \"sun\"", day));
+        }
+    }
+
+    problems
+}
+
+fn on_day(slot: &Slot, day: Weekday) -> bool {
+    slot.days.is_empty() || slot.days.iter().any(|d| parse_day(d) == Some(day))
+}
+
+// When the occurrence of slot covering now ends, if there is one.
+fn slot_end(slot: &Slot, now: DateTime<Utc>) -> Option<DateTime<Utc>> {
+    let (start, end) = match (parse_time(&slot.start), parse_time(&slot.end)) {
+        (Some(start), Some(end)) => (start, end),
+        _ => return None,
+    };
+
+    let today = now.date();
+    let time = now.time();
+
+    if start < end {
+        if on_day(slot, today.weekday()) && time >= start && time < end {
+            return today.and_time(end);
+        }
+    } else {
+        // runs past midnight, so could have started today or yesterday:
+        if on_day(slot, today.weekday()) && time >= start {
+            return today.succ().and_time(end);
+        }
+
+        if on_day(slot, today.pred().weekday()) && time < end {
+            return today.and_time(end);
+        }
+    }
+
+    None
+}
+
+fn slot_mountpoint(slot: &Slot) -> Option<String> {
+    mountpoint::normalize(&slot.mount).ok()
+}
+
+struct RemoteSlots {
+    url: String,
+    fetched: Instant,
+    slots: Vec<Slot>,
+}
+
+pub struct Scheduler {
+    remote: Mutex<Option<RemoteSlots>>,
+    // streams already warned their slot is ending:
+    warned: Mutex<HashSet<Uuid>>,
+}
+
+impl Scheduler {
+    pub fn new() -> Scheduler {
+        Scheduler {
+            remote: Mutex::new(None),
+            warned: Mutex::new(HashSet::new()),
+        }
+    }
+
+    // The configured slots, along with any from the schedule URL. If the
+    // URL can't be fetched, the slots it last gave are kept.
+    fn slots(&self, schedule: &Schedule) -> Vec<Slot> {
+        let mut slots = schedule.slots.clone();
+
+        let url = match schedule.url {
+            Some(ref url) => url,
+            None => return slots,
+        };
+
+        let stale = match *self.remote.lock().expect("lock on remote schedule") {
+            Some(ref remote) => remote.url != *url
+                || remote.fetched.elapsed() >= Duration::from_secs(schedule.refresh),
+            None => true,
+        };
+
+        // fetched without the lock, so sources starting meanwhile aren't
+        // held up by a slow booking system:
+        if stale {
+            let fetched = fetch(url);
+            let mut remote = self.remote.lock().expect("lock on remote schedule");
+
+            match fetched {
+                Ok(fetched) => {
+                    *remote = Some(RemoteSlots { url: url.clone(), fetched: Instant::now(), slots: fetched });
+                }
+                Err(e) => {
+                    Log::new().error(&format!("Couldn't fetch schedule from {}: {}", url, e));
+
+                    // try again next refresh, rather than on every check:
+                    match *remote {
+                        Some(ref mut remote) if remote.url == *url => remote.fetched = Instant::now(),
+                        _ => *remote = Some(RemoteSlots { url: url.clone(), fetched: Instant::now(), slots: Vec::new() }),
+                    }
+                }
+            }
+        }
+
+        if let Some(ref remote) = *self.remote.lock().expect("lock on remote schedule") {
+            slots.extend(remote.slots.iter().cloned());
+        }
+
+        slots
+    }
+
+    // When the slot user may broadcast on mountpoint in ends. Some(None) if
+    // the mountpoint isn't scheduled, None if they're outside their slots.
+    fn current_slot_end(&self, schedule: &Schedule, mountpoint: &str, user: Option<&str>) -> Option<Option<DateTime<Utc>>> {
+        let now = Utc::now();
+
+        let slots = self.slots(schedule).into_iter()
+            .filter(|slot| slot_mountpoint(slot).as_ref().map(String::as_str) == Some(mountpoint))
+            .collect::<Vec<_>>();
+
+        if slots.is_empty() {
+            return Some(None);
+        }
+
+        slots.iter()
+            .filter(|slot| slot.user.is_none() || slot.user.as_ref().map(String::as_str) == user)
+            .filter_map(|slot| slot_end(slot, now))
+            .max()
+            .map(Some)
+    }
+
+    // Whether a source with user may start broadcasting on mountpoint now.
+    pub fn allows(&self, schedule: &Schedule, mountpoint: &str, user: Option<&str>) -> bool {
+        self.current_slot_end(schedule, mountpoint, user).is_some()
+    }
+}
+
+fn fetch(url: &str) -> Result<Vec<Slot>, String> {
+    let mut response = Client::new()
+        .get(url)
+        .send()
+        .map_err(|e| format!("{}", e))?;
+
+    if !response.status().is_success() {
+        return Err(format!("{}", response.status()));
+    }
+
+    let slots = response.json::<Vec<Slot>>()
+        .map_err(|e| format!("{}", e))?;
+
+    for (index, slot) in slots.iter().enumerate() {
+        if let Some(problem) = check_slot(slot).into_iter().next() {
+            return Err(format!("slot {}: {}", index, problem));
+        }
+    }
+
+    Ok(slots)
+}
+
+pub fn run(rustcast: Arc<Rustcast>) {
+    while !rustcast.is_shutdown() {
+        let config = rustcast.config();
+
+        if let Some(ref schedule) = config.schedule {
+            let live = rustcast.live_streams().into_iter()
+                .filter(|&(_, ref stream)| stream.is_scheduled());
+
+            for (mountpoint, stream) in live {
+                let user = stream.user();
+
+                match rustcast.schedule.current_slot_end(schedule, &mountpoint, user.as_ref().map(String::as_str)) {
+                    Some(None) => (),
+                    Some(Some(end)) => {
+                        let remaining = end.signed_duration_since(Utc::now());
+
+                        if remaining <= chrono::Duration::seconds(schedule.warning as i64)
+                            && rustcast.schedule.warned.lock().expect("lock on warned streams").insert(stream.uuid)
+                        {
+                            rustcast.publish_event(Event::SlotEnding {
+                                mountpoint: mountpoint.clone(),
+                                uuid: stream.uuid,
+                                user: user.clone(),
+                                remaining: remaining.num_seconds().max(0) as u64,
+                            });
+                        }
+                    }
+                    None => {
+                        if schedule.cutoff && !stream.stopped() {
+                            rustcast.log.info(&format!("Stopping stream {} on {} (slot ended)",
+                                stream.uuid, mountpoint));
+
+                            stream.stop();
+                        }
+                    }
+                }
+            }
+
+            // forget streams that have ended:
+            let live = rustcast.live_streams().into_iter()
+                .map(|(_, stream)| stream.uuid)
+                .collect::<HashSet<_>>();
+
+            rustcast.schedule.warned.lock().expect("lock on warned streams")
+                .retain(|uuid| live.contains(uuid));
+        }
+
+        thread::sleep(POLL_INTERVAL);
+    }
+}
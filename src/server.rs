@@ -1,385 +1,3058 @@
-use std::collections::HashMap;
-use std::fs::File;
-use std::io::{self, Write};
+use std::any::Any;
+use std::cmp;
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::io;
+use std::net::{IpAddr, SocketAddr};
 use std::ops::Deref;
-use std::sync::{Arc, RwLock};
+use std::panic::{self, AssertUnwindSafe};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex, RwLock};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use std::thread;
-use std::time::Instant;
+use std::time::{Duration, Instant};
 
 use base64;
-use lame::Lame;
+use chrono::Utc;
+use lewton::VorbisError;
+use reqwest::Url;
+use serde::Serialize;
 use serde_json;
 use tiny_http::{Server, Request, Method, Response, Header};
 use uuid::Uuid;
 
-use audio::{AudioStream, StreamRead, StreamError, Metadata};
-use config::Config;
-use fanout::{Channel, Receiver};
-use hooks::{self, StreamStart, StreamStartParams, StreamEndParams};
+use admin;
+use archive::{Archive, RawDump};
+use audio::{self, AudioStream, StreamRead, StreamError, Metadata};
+use burst::BurstBuffer;
+use catchup::{self, Catalog};
+use cluster::{self, Cluster};
+use chat::ChatSink;
+use config::{self, Config, ConfigChange, ConfigError, MountConfig, OutputCodec, PipeConfig, ShmSource, SourceAuth};
+use cookie::{self, Cookies};
+use cors;
+#[cfg(unix)]
+use daemon;
+use dedup::{self, Dedup};
+use events::{Event, EventBus, EventSink, HandlerSink, LogSink};
+use fanout::{Channel, CloseReason, Receiver};
+use health;
+use heartbeat;
+use history::History;
+use hooks::{self, Handlers, HttpWebhooks, StreamEndParams, StreamLifecycleHandler, StreamOverrides, StreamStart, StreamStartParams};
+use htpasswd;
+use jwt::{self, Validator};
+use ldap;
+use limit::{DurationLimit, LimitAction};
+use listeners::Listeners;
+use lockout::Lockout;
 use log::Log;
+use metrics;
+use mountpoint;
+use mqtt::{MqttClient, MqttSink};
+use oembed;
 use ogg::OggStream;
+use pace::{self, Pacer};
+use pipe::Pipe;
+use proxy;
+use query::Query;
+use royalty;
+use schedule::{self, Scheduler};
+use player::{self, Layout};
+use players::Players;
+use playlist::{self, PlaylistFormat};
+use shm::{Ring, ShmStream};
+#[cfg(unix)]
+use signal;
+use silence::Silence;
+#[cfg(unix)]
+use sockopt;
+use source::{self, Mp3Source, PcmFormat, PcmSource};
+use sourcehealth::{self, HealthChange, Monitor};
+use stats::{StatsDb, StatsSink};
+use statsd;
+use tee::{EncoderSink, Tee};
+use timeshift::{self, Timeshift};
+use transfer::{self, CountingWriter, Transfer};
+use useragent;
+use yp;
+
+pub type StreamData = Arc<Box<[u8]>>;
+
+// What a stream sends its listeners: its audio, with anything they might
+// want to act on in between.
+#[derive(Clone)]
+pub enum Frame {
+    Audio(StreamData),
+    Metadata(Arc<Metadata>),
+    // the source has gone, and nothing more will follow:
+    End,
+}
 
-type StreamData = Arc<Box<[u8]>>;
+// A source still being authenticated, which holds its mountpoint until it
+// goes live or gives up.
+#[derive(Clone)]
+pub struct Pending {
+    pub uuid: Uuid,
+    pub since: Instant,
+    pub user: Option<String>,
+    pub client_ip: Option<IpAddr>,
+}
 
 #[derive(Clone)]
-enum StreamEntry {
-    Starting,
+pub enum StreamEntry {
+    Starting(Pending),
     Live(Arc<Stream>),
 }
 
-struct Rustcast {
-    log: Log,
-    config: Config,
+pub struct Rustcast {
+    pub log: Log,
+    config_path: PathBuf,
+    config: RwLock<Arc<Config>>,
+    silence: Silence,
+    history: History,
+    pub transfer: Arc<Transfer>,
+    pub players: Players,
+    pub listeners: Arc<Listeners>,
+    pub catalog: Arc<Catalog>,
+    pub cluster: Cluster,
+    pub dedup: Dedup,
+    lockout: Lockout,
+    pub stats: Arc<StatsDb>,
     streams: RwLock<HashMap<String, StreamEntry>>,
+    // the configured webhooks, the first of the handlers:
+    webhooks: Arc<HttpWebhooks>,
+    handlers: RwLock<Handlers>,
+    events: EventBus,
+    jwt: Validator,
+    pub schedule: Scheduler,
+    mqtt: Arc<MqttClient>,
+    shutdown: AtomicBool,
 }
 
+// how long a source taking over a mountpoint waits for the old source to
+// stop:
+const TAKEOVER_TIMEOUT: Duration = Duration::from_secs(5);
+
+// how often the server checks whether it's been shut down:
+const SHUTDOWN_POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+// how long shutting down waits for streams to end, so their stream_end
+// events go out:
+const SHUTDOWN_STREAMS_TIMEOUT: Duration = Duration::from_secs(5);
+
+// Each kind of event sink gets a worker thread of its own, see events.rs.
+#[derive(Clone, Copy)]
+enum SinkKind {
+    Handlers,
+    Log,
+    Mqtt,
+    Stats,
+    Chat,
+}
+
+const SINK_KINDS: [(SinkKind, &'static str); 5] = [
+    (SinkKind::Handlers, "webhooks"),
+    (SinkKind::Log, "log"),
+    (SinkKind::Mqtt, "mqtt"),
+    (SinkKind::Stats, "stats_db"),
+    (SinkKind::Chat, "notifications"),
+];
+
 #[derive(Debug)]
-enum StartStreamError {
+pub enum StartStreamError {
     AlreadyLive,
+    // the source's password or token is wrong:
+    Unauthorized,
+    // the source is who it says, but isn't allowed on the mountpoint now:
     Rejected,
+    // authenticating took longer than source_auth_timeout, and another
+    // source may have the mountpoint now:
+    TimedOut,
     Hook(hooks::HookError),
 }
 
 impl Rustcast {
-    pub fn new(config: Config) -> Rustcast {
+    pub fn new(config_path: PathBuf, config: Config) -> Rustcast {
+        let config = Arc::new(config);
+        let webhooks = Arc::new(HttpWebhooks::new(Arc::clone(&config)));
+
         Rustcast {
-            log: Log::new(),
-            config: config,
+            log: Log::keeping_recent(RECENT_LOG_LINES),
+            config_path,
+            config: RwLock::new(config),
+            silence: Silence::new(),
+            history: History::new(),
+            transfer: Arc::new(Transfer::new()),
+            players: Players::new(),
+            listeners: Arc::new(Listeners::new()),
+            catalog: Arc::new(Catalog::new()),
+            cluster: Cluster::new(),
+            jwt: Validator::new(),
+            schedule: Scheduler::new(),
+            dedup: Dedup::new(),
+            lockout: Lockout::new(),
+            stats: Arc::new(StatsDb::new()),
             streams: RwLock::new(HashMap::new()),
+            handlers: RwLock::new(vec![Arc::clone(&webhooks) as Arc<StreamLifecycleHandler>]),
+            webhooks,
+            events: EventBus::new(),
+            mqtt: Arc::new(MqttClient::new()),
+            shutdown: AtomicBool::new(false),
         }
     }
 
-    pub fn get_stream(&self, mountpoint: &str) -> Option<StreamEntry> {
-        self.streams.read()
-            .expect("reader lock on streams")
-            .get(mountpoint).cloned()
+    // Registers an in-process handler for stream and listener events, which
+    // is called after the configured webhooks.
+    pub fn add_handler(&self, handler: Arc<StreamLifecycleHandler>) {
+        self.handlers.write()
+            .expect("writer lock on handlers")
+            .push(handler);
     }
 
-    pub fn start_stream<'a>(&'a self, mountpoint: &str, password: Option<&str>) -> Result<StreamSource<'a>, StartStreamError> {
-        // insert stream entry in starting state to lock this mountpoint while
-        // we auth:
-        {
-            let mut streams = self.streams.write()
-                .expect("writer lock on streams");
+    fn handlers(&self) -> Handlers {
+        self.handlers.read()
+            .expect("reader lock on handlers")
+            .clone()
+    }
 
-            if let Some(_) = streams.get(mountpoint) {
-                return Err(StartStreamError::AlreadyLive);
-            }
+    // the sinks of kind each event goes to, according to the current config:
+    fn sinks(&self, kind: SinkKind) -> Vec<Box<EventSink>> {
+        let config = self.config();
+        let mut sinks: Vec<Box<EventSink>> = Vec::new();
 
-            streams.insert(mountpoint.to_owned(), StreamEntry::Starting);
+        match kind {
+            SinkKind::Handlers => {
+                sinks.push(Box::new(HandlerSink::new(Arc::clone(&config), self.handlers())));
+            }
+            SinkKind::Log => {
+                if config.events.log {
+                    sinks.push(Box::new(LogSink));
+                }
+            }
+            SinkKind::Mqtt => {
+                if let Some(ref mqtt) = config.mqtt {
+                    sinks.push(Box::new(MqttSink::new(Arc::clone(&self.mqtt), mqtt.clone(), Arc::clone(&self.listeners))));
+                }
+            }
+            SinkKind::Stats => {
+                if let Some(ref path) = config.stats_db {
+                    sinks.push(Box::new(StatsSink::new(Arc::clone(&self.stats), PathBuf::from(path))));
+                }
+            }
+            SinkKind::Chat => {
+                for notification in &config.notifications {
+                    sinks.push(Box::new(ChatSink::new(Arc::clone(&config), notification.clone())));
+                }
+            }
         }
 
-        // authenticate stream source:
-        let stream = Arc::new(Stream::new());
-
-        // StreamSource will remove the mountpoint on drop:
-        let stream_source = StreamSource {
-            rustcast: self,
-            mountpoint: mountpoint.to_owned(),
-            stream: Arc::clone(&stream),
-        };
+        sinks
+    }
 
-        let params = StreamStartParams {
-            mountpoint: mountpoint,
-            uuid: &stream.uuid,
-            password: password,
-        };
+    pub fn publish_event(&self, event: Event) {
+        self.events.publish(event);
+    }
 
-        match hooks::stream_start(&self.config, params) {
-            Ok(StreamStart::Ok) => (),
-            Ok(StreamStart::Reject) => return Err(StartStreamError::Rejected),
-            Err(e) => return Err(StartStreamError::Hook(e)),
-        }
+    // Logs an error an operator would want to know about, and publishes it
+    // as an event.
+    fn report_error(&self, mountpoint: Option<&str>, message: String) {
+        self.log.error(&message);
 
-        // auth success, insert live stream entry into mountpoints:
-        {
-            let mut streams = self.streams.write()
-                .expect("writer lock on streams");
+        self.publish_event(Event::Error {
+            mountpoint: mountpoint.map(str::to_owned),
+            message,
+        });
+    }
 
-            let stream_ref = streams.get_mut(mountpoint)
-                .expect("mountpoint to exist in streams in Starting state");
+    // Stops accepting connections and ends every live stream. Background
+    // threads notice within a second or so.
+    pub fn shutdown(&self) {
+        self.log.info("Shutting down");
+        self.shutdown.store(true, Ordering::SeqCst);
 
-            *stream_ref = StreamEntry::Live(Arc::clone(&stream));
+        // listeners would otherwise only notice once their source does:
+        for (_, stream) in self.live_streams() {
+            stream.close_listeners(CloseReason::Shutdown);
         }
-
-        Ok(stream_source)
     }
-}
 
-struct StreamSource<'a> {
-    rustcast: &'a Rustcast,
-    mountpoint: String,
-    stream: Arc<Stream>,
-}
+    pub fn is_shutdown(&self) -> bool {
+        self.shutdown.load(Ordering::SeqCst)
+    }
 
-impl<'a> Drop for StreamSource<'a> {
-    fn drop(&mut self) {
-        let mut streams = self.rustcast.streams.write()
-            .expect("writer lock on streams");
+    // Sleeps for duration, or until shut down if that's sooner, so
+    // background threads that wait a while between runs don't hold up
+    // shutting down.
+    pub fn sleep(&self, duration: Duration) {
+        let started = Instant::now();
 
-        streams.remove(&self.mountpoint);
-    }
-}
+        while !self.is_shutdown() {
+            let elapsed = started.elapsed();
 
-impl<'a> Deref for StreamSource<'a> {
-    type Target = Arc<Stream>;
+            if elapsed >= duration {
+                break;
+            }
 
-    fn deref(&self) -> &Self::Target {
-        &self.stream
+            thread::sleep(cmp::min(SHUTDOWN_POLL_INTERVAL, duration - elapsed));
+        }
     }
-}
 
-struct Stream {
-    channel: Channel<StreamData>,
-    metadata: RwLock<Metadata>,
-    uuid: Uuid,
-}
+    // Waits up to timeout for every stream to end, once shut down.
+    fn wait_for_streams(&self, timeout: Duration) {
+        let started = Instant::now();
 
-impl Stream {
-    pub fn new() -> Stream {
-        Stream {
-            channel: Channel::new(16),
-            metadata: RwLock::new(Metadata { artist: None, title: None }),
-            uuid: Uuid::new_v4(),
+        while started.elapsed() < timeout && !self.streams.read().expect("reader lock on streams").is_empty() {
+            thread::sleep(Duration::from_millis(100));
         }
     }
 
-    pub fn publish(&self, bytes: StreamData) {
-        self.channel.publish(bytes);
+    pub fn config(&self) -> Arc<Config> {
+        Arc::clone(&self.config.read().expect("reader lock on config"))
     }
 
-    pub fn subscribe(&self) -> Receiver<StreamData> {
-        self.channel.subscribe()
-    }
-}
+    // Rereads the config file and swaps it in. Most settings are read as
+    // they're needed and apply straight away (to streams started after the
+    // reload), a few are only read at startup.
+    pub fn reload_config(&self) -> Result<Vec<ConfigChange>, ConfigError> {
+        let mut new_config = config::open(&self.config_path)?;
 
-fn audio_stream(req: Request) -> Box<AudioStream> {
-    let source = req.upgrade("icecast", Response::empty(200));
-    let ogg = OggStream::new(source).unwrap();
-    Box::new(ogg)
-}
+        let changes = {
+            let mut current = self.config.write()
+                .expect("writer lock on config");
 
-fn password_from_headers(headers: &[Header]) -> Option<String> {
-    headers.iter()
-        .filter(|header| header.field.equiv("Authorization"))
-        .filter_map(|header| {
-            let mut h = header.value.as_str().split(" ");
-            if let Some("Basic") = h.next() {
-                h.next()
-            } else {
-                None
+            if let Some(ref listen) = current.listen_override {
+                new_config.override_listen(listen.clone());
             }
-        })
-        .nth(0)
-        .and_then(|basic| base64::decode(basic).ok())
-        .and_then(|bytes| String::from_utf8(bytes).ok())
-        .and_then(|creds| creds.split(":").nth(1).map(str::to_owned))
-}
 
-fn handle_source(rustcast: &Rustcast, req: Request) -> io::Result<()> {
-    let password = password_from_headers(req.headers());
-    let password_ref = password.as_ref().map(String::as_str);
+            let changes = config::diff(&current, &new_config);
+            *current = Arc::new(new_config);
+            self.webhooks.set_config(Arc::clone(&current));
+            changes
+        };
 
-    let stream = match rustcast.start_stream(req.url(), password_ref) {
-        Ok(stream) => {
-            stream
+        for change in &changes {
+            self.log.info(&format!("Config changed: {} ({})", change.key,
+                if change.live { "applied" } else { "requires restart" }));
         }
-        Err(StartStreamError::AlreadyLive) => {
-            rustcast.log.info(&format!("Stream already live on {}, rejecting new source", req.url()));
 
-            return req.respond(Response::from_string("<h1>Stream already live</h1>")
-                .with_status_code(409));
+        self.publish_event(Event::ConfigReloaded {
+            changes: changes.clone(),
+        });
+
+        Ok(changes)
+    }
+
+    pub fn update_metadata(&self, mountpoint: &str, stream: &Stream, mut metadata: Metadata) {
+        if let Some(prefix) = stream.overrides().metadata_prefix {
+            metadata.title = metadata.title.map(|title| format!("{}{}", prefix, title));
         }
-        Err(StartStreamError::Rejected) => {
-            rustcast.log.info(&format!("Rejecting stream source on {}", req.url()));
 
-            return req.respond(Response::from_string("<h1>Forbidden</h1>")
-                .with_status_code(403));
+        let mut current = stream.metadata.write().unwrap();
+
+        if *current == metadata {
+            return;
         }
-        Err(StartStreamError::Hook(e)) => {
-            rustcast.log.error(&format!("stream_start hook failed for {}: {:?}", req.url(), e));
 
-            return req.respond(Response::from_string("<h1>Internal Server Error</h1>")
-                .with_status_code(500));
+        let config = self.config();
+
+        self.history.record(mountpoint, &metadata, config.history_size);
+
+        self.publish_event(Event::MetadataChanged {
+            mountpoint: mountpoint.to_owned(),
+            uuid: stream.uuid,
+            metadata: metadata.clone(),
+        });
+
+        for (mirror_mountpoint, mirror) in stream.mirrors() {
+            self.update_metadata(&mirror_mountpoint, &mirror, metadata.clone());
         }
-    };
 
-    let stream_dump_path = rustcast.config.stream_dump.replace("{uuid}",
-        &format!("{}", stream.uuid.hyphenated()));
+        stream.publish_metadata(&metadata);
 
-    let mut stream_dump = File::create(stream_dump_path)?;
+        *current = metadata;
+    }
 
-    let mut audio_stream = audio_stream(req);
+    pub fn get_stream(&self, mountpoint: &str) -> Option<StreamEntry> {
+        self.streams.read()
+            .expect("reader lock on streams")
+            .get(mountpoint).cloned()
+    }
 
-    // ogg reports bitrate in bits per second, but LAME's idea of bitrate
-    // is in kilobits per second:
-    let kilobitrate = audio_stream.bitrate_nominal() / 1000;
+    // live streams that aren't hidden, for anything listing mountpoints to
+    // the public:
+    pub fn public_streams(&self) -> Vec<(String, Arc<Stream>)> {
+        let config = self.config();
 
-    let mut lame = Lame::new().unwrap();
-    lame.set_sample_rate(audio_stream.sample_rate()).unwrap();
-    lame.set_channels(audio_stream.channels()).unwrap();
-    lame.set_quality(0).unwrap();
-    lame.set_kilobitrate(kilobitrate).unwrap();
-    lame.init_params().unwrap();
+        self.live_streams().into_iter()
+            .filter(|&(ref mountpoint, _)| !config.hidden(mountpoint))
+            .collect()
+    }
 
-    let start = Instant::now();
+    pub fn live_streams(&self) -> Vec<(String, Arc<Stream>)> {
+        self.streams.read()
+            .expect("reader lock on streams")
+            .iter()
+            .filter_map(|(mountpoint, entry)| match *entry {
+                StreamEntry::Live(ref stream) => Some((mountpoint.clone(), Arc::clone(stream))),
+                StreamEntry::Starting(_) => None,
+            })
+            .collect()
+    }
 
-    rustcast.log.info(&format!("Started stream {} on {} ({} {}hz {}ch {}kbps)",
-        stream.uuid,
-        stream.mountpoint,
-        audio_stream.codec_name(),
-        audio_stream.sample_rate(),
-        audio_stream.channels(),
-        audio_stream.bitrate_nominal() / 1000));
+    // Sources still being authenticated, for spotting ones stuck on a hook
+    // that never answers.
+    pub fn starting_streams(&self) -> Vec<(String, Pending)> {
+        self.streams.read()
+            .expect("reader lock on streams")
+            .iter()
+            .filter_map(|(mountpoint, entry)| match *entry {
+                StreamEntry::Starting(ref pending) => Some((mountpoint.clone(), pending.clone())),
+                StreamEntry::Live(_) => None,
+            })
+            .collect()
+    }
 
-    loop {
-        let packet = match audio_stream.read() {
-            Err(StreamError::IoError(_)) => break,
-            Err(StreamError::BadPacket) => continue,
-            Ok(StreamRead::Eof) => break,
-            Ok(StreamRead::Audio(packet)) => packet,
-            Ok(StreamRead::Metadata(metadata)) => {
-                *stream.metadata.write().unwrap() = metadata;
-                continue;
+    // Locks mountpoint for pending if nothing has it, returning the live
+    // stream there otherwise. A source that's been starting for longer than
+    // source_auth_timeout is taken to be stuck, and loses the mountpoint.
+    fn lock_mountpoint(&self, streams: &mut HashMap<String, StreamEntry>, mountpoint: &str, pending: &Pending) -> Result<Option<Arc<Stream>>, StartStreamError> {
+        let timeout = Duration::from_secs(self.config().source_auth_timeout);
+
+        match streams.get(mountpoint).cloned() {
+            Some(StreamEntry::Starting(ref stuck)) if stuck.since.elapsed() >= timeout => {
+                self.log.info(&format!("Giving up on stream {} on {} (stuck starting for {}s)",
+                    stuck.uuid, mountpoint, stuck.since.elapsed().as_secs()));
             }
+            Some(StreamEntry::Starting(_)) => return Err(StartStreamError::AlreadyLive),
+            Some(StreamEntry::Live(current)) => return Ok(Some(current)),
+            None => (),
+        }
+
+        streams.insert(mountpoint.to_owned(), StreamEntry::Starting(pending.clone()));
+        Ok(None)
+    }
+
+    // Whether a source with user may broadcast on mountpoint now.
+    fn within_schedule(&self, mountpoint: &str, user: Option<&str>) -> bool {
+        match self.config().schedule {
+            Some(ref schedule) => self.schedule.allows(schedule, mountpoint, user),
+            None => true,
+        }
+    }
+
+    // Checks a source is allowed on mountpoint, returning its priority and
+    // any settings the hooks override.
+    fn authenticate_source(&self, config: &Arc<Config>, mountpoint: &str, stream: &Stream, user: Option<&str>, password: Option<&str>, client_ip: Option<IpAddr>, trusted: bool) -> Result<(u32, StreamOverrides), StartStreamError> {
+        // sources rustcast starts itself have no password to check:
+        let priority = if trusted {
+            0
+        } else {
+            self.check_source_credentials(config, mountpoint, user, password)?
         };
 
-        assert!(packet.len() == (audio_stream.channels() as usize));
+        let url = config.public_stream_url(mountpoint);
 
-        let (left, right) = match packet.len() {
-            1     => (&packet[0], &packet[0]),
-            2 | _ => (&packet[0], &packet[1]),
+        let params = StreamStartParams {
+            mountpoint: mountpoint,
+            uuid: &stream.uuid,
+            url: url.as_ref().map(String::as_str),
+            ip: client_ip,
+            user: user,
+            password: password,
         };
 
-        let num_samples = left.len();
+        // the hooks have the final say on priority if they give one:
+        match hooks::start_stream(&self.handlers(), &params) {
+            Ok(StreamStart::Ok { priority: hook_priority, overrides }) =>
+                Ok((hook_priority.unwrap_or(priority), overrides)),
+            Ok(StreamStart::Reject) => Err(StartStreamError::Rejected),
+            Err(e) => Err(StartStreamError::Hook(e)),
+        }
+    }
 
-        // vector size calculation is a suggestion from lame/lame.h:
-        let mut mp3buff: Vec<u8> = vec![0; (num_samples * 5) / 4 + 7200];
+    // Checks a source's password, returning the priority it gives. Which
+    // credentials are tried goes by what the mountpoint has configured, not
+    // what the password looks like: its passwords (or htpasswd or LDAP) are
+    // tried first, then the password as a JWT if [jwt] is set. A mountpoint
+    // without passwords takes only tokens once [jwt] is set, and anyone
+    // otherwise.
+    fn check_source_credentials(&self, config: &Config, mountpoint: &str, user: Option<&str>, password: Option<&str>) -> Result<u32, StartStreamError> {
+        let mount = config.mount(mountpoint);
+        let given = password.unwrap_or("");
+
+        // whether the mountpoint has passwords of its own, none of which
+        // matched:
+        let has_passwords = if let Some(auth) = mount.and_then(|mount| mount.source_auth.as_ref()) {
+            let user = user.unwrap_or("");
+
+            let result = match *auth {
+                SourceAuth::Htpasswd { ref file } => htpasswd::check(file, user, given)
+                    .map_err(|e| format!("{:?}", e)),
+                SourceAuth::Ldap { ref url, ref bind_dn } => ldap::check(url, bind_dn, user, given)
+                    .map_err(|e| format!("{}", e)),
+            };
 
-        let buff = match lame.encode(left, right, &mut mp3buff) {
-            Ok(sz) => {
-                mp3buff.resize(sz, 0);
-                Arc::new(mp3buff.into_boxed_slice())
+            match result {
+                Ok(true) => return Ok(0),
+                Ok(false) => true,
+                Err(e) => {
+                    self.report_error(Some(mountpoint), format!("Couldn't check source {:?} on {}: {}", user, mountpoint, e));
+                    return Err(StartStreamError::Rejected);
+                }
             }
-            Err(e) => panic!("lame encode error: {:?}", e),
-        };
+        } else {
+            let sources = mount.map(|mount| &mount.sources[..]).unwrap_or(&[]);
 
-        stream_dump.write_all(&buff)?;
-        stream.publish(buff);
-    };
+            let expected = mount.and_then(|mount| mount.password.as_ref())
+                .or(config.source_password(mountpoint));
 
-    rustcast.log.info(&format!("Finished stream {} on {} (duration {} sec)",
-        stream.uuid,
-        stream.mountpoint,
-        start.elapsed().as_secs()));
+            let user = sources.iter()
+                .find(|user| admin::constant_time_eq(user.password.as_bytes(), given.as_bytes()));
 
-    let params = StreamEndParams {
-        mountpoint: &stream.mountpoint,
-        uuid: &stream.uuid,
-    };
+            if let Some(user) = user {
+                return Ok(user.priority);
+            }
 
-    match hooks::stream_end(&rustcast.config, params) {
-        Ok(()) => (),
-        Err(e) => {
-            rustcast.log.error(&format!("stream_end hook failed for {}: {:?}", stream.mountpoint, e));
+            if let Some(expected) = expected {
+                if admin::constant_time_eq(expected.as_bytes(), given.as_bytes()) {
+                    return Ok(0);
+                }
+            }
+
+            expected.is_some() || !sources.is_empty()
+        };
+
+        let jwt = match config.jwt {
+            Some(ref jwt) => jwt,
+            None if has_passwords => return Err(StartStreamError::Unauthorized),
+            None => return Ok(0),
+        };
+
+        // a JWT allowing the mountpoint is enough on its own:
+        match self.jwt.validate(jwt, given) {
+            Ok(ref claims) if claims.allows(&jwt.source_claim, mountpoint) => {
+                Ok(claims.priority().unwrap_or(0))
+            }
+            Ok(_) => {
+                self.log.info(&format!("Source token for {} doesn't allow it", mountpoint));
+                Err(StartStreamError::Rejected)
+            }
+            Err(e) => {
+                self.log.info(&format!("Source for {} gave a wrong password or invalid token: {:?}", mountpoint, e));
+                Err(StartStreamError::Unauthorized)
+            }
         }
     }
 
-    Ok(())
-}
+    // Waits for a source that's been kicked off mountpoint to let go of it,
+    // then locks the mountpoint for pending.
+    fn take_over(&self, mountpoint: &str, pending: &Pending) -> Result<(), StartStreamError> {
+        let started = Instant::now();
 
-enum RequestFormat {
-    Mp3,
-    Json,
-}
+        loop {
+            {
+                let mut streams = self.streams.write()
+                    .expect("writer lock on streams");
 
-fn extract_request_format(path: &str) -> (RequestFormat, String) {
-    fn chomp<'a>(string: &'a str, suffix: &str) -> Option<&'a str> {
-        if string.ends_with(suffix) {
-            Some(&string[0..(string.len() - suffix.len())])
-        } else {
-            None
+                if let Ok(None) = self.lock_mountpoint(&mut streams, mountpoint, pending) {
+                    return Ok(());
+                }
+            }
+
+            if started.elapsed() >= TAKEOVER_TIMEOUT {
+                return Err(StartStreamError::AlreadyLive);
+            }
+
+            thread::sleep(Duration::from_millis(50));
         }
     }
 
-    if let Some(mountpoint) = chomp(path, ".mp3") {
-        (RequestFormat::Mp3, mountpoint.to_owned())
-    } else if let Some(mountpoint) = chomp(path, ".json") {
-        (RequestFormat::Json, mountpoint.to_owned())
-    } else {
-        (RequestFormat::Mp3, path.to_owned())
+    // Checks a source with priority may take over from current, a live
+    // stream on mountpoint. current is left playing until the new source
+    // is producing audio, see StreamSource::producing.
+    fn take_over_from<'a>(&'a self, mountpoint: &str, current: Arc<Stream>, stream: &Arc<Stream>, pending: &Pending, priority: u32) -> Result<StreamSource<'a>, StartStreamError> {
+        if !current.mirror && !current.is_relay() && priority <= current.priority() {
+            return Err(StartStreamError::AlreadyLive);
+        }
+
+        Ok(StreamSource::new(self, mountpoint, stream, Some(Takeover {
+            current,
+            pending: pending.clone(),
+        })))
     }
-}
 
-#[derive(Serialize)]
-struct MountpointJson {
-    artist: Option<String>,
-    title: Option<String>,
-}
+    // Kicks the stream a source is taking over from, then locks the
+    // mountpoint for the source once the old one lets go of it.
+    fn finish_takeover(&self, mountpoint: &str, takeover: Takeover) -> Result<(), StartStreamError> {
+        let Takeover { current, pending } = takeover;
 
-fn handle_client(rustcast: &Rustcast, req: Request) -> io::Result<()> {
-    use std::io::prelude::*;
+        self.log.info(&format!("Stream {} on {} taken over by higher priority source",
+            current.uuid, mountpoint));
 
-    let (format, mountpoint) = extract_request_format(req.url());
+        current.kick();
 
-    let stream = match rustcast.get_stream(&mountpoint) {
-        Some(StreamEntry::Live(stream)) => stream,
-        Some(StreamEntry::Starting) | None =>
-            return req.respond(
-                Response::from_string("<h1>Not found</h1>\n")
-                    .with_status_code(404)),
-    };
+        // nothing's running a mirror to notice it was kicked, so take it
+        // down here:
+        if current.mirror {
+            self.remove_stream(mountpoint, &current);
+        }
 
-    match format {
-        RequestFormat::Mp3 => {
-            let mut response = req.into_writer();
-            response.write_all(b"HTTP/1.0 200 OK\r\nServer: Rustcast\r\nContent-Type: audio/mpeg\r\n\r\n")?;
+        drop(current);
 
-            let rx = stream.subscribe();
-            while let Some(buffer) = rx.recv() {
-                response.write_all(&buffer)?;
-            }
+        self.take_over(mountpoint, &pending)
+    }
 
-            Ok(())
-        }
-        RequestFormat::Json => {
-            let data = {
-                let metadata = stream.metadata.read().unwrap();
+    // Locks mountpoint for an already authenticated stream, taking over from
+    // whatever's live there if stream's priority allows.
+    fn claim<'a>(&'a self, mountpoint: &str, stream: &Arc<Stream>, pending: &Pending, priority: u32) -> Result<StreamSource<'a>, StartStreamError> {
+        let current = {
+            let mut streams = self.streams.write()
+                .expect("writer lock on streams");
 
-                MountpointJson {
-                    artist: metadata.artist.clone(),
-                    title: metadata.title.clone(),
-                }
-            };
+            self.lock_mountpoint(&mut streams, mountpoint, pending)?
+        };
 
-            req.respond(Response::from_string(serde_json::to_string(&data).unwrap())
-                .with_status_code(200))
+        match current {
+            Some(current) => self.take_over_from(mountpoint, current, stream, pending, priority),
+            None => Ok(StreamSource::new(self, mountpoint, stream, None)),
         }
     }
-}
 
-fn handle_request(rustcast: Arc<Rustcast>, req: Request) -> io::Result<()> {
-    match *req.method() {
-        Method::Source => handle_source(&rustcast, req),
-        Method::Get => handle_client(&rustcast, req),
-        _ => {
-            req.respond(Response::from_string("<h1>Method not allowed</h1>\n")
-                .with_status_code(404))
+    // Puts a source live on the mountpoint it's locked, once it has it.
+    fn go_live(&self, source: &StreamSource) -> Result<(), StartStreamError> {
+        let mountpoint = &source.mountpoint;
+        let stream = &source.stream;
+
+        {
+            let mut streams = self.streams.write()
+                .expect("writer lock on streams");
+
+            // if authenticating took long enough for the mountpoint to be
+            // given up on, another source may have it by now:
+            let ours = match streams.get(mountpoint) {
+                Some(&StreamEntry::Starting(ref pending)) => pending.uuid == stream.uuid,
+                _ => false,
+            };
+
+            if !ours {
+                self.log.info(&format!("Stream {} on {} took too long to start",
+                    stream.uuid, mountpoint));
+
+                return Err(StartStreamError::TimedOut);
+            }
+
+            streams.insert(mountpoint.to_owned(), StreamEntry::Live(Arc::clone(stream)));
         }
+
+        source.live.store(true, Ordering::SeqCst);
+
+        stream.set_buffer_sizes(&self.config(), mountpoint);
+        self.start_mirrors(mountpoint, stream);
+
+        self.publish_event(Event::StreamStarted {
+            mountpoint: mountpoint.to_owned(),
+            uuid: stream.uuid,
+        });
+
+        Ok(())
     }
-}
 
-pub fn run(config: Config) {
-    let rustcast = Arc::new(Rustcast::new(config));
+    // Tells the hooks that a source they accepted has ended without going
+    // live, so each stream_start they see is followed by a stream_end.
+    fn abandon(&self, mountpoint: &str, uuid: &Uuid) {
+        let params = StreamEndParams { mountpoint, uuid, duration: 0 };
 
-    let server = Server::http(&rustcast.config.listen).unwrap();
+        hooks::notify(&self.handlers(), &format!("stream_end for {}", mountpoint),
+            |handler| handler.stream_end(&params));
+    }
 
-    rustcast.log.info(&format!("Listening on {}", rustcast.config.listen));
+    // Starts a stream for a source client, which must give the mountpoint's
+    // credentials whether or not its address is known.
+    pub fn start_stream<'a>(&'a self, mountpoint: &str, password: Option<&str>, client_ip: Option<IpAddr>) -> Result<StreamSource<'a>, StartStreamError> {
+        self.open_stream(mountpoint, None, password, client_ip, false)
+    }
 
-    for request in server.incoming_requests() {
-        let rustcast = rustcast.clone();
-        thread::spawn(move || {
-            handle_request(rustcast, request)
-        });
+    // Like start_stream, for a source that gave a user name as well, which
+    // source_auth checks and the hooks are told.
+    pub fn start_stream_as<'a>(&'a self, mountpoint: &str, user: Option<&str>, password: Option<&str>, client_ip: Option<IpAddr>) -> Result<StreamSource<'a>, StartStreamError> {
+        self.open_stream(mountpoint, user, password, client_ip, false)
+    }
+
+    // Starts a stream for a source inside the process: shm sources, relays
+    // and sources made through Handle, which aren't asked for a password or
+    // held to the schedule. The stream_start hooks still have their say.
+    pub fn start_in_process<'a>(&'a self, mountpoint: &str) -> Result<StreamSource<'a>, StartStreamError> {
+        self.open_stream(mountpoint, None, None, None, true)
     }
+
+    fn open_stream<'a>(&'a self, mountpoint: &str, user: Option<&str>, password: Option<&str>, client_ip: Option<IpAddr>, trusted: bool) -> Result<StreamSource<'a>, StartStreamError> {
+        // insert stream entry in starting state to lock this mountpoint while
+        // we auth. a live mountpoint can still be taken over by a higher
+        // priority source, so that's checked once the new source is authed:
+        let stream = Arc::new(Stream::new());
+
+        let pending = Pending {
+            uuid: stream.uuid,
+            since: Instant::now(),
+            user: user.map(str::to_owned),
+            client_ip,
+        };
+
+        let current = {
+            let mut streams = self.streams.write()
+                .expect("writer lock on streams");
+
+            self.lock_mountpoint(&mut streams, mountpoint, &pending)?
+        };
+
+        // authenticate stream source:
+
+        // gives the mountpoint back if the source doesn't get it, without
+        // ending anything. only made if we locked the mountpoint, and turned
+        // into a StreamSource once it's known the stream stays here:
+        let locked = match current {
+            Some(_) => None,
+            None => Some(Locked {
+                rustcast: self,
+                mountpoint: Some(mountpoint.to_owned()),
+                uuid: stream.uuid,
+            }),
+        };
+
+        let (priority, overrides) = self.authenticate_source(&self.config(), mountpoint, &stream, user, password, client_ip, trusted)?;
+
+        let mut accepted = Accepted {
+            rustcast: self,
+            mountpoint,
+            uuid: stream.uuid,
+            started: false,
+        };
+
+        // the hooks can send the stream to another mountpoint:
+        let rewrite = match overrides.mountpoint {
+            Some(ref target) => match mountpoint::normalize(&self.config().within_station(mountpoint, target)) {
+                Ok(ref target) if target == mountpoint => None,
+                Ok(target) => Some(target),
+                Err(e) => {
+                    self.report_error(Some(mountpoint), format!("stream_start hook gave an invalid mountpoint for {}: {}",
+                        mountpoint, e));
+                    return Err(StartStreamError::Rejected);
+                }
+            },
+            None => None,
+        };
+
+        let target = rewrite.as_ref().map(String::as_str).unwrap_or(mountpoint);
+
+        // sources can't be sent anywhere they couldn't connect to themselves:
+        if rewrite.is_some() && self.config().strict_mounts && self.config().mount(target).is_none() {
+            self.log.info(&format!("Rejecting stream {} for {}, stream_start hook sent it to unknown mountpoint {}",
+                stream.uuid, mountpoint, target));
+            return Err(StartStreamError::Rejected);
+        }
+
+        // the schedule is checked on the mountpoint the stream will be live
+        // on, as schedule::run enforces it there:
+        if !trusted {
+            if !self.within_schedule(target, user) {
+                self.log.info(&format!("Source {} on {} is outside its scheduled slots",
+                    user.unwrap_or("(no user)"), target));
+                return Err(StartStreamError::Rejected);
+            }
+
+            stream.set_scheduled();
+        }
+
+        stream.set_priority(priority);
+        stream.set_overrides(overrides);
+        stream.set_user(user);
+
+        let stream_source = match (rewrite, locked, current) {
+            (Some(target), locked, _) => {
+                // let go of the mountpoint the source asked for, which it
+                // never went live on:
+                drop(locked);
+
+                self.log.info(&format!("Stream {} for {} sent to {} by stream_start hook",
+                    stream.uuid, mountpoint, target));
+
+                self.claim(&target, &stream, &pending, priority)?
+            }
+            (None, Some(locked), _) => locked.into_source(&stream),
+            (None, None, Some(current)) => self.take_over_from(mountpoint, current, &stream, &pending, priority)?,
+            (None, None, None) => unreachable!(),
+        };
+
+        // a source taking over goes live once it's producing audio:
+        if !stream_source.is_taking_over() {
+            self.go_live(&stream_source)?;
+        }
+
+        accepted.started = true;
+
+        Ok(stream_source)
+    }
+
+    // Removes mountpoint, as long as it's still stream that's live there.
+    fn remove_stream(&self, mountpoint: &str, stream: &Arc<Stream>) {
+        let mut streams = self.streams.write()
+            .expect("writer lock on streams");
+
+        let current = match streams.get(mountpoint) {
+            Some(&StreamEntry::Live(ref current)) => Arc::ptr_eq(current, stream),
+            _ => false,
+        };
+
+        if current {
+            streams.remove(mountpoint);
+        }
+    }
+
+    // Puts stream live on the mountpoints in its mount's mirror_to too.
+    // Mountpoints that already have a source are left alone.
+    fn start_mirrors(&self, mountpoint: &str, stream: &Stream) {
+        let config = self.config();
+
+        let targets = match config.mount(mountpoint) {
+            Some(mount) => &mount.mirror_to,
+            None => return,
+        };
+
+        let mut streams = self.streams.write()
+            .expect("writer lock on streams");
+
+        let mut mirrors = stream.mirrors.write()
+            .expect("writer lock on mirrors");
+
+        for target in targets.iter().map(|target| config.within_station(mountpoint, target)) {
+            if streams.contains_key(&target) {
+                self.log.info(&format!("Not mirroring {} to {} (already live)", mountpoint, target));
+                continue;
+            }
+
+            let mirror = Arc::new(Stream { mirror: true, ..Stream::new() });
+            mirror.set_buffer_sizes(&config, &target);
+
+            streams.insert(target.clone(), StreamEntry::Live(Arc::clone(&mirror)));
+            mirrors.push((target.clone(), mirror));
+
+            self.log.info(&format!("Mirroring {} to {}", mountpoint, target));
+        }
+    }
+}
+
+pub struct StreamSource<'a> {
+    rustcast: &'a Rustcast,
+    mountpoint: String,
+    stream: Arc<Stream>,
+    // the live stream this is taking over from, until it's producing audio:
+    takeover: Mutex<Option<Takeover>>,
+    // whether the stream has gone live on the mountpoint:
+    live: AtomicBool,
+}
+
+// A live stream a source is to take over from, holding what the source
+// locks the mountpoint with once it's kicked.
+struct Takeover {
+    current: Arc<Stream>,
+    pending: Pending,
+}
+
+// A source the stream_start hooks have accepted. Unless it's marked
+// started, dropping it tells the hooks the source ended, as it was turned
+// away after the hooks had their say.
+struct Accepted<'a> {
+    rustcast: &'a Rustcast,
+    mountpoint: &'a str,
+    uuid: Uuid,
+    started: bool,
+}
+
+impl<'a> Drop for Accepted<'a> {
+    fn drop(&mut self) {
+        if !self.started {
+            self.rustcast.abandon(self.mountpoint, &self.uuid);
+        }
+    }
+}
+
+// A mountpoint locked for a source that's still being authenticated. Unlike
+// StreamSource, dropping it only gives the mountpoint back, as nothing's
+// been published there.
+struct Locked<'a> {
+    rustcast: &'a Rustcast,
+    // None once handed on to a StreamSource, which gives it back instead:
+    mountpoint: Option<String>,
+    uuid: Uuid,
+}
+
+impl<'a> Locked<'a> {
+    fn into_source(mut self, stream: &Arc<Stream>) -> StreamSource<'a> {
+        let mountpoint = self.mountpoint.take().expect("a locked mountpoint");
+        StreamSource::new(self.rustcast, &mountpoint, stream, None)
+    }
+}
+
+impl<'a> Drop for Locked<'a> {
+    fn drop(&mut self) {
+        let mountpoint = match self.mountpoint.take() {
+            Some(mountpoint) => mountpoint,
+            None => return,
+        };
+
+        let mut streams = self.rustcast.streams.write()
+            .expect("writer lock on streams");
+
+        let ours = match streams.get(&mountpoint) {
+            Some(&StreamEntry::Starting(ref pending)) => pending.uuid == self.uuid,
+            _ => false,
+        };
+
+        if ours {
+            streams.remove(&mountpoint);
+        }
+    }
+}
+
+impl<'a> StreamSource<'a> {
+    fn new(rustcast: &'a Rustcast, mountpoint: &str, stream: &Arc<Stream>, takeover: Option<Takeover>) -> StreamSource<'a> {
+        StreamSource {
+            rustcast,
+            mountpoint: mountpoint.to_owned(),
+            stream: Arc::clone(stream),
+            takeover: Mutex::new(takeover),
+            live: AtomicBool::new(false),
+        }
+    }
+
+    pub fn mountpoint(&self) -> &str {
+        &self.mountpoint
+    }
+
+    fn is_taking_over(&self) -> bool {
+        self.takeover.lock().expect("lock on takeover").is_some()
+    }
+
+    // Whether the stream went live, rather than giving up while it was
+    // still taking over.
+    pub fn went_live(&self) -> bool {
+        self.live.load(Ordering::SeqCst)
+    }
+
+    // Called by sources as they produce audio. A source taking over from
+    // another only kicks it now, so a new source that stalls before sending
+    // anything doesn't leave the mountpoint dead, and goes live in its
+    // place. Does nothing once the source is live.
+    pub fn producing(&self) -> Result<(), StartStreamError> {
+        let takeover = match self.takeover.lock().expect("lock on takeover").take() {
+            Some(takeover) => takeover,
+            None => return Ok(()),
+        };
+
+        self.rustcast.finish_takeover(&self.mountpoint, takeover)?;
+        self.rustcast.go_live(self)
+    }
+}
+
+impl<'a> Drop for StreamSource<'a> {
+    fn drop(&mut self) {
+        let mut streams = self.rustcast.streams.write()
+            .expect("writer lock on streams");
+
+        // a source that was given up on while starting no longer has its
+        // mountpoint, so leaves it to whoever does:
+        let ours = match streams.get(&self.mountpoint) {
+            Some(&StreamEntry::Starting(ref pending)) => pending.uuid == self.stream.uuid,
+            Some(&StreamEntry::Live(ref stream)) => Arc::ptr_eq(stream, &self.stream),
+            None => false,
+        };
+
+        if ours {
+            streams.remove(&self.mountpoint);
+        }
+
+        self.stream.publish_end();
+
+        // only take down mirrors that are still ours:
+        for (mountpoint, mirror) in self.stream.mirrors() {
+            let ours = match streams.get(&mountpoint) {
+                Some(&StreamEntry::Live(ref stream)) => Arc::ptr_eq(stream, &mirror),
+                _ => false,
+            };
+
+            if ours {
+                streams.remove(&mountpoint);
+                mirror.publish_end();
+            }
+        }
+    }
+}
+
+impl<'a> Deref for StreamSource<'a> {
+    type Target = Arc<Stream>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.stream
+    }
+}
+
+pub struct Stream {
+    channel: Channel<Frame>,
+    pub metadata: RwLock<Metadata>,
+    pub uuid: Uuid,
+    priority: AtomicUsize,
+    // output bitrate in kbps, or 0 if it's not known:
+    bitrate: AtomicUsize,
+    // set when a higher priority source takes over the mountpoint. shared
+    // so that listeners can tell why the stream ended after letting go of
+    // it:
+    kicked: Arc<AtomicBool>,
+    // streams on other mountpoints that everything published here is also
+    // published to, from mirror_to:
+    mirrors: RwLock<Vec<(String, Arc<Stream>)>>,
+    // whether this is a mirror of another mountpoint, which gives way to any
+    // source of the mountpoint's own:
+    mirror: bool,
+    // whether this is relayed from another node in the cluster, which also
+    // gives way to any source connecting here:
+    relay: AtomicBool,
+    burst: Mutex<BurstBuffer>,
+    timeshift: Arc<Timeshift>,
+    // settings the stream_start hooks gave for this stream:
+    overrides: RwLock<StreamOverrides>,
+    // the user name the source gave, if any:
+    user: RwLock<Option<String>>,
+    // set to stop the source, like when its slot in the schedule ends:
+    stopped: AtomicBool,
+    // how well the source is getting audio to us, once it's sending:
+    health: Mutex<Option<Monitor>>,
+    // whether the schedule applies, which it does to every source but those
+    // started in-process:
+    scheduled: AtomicBool,
+    // what the source is sending, once its headers have been read:
+    input: RwLock<Option<InputFormat>>,
+    started: Instant,
+    // milliseconds of audio decoded from the source, which unlike the time
+    // since it started doesn't count stalls in its connection:
+    position: AtomicUsize,
+}
+
+// The audio a source is sending.
+#[derive(Clone)]
+pub struct InputFormat {
+    pub codec: &'static str,
+    pub sample_rate: u32,
+    pub channels: u8,
+    // nominal bitrate in kbps, if the source gives one:
+    pub bitrate: Option<u32>,
+}
+
+impl Stream {
+    pub fn new() -> Stream {
+        Stream {
+            channel: Channel::new(config::DEFAULT_LISTENER_BUFFER),
+            metadata: RwLock::new(Metadata::default()),
+            uuid: Uuid::new_v4(),
+            priority: AtomicUsize::new(0),
+            bitrate: AtomicUsize::new(0),
+            kicked: Arc::new(AtomicBool::new(false)),
+            mirrors: RwLock::new(Vec::new()),
+            mirror: false,
+            relay: AtomicBool::new(false),
+            burst: Mutex::new(BurstBuffer::new()),
+            timeshift: Arc::new(Timeshift::new()),
+            overrides: RwLock::new(StreamOverrides::default()),
+            user: RwLock::new(None),
+            stopped: AtomicBool::new(false),
+            health: Mutex::new(None),
+            scheduled: AtomicBool::new(false),
+            input: RwLock::new(None),
+            started: Instant::now(),
+            position: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn priority(&self) -> u32 {
+        self.priority.load(Ordering::SeqCst) as u32
+    }
+
+    pub fn is_mirror(&self) -> bool {
+        self.mirror
+    }
+
+    pub fn is_relay(&self) -> bool {
+        self.relay.load(Ordering::SeqCst)
+    }
+
+    pub fn set_relay(&self) {
+        self.relay.store(true, Ordering::SeqCst);
+    }
+
+    fn set_priority(&self, priority: u32) {
+        self.priority.store(priority as usize, Ordering::SeqCst);
+    }
+
+    pub fn bitrate(&self) -> Option<u32> {
+        match self.bitrate.load(Ordering::SeqCst) {
+            0 => None,
+            bitrate => Some(bitrate as u32),
+        }
+    }
+
+    pub fn overrides(&self) -> StreamOverrides {
+        self.overrides.read()
+            .expect("reader lock on stream overrides")
+            .clone()
+    }
+
+    fn set_overrides(&self, overrides: StreamOverrides) {
+        *self.overrides.write().expect("writer lock on stream overrides") = overrides;
+    }
+
+    pub fn user(&self) -> Option<String> {
+        self.user.read().expect("reader lock on stream user").clone()
+    }
+
+    fn set_user(&self, user: Option<&str>) {
+        *self.user.write().expect("writer lock on stream user") = user.map(str::to_owned);
+    }
+
+    pub fn stop(&self) {
+        self.stopped.store(true, Ordering::SeqCst);
+    }
+
+    pub fn stopped(&self) -> bool {
+        self.stopped.load(Ordering::SeqCst)
+    }
+
+    fn monitor_health(&self, bytes_read: Option<u64>) {
+        *self.health.lock().expect("lock on source health") = Some(Monitor::new(bytes_read));
+    }
+
+    fn with_health<F: FnOnce(&mut Monitor)>(&self, f: F) {
+        if let Some(ref mut health) = *self.health.lock().expect("lock on source health") {
+            f(health);
+        }
+    }
+
+    // Judges the stream's source if its source_health window is up.
+    pub fn check_health(&self, settings: &config::SourceHealth) -> Option<HealthChange> {
+        self.health.lock().expect("lock on source health")
+            .as_mut()
+            .and_then(|health| health.check(settings))
+    }
+
+    pub fn is_scheduled(&self) -> bool {
+        self.scheduled.load(Ordering::SeqCst)
+    }
+
+    fn set_scheduled(&self) {
+        self.scheduled.store(true, Ordering::SeqCst);
+    }
+
+    pub fn dump_enabled(&self) -> bool {
+        self.overrides().dump != Some(false)
+    }
+
+    pub fn input(&self) -> Option<InputFormat> {
+        self.input.read().expect("reader lock on stream input").clone()
+    }
+
+    fn set_input(&self, input: InputFormat) {
+        *self.input.write().expect("writer lock on stream input") = Some(input.clone());
+
+        for &(_, ref mirror) in self.mirrors().iter() {
+            mirror.set_input(input.clone());
+        }
+    }
+
+    // How long the stream has been live.
+    pub fn uptime(&self) -> Duration {
+        self.started.elapsed()
+    }
+
+    // How much audio has been decoded from the source, or None for sources
+    // that aren't decoded.
+    pub fn audio_duration(&self) -> Option<Duration> {
+        match self.position.load(Ordering::SeqCst) {
+            0 => None,
+            millis => Some(Duration::from_millis(millis as u64)),
+        }
+    }
+
+    fn set_position(&self, seconds: f64) {
+        self.position.store((seconds * 1000.0) as usize, Ordering::SeqCst);
+    }
+
+    fn set_bitrate(&self, kilobitrate: i32) {
+        self.bitrate.store(cmp::max(kilobitrate, 0) as usize, Ordering::SeqCst);
+
+        for &(_, ref mirror) in self.mirrors().iter() {
+            mirror.set_bitrate(kilobitrate);
+        }
+    }
+
+    fn kick(&self) {
+        self.kicked.store(true, Ordering::SeqCst);
+
+        for &(_, ref mirror) in self.mirrors().iter() {
+            mirror.kick();
+        }
+    }
+
+    // Sizes the buffers kept for listeners by the mountpoint's config.
+    fn set_buffer_sizes(&self, config: &Config, mountpoint: &str) {
+        let size = config.mount(mountpoint)
+            .and_then(|mount| mount.burst_size)
+            .unwrap_or(0);
+
+        self.burst.lock()
+            .expect("lock on burst buffer")
+            .set_size(size);
+
+        self.channel.set_buffer_size(config.listener_buffer(mountpoint));
+
+        let timeshift = config.mount(mountpoint)
+            .and_then(|mount| mount.timeshift)
+            .unwrap_or(0);
+
+        self.timeshift.set_length(Duration::from_secs(timeshift));
+    }
+
+    // Throws away the recent audio kept for new listeners, once it's no
+    // longer recent.
+    pub fn clear_burst(&self) {
+        self.burst.lock()
+            .expect("lock on burst buffer")
+            .clear();
+
+        for &(_, ref mirror) in self.mirrors().iter() {
+            mirror.clear_burst();
+        }
+    }
+
+    fn mirrors(&self) -> Vec<(String, Arc<Stream>)> {
+        self.mirrors.read()
+            .expect("reader lock on mirrors")
+            .clone()
+    }
+
+    pub fn kicked(&self) -> bool {
+        self.kicked.load(Ordering::SeqCst)
+    }
+
+    pub fn publish(&self, bytes: StreamData) {
+        let mut kicked_mirrors = false;
+
+        for &(_, ref mirror) in self.mirrors.read().expect("reader lock on mirrors").iter() {
+            if mirror.kicked() {
+                kicked_mirrors = true;
+            } else {
+                mirror.publish(Arc::clone(&bytes));
+            }
+        }
+
+        // let go of mirrors whose mountpoints have a source of their own now,
+        // so their listeners move over to it:
+        if kicked_mirrors {
+            self.mirrors.write()
+                .expect("writer lock on mirrors")
+                .retain(|&(_, ref mirror)| !mirror.kicked());
+        }
+
+        // the burst buffer is held while publishing, so a listener
+        // subscribing with a burst gets neither a gap nor a repeat:
+        let mut burst = self.burst.lock().expect("lock on burst buffer");
+        burst.push(Arc::clone(&bytes));
+        self.timeshift.push(Arc::clone(&bytes));
+        self.channel.publish(Frame::Audio(bytes));
+    }
+
+    // Mirrors have their metadata updated separately, so aren't sent this.
+    fn publish_metadata(&self, metadata: &Metadata) {
+        self.channel.publish(Frame::Metadata(Arc::new(metadata.clone())));
+    }
+
+    // Tells listeners the source has gone, without waiting for everything
+    // holding on to the stream to let go of it.
+    fn publish_end(&self) {
+        self.channel.publish(Frame::End);
+        self.timeshift.end();
+    }
+
+    pub fn subscribe(&self) -> Receiver<Frame> {
+        self.channel.subscribe()
+    }
+
+    // Subscribes along with up to burst bytes of the most recent audio, to
+    // send before anything received.
+    pub fn subscribe_with_burst(&self, burst: usize) -> (Vec<StreamData>, Receiver<Frame>) {
+        let buffer = self.burst.lock().expect("lock on burst buffer");
+        let rx = self.channel.subscribe();
+        (buffer.tail(burst), rx)
+    }
+
+    // Listens delay behind live, if the mount keeps a timeshift.
+    pub fn subscribe_timeshifted(&self, delay: Duration) -> Option<timeshift::Reader> {
+        if self.timeshift.enabled() {
+            Some(Timeshift::read(&self.timeshift, delay))
+        } else {
+            None
+        }
+    }
+
+    pub fn listeners(&self) -> usize {
+        self.channel.subscriber_count() + self.timeshift.readers()
+    }
+
+    // Disconnects everyone listening right now. The stream carries on, and
+    // new listeners can still join. Returns how many were disconnected.
+    pub fn close_listeners(&self, reason: CloseReason) -> usize {
+        self.channel.close(reason) + self.timeshift.close_readers()
+    }
+
+    // Whether anything would get what's published, here or on a mirror.
+    pub fn has_listeners(&self) -> bool {
+        self.listeners() > 0 || self.mirrors().iter()
+            .any(|&(_, ref mirror)| !mirror.kicked() && mirror.has_listeners())
+    }
+}
+
+fn audio_stream(rustcast: &Rustcast, stream: &StreamSource, req: Request) -> Result<Box<AudioStream>, VorbisError> {
+    use std::io::Write;
+
+    // clients sending PUT with Expect: 100-continue wait to be told to go
+    // ahead before sending any audio. that's only an interim response, so
+    // the real one follows it:
+    let continues = expects_continue(req.headers());
+    let status = if continues { 100 } else { 200 };
+
+    let mut source = req.upgrade("icecast", Response::empty(status));
+
+    if continues {
+        let ok = source.write_all(b"HTTP/1.0 200 OK\r\nServer: Rustcast\r\n\r\n")
+            .and_then(|()| source.flush());
+
+        // the source's gone, which reading its audio will find out:
+        if let Err(e) = ok {
+            rustcast.log.info(&format!("Couldn't answer source on {}: {}", stream.mountpoint, e));
+        }
+    }
+
+    let source: Box<io::Read + Send> = if stream.dump_enabled() {
+        RawDump::wrap(rustcast.config(), &stream.mountpoint, stream.uuid, source)
+    } else {
+        Box::new(source)
+    };
+
+    Ok(Box::new(OggStream::new(source)?))
+}
+
+fn expects_continue(headers: &[Header]) -> bool {
+    headers.iter()
+        .filter(|header| header.field.equiv("Expect"))
+        .any(|header| header.value.as_str().eq_ignore_ascii_case("100-continue"))
+}
+
+fn content_type(headers: &[Header]) -> Option<&str> {
+    headers.iter()
+        .find(|header| header.field.equiv("Content-Type"))
+        .map(|header| header.value.as_str())
+}
+
+// The user name and password of HTTP basic auth.
+pub fn credentials_from_headers(headers: &[Header]) -> Option<(String, String)> {
+    headers.iter()
+        .filter(|header| header.field.equiv("Authorization"))
+        .filter_map(|header| {
+            let mut h = header.value.as_str().split(" ");
+            if let Some("Basic") = h.next() {
+                h.next()
+            } else {
+                None
+            }
+        })
+        .nth(0)
+        .and_then(|basic| base64::decode(basic).ok())
+        .and_then(|bytes| String::from_utf8(bytes).ok())
+        .and_then(|creds| {
+            let mut parts = creds.splitn(2, ":");
+            match (parts.next(), parts.next()) {
+                (Some(user), Some(password)) => Some((user.to_owned(), password.to_owned())),
+                _ => None,
+            }
+        })
+}
+
+fn bad_request(req: Request) -> io::Result<()> {
+    req.respond(Response::from_string("<h1>Bad request</h1>\n")
+        .with_status_code(400))
+}
+
+// Turns a source away the way Icecast does. Source clients like butt,
+// liquidsoap and Mixxx go by the status code, showing a 401 as a wrong
+// password and a 403 as the mountpoint being in use, and some show the body
+// as it is, so it's plain text rather than HTML.
+fn source_error(req: Request, status: u16, message: &str) -> io::Result<()> {
+    let content_type = Header::from_bytes(&b"Content-Type"[..], &b"text/plain"[..])
+        .expect("valid header");
+
+    let mut response = Response::from_string(format!("{}\n", message))
+        .with_status_code(status)
+        .with_header(content_type);
+
+    if status == 401 {
+        // the realm Icecast gives, which some clients look for:
+        let authenticate = Header::from_bytes(&b"WWW-Authenticate"[..], &b"Basic realm=\"Icecast2 Server\""[..])
+            .expect("valid header");
+
+        response = response.with_header(authenticate);
+    }
+
+    req.respond(response)
+}
+
+fn handle_source(rustcast: &Rustcast, req: Request) -> io::Result<()> {
+    let config = rustcast.config();
+
+    let credentials = credentials_from_headers(req.headers());
+    let user = credentials.as_ref().map(|&(ref user, _)| user.as_str());
+
+    // a JWT can also be given the same ways as listeners give theirs:
+    let password = credentials.as_ref().map(|&(_, ref password)| password.clone())
+        .or_else(|| config.jwt.as_ref()
+            .and_then(|jwt| jwt::token_from_request(jwt, &req, &Query::from_url(req.url()))));
+    let password_ref = password.as_ref().map(String::as_str);
+    let client_ip = proxy::client_ip(&config, &req);
+
+    // not logged, or a brute force would fill the log:
+    if config.source_lockout.is_some() && rustcast.lockout.banned(client_ip) {
+        return source_error(req, 403, "Forbidden");
+    }
+
+    let mountpoint = match mountpoint::from_url(req.url()) {
+        Ok(mountpoint) => mountpoint,
+        Err(e) => {
+            rustcast.log.info(&format!("Rejecting stream source on {:?} from {} ({})",
+                req.url(), client_ip, e));
+
+            return source_error(req, 400, "Invalid mountpoint");
+        }
+    };
+
+    let mountpoint = match config.station_mountpoint(host(&req), &mountpoint) {
+        Some(mountpoint) => mountpoint,
+        None => {
+            rustcast.log.info(&format!("Rejecting stream source on {} from {} (another station's mountpoint)",
+                mountpoint, client_ip));
+
+            return source_error(req, 404, "Not Found");
+        }
+    };
+
+    if config.strict_mounts && config.mount(&mountpoint).is_none() {
+        rustcast.log.info(&format!("Rejecting stream source on unknown mountpoint {} from {}",
+            mountpoint, client_ip));
+
+        return source_error(req, 404, "Mountpoint not configured");
+    }
+
+    // turned away before the mountpoint is locked, rather than failing to
+    // decode once it's live:
+    if let Err(e) = audio::check_source_content_type(content_type(req.headers())) {
+        rustcast.log.info(&format!("Rejecting stream source on {} from {} ({})",
+            mountpoint, client_ip, e));
+
+        return source_error(req, 415, &e);
+    }
+
+    let stream = match rustcast.start_stream_as(&mountpoint, user, password_ref, Some(client_ip)) {
+        Ok(stream) => {
+            rustcast.lockout.succeed(client_ip);
+            stream
+        }
+        Err(StartStreamError::AlreadyLive) => {
+            rustcast.log.info(&format!("Stream already live on {}, rejecting new source from {}",
+                mountpoint, client_ip));
+
+            return source_error(req, 403, "Mountpoint in use");
+        }
+        Err(StartStreamError::Unauthorized) => {
+            rustcast.log.info(&format!("Wrong password for stream source on {} from {}", mountpoint, client_ip));
+
+            if let Some(ref lockout) = config.source_lockout {
+                if rustcast.lockout.fail(client_ip, lockout) {
+                    rustcast.log.info(&format!("Banning {} from sending streams for {} seconds ({} wrong passwords)",
+                        client_ip, lockout.ban, lockout.max_failures));
+                }
+            }
+
+            return source_error(req, 401, "Authentication Required");
+        }
+        Err(StartStreamError::Rejected) => {
+            rustcast.log.info(&format!("Rejecting stream source on {} from {}", mountpoint, client_ip));
+
+            return source_error(req, 403, "Forbidden");
+        }
+        Err(StartStreamError::TimedOut) => {
+            return source_error(req, 503, "Service Unavailable");
+        }
+        Err(StartStreamError::Hook(e)) => {
+            rustcast.log.error(&format!("stream_start hook failed for {}: {:?}", mountpoint, e));
+
+            return source_error(req, 500, "Internal Server Error");
+        }
+    };
+
+    // reading the stream's headers can go wrong too, so happens under the
+    // same guard as the rest of the source:
+    guard_source(rustcast, stream, |stream| {
+        match audio_stream(rustcast, stream, req) {
+            Ok(audio_stream) => transcode(rustcast, stream, audio_stream),
+            Err(e) => {
+                rustcast.log.error(&format!("Couldn't read stream {} on {}: {:?}",
+                    stream.uuid, stream.mountpoint, e));
+            }
+        }
+    });
+
+    Ok(())
+}
+
+fn duration_warning(rustcast: &Rustcast, stream: &StreamSource, remaining: u64) {
+    rustcast.publish_event(Event::DurationWarning {
+        mountpoint: stream.mountpoint.clone(),
+        uuid: stream.uuid,
+        remaining,
+    });
+}
+
+pub fn panic_message(panic: &(Any + Send)) -> &str {
+    if let Some(message) = panic.downcast_ref::<&str>() {
+        message
+    } else if let Some(message) = panic.downcast_ref::<String>() {
+        message
+    } else {
+        "unknown panic"
+    }
+}
+
+// Runs a stream's source, making sure the stream ends properly even if it
+// panics: the dump file is closed as the source returns or unwinds, then the
+// stream_end hook is called, and the mountpoint is freed once stream is
+// dropped.
+fn guard_source<F>(rustcast: &Rustcast, stream: StreamSource, source: F) where F: FnOnce(&StreamSource) {
+    let started = Instant::now();
+
+    let result = panic::catch_unwind(AssertUnwindSafe(|| source(&stream)));
+
+    if let Err(panic) = result {
+        rustcast.report_error(Some(&stream.mountpoint), format!("Stopping stream {} on {} (source crashed: {})",
+            stream.uuid, stream.mountpoint, panic_message(&*panic)));
+    }
+
+    end_stream(rustcast, &stream, started);
+}
+
+pub fn run_source(rustcast: &Rustcast, stream: StreamSource, audio_stream: Box<AudioStream>) -> io::Result<()> {
+    guard_source(rustcast, stream, |stream| transcode(rustcast, stream, audio_stream));
+    Ok(())
+}
+
+fn transcode(rustcast: &Rustcast, stream: &StreamSource, mut audio_stream: Box<AudioStream>) {
+    // settings stay the same for the whole stream, even across reloads:
+    let config = rustcast.config();
+
+    let codec = config.codec(&stream.mountpoint);
+
+    // stream dumps are written as MP3, with Xing headers, so other codecs
+    // only get raw dumps:
+    let archive = if stream.dump_enabled() && codec == OutputCodec::Mp3 {
+        Archive::new(Arc::clone(&config), Arc::clone(&rustcast.catalog), &stream.mountpoint, stream.uuid)
+    } else {
+        None
+    };
+
+    // ogg reports bitrate in bits per second, but encoders' idea of bitrate
+    // is in kilobits per second:
+    let hook_bitrate = stream.overrides().bitrate.filter(|&bitrate| {
+        let valid = codec.allows_bitrate(bitrate);
+
+        if !valid {
+            rustcast.log.error(&format!("Ignoring bitrate {} kbps from stream_start hook for {}, it isn't an {} bitrate",
+                bitrate, stream.mountpoint, codec.name()));
+        }
+
+        valid
+    });
+
+    let kilobitrate = hook_bitrate
+        .or_else(|| config.mount(&stream.mountpoint).and_then(|mount| mount.bitrate))
+        .unwrap_or(audio_stream.bitrate_nominal() / 1000);
+
+    let encoder = EncoderSink::new(Arc::clone(&stream.stream), Arc::clone(&config), &stream.mountpoint,
+        codec, kilobitrate, audio_stream.sample_rate(), audio_stream.channels(), archive);
+
+    let mut tee = Tee::new();
+
+    match encoder {
+        Ok(encoder) => tee.add(Box::new(encoder)),
+        Err(e) => {
+            rustcast.report_error(Some(&stream.mountpoint), format!("Couldn't start stream {} on {}: {}",
+                stream.uuid, stream.mountpoint, e));
+            return;
+        }
+    }
+
+    stream.set_bitrate(kilobitrate);
+    stream.set_input(input_format(&*audio_stream));
+
+    let mut duration_limit = config.mount(&stream.mountpoint)
+        .and_then(DurationLimit::new);
+
+    // chained Ogg streams can change sample rate or channels between tracks:
+    let mut format = (audio_stream.sample_rate(), audio_stream.channels());
+
+    // seconds of audio read, kept in seconds as the sample rate can change:
+    let mut position: f64 = 0.0;
+
+    // packets the encoders have had to drop, and whether they still are:
+    let mut dropped = 0;
+    let mut behind = false;
+
+    stream.monitor_health(audio_stream.bytes_read());
+
+    rustcast.log.info(&format!("Started stream {} on {} ({} {}hz {}ch {}kbps)",
+        stream.uuid,
+        stream.mountpoint,
+        audio_stream.codec_name(),
+        audio_stream.sample_rate(),
+        audio_stream.channels(),
+        audio_stream.bitrate_nominal() / 1000));
+
+    loop {
+        if rustcast.is_shutdown() {
+            rustcast.log.info(&format!("Stopping stream {} on {} (shutting down)",
+                stream.uuid, stream.mountpoint));
+            break;
+        }
+
+        if stream.kicked() {
+            rustcast.log.info(&format!("Stopping stream {} on {} (taken over)",
+                stream.uuid, stream.mountpoint));
+            break;
+        }
+
+        // whatever stopped it has already said why:
+        if stream.stopped() {
+            break;
+        }
+
+        let mut packet = match audio_stream.read() {
+            Err(StreamError::IoError(_)) => break,
+            Err(StreamError::BadPacket) => {
+                stream.with_health(|health| {
+                    health.bad_packet();
+                    health.set_bytes_read(audio_stream.bytes_read());
+                });
+                continue;
+            }
+            Ok(StreamRead::Eof) => break,
+            Ok(StreamRead::Audio(packet)) => packet,
+            Ok(StreamRead::Metadata(metadata)) => {
+                rustcast.update_metadata(&stream.mountpoint, stream, metadata);
+                continue;
+            }
+        };
+
+        assert!(packet.len() == (audio_stream.channels() as usize));
+
+        if let Err(e) = stream.producing() {
+            rustcast.log.info(&format!("Stopping stream {} on {} (couldn't take over: {:?})",
+                stream.uuid, stream.mountpoint, e));
+            break;
+        }
+
+        let sample_rate = audio_stream.sample_rate();
+
+        stream.with_health(|health| {
+            health.packet(packet[0].len() as f64 / sample_rate as f64);
+            health.set_bytes_read(audio_stream.bytes_read());
+        });
+
+        if (sample_rate, audio_stream.channels()) != format {
+            rustcast.log.info(&format!("Stream {} on {} changed from {}hz {}ch to {}hz {}ch, restarting encoders",
+                stream.uuid, stream.mountpoint, format.0, format.1, sample_rate, audio_stream.channels()));
+
+            format = (sample_rate, audio_stream.channels());
+
+            for e in tee.reformat(format.0, format.1) {
+                rustcast.report_error(Some(&stream.mountpoint), format!("Dropped output of stream {} on {} ({})",
+                    stream.uuid, stream.mountpoint, e));
+            }
+
+            stream.set_input(input_format(&*audio_stream));
+        }
+
+        if let Some(limit) = duration_limit.as_mut() {
+            match limit.process(position, sample_rate, &mut packet) {
+                LimitAction::Continue => (),
+                LimitAction::Warn(remaining) => {
+                    rustcast.log.info(&format!("Stream {} on {} will be stopped in {} sec (max duration reached)",
+                        stream.uuid, stream.mountpoint, remaining));
+
+                    duration_warning(rustcast, stream, remaining);
+                }
+                LimitAction::Stop => {
+                    rustcast.log.info(&format!("Stopping stream {} on {} (max duration reached)",
+                        stream.uuid, stream.mountpoint));
+                    break;
+                }
+            }
+        }
+
+        position += packet[0].len() as f64 / sample_rate as f64;
+        stream.set_position(position);
+
+        for e in tee.write(&packet) {
+            rustcast.report_error(Some(&stream.mountpoint), format!("Dropped output of stream {} on {} ({})",
+                stream.uuid, stream.mountpoint, e));
+        }
+
+        // logged when it starts and stops, rather than for every packet:
+        let now_dropped = tee.dropped();
+
+        if now_dropped > dropped && !behind {
+            rustcast.log.error(&format!("Encoding for stream {} on {} is falling behind, dropping audio",
+                stream.uuid, stream.mountpoint));
+            behind = true;
+        } else if now_dropped == dropped && behind {
+            rustcast.log.info(&format!("Encoding for stream {} on {} caught up, {} packets dropped so far",
+                stream.uuid, stream.mountpoint, now_dropped));
+            behind = false;
+        }
+
+        dropped = now_dropped;
+
+        if tee.is_empty() {
+            rustcast.log.info(&format!("Stopping stream {} on {} (no outputs left)",
+                stream.uuid, stream.mountpoint));
+            break;
+        }
+    };
+}
+
+fn input_format(audio_stream: &AudioStream) -> InputFormat {
+    InputFormat {
+        codec: audio_stream.codec_name(),
+        sample_rate: audio_stream.sample_rate(),
+        channels: audio_stream.channels(),
+        bitrate: match audio_stream.bitrate_nominal() / 1000 {
+            bitrate if bitrate > 0 => Some(bitrate as u32),
+            _ => None,
+        },
+    }
+}
+
+// Logs the end of a stream and publishes it, for the stream_end hook.
+pub fn end_stream(rustcast: &Rustcast, stream: &StreamSource, started: Instant) {
+    let connected = started.elapsed().as_secs();
+
+    // a source that never took over has only the hooks to tell:
+    if !stream.went_live() {
+        rustcast.log.info(&format!("Stream {} on {} ended before taking over (connected {} sec)",
+            stream.uuid, stream.mountpoint, connected));
+
+        rustcast.abandon(&stream.mountpoint, &stream.uuid);
+        return;
+    }
+
+    // the audio decoded is the stream's real length, the time connected
+    // also counts any stalls:
+    let duration = stream.audio_duration()
+        .map(|duration| duration.as_secs())
+        .unwrap_or(connected);
+
+    rustcast.log.info(&format!("Finished stream {} on {} (duration {} sec, connected {} sec)",
+        stream.uuid,
+        stream.mountpoint,
+        duration,
+        connected));
+
+    rustcast.publish_event(Event::StreamEnded {
+        mountpoint: stream.mountpoint.clone(),
+        uuid: stream.uuid,
+        duration,
+    });
+}
+
+enum RequestFormat {
+    Mp3,
+    Json,
+    History,
+    Cover,
+    Archive,
+    Player(Layout),
+    Playlist(PlaylistFormat),
+}
+
+// The mountpoint a path is for, if it ends in one of the extensions the
+// mountpoint's stream is served at.
+fn strip_audio_extension<'a>(config: &Config, path: &'a str) -> Option<&'a str> {
+    let dot = path.rfind('.')?;
+
+    if path[dot..].contains('/') {
+        return None;
+    }
+
+    let (mountpoint, extension) = (&path[..dot], &path[dot + 1..]);
+
+    if config.audio_extensions(mountpoint).iter().any(|ext| ext.eq_ignore_ascii_case(extension)) {
+        Some(mountpoint)
+    } else {
+        None
+    }
+}
+
+// Splits the format a request is for off the end of its path. Paths without
+// a format suffix are left for content negotiation.
+fn extract_request_format(config: &Config, path: &str) -> (Option<RequestFormat>, String) {
+    fn chomp<'a>(string: &'a str, suffix: &str) -> Option<&'a str> {
+        if string.ends_with(suffix) {
+            Some(&string[0..(string.len() - suffix.len())])
+        } else {
+            None
+        }
+    }
+
+    if let Some(mountpoint) = strip_audio_extension(config, path) {
+        (Some(RequestFormat::Mp3), mountpoint.to_owned())
+    } else if let Some(mountpoint) = chomp(path, "/history.json") {
+        (Some(RequestFormat::History), mountpoint.to_owned())
+    } else if let Some(mountpoint) = chomp(path, "/cover.jpg") {
+        (Some(RequestFormat::Cover), mountpoint.to_owned())
+    } else if let Some(mountpoint) = chomp(path, "/archive") {
+        (Some(RequestFormat::Archive), mountpoint.to_owned())
+    } else if let Some(mountpoint) = chomp(path, "/player") {
+        (Some(RequestFormat::Player(Layout::Page)), mountpoint.to_owned())
+    } else if let Some(mountpoint) = chomp(path, "/embed") {
+        (Some(RequestFormat::Player(Layout::Embed)), mountpoint.to_owned())
+    } else if let Some(mountpoint) = chomp(path, ".m3u") {
+        (Some(RequestFormat::Playlist(PlaylistFormat::M3u)), mountpoint.to_owned())
+    } else if let Some(mountpoint) = chomp(path, ".pls") {
+        (Some(RequestFormat::Playlist(PlaylistFormat::Pls)), mountpoint.to_owned())
+    } else if let Some(mountpoint) = chomp(path, ".xspf") {
+        (Some(RequestFormat::Playlist(PlaylistFormat::Xspf)), mountpoint.to_owned())
+    } else if let Some(mountpoint) = chomp(path, ".json") {
+        (Some(RequestFormat::Json), mountpoint.to_owned())
+    } else {
+        (None, path.to_owned())
+    }
+}
+
+// The formats served for a mountpoint without a suffix, by the media types
+// they're served as. Anything audio is the stream, since that's all rustcast
+// sends to listeners, whatever content_type the mount gives it.
+fn format_for_media_type(media_type: &str, content_type: &str) -> Option<RequestFormat> {
+    match media_type {
+        "*/*" | "audio/*" => Some(RequestFormat::Mp3),
+        "audio/mp3" if content_type == config::DEFAULT_CONTENT_TYPE => Some(RequestFormat::Mp3),
+        media_type if media_type == content_type => Some(RequestFormat::Mp3),
+        "application/*" | "application/json" => Some(RequestFormat::Json),
+        "audio/x-mpegurl" | "audio/mpegurl" => Some(RequestFormat::Playlist(PlaylistFormat::M3u)),
+        "audio/x-scpls" => Some(RequestFormat::Playlist(PlaylistFormat::Pls)),
+        "application/xspf+xml" => Some(RequestFormat::Playlist(PlaylistFormat::Xspf)),
+        _ => None,
+    }
+}
+
+// Picks a format from the Accept header, preferring the media types with the
+// highest q value and, among those, the ones listed first. Requests without
+// an Accept header get MP3, as they always have. None means nothing the
+// client accepts can be served.
+fn negotiate_format(req: &Request, content_type: &str) -> Option<RequestFormat> {
+    let accept = req.headers().iter()
+        .filter(|header| header.field.equiv("Accept"))
+        .map(|header| header.value.as_str())
+        .collect::<Vec<_>>();
+
+    if accept.is_empty() {
+        return Some(RequestFormat::Mp3);
+    }
+
+    let mut media_types = accept.iter()
+        .flat_map(|value| value.split(','))
+        .filter_map(|media_range| {
+            let mut params = media_range.split(';').map(str::trim);
+            let media_type = params.next().unwrap_or("").to_lowercase();
+
+            let q = params
+                .filter_map(|param| {
+                    let mut param = param.splitn(2, '=');
+
+                    match (param.next(), param.next()) {
+                        (Some("q"), Some(q)) => q.parse::<f32>().ok(),
+                        _ => None,
+                    }
+                })
+                .next()
+                .unwrap_or(1.0);
+
+            if q > 0.0 {
+                Some((media_type, q))
+            } else {
+                None
+            }
+        })
+        .collect::<Vec<_>>();
+
+    // sort_by is stable, so equally preferred types stay in the order given:
+    media_types.sort_by(|&(_, a), &(_, b)| b.partial_cmp(&a).unwrap_or(cmp::Ordering::Equal));
+
+    media_types.iter()
+        .filter_map(|&(ref media_type, _)| format_for_media_type(media_type, content_type))
+        .next()
+}
+
+fn not_acceptable(req: Request) -> io::Result<()> {
+    req.respond(Response::from_string("<h1>Not acceptable</h1>\n")
+        .with_status_code(406))
+}
+
+#[derive(Serialize)]
+struct MountpointJson {
+    uuid: String,
+    // seconds since the stream went live:
+    uptime: u64,
+    // what the source is sending:
+    codec: Option<&'static str>,
+    sample_rate: Option<u32>,
+    channels: Option<u8>,
+    input_bitrate: Option<u32>,
+    // what listeners get, in kbps:
+    output_bitrate: Option<u32>,
+    artist: Option<String>,
+    title: Option<String>,
+    album: Option<String>,
+    genre: Option<String>,
+    date: Option<String>,
+    tracknumber: Option<String>,
+    extra: BTreeMap<String, String>,
+}
+
+fn not_found(req: Request) -> io::Result<()> {
+    req.respond(Response::from_string("<h1>Not found</h1>\n")
+        .with_status_code(404))
+}
+
+fn live_stream(rustcast: &Rustcast, mountpoint: &str) -> Option<Arc<Stream>> {
+    match rustcast.get_stream(mountpoint) {
+        Some(StreamEntry::Live(stream)) => Some(stream),
+        Some(StreamEntry::Starting(_)) | None => None,
+    }
+}
+
+// Sends an MP3 file from the mount's config to a listener, if the mount has
+// one. A missing file is logged, and the listener carries on without it.
+fn play_file<W: io::Write, F>(rustcast: &Rustcast, mountpoint: &str, what: &str, file: F, response: &mut W) -> io::Result<()>
+    where F: Fn(&MountConfig) -> Option<&String>
+{
+    let config = rustcast.config();
+
+    let path = match config.mount(mountpoint).and_then(file) {
+        Some(path) => path,
+        None => return Ok(()),
+    };
+
+    match fs::read(path) {
+        Ok(data) => response.write_all(&data),
+        Err(e) => {
+            rustcast.log.error(&format!("Couldn't read {} {} for {}: {}", what, path, mountpoint, e));
+            Ok(())
+        }
+    }
+}
+
+// Sends the mount's intro to a new listener, if it has one.
+fn play_intro<W: io::Write>(rustcast: &Rustcast, mountpoint: &str, response: &mut W) -> io::Result<()> {
+    play_file(rustcast, mountpoint, "intro", |mount| mount.intro.as_ref(), response)
+}
+
+// Tells a listener who's reached max_listen_seconds that their time's up.
+fn play_time_up<W: io::Write>(rustcast: &Rustcast, mountpoint: &str, response: &mut W) -> io::Result<()> {
+    rustcast.log.info(&format!("Listener on {} reached max_listen_seconds", mountpoint));
+
+    play_file(rustcast, mountpoint, "max_listen_message", |mount| mount.max_listen_message.as_ref(), response)
+}
+
+// Plays silence down the response until a source goes live on mountpoint,
+// or returns None after timeout.
+fn play_silence<W: io::Write>(rustcast: &Rustcast, mountpoint: &str, response: &mut W, timeout: Duration) -> io::Result<Option<Arc<Stream>>> {
+    let started = Instant::now();
+    let frame_duration = rustcast.silence.frame_duration();
+    let mut frames_sent = 0;
+
+    loop {
+        for frame in rustcast.silence.frames() {
+            response.write_all(frame)?;
+            frames_sent += 1;
+
+            // pace against the start time so we don't drift ahead of or
+            // behind real time:
+            let due = started + frame_duration * frames_sent;
+            let now = Instant::now();
+
+            if due > now {
+                thread::sleep(due - now);
+            }
+        }
+
+        if rustcast.silence.frames().is_empty() {
+            thread::sleep(Duration::from_secs(1));
+        }
+
+        if let Some(stream) = live_stream(rustcast, mountpoint) {
+            return Ok(Some(stream));
+        }
+
+        if started.elapsed() >= timeout {
+            return Ok(None);
+        }
+    }
+}
+
+// Like play_silence, but sends nothing while waiting.
+fn wait_for_stream(rustcast: &Rustcast, mountpoint: &str, timeout: Duration) -> Option<Arc<Stream>> {
+    let started = Instant::now();
+
+    loop {
+        if let Some(stream) = live_stream(rustcast, mountpoint) {
+            return Some(stream);
+        }
+
+        if started.elapsed() >= timeout {
+            return None;
+        }
+
+        thread::sleep(Duration::from_millis(100));
+    }
+}
+
+// How much of the burst buffer a listener wants to start with, in bytes. A
+// suffix Range (bytes=-N) or ?burst=N asks for at most the last N bytes, eg.
+// ?burst=0 starts at the live edge. Otherwise they get the whole buffer.
+fn requested_burst(req: &Request, query: &Query) -> usize {
+    let from_query = query.parse("burst");
+
+    let from_range = req.headers().iter()
+        .filter(|header| header.field.equiv("Range"))
+        .filter_map(|header| {
+            let range = header.value.as_str().trim();
+
+            if range.starts_with("bytes=-") {
+                range["bytes=-".len()..].parse().ok()
+            } else {
+                None
+            }
+        })
+        .next();
+
+    from_query.or(from_range).unwrap_or(usize::max_value())
+}
+
+// Checks a listener's JWT on mountpoints that need one, responding with
+// why they can't listen if not.
+fn authorize_listener(rustcast: &Rustcast, req: Request, mountpoint: &str, query: &Query) -> Result<Request, io::Result<()>> {
+    let config = rustcast.config();
+
+    let jwt = match config.jwt {
+        Some(ref jwt) if config.mount(mountpoint).map(|mount| mount.require_jwt).unwrap_or(false) => jwt,
+        _ => return Ok(req),
+    };
+
+    let ip = proxy::client_ip(&config, &req);
+
+    let token = match jwt::token_from_request(jwt, &req, query) {
+        Some(token) => token,
+        None => return Err(unauthorized(req)),
+    };
+
+    match rustcast.jwt.validate(jwt, &token) {
+        Ok(ref claims) if claims.allows(&jwt.listen_claim, mountpoint) => Ok(req),
+        Ok(_) => {
+            rustcast.log.info(&format!("Listener {} token doesn't allow {}", ip, mountpoint));
+            Err(req.respond(Response::from_string("<h1>Forbidden</h1>\n")
+                .with_status_code(403)))
+        }
+        Err(e) => {
+            rustcast.log.info(&format!("Listener {} gave an invalid token for {}: {:?}", ip, mountpoint, e));
+            Err(unauthorized(req))
+        }
+    }
+}
+
+fn unauthorized(req: Request) -> io::Result<()> {
+    let authenticate = Header::from_bytes(&b"WWW-Authenticate"[..], &b"Bearer"[..])
+        .expect("valid header");
+
+    req.respond(Response::from_string("<h1>Unauthorized</h1>\n")
+        .with_status_code(401)
+        .with_header(authenticate))
+}
+
+fn handle_mp3(rustcast: &Rustcast, req: Request, mountpoint: &str, query: &Query) -> io::Result<()> {
+    let stream = live_stream(rustcast, mountpoint);
+
+    // listeners can be configured to hang around for a source to start:
+    let wait_for_source = rustcast.config().mount(mountpoint)
+        .and_then(|mount| mount.wait_for_source);
+
+    if stream.is_none() && wait_for_source.is_none() {
+        return not_found(req);
+    }
+
+    let full = stream.as_ref()
+        .and_then(|stream| stream.overrides().max_listeners.map(|max| stream.listeners() >= max))
+        .unwrap_or(false);
+
+    if full {
+        rustcast.log.info(&format!("Turned away listener to {} (max listeners reached)", mountpoint));
+
+        return req.respond(Response::from_string("<h1>Too many listeners</h1>\n")
+            .with_status_code(503));
+    }
+
+    let over_cap = rustcast.config().mount(mountpoint)
+        .map(|mount| rustcast.transfer.over_cap(mountpoint, mount))
+        .unwrap_or(false);
+
+    if over_cap {
+        rustcast.log.info(&format!("Turned away listener to {} (transfer cap reached)", mountpoint));
+
+        return req.respond(Response::from_string("<h1>Transfer cap reached</h1>\n")
+            .with_status_code(503));
+    }
+
+    // players and monitoring probes check a stream with HEAD before
+    // connecting, which shouldn't count as a listener:
+    if *req.method() == Method::Head {
+        let content_type = Header::from_bytes(&b"Content-Type"[..], rustcast.config().content_type(mountpoint).as_bytes())
+            .expect("valid header");
+
+        return req.respond(Response::empty(200)
+            .with_header(content_type));
+    }
+
+    let ip = proxy::client_ip(&rustcast.config(), &req);
+
+    let user_agent = req.headers().iter()
+        .find(|header| header.field.equiv("User-Agent"))
+        .map(|header| header.value.as_str().to_owned());
+
+    let player = useragent::classify(user_agent.as_ref().map(String::as_str));
+
+    // listeners keep the session they've been given, or get a new one:
+    let (session, set_cookie) = match rustcast.config().session_cookie {
+        Some(ref config) => match Cookies::from_request(&req).get(&config.name) {
+            Some(session) if !session.is_empty() => (Some(session.to_owned()), None),
+            _ => {
+                let session = cookie::new_session();
+                let set_cookie = cookie::set_session(config, &session);
+                (Some(session), Some(set_cookie))
+            }
+        },
+        None => (None, None),
+    };
+
+    let dedup_window = rustcast.config().mount(mountpoint)
+        .and_then(|mount| mount.listener_dedup_window)
+        .map(|minutes| Duration::from_secs(minutes * 60));
+
+    // a listener back within the dedup window carries on their last session:
+    let reconnected = dedup_window.is_some()
+        && rustcast.dedup.reconnect(mountpoint, ip, user_agent.as_ref().map(String::as_str),
+            session.as_ref().filter(|_| set_cookie.is_none()).map(String::as_str));
+
+    // listed before the event goes out, so it's counted in it:
+    let listener = rustcast.listeners.connect(mountpoint, ip, user_agent.clone(), player);
+
+    if reconnected {
+        rustcast.log.info(&format!("Listener {} reconnected to {} ({})", ip, mountpoint, player));
+
+        rustcast.players.reconnect(mountpoint, player);
+    } else {
+        rustcast.log.info(&format!("Listener {} connected to {} ({})", ip, mountpoint, player));
+
+        rustcast.players.connect(mountpoint, player);
+
+        rustcast.publish_event(Event::ListenerJoined {
+            mountpoint: mountpoint.to_owned(),
+            ip,
+            user_agent: user_agent.clone(),
+            player,
+            session: session.clone(),
+        });
+    }
+
+    let burst = requested_burst(&req, query);
+
+    // listeners coming back from a pause ask to carry on that far behind:
+    let offset = query.parse("offset")
+        .filter(|&offset| offset > 0)
+        .map(Duration::from_secs);
+
+    let response = Box::new(CountingWriter::new(req.into_writer(),
+        Arc::clone(&rustcast.transfer), mountpoint));
+
+    let mut health = ListenerHealth::default();
+
+    let result = play_to_listener(rustcast, response, mountpoint, stream, wait_for_source, burst, offset, set_cookie, listener, &mut health);
+
+    rustcast.listeners.disconnect(listener);
+
+    rustcast.log.info(&format!("Listener {} disconnected from {} (buffer peaked at {} of {} packets, {} dropped)",
+        ip, mountpoint, health.peak_buffer, health.buffer_size, health.dropped));
+
+    rustcast.players.disconnect(mountpoint, player);
+
+    let left = Event::ListenerLeft {
+        mountpoint: mountpoint.to_owned(),
+        ip,
+        user_agent: user_agent.clone(),
+        player,
+        session,
+        peak_buffer: health.peak_buffer,
+        dropped: health.dropped,
+        left: Utc::now(),
+    };
+
+    match dedup_window {
+        Some(window) => rustcast.dedup.hold(mountpoint, ip, user_agent.as_ref().map(String::as_str),
+            session.as_ref().map(String::as_str), window, left),
+        None => rustcast.publish_event(left),
+    }
+
+    result
+}
+
+// paces listeners on mounts that ask for it, as long as the stream's bitrate
+// is known:
+fn pacer(rustcast: &Rustcast, mountpoint: &str, stream: &Stream) -> Option<Pacer> {
+    let config = rustcast.config();
+    let mount = config.mount(mountpoint).filter(|mount| mount.pace_listeners)?;
+
+    let kilobitrate = stream.bitrate()
+        .or(mount.bitrate.map(|bitrate| cmp::max(bitrate, 0) as u32))
+        .filter(|&bitrate| bitrate > 0)?;
+
+    let burst = mount.pace_burst.map(Duration::from_secs).unwrap_or(pace::DEFAULT_BURST);
+
+    Some(Pacer::new(kilobitrate, burst))
+}
+
+// How well a listener kept up with the stream, for logs and stats.
+#[derive(Default)]
+struct ListenerHealth {
+    // the most packets waiting to be sent to the listener at once, and how
+    // many could:
+    peak_buffer: usize,
+    buffer_size: usize,
+    // packets the listener missed by falling behind:
+    dropped: usize,
+}
+
+fn play_to_listener(rustcast: &Rustcast, mut response: Box<io::Write + Send>, mountpoint: &str, stream: Option<Arc<Stream>>, wait_for_source: Option<u64>, burst: usize, offset: Option<Duration>, set_cookie: Option<String>, listener: u64, health: &mut ListenerHealth) -> io::Result<()> {
+    use std::io::prelude::*;
+
+    write!(response, "HTTP/1.0 200 OK\r\nServer: Rustcast\r\nContent-Type: {}\r\n",
+        rustcast.config().content_type(mountpoint))?;
+
+    if let Some(set_cookie) = set_cookie {
+        write!(response, "Set-Cookie: {}\r\n", set_cookie)?;
+    }
+
+    response.write_all(b"\r\n")?;
+
+    // counted from when they connected, including any time spent waiting
+    // for a source:
+    let deadline = rustcast.config().mount(mountpoint)
+        .and_then(|mount| mount.max_listen_seconds)
+        .map(|seconds| Instant::now() + Duration::from_secs(seconds));
+
+    let time_up = || deadline.map(|deadline| Instant::now() >= deadline).unwrap_or(false);
+
+    play_intro(rustcast, mountpoint, &mut response)?;
+
+    let mut stream = match stream {
+        Some(stream) => stream,
+        None => {
+            let timeout = Duration::from_secs(wait_for_source.unwrap_or(0));
+
+            let next = if rustcast.config().codec(mountpoint) == OutputCodec::Mp3 {
+                play_silence(rustcast, mountpoint, &mut response, timeout)?
+            } else {
+                wait_for_stream(rustcast, mountpoint, timeout)
+            };
+
+            match next {
+                Some(stream) => stream,
+                None => return Ok(()),
+            }
+        }
+    };
+
+    loop {
+        if time_up() {
+            return play_time_up(rustcast, mountpoint, &mut response);
+        }
+
+        if let Some(reader) = offset.and_then(|offset| stream.subscribe_timeshifted(offset)) {
+            drop(stream);
+            return play_timeshifted(rustcast, mountpoint, &mut response, reader, listener, time_up);
+        }
+
+        let (buffered, rx) = stream.subscribe_with_burst(burst);
+        let kicked = Arc::clone(&stream.kicked);
+        let mut pacer = pacer(rustcast, mountpoint, &stream);
+
+        // kicked from the admin API while waiting for a source:
+        if !rustcast.listeners.listening(listener, rx.closer()) {
+            rustcast.log.info(&format!("Closing listener on {} ({:?})", mountpoint, CloseReason::Kicked));
+            return Ok(());
+        }
+
+        // don't keep the stream alive ourselves, or rx would never see it
+        // end:
+        drop(stream);
+
+        let mut buffered = buffered.into_iter().map(Frame::Audio);
+
+        health.buffer_size = rx.buffer_size();
+
+        let mut timed_out = false;
+
+        while let Some(frame) = buffered.next().or_else(|| rx.recv()) {
+            health.peak_buffer = cmp::max(health.peak_buffer, rx.queued());
+
+            let buffer = match frame {
+                Frame::Audio(buffer) => buffer,
+                // plain HTTP listeners don't get metadata in the stream:
+                Frame::Metadata(_) => continue,
+                Frame::End => break,
+            };
+
+            if let Some(pacer) = pacer.as_mut() {
+                pacer.wait(buffer.len());
+            }
+
+            response.write_all(&buffer)?;
+
+            if time_up() {
+                timed_out = true;
+                break;
+            }
+        }
+
+        health.dropped += rx.dropped();
+
+        if timed_out {
+            drop(rx);
+            return play_time_up(rustcast, mountpoint, &mut response);
+        }
+
+        if let Some(reason) = rx.close_reason() {
+            rustcast.log.info(&format!("Closing listener on {} ({:?})", mountpoint, reason));
+            return Ok(());
+        }
+
+        if rx.dropped() > 0 {
+            rustcast.log.info(&format!("Listener on {} fell more than {} packets behind",
+                mountpoint, rx.buffer_size()));
+        }
+
+        if rustcast.is_shutdown() {
+            return Ok(());
+        }
+
+        // the source has gone, but listeners can be kept around for it to
+        // reconnect:
+        let config = rustcast.config();
+        let mount = config.mount(mountpoint);
+
+        let mut timeout = mount.and_then(|mount| mount.reconnect_timeout)
+            .map(Duration::from_secs);
+
+        // a source that was taken over is about to be replaced, so stick
+        // around for its replacement regardless:
+        if kicked.load(Ordering::SeqCst) {
+            timeout = Some(cmp::max(timeout.unwrap_or(TAKEOVER_TIMEOUT), TAKEOVER_TIMEOUT));
+        }
+
+        let timeout = match timeout {
+            Some(timeout) => timeout,
+            None => return Ok(()),
+        };
+
+        // the silence played is MP3:
+        let silence = mount.and_then(|mount| mount.reconnect_silence).unwrap_or(true)
+            && config.codec(mountpoint) == OutputCodec::Mp3;
+
+        let next = if silence {
+            play_silence(rustcast, mountpoint, &mut response, timeout)?
+        } else {
+            wait_for_stream(rustcast, mountpoint, timeout)
+        };
+
+        stream = match next {
+            Some(stream) => stream,
+            None => return Ok(()),
+        };
+    }
+}
+
+// Plays a stream from its timeshift, which keeps to real time itself, until
+// the listener has heard up to where its source ended.
+fn play_timeshifted<W: io::Write, F>(rustcast: &Rustcast, mountpoint: &str, response: &mut W, mut reader: timeshift::Reader, listener: u64, time_up: F) -> io::Result<()>
+    where F: Fn() -> bool
+{
+    while let Some(buffer) = reader.recv() {
+        if rustcast.listeners.kicked(listener) {
+            rustcast.log.info(&format!("Closing listener on {} ({:?})", mountpoint, CloseReason::Kicked));
+            return Ok(());
+        }
+
+        response.write_all(&buffer)?;
+
+        if time_up() {
+            drop(reader);
+            return play_time_up(rustcast, mountpoint, response);
+        }
+    }
+
+    Ok(())
+}
+
+// JSON responses are fetched by now playing widgets on station websites, so
+// they carry CORS headers for the configured origins:
+fn respond_json<T: Serialize>(rustcast: &Rustcast, req: Request, data: &T) -> io::Result<()> {
+    let content_type = Header::from_bytes(&b"Content-Type"[..], &b"application/json"[..])
+        .expect("valid header");
+
+    let mut response = Response::from_string(serde_json::to_string(data).unwrap())
+        .with_header(content_type)
+        .with_status_code(200);
+
+    for header in cors::headers(&rustcast.config(), &req) {
+        response.add_header(header);
+    }
+
+    req.respond(response)
+}
+
+fn handle_json(rustcast: &Rustcast, req: Request, mountpoint: &str) -> io::Result<()> {
+    let stream = match live_stream(rustcast, mountpoint) {
+        Some(stream) => stream,
+        None => return not_found(req),
+    };
+
+    let data = {
+        let metadata = stream.metadata.read().unwrap();
+        let input = stream.input();
+
+        MountpointJson {
+            uuid: format!("{}", stream.uuid.hyphenated()),
+            uptime: stream.uptime().as_secs(),
+            codec: input.as_ref().map(|input| input.codec),
+            sample_rate: input.as_ref().map(|input| input.sample_rate),
+            channels: input.as_ref().map(|input| input.channels),
+            input_bitrate: input.as_ref().and_then(|input| input.bitrate),
+            output_bitrate: stream.bitrate(),
+            artist: metadata.artist.clone(),
+            title: metadata.title.clone(),
+            album: metadata.album.clone(),
+            genre: metadata.genre.clone(),
+            date: metadata.date.clone(),
+            tracknumber: metadata.tracknumber.clone(),
+            extra: metadata.extra.clone(),
+        }
+    };
+
+    respond_json(rustcast, req, &data)
+}
+
+fn handle_history(rustcast: &Rustcast, req: Request, mountpoint: &str) -> io::Result<()> {
+    let history = match rustcast.history.get(mountpoint) {
+        Some(history) => history,
+        None => return not_found(req),
+    };
+
+    respond_json(rustcast, req, &history)
+}
+
+fn handle_cover(rustcast: &Rustcast, req: Request, mountpoint: &str) -> io::Result<()> {
+    let stream = match live_stream(rustcast, mountpoint) {
+        Some(stream) => stream,
+        None => return not_found(req),
+    };
+
+    let picture = stream.metadata.read().unwrap().picture.clone();
+
+    let picture = match picture {
+        Some(picture) => picture,
+        None => return not_found(req),
+    };
+
+    let etag = Header::from_bytes(&b"ETag"[..], picture.etag.as_bytes())
+        .expect("valid header");
+
+    // artwork changes with every track, so don't let it be cached for long:
+    let cache_control = Header::from_bytes(&b"Cache-Control"[..], &b"public, max-age=30"[..])
+        .expect("valid header");
+
+    let not_modified = req.headers().iter()
+        .any(|header| header.field.equiv("If-None-Match") && header.value.as_str() == picture.etag);
+
+    if not_modified {
+        return req.respond(Response::empty(304)
+            .with_header(etag)
+            .with_header(cache_control));
+    }
+
+    let content_type = Header::from_bytes(&b"Content-Type"[..], picture.mime_type.as_bytes())
+        .expect("valid header");
+
+    let mut response = Response::from_data(picture.data.clone())
+        .with_header(content_type)
+        .with_header(etag)
+        .with_header(cache_control)
+        .with_status_code(200);
+
+    for header in cors::headers(&rustcast.config(), &req) {
+        response.add_header(header);
+    }
+
+    req.respond(response)
+}
+
+// The Host req was sent to, which picks the station it's for.
+fn host(req: &Request) -> Option<&str> {
+    req.headers().iter()
+        .find(|header| header.field.equiv("Host"))
+        .map(|header| header.value.as_str())
+}
+
+// The URL listeners reach rustcast on, without a trailing slash: the
+// public_url of the station req was sent to, or wherever req was sent to.
+pub fn base_url(rustcast: &Rustcast, req: &Request) -> String {
+    let config = rustcast.config();
+
+    let public_url = match host(req).and_then(|host| config.station_for_host(host)) {
+        Some(station) => config.stations[station].public_url.as_ref(),
+        None => config.public_url.as_ref(),
+    };
+
+    if let Some(url) = public_url {
+        return url.trim_end_matches('/').to_owned();
+    }
+
+    let host = host(req)
+        .map(str::to_owned)
+        .unwrap_or_else(|| config.listen.clone());
+
+    format!("{}://{}", proxy::client_scheme(&config, req), host)
+}
+
+// The URL listeners should use for a mountpoint's stream. Unless public_url
+// is configured, this is based on the Host the request was made to.
+fn stream_url(rustcast: &Rustcast, req: &Request, mountpoint: &str) -> String {
+    format!("{}{}", base_url(rustcast, req), rustcast.config().stream_path(mountpoint))
+}
+
+// The mountpoint a request path is for, whatever it asks for of it.
+pub fn request_mountpoint(config: &Config, path: &str) -> String {
+    extract_request_format(config, path).1
+}
+
+fn handle_playlist(rustcast: &Rustcast, req: Request, mountpoint: &str, format: PlaylistFormat) -> io::Result<()> {
+    // configured mountpoints get a playlist even while off air, since
+    // listeners may be waiting on them for a source:
+    let known = live_stream(rustcast, mountpoint).is_some()
+        || rustcast.config().mount(mountpoint).is_some();
+
+    if !known {
+        return not_found(req);
+    }
+
+    let body = playlist::render(format, &stream_url(rustcast, &req, mountpoint), mountpoint);
+
+    let content_type = Header::from_bytes(&b"Content-Type"[..], format.content_type().as_bytes())
+        .expect("valid header");
+
+    req.respond(Response::from_string(body)
+        .with_header(content_type)
+        .with_status_code(200))
+}
+
+fn handle_player(rustcast: &Rustcast, req: Request, mountpoint: &str, layout: Layout) -> io::Result<()> {
+    let config = rustcast.config();
+
+    // like playlists, off air mountpoints with settings get a page, which
+    // starts working once they're live:
+    let known = live_stream(rustcast, mountpoint).is_some()
+        || config.mount(mountpoint).is_some();
+
+    if !known {
+        return not_found(req);
+    }
+
+    let title = config.mount(mountpoint)
+        .and_then(|mount| mount.name.as_ref())
+        .map(String::as_str)
+        .unwrap_or(mountpoint);
+
+    let public_path = config.public_path(mountpoint);
+
+    let page_url = format!("{}{}/player", base_url(rustcast, &req), public_path);
+
+    let oembed = Url::parse_with_params("http://localhost/oembed", &[("url", page_url)])
+        .expect("valid url");
+
+    let paths = player::Paths {
+        stream: config.stream_path(mountpoint),
+        json: format!("{}.json", public_path),
+        cover: format!("{}/cover.jpg", public_path),
+        oembed: format!("/oembed?{}", oembed.query().unwrap_or("")),
+    };
+
+    let body = player::render(layout, title, &paths);
+
+    let content_type = Header::from_bytes(&b"Content-Type"[..], player::CONTENT_TYPE.as_bytes())
+        .expect("valid header");
+
+    req.respond(Response::from_string(body)
+        .with_header(content_type)
+        .with_status_code(200))
+}
+
+fn redirect(req: Request, location: &str) -> io::Result<()> {
+    let location = Header::from_bytes(&b"Location"[..], location.as_bytes())
+        .expect("valid header");
+
+    req.respond(Response::from_string("<h1>Found</h1>\n")
+        .with_header(location)
+        .with_status_code(302))
+}
+
+fn handle_client(rustcast: &Rustcast, req: Request) -> io::Result<()> {
+    // query parameters are for the handler, not part of the mountpoint:
+    let path = match mountpoint::from_url(req.url()) {
+        Ok(path) => path,
+        Err(_) => return bad_request(req),
+    };
+
+    let query = Query::from_url(req.url());
+
+    let config = rustcast.config();
+
+    // stations' mountpoints are under their names:
+    let path = match config.station_mountpoint(host(&req), &path) {
+        Some(path) => path,
+        None => return not_found(req),
+    };
+
+    let (format, mut mountpoint) = extract_request_format(&config, &path);
+
+    if let Some(target) = config.redirects.get(&mountpoint) {
+        // redirects to another mount keep whatever the request was for, so
+        // /old.json goes to /new.json:
+        let location = if target.starts_with("/") {
+            let (_, target) = extract_request_format(&config, target);
+
+            let query = match req.url().find('?') {
+                Some(offset) => &req.url()[offset..],
+                None => "",
+            };
+
+            format!("{}{}{}", target, &path[mountpoint.len()..], query)
+        } else {
+            target.clone()
+        };
+
+        return redirect(req, &location);
+    }
+
+    // aliases are served as if the target mountpoint had been requested:
+    if let Some(target) = config.aliases.get(&mountpoint) {
+        mountpoint = extract_request_format(&config, target).1;
+    }
+
+    let format = match format.or_else(|| negotiate_format(&req, config.content_type(&mountpoint))) {
+        Some(format) => format,
+        None => return not_acceptable(req),
+    };
+
+    // everything about a mountpoint that needs a token needs one, not just
+    // its audio:
+    let req = match authorize_listener(rustcast, req, &mountpoint, &query) {
+        Ok(req) => req,
+        Err(result) => return result,
+    };
+
+    match format {
+        RequestFormat::Mp3 => handle_mp3(rustcast, req, &mountpoint, &query),
+        RequestFormat::Json => handle_json(rustcast, req, &mountpoint),
+        RequestFormat::History => handle_history(rustcast, req, &mountpoint),
+        RequestFormat::Cover => handle_cover(rustcast, req, &mountpoint),
+        RequestFormat::Archive => catchup::handle(rustcast, req, &mountpoint, &query),
+        RequestFormat::Player(layout) => handle_player(rustcast, req, &mountpoint, layout),
+        RequestFormat::Playlist(format) => handle_playlist(rustcast, req, &mountpoint, format),
+    }
+}
+
+// the methods served on every URL outside /admin/:
+const ALLOWED_METHODS: &'static str = "GET, HEAD, OPTIONS, PUT, SOURCE";
+
+fn allow_header() -> Header {
+    Header::from_bytes(&b"Allow"[..], ALLOWED_METHODS.as_bytes())
+        .expect("valid header")
+}
+
+fn handle_options(rustcast: &Rustcast, req: Request) -> io::Result<()> {
+    let mut response = Response::empty(204)
+        .with_header(allow_header());
+
+    // browsers only send what's CORS safelisted without asking first, so
+    // only GET and HEAD are offered to them:
+    for header in cors::preflight_headers(&rustcast.config(), &req, "GET, HEAD") {
+        response.add_header(header);
+    }
+
+    req.respond(response)
+}
+
+fn method_not_allowed(req: Request) -> io::Result<()> {
+    req.respond(Response::from_string("<h1>Method not allowed</h1>\n")
+        .with_header(allow_header())
+        .with_status_code(405))
+}
+
+fn handle_request(rustcast: Arc<Rustcast>, req: Request) -> io::Result<()> {
+    match *req.method() {
+        Method::Source => handle_source(&rustcast, req),
+        _ if req.url().starts_with("/admin/") => admin::handle(&rustcast, req),
+        // newer source clients send PUT, the way Icecast 2.4 takes them:
+        Method::Put => handle_source(&rustcast, req),
+        // HEAD is answered by the GET handlers, tiny_http leaves out the body:
+        Method::Get | Method::Head if health::is_health_url(req.url()) => health::handle(&rustcast, req),
+        Method::Get | Method::Head if rustcast.config().metrics && metrics::is_metrics_url(req.url()) => metrics::handle(&rustcast, req),
+        Method::Get | Method::Head if oembed::is_oembed_url(req.url()) => oembed::handle(&rustcast, req),
+        Method::Get if cluster::is_cluster_url(req.url()) => cluster::handle(&rustcast, req),
+        Method::Get | Method::Head => handle_client(&rustcast, req),
+        Method::Options => handle_options(&rustcast, req),
+        _ => method_not_allowed(req),
+    }
+}
+
+fn run_shm_source(rustcast: Arc<Rustcast>, config: ShmSource) {
+    let ring = match Ring::create(&config) {
+        Ok(ring) => Arc::new(ring),
+        Err(e) => {
+            rustcast.log.error(&format!("Couldn't create shared memory ring {} for {}: {:?}",
+                config.path, config.mountpoint, e));
+            return;
+        }
+    };
+
+    rustcast.log.info(&format!("Waiting for shared memory source on {} ({})",
+        config.mountpoint, config.path));
+
+    while !rustcast.is_shutdown() {
+        if !ring.wait_for_writer(|| rustcast.is_shutdown()) {
+            break;
+        }
+
+        let stream = match rustcast.start_in_process(&config.mountpoint) {
+            Ok(stream) => stream,
+            Err(e) => {
+                rustcast.log.error(&format!("Couldn't start shared memory source on {}: {:?}",
+                    config.mountpoint, e));
+                thread::sleep(Duration::from_secs(1));
+                continue;
+            }
+        };
+
+        let audio_stream = Box::new(ShmStream::new(Arc::clone(&ring), &config));
+
+        if let Err(e) = run_source(&rustcast, stream, audio_stream) {
+            rustcast.log.error(&format!("Shared memory source on {} failed: {:?}",
+                config.mountpoint, e));
+        }
+    }
+}
+
+fn run_pipe(rustcast: Arc<Rustcast>, config: PipeConfig) {
+    let mut pipe = Pipe::new(config);
+
+    while !rustcast.is_shutdown() {
+        match rustcast.get_stream(pipe.mountpoint()) {
+            Some(StreamEntry::Live(stream)) => {
+                if let Some(backoff) = pipe.backoff() {
+                    rustcast.sleep(backoff);
+                }
+
+                let rx = stream.subscribe();
+
+                // don't keep the stream alive ourselves, or rx would never
+                // see it end:
+                drop(stream);
+
+                pipe.run(&rustcast.log, &rx);
+            }
+            Some(StreamEntry::Starting(_)) | None => {
+                thread::sleep(Duration::from_secs(1));
+            }
+        }
+    }
+}
+
+#[cfg(unix)]
+fn watch_sighup(rustcast: Arc<Rustcast>) {
+    while !rustcast.is_shutdown() {
+        thread::sleep(Duration::from_secs(1));
+
+        if signal::take_sighup() {
+            rustcast.log.info("Reloading config (SIGHUP)");
+
+            if let Err(e) = rustcast.reload_config() {
+                rustcast.log.error(&format!("Couldn't reload config: {}", e));
+            }
+        }
+    }
+}
+
+// A running server, returned by start.
+pub struct Handle {
+    rustcast: Arc<Rustcast>,
+    addr: SocketAddr,
+    thread: thread::JoinHandle<()>,
+    // the background threads, which all stop once shut down:
+    workers: Vec<thread::JoinHandle<()>>,
+}
+
+impl Handle {
+    pub fn rustcast(&self) -> &Arc<Rustcast> {
+        &self.rustcast
+    }
+
+    // The address the server is listening on, which is where to find it
+    // when listen gave port 0.
+    pub fn local_addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    // Blocks until the server shuts down, and the events from its last
+    // streams have been handed on.
+    pub fn join(self) {
+        let _ = self.thread.join();
+
+        self.rustcast.wait_for_streams(SHUTDOWN_STREAMS_TIMEOUT);
+
+        for worker in self.workers {
+            if worker.join().is_err() {
+                self.rustcast.log.error("A background thread panicked");
+            }
+        }
+
+        // listeners who left within their dedup window won't be back now:
+        for event in self.rustcast.dedup.flush() {
+            self.rustcast.publish_event(event);
+        }
+
+        self.rustcast.events.close();
+    }
+
+    // Starts a stream on mountpoint from PCM audio, which is encoded like
+    // audio from any other source.
+    pub fn create_pcm_source(&self, mountpoint: &str, format: PcmFormat) -> Result<PcmSource, StartStreamError> {
+        source::create_pcm_source(Arc::clone(&self.rustcast), mountpoint, format)
+    }
+
+    // Starts a stream on mountpoint from already encoded MP3 audio, which is
+    // passed through to listeners as is.
+    pub fn create_mp3_source(&self, mountpoint: &str) -> Result<Mp3Source, StartStreamError> {
+        source::create_mp3_source(&self.rustcast, mountpoint)
+    }
+
+    // Stops accepting connections and ends every live stream, then waits
+    // for the server to stop.
+    pub fn shutdown(self) {
+        self.rustcast.shutdown();
+        self.join();
+    }
+}
+
+fn accept(rustcast: Arc<Rustcast>, server: Server) {
+    while !rustcast.is_shutdown() {
+        match server.recv_timeout(SHUTDOWN_POLL_INTERVAL) {
+            Ok(Some(request)) => {
+                let rustcast = rustcast.clone();
+                thread::spawn(move || {
+                    handle_request(rustcast, request)
+                });
+            }
+            Ok(None) => (),
+            Err(e) => rustcast.log.error(&format!("Couldn't accept connection: {:?}", e)),
+        }
+    }
+
+    rustcast.log.info("Shut down");
+}
+
+// log lines kept for the admin page:
+const RECENT_LOG_LINES: usize = 200;
+
+// Starts serving in the background.
+pub fn start(config_path: PathBuf, config: Config) -> io::Result<Handle> {
+    let rustcast = Arc::new(Rustcast::new(config_path, config));
+    let config = rustcast.config();
+
+    // so the server's own listening socket can be told apart from any others:
+    #[cfg(unix)]
+    let listening = sockopt::listeners();
+
+    let server = Server::http(&config.listen)
+        .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+
+    let addr = server.server_addr();
+
+    rustcast.log.info(&format!("Listening on {}", addr));
+
+    // set before dropping privileges, in case any need root:
+    #[cfg(unix)]
+    {
+        if let Err(e) = sockopt::apply(&listening, addr, &config.socket) {
+            rustcast.log.error(&format!("Couldn't set socket options: {:?}", e));
+        }
+    }
+
+    let mut workers = Vec::new();
+
+    // workers hold a Weak, so they don't keep the server alive:
+    for &(kind, name) in SINK_KINDS.iter() {
+        let weak = Arc::downgrade(&rustcast);
+
+        rustcast.events.add_worker(name, move || {
+            match weak.upgrade() {
+                Some(rustcast) => rustcast.sinks(kind),
+                None => Vec::new(),
+            }
+        });
+    }
+
+    // neither exists on Windows, where config is reloaded through the admin
+    // API instead:
+    #[cfg(unix)]
+    {
+        if config.user.is_some() || config.group.is_some() {
+            let user = config.user.as_ref().map(String::as_str);
+            let group = config.group.as_ref().map(String::as_str);
+
+            daemon::drop_privileges(user, group)?;
+
+            rustcast.log.info(&format!("Running as user {}, group {}",
+                user.unwrap_or("(unchanged)"), group.unwrap_or("(default)")));
+        }
+
+        let rustcast = rustcast.clone();
+        workers.push(thread::spawn(move || {
+            watch_sighup(rustcast)
+        }));
+    }
+
+    for shm_source in config.shm_sources.iter().cloned() {
+        let rustcast = rustcast.clone();
+        workers.push(thread::spawn(move || {
+            run_shm_source(rustcast, shm_source)
+        }));
+    }
+
+    for pipe in config.pipes.iter().cloned() {
+        let rustcast = rustcast.clone();
+        workers.push(thread::spawn(move || {
+            run_pipe(rustcast, pipe)
+        }));
+    }
+
+    {
+        let rustcast = rustcast.clone();
+        workers.push(thread::spawn(move || {
+            yp::run(rustcast)
+        }));
+    }
+
+    {
+        let rustcast = rustcast.clone();
+        workers.push(thread::spawn(move || {
+            statsd::run(rustcast)
+        }));
+    }
+
+    {
+        let rustcast = rustcast.clone();
+        workers.push(thread::spawn(move || {
+            dedup::run(rustcast)
+        }));
+    }
+
+    {
+        let rustcast = rustcast.clone();
+        workers.push(thread::spawn(move || {
+            schedule::run(rustcast)
+        }));
+    }
+
+    {
+        let rustcast = rustcast.clone();
+        workers.push(thread::spawn(move || {
+            sourcehealth::run(rustcast)
+        }));
+    }
+
+    {
+        let rustcast = rustcast.clone();
+        workers.push(thread::spawn(move || {
+            transfer::run(rustcast)
+        }));
+    }
+
+    {
+        let rustcast = rustcast.clone();
+        workers.push(thread::spawn(move || {
+            heartbeat::run(rustcast)
+        }));
+    }
+
+    {
+        let rustcast = rustcast.clone();
+        workers.push(thread::spawn(move || {
+            royalty::run(rustcast)
+        }));
+    }
+
+    {
+        let rustcast = rustcast.clone();
+        workers.push(thread::spawn(move || {
+            cluster::run(rustcast)
+        }));
+    }
+
+    let thread = {
+        let rustcast = rustcast.clone();
+        thread::spawn(move || {
+            accept(rustcast, server)
+        })
+    };
+
+    Ok(Handle { rustcast, addr, thread, workers })
 }
@@ -0,0 +1,127 @@
+// Runs rustcast as a Windows service, for stations run from a Windows
+// machine. The service is registered with its config file on the command
+// line, eg:
+//
+//     sc create rustcast binPath= "C:\rustcast\rustcast.exe --service C:\rustcast\rustcast.toml"
+//
+// Stopping the service shuts the server down cleanly, and log messages go to
+// the Windows event log as well as stdout.
+
+use std::ffi::OsString;
+use std::path::PathBuf;
+use std::ptr;
+use std::sync::atomic::{AtomicPtr, Ordering};
+use std::sync::mpsc;
+use std::time::Duration;
+
+use windows_service::{self, service_control_handler, service_dispatcher};
+use windows_service::service::{ServiceControl, ServiceControlAccept, ServiceExitCode, ServiceState, ServiceStatus, ServiceType};
+use windows_service::service_control_handler::{ServiceControlHandlerResult, ServiceStatusHandle};
+
+use config;
+use log::{self, Log};
+use server;
+
+pub const SERVICE_NAME: &'static str = "rustcast";
+
+// what run was given, for service_main:
+struct Args {
+    config_path: PathBuf,
+    // from --listen:
+    listen: Option<String>,
+}
+
+// the service control manager calls service_main with no way to pass it
+// anything, so run's arguments are stashed here:
+static ARGS: AtomicPtr<Args> = AtomicPtr::new(ptr::null_mut());
+
+define_windows_service!(ffi_service_main, service_main);
+
+// Hands this process over to the service control manager, returning once the
+// service has stopped. Fails if the process wasn't started as a service.
+pub fn run(config_path: PathBuf, listen: Option<String>) -> windows_service::Result<()> {
+    ARGS.store(Box::into_raw(Box::new(Args { config_path, listen })), Ordering::SeqCst);
+
+    service_dispatcher::start(SERVICE_NAME, ffi_service_main)
+}
+
+fn set_status(status_handle: &ServiceStatusHandle, state: ServiceState, exit_code: u32) {
+    let controls_accepted = match state {
+        ServiceState::Running => ServiceControlAccept::STOP | ServiceControlAccept::SHUTDOWN,
+        _ => ServiceControlAccept::empty(),
+    };
+
+    let _ = status_handle.set_service_status(ServiceStatus {
+        service_type: ServiceType::OWN_PROCESS,
+        current_state: state,
+        controls_accepted,
+        exit_code: ServiceExitCode::Win32(exit_code),
+        checkpoint: 0,
+        wait_hint: Duration::from_secs(10),
+    });
+}
+
+fn service_main(_arguments: Vec<OsString>) {
+    log::open_event_log(SERVICE_NAME);
+
+    let log = Log::new();
+
+    let (config_path, listen) = unsafe {
+        let ptr = ARGS.load(Ordering::SeqCst);
+        assert!(!ptr.is_null(), "service started without a config path");
+        ((*ptr).config_path.clone(), (*ptr).listen.clone())
+    };
+
+    let (stop_tx, stop_rx) = mpsc::channel();
+
+    let status_handle = match service_control_handler::register(SERVICE_NAME, move |control| {
+        match control {
+            ServiceControl::Stop | ServiceControl::Shutdown => {
+                let _ = stop_tx.send(());
+                ServiceControlHandlerResult::NoError
+            }
+            ServiceControl::Interrogate => ServiceControlHandlerResult::NoError,
+            _ => ServiceControlHandlerResult::NotImplemented,
+        }
+    }) {
+        Ok(status_handle) => status_handle,
+        Err(e) => {
+            log.error(&format!("Couldn't register service control handler: {:?}", e));
+            return;
+        }
+    };
+
+    set_status(&status_handle, ServiceState::StartPending, 0);
+
+    let mut config = match config::open(&config_path) {
+        Ok(config) => config,
+        Err(e) => {
+            log.error(&format!("Couldn't open config file: {}", e));
+            set_status(&status_handle, ServiceState::Stopped, 1);
+            return;
+        }
+    };
+
+    if let Some(listen) = listen {
+        config.override_listen(listen);
+    }
+
+    let handle = match server::start(config_path, config) {
+        Ok(handle) => handle,
+        Err(e) => {
+            log.error(&format!("Couldn't start server: {}", e));
+            set_status(&status_handle, ServiceState::Stopped, 1);
+            return;
+        }
+    };
+
+    set_status(&status_handle, ServiceState::Running, 0);
+
+    let _ = stop_rx.recv();
+
+    set_status(&status_handle, ServiceState::StopPending, 0);
+
+    handle.shutdown();
+
+    set_status(&status_handle, ServiceState::Stopped, 0);
+}
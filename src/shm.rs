@@ -0,0 +1,271 @@
+extern crate memmap;
+
+use std::fs::OpenOptions;
+use std::io;
+use std::ptr;
+use std::sync::Arc;
+use std::sync::atomic::{self, AtomicU32, AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+#[cfg(target_os = "linux")]
+use libc;
+#[cfg(not(target_os = "linux"))]
+use std::{cmp, thread};
+
+use self::memmap::{MmapMut, MmapOptions};
+
+use audio::{AudioStream, StreamRead, StreamError};
+use config::ShmSource;
+
+// Ring layout, all integers native endian:
+//
+//   0..8    magic, b"RCSTSHM1"
+//   8..16   u64 capacity of the data region in bytes
+//   16..24  u64 total bytes ever written by the producer
+//   24..28  u32 wakeup counter, bumped by the producer after every write
+//   28..64  reserved
+//   64..    data region, interleaved signed 16 bit little endian PCM
+//
+// The producer copies frames into the data region at (written % capacity),
+// bumps the written counter with release ordering, then bumps the wakeup
+// counter and wakes anything waiting on it with FUTEX_WAKE.
+const MAGIC: &'static [u8; 8] = b"RCSTSHM1";
+const HEADER_SIZE: usize = 64;
+const CAPACITY_OFFSET: usize = 8;
+const WRITTEN_OFFSET: usize = 16;
+const WAKEUP_OFFSET: usize = 24;
+
+// how many sample frames we hand to the encoder at a time:
+const CHUNK_FRAMES: usize = 1152;
+
+// how often waiting for a producer to start checks whether to give up:
+const STOP_CHECK_INTERVAL: Duration = Duration::from_millis(100);
+
+// how often the wakeup counter is checked where there are no futexes:
+#[cfg(not(target_os = "linux"))]
+const POLL_INTERVAL: Duration = Duration::from_millis(1);
+
+// Sleeps until the wakeup counter moves on from value or timeout passes,
+// returning early, sometimes spuriously, so callers check again either way.
+#[cfg(target_os = "linux")]
+fn wait_for_wakeup(counter: &AtomicU32, value: u32, timeout: Duration) {
+    let timeout = libc::timespec {
+        tv_sec: timeout.as_secs() as libc::time_t,
+        tv_nsec: timeout.subsec_nanos() as libc::c_long,
+    };
+
+    // not FUTEX_PRIVATE_FLAG, as the producer is another process. it fails
+    // with EAGAIN if the counter's already moved on, or EINTR, which is as
+    // good as a wakeup:
+    unsafe {
+        libc::syscall(libc::SYS_futex, counter as *const AtomicU32, libc::FUTEX_WAIT,
+            value, &timeout as *const libc::timespec);
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn wait_for_wakeup(counter: &AtomicU32, value: u32, timeout: Duration) {
+    if counter.load(Ordering::Acquire) == value {
+        thread::sleep(cmp::min(timeout, POLL_INTERVAL));
+    }
+}
+
+// The smallest buffer_size that holds a whole chunk, for config validation.
+// Smaller rings would be lapped before a chunk could be read from them.
+pub fn min_buffer_size(channels: u8) -> usize {
+    CHUNK_FRAMES * channels as usize * 2
+}
+
+pub struct Ring {
+    map: MmapMut,
+    capacity: usize,
+}
+
+impl Ring {
+    pub fn create(config: &ShmSource) -> io::Result<Ring> {
+        let frame_bytes = config.channels as usize * 2;
+
+        // keep whole frames in the ring so they never straddle the wrap:
+        let capacity = config.buffer_size - (config.buffer_size % frame_bytes);
+
+        if capacity < min_buffer_size(config.channels) {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                format!("buffer_size must be at least {} bytes", min_buffer_size(config.channels))));
+        }
+
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .open(&config.path)?;
+
+        file.set_len((HEADER_SIZE + capacity) as u64)?;
+
+        let mut map = unsafe { MmapOptions::new().map_mut(&file)? };
+
+        map[0..8].copy_from_slice(MAGIC);
+
+        let ring = Ring { map, capacity };
+        ring.header(CAPACITY_OFFSET).store(capacity as u64, Ordering::SeqCst);
+        ring.header(WRITTEN_OFFSET).store(0, Ordering::SeqCst);
+        ring.wakeups().store(0, Ordering::SeqCst);
+
+        Ok(ring)
+    }
+
+    fn header(&self, offset: usize) -> &AtomicU64 {
+        // the mapping is page aligned, so the header fields are naturally
+        // aligned too:
+        unsafe { &*(self.map.as_ptr().offset(offset as isize) as *const AtomicU64) }
+    }
+
+    fn wakeups(&self) -> &AtomicU32 {
+        unsafe { &*(self.map.as_ptr().offset(WAKEUP_OFFSET as isize) as *const AtomicU32) }
+    }
+
+    pub fn written(&self) -> u64 {
+        self.header(WRITTEN_OFFSET).load(Ordering::Acquire)
+    }
+
+    // Waits for the producer's next write, for at most timeout. The wakeup
+    // counter is read before anything's checked, so a write landing since
+    // then doesn't wait at all:
+    fn wait(&self, wakeups: u32, timeout: Duration) {
+        wait_for_wakeup(self.wakeups(), wakeups, timeout);
+    }
+
+    fn copy_out(&self, pos: u64, buf: &mut [u8]) {
+        let start = (pos % self.capacity as u64) as usize;
+        let first = buf.len().min(self.capacity - start);
+
+        unsafe {
+            let data = self.map.as_ptr().offset(HEADER_SIZE as isize);
+            ptr::copy_nonoverlapping(data.offset(start as isize), buf.as_mut_ptr(), first);
+            ptr::copy_nonoverlapping(data, buf[first..].as_mut_ptr(), buf.len() - first);
+        }
+    }
+
+    // Whether the producer may have written over len bytes at pos while
+    // they were copied out, leaving them torn. A write of up to len bytes
+    // can be under way past the written counter, so that much is left
+    // spare:
+    fn lapped(&self, pos: u64, len: usize) -> bool {
+        // keeps the copy from being read after the counter:
+        atomic::fence(Ordering::Acquire);
+
+        let written = self.header(WRITTEN_OFFSET).load(Ordering::Relaxed);
+        written < pos || written + len as u64 > pos + self.capacity as u64
+    }
+
+    // blocks until a producer starts writing into the ring, returning
+    // false instead if stop says to give up first, like on shutting down:
+    pub fn wait_for_writer<F: Fn() -> bool>(&self, stop: F) -> bool {
+        let initial = self.written();
+
+        loop {
+            let wakeups = self.wakeups().load(Ordering::Acquire);
+
+            if self.written() != initial {
+                return true;
+            }
+
+            if stop() {
+                return false;
+            }
+
+            self.wait(wakeups, STOP_CHECK_INTERVAL);
+        }
+    }
+}
+
+pub struct ShmStream {
+    ring: Arc<Ring>,
+    read_pos: u64,
+    sample_rate: u32,
+    channels: u8,
+    bitrate: i32,
+    idle_timeout: Duration,
+}
+
+impl ShmStream {
+    pub fn new(ring: Arc<Ring>, config: &ShmSource) -> ShmStream {
+        // start at the live edge rather than replaying whatever is sitting in
+        // the ring from before:
+        let read_pos = ring.written();
+
+        ShmStream {
+            ring,
+            read_pos,
+            sample_rate: config.sample_rate,
+            channels: config.channels,
+            bitrate: config.bitrate,
+            idle_timeout: Duration::from_secs(config.idle_timeout),
+        }
+    }
+}
+
+impl AudioStream for ShmStream {
+    fn codec_name(&self) -> &'static str {
+        "PCM"
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    fn channels(&self) -> u8 {
+        self.channels
+    }
+
+    fn bitrate_nominal(&self) -> i32 {
+        self.bitrate
+    }
+
+    fn read(&mut self) -> Result<StreamRead, StreamError> {
+        let channels = self.channels as usize;
+        let chunk_bytes = CHUNK_FRAMES * channels * 2;
+        let mut buf = vec![0u8; chunk_bytes];
+        let idle_since = Instant::now();
+
+        loop {
+            let wakeups = self.ring.wakeups().load(Ordering::Acquire);
+            let written = self.ring.written();
+
+            // producer restarted or lapped us, skip to the live edge:
+            if written < self.read_pos || written - self.read_pos > self.ring.capacity as u64 {
+                self.read_pos = written;
+            }
+
+            if written - self.read_pos >= chunk_bytes as u64 {
+                self.ring.copy_out(self.read_pos, &mut buf);
+
+                // a torn chunk would go out as noise, so it's dropped and
+                // the next is read from the live edge instead:
+                if self.ring.lapped(self.read_pos, chunk_bytes) {
+                    self.read_pos = self.ring.written();
+                    continue;
+                }
+
+                self.read_pos += chunk_bytes as u64;
+                break;
+            }
+
+            let idle = idle_since.elapsed();
+
+            if idle >= self.idle_timeout {
+                return Ok(StreamRead::Eof);
+            }
+
+            self.ring.wait(wakeups, self.idle_timeout - idle);
+        }
+
+        let mut pcm = vec![Vec::with_capacity(CHUNK_FRAMES); channels];
+
+        for (index, sample) in buf.chunks(2).enumerate() {
+            let sample = (sample[0] as u16 | (sample[1] as u16) << 8) as i16;
+            pcm[index % channels].push(sample);
+        }
+
+        Ok(StreamRead::Audio(pcm))
+    }
+}
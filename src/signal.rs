@@ -0,0 +1,23 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use libc;
+
+static SIGHUP_RECEIVED: AtomicBool = AtomicBool::new(false);
+
+extern "C" fn on_sighup(_: libc::c_int) {
+    SIGHUP_RECEIVED.store(true, Ordering::SeqCst);
+}
+
+// Catches SIGHUP, which the server takes as a sign to reload its config.
+// Only the rustcast binary calls this, so applications embedding rustcast
+// keep whatever signal handling they have.
+pub fn catch_sighup() {
+    unsafe {
+        libc::signal(libc::SIGHUP, on_sighup as libc::sighandler_t);
+    }
+}
+
+// returns whether a SIGHUP has arrived since the last call:
+pub fn take_sighup() -> bool {
+    SIGHUP_RECEIVED.swap(false, Ordering::SeqCst)
+}
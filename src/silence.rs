@@ -0,0 +1,56 @@
+use std::time::Duration;
+
+use lame::Lame;
+
+use mp3::FrameHeader;
+
+const SAMPLE_RATE: u32 = 44100;
+const SAMPLES_PER_FRAME: u32 = 1152;
+
+// A second or so of silent MP3 frames, encoded once up front, for filling
+// air on mountpoints that don't have a source yet.
+pub struct Silence {
+    frames: Vec<Box<[u8]>>,
+}
+
+impl Silence {
+    pub fn new() -> Silence {
+        let mut lame = Lame::new().unwrap();
+        lame.set_sample_rate(SAMPLE_RATE).unwrap();
+        lame.set_channels(2).unwrap();
+        lame.set_quality(9).unwrap();
+        lame.set_kilobitrate(128).unwrap();
+        lame.init_params().unwrap();
+
+        let pcm = vec![0i16; SAMPLE_RATE as usize];
+
+        // vector size calculation is a suggestion from lame/lame.h:
+        let mut mp3buff: Vec<u8> = vec![0; (pcm.len() * 5) / 4 + 7200];
+        let sz = lame.encode(&pcm, &pcm, &mut mp3buff).expect("encoding silence");
+        mp3buff.truncate(sz);
+
+        let mut frames = Vec::new();
+        let mut offset = 0;
+
+        while let Some(header) = FrameHeader::parse(&mp3buff[offset..]) {
+            let end = offset + header.frame_len();
+
+            if end > mp3buff.len() {
+                break;
+            }
+
+            frames.push(mp3buff[offset..end].to_vec().into_boxed_slice());
+            offset = end;
+        }
+
+        Silence { frames }
+    }
+
+    pub fn frames(&self) -> &[Box<[u8]>] {
+        &self.frames
+    }
+
+    pub fn frame_duration(&self) -> Duration {
+        Duration::from_secs(SAMPLES_PER_FRAME as u64) / SAMPLE_RATE
+    }
+}
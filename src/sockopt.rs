@@ -0,0 +1,134 @@
+// Tuning for the sockets listeners and sources connect on. tiny_http doesn't
+// hand out the sockets it accepts, so the options are set on the listening
+// socket instead, which accepted sockets inherit on Linux and the BSDs.
+
+use std::fs;
+use std::io;
+use std::mem;
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV4, SocketAddrV6};
+
+use libc;
+
+use config::SocketOptions;
+
+fn check(ret: libc::c_int) -> io::Result<()> {
+    if ret == -1 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(())
+    }
+}
+
+fn set(fd: libc::c_int, level: libc::c_int, option: libc::c_int, value: libc::c_int) -> io::Result<()> {
+    check(unsafe {
+        libc::setsockopt(fd, level, option,
+            &value as *const _ as *const libc::c_void,
+            mem::size_of::<libc::c_int>() as libc::socklen_t)
+    })
+}
+
+// The address fd is bound to, if it's a listening TCP socket.
+fn listening_addr(fd: libc::c_int) -> Option<SocketAddr> {
+    let mut accepting: libc::c_int = 0;
+    let mut len = mem::size_of::<libc::c_int>() as libc::socklen_t;
+
+    let ret = unsafe {
+        libc::getsockopt(fd, libc::SOL_SOCKET, libc::SO_ACCEPTCONN,
+            &mut accepting as *mut _ as *mut libc::c_void, &mut len)
+    };
+
+    if ret == -1 || accepting == 0 {
+        return None;
+    }
+
+    let mut addr: libc::sockaddr_storage = unsafe { mem::zeroed() };
+    let mut len = mem::size_of::<libc::sockaddr_storage>() as libc::socklen_t;
+
+    if unsafe { libc::getsockname(fd, &mut addr as *mut _ as *mut libc::sockaddr, &mut len) } == -1 {
+        return None;
+    }
+
+    match addr.ss_family as libc::c_int {
+        libc::AF_INET => {
+            let addr = unsafe { &*(&addr as *const _ as *const libc::sockaddr_in) };
+            let ip = Ipv4Addr::from(u32::from_be(addr.sin_addr.s_addr));
+            Some(SocketAddr::V4(SocketAddrV4::new(ip, u16::from_be(addr.sin_port))))
+        }
+        libc::AF_INET6 => {
+            let addr = unsafe { &*(&addr as *const _ as *const libc::sockaddr_in6) };
+            let ip = Ipv6Addr::from(addr.sin6_addr.s6_addr);
+            Some(SocketAddr::V6(SocketAddrV6::new(ip, u16::from_be(addr.sin6_port),
+                addr.sin6_flowinfo, addr.sin6_scope_id)))
+        }
+        _ => None,
+    }
+}
+
+// The descriptors we have open that are listening TCP sockets. Only the
+// descriptors that are actually open are looked at, as listed by the
+// system, rather than every number up to the limit.
+pub fn listeners() -> Vec<libc::c_int> {
+    let dir = if cfg!(target_os = "linux") { "/proc/self/fd" } else { "/dev/fd" };
+
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(_) => return Vec::new(),
+    };
+
+    entries
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| entry.file_name().to_str().and_then(|name| name.parse().ok()))
+        .filter(|&fd| listening_addr(fd).is_some())
+        .collect()
+}
+
+// tiny_http doesn't give out its listening socket either, so it's the one
+// that wasn't listening before the server started (before, from listeners)
+// and is bound to exactly the server's address. Until tiny_http hands out
+// the socket itself this is the best we can do, and it's done once at
+// startup.
+fn find_listener(before: &[libc::c_int], addr: SocketAddr) -> io::Result<libc::c_int> {
+    listeners().into_iter()
+        .filter(|fd| !before.contains(fd))
+        .find(|&fd| listening_addr(fd) == Some(addr))
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("no socket listening on {}", addr)))
+}
+
+pub fn apply(before: &[libc::c_int], addr: SocketAddr, options: &SocketOptions) -> io::Result<()> {
+    if !options.nodelay && options.send_buffer.is_none() && options.keepalive.is_none() {
+        return Ok(());
+    }
+
+    let fd = find_listener(before, addr)?;
+
+    if options.nodelay {
+        set(fd, libc::IPPROTO_TCP, libc::TCP_NODELAY, 1)?;
+    }
+
+    if let Some(size) = options.send_buffer {
+        set(fd, libc::SOL_SOCKET, libc::SO_SNDBUF, size as libc::c_int)?;
+    }
+
+    if let Some(idle) = options.keepalive {
+        set(fd, libc::SOL_SOCKET, libc::SO_KEEPALIVE, 1)?;
+        set_keepalive_idle(fd, idle)?;
+    }
+
+    Ok(())
+}
+
+#[cfg(target_os = "linux")]
+fn set_keepalive_idle(fd: libc::c_int, idle: u64) -> io::Result<()> {
+    set(fd, libc::IPPROTO_TCP, libc::TCP_KEEPIDLE, idle as libc::c_int)
+}
+
+#[cfg(target_os = "macos")]
+fn set_keepalive_idle(fd: libc::c_int, idle: u64) -> io::Result<()> {
+    set(fd, libc::IPPROTO_TCP, libc::TCP_KEEPALIVE, idle as libc::c_int)
+}
+
+// elsewhere keepalive is on, but probes start after the system default:
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+fn set_keepalive_idle(_fd: libc::c_int, _idle: u64) -> io::Result<()> {
+    Ok(())
+}
@@ -0,0 +1,203 @@
+// Sources fed from Rust code rather than over the network, for applications
+// embedding rustcast to generate audio in-process.
+
+use std::io::{self, Write};
+use std::sync::Arc;
+use std::sync::mpsc::{self, Receiver, SyncSender};
+use std::thread;
+use std::time::Instant;
+
+use archive::Archive;
+use audio::{AudioStream, Metadata, PcmData, StreamError, StreamRead};
+use server::{self, Rustcast, StartStreamError, StreamSource};
+
+// packets of PCM that can be queued up before writes block:
+const PCM_BUFFER: usize = 16;
+
+#[derive(Clone, Copy)]
+pub struct PcmFormat {
+    pub sample_rate: u32,
+    pub channels: u8,
+    // output bitrate in bits per second:
+    pub bitrate: i32,
+}
+
+enum PcmMessage {
+    Audio(PcmData),
+    Metadata(Metadata),
+}
+
+struct ChannelStream {
+    rx: Receiver<PcmMessage>,
+    format: PcmFormat,
+}
+
+impl AudioStream for ChannelStream {
+    fn codec_name(&self) -> &'static str {
+        "PCM"
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.format.sample_rate
+    }
+
+    fn channels(&self) -> u8 {
+        self.format.channels
+    }
+
+    fn bitrate_nominal(&self) -> i32 {
+        self.format.bitrate
+    }
+
+    fn read(&mut self) -> Result<StreamRead, StreamError> {
+        match self.rx.recv() {
+            Ok(PcmMessage::Audio(pcm)) => Ok(StreamRead::Audio(pcm)),
+            Ok(PcmMessage::Metadata(metadata)) => Ok(StreamRead::Metadata(metadata)),
+            // the PcmSource was dropped:
+            Err(_) => Ok(StreamRead::Eof),
+        }
+    }
+}
+
+fn stream_ended() -> io::Error {
+    io::Error::new(io::ErrorKind::BrokenPipe, "stream ended")
+}
+
+fn invalid_pcm(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, message)
+}
+
+// A live stream fed with PCM audio, which is encoded on a background thread.
+// The stream ends when this is dropped.
+pub struct PcmSource {
+    tx: SyncSender<PcmMessage>,
+    format: PcmFormat,
+}
+
+impl PcmSource {
+    // Queues a packet of audio, with one Vec of samples per channel. Blocks
+    // if the encoder is behind, and fails once the stream has ended (eg. it
+    // hit its max duration or was taken over). Packets without a Vec for
+    // each of the format's channels, all the same length and not empty, are
+    // refused with InvalidInput, as the encoder couldn't make sense of them.
+    pub fn write(&self, pcm: PcmData) -> io::Result<()> {
+        if pcm.len() != self.format.channels as usize {
+            return Err(invalid_pcm(format!("{} channels of samples given, the stream has {}",
+                pcm.len(), self.format.channels)));
+        }
+
+        if pcm.first().map(Vec::is_empty).unwrap_or(true) {
+            return Err(invalid_pcm("no samples given".to_owned()));
+        }
+
+        if pcm.iter().any(|samples| samples.len() != pcm[0].len()) {
+            return Err(invalid_pcm("channels have different numbers of samples".to_owned()));
+        }
+
+        self.tx.send(PcmMessage::Audio(pcm)).map_err(|_| stream_ended())
+    }
+
+    pub fn set_metadata(&self, metadata: Metadata) -> io::Result<()> {
+        self.tx.send(PcmMessage::Metadata(metadata)).map_err(|_| stream_ended())
+    }
+}
+
+pub fn create_pcm_source(rustcast: Arc<Rustcast>, mountpoint: &str, format: PcmFormat) -> Result<PcmSource, StartStreamError> {
+    let (tx, rx) = mpsc::sync_channel(PCM_BUFFER);
+    let (started_tx, started_rx) = mpsc::channel();
+    let mountpoint = mountpoint.to_owned();
+
+    // the stream borrows rustcast, so has to be started on the thread that
+    // runs it:
+    thread::spawn(move || {
+        let stream = match rustcast.start_in_process(&mountpoint) {
+            Ok(stream) => {
+                let _ = started_tx.send(Ok(()));
+                stream
+            }
+            Err(e) => {
+                let _ = started_tx.send(Err(e));
+                return;
+            }
+        };
+
+        let audio_stream = Box::new(ChannelStream { rx, format });
+
+        if let Err(e) = server::run_source(&rustcast, stream, audio_stream) {
+            rustcast.log.error(&format!("In-process source on {} failed: {:?}", mountpoint, e));
+        }
+    });
+
+    started_rx.recv().expect("source thread to report whether the stream started")?;
+
+    Ok(PcmSource { tx, format })
+}
+
+// A live stream fed with MP3 audio, which is sent to listeners as written.
+// The stream ends when this is dropped.
+pub struct Mp3Source<'a> {
+    rustcast: &'a Rustcast,
+    stream: StreamSource<'a>,
+    archive: Option<Archive>,
+    started: Instant,
+}
+
+impl<'a> Mp3Source<'a> {
+    pub fn set_metadata(&self, metadata: Metadata) {
+        self.rustcast.update_metadata(self.stream.mountpoint(), &self.stream, metadata);
+    }
+}
+
+impl<'a> Write for Mp3Source<'a> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.rustcast.is_shutdown() || self.stream.kicked() || self.stream.stopped() {
+            return Err(stream_ended());
+        }
+
+        self.stream.producing().map_err(|_| stream_ended())?;
+
+        let data = Arc::new(buf.to_vec().into_boxed_slice());
+
+        if let Some(archive) = self.archive.as_mut() {
+            archive.set_metadata(&self.stream.metadata.read().unwrap());
+            archive.write(&data);
+        }
+
+        self.stream.publish(data);
+
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl<'a> Drop for Mp3Source<'a> {
+    fn drop(&mut self) {
+        // close out the final dump segment before announcing the end of
+        // stream:
+        self.archive.take();
+
+        server::end_stream(self.rustcast, &self.stream, self.started);
+    }
+}
+
+pub fn create_mp3_source<'a>(rustcast: &'a Rustcast, mountpoint: &str) -> Result<Mp3Source<'a>, StartStreamError> {
+    let stream = rustcast.start_in_process(mountpoint)?;
+
+    let archive = if stream.dump_enabled() {
+        Archive::new(rustcast.config(), Arc::clone(&rustcast.catalog), mountpoint, stream.uuid)
+    } else {
+        None
+    };
+
+    rustcast.log.info(&format!("Started stream {} on {} (in-process MP3)", stream.uuid, mountpoint));
+
+    Ok(Mp3Source {
+        rustcast,
+        stream,
+        archive,
+        started: Instant::now(),
+    })
+}
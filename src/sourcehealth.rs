@@ -0,0 +1,213 @@
+// Watches how well a source is getting audio to us: whether it arrives as
+// fast as it plays (or the source is underrunning), whether it comes in fits
+// and starts (stuttering), how much of it can't be decoded, and the bitrate
+// it comes in at. Every window the source is judged healthy or not, and a
+// change either way is logged and published as a source_health event, so
+// whoever looks after the studio hears about it before listeners do. Sources
+// are judged from run on a timer, not as their packets arrive, so one that's
+// stopped sending altogether is noticed too.
+
+use std::cmp;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use config::SourceHealth;
+use events::Event;
+use server::Rustcast;
+
+// how often sources are checked for having reached the end of a window:
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Serialize, Clone, Debug)]
+pub struct SourceStats {
+    // seconds of audio received per second, 1.0 when keeping up:
+    pub speed: f64,
+    // the longest wait for a packet, in milliseconds:
+    pub longest_gap: u64,
+    // the share of packets that couldn't be decoded, from 0 to 1:
+    pub decode_errors: f64,
+    // kbps received, None for sources that aren't sent over the network:
+    pub kilobitrate: Option<u32>,
+}
+
+// The source has become unhealthy, or recovered.
+pub struct HealthChange {
+    pub healthy: bool,
+    pub problems: Vec<String>,
+    pub stats: SourceStats,
+}
+
+pub struct Monitor {
+    window_started: Instant,
+    // seconds of audio received this window:
+    audio: f64,
+    packets: u64,
+    bad_packets: u64,
+    // bytes the source had sent when the window started, and by its latest
+    // packet:
+    bytes_at_start: Option<u64>,
+    bytes_read: Option<u64>,
+    last_packet: Instant,
+    longest_gap: Duration,
+    healthy: bool,
+}
+
+fn secs(duration: Duration) -> f64 {
+    duration.as_secs() as f64 + duration.subsec_nanos() as f64 / 1e9
+}
+
+fn millis(duration: Duration) -> u64 {
+    duration.as_secs() * 1000 + duration.subsec_nanos() as u64 / 1_000_000
+}
+
+impl Monitor {
+    pub fn new(bytes_read: Option<u64>) -> Monitor {
+        let now = Instant::now();
+
+        Monitor {
+            window_started: now,
+            audio: 0.0,
+            packets: 0,
+            bad_packets: 0,
+            bytes_at_start: bytes_read,
+            bytes_read,
+            last_packet: now,
+            longest_gap: Duration::from_secs(0),
+            healthy: true,
+        }
+    }
+
+    fn arrived(&mut self) {
+        let now = Instant::now();
+        let gap = now.duration_since(self.last_packet);
+
+        if gap > self.longest_gap {
+            self.longest_gap = gap;
+        }
+
+        self.last_packet = now;
+    }
+
+    // A packet of seconds of audio has arrived.
+    pub fn packet(&mut self, seconds: f64) {
+        self.arrived();
+        self.audio += seconds;
+        self.packets += 1;
+    }
+
+    // A packet that couldn't be decoded has arrived.
+    pub fn bad_packet(&mut self) {
+        self.arrived();
+        self.bad_packets += 1;
+    }
+
+    // How many bytes the source has sent, after reading a packet.
+    pub fn set_bytes_read(&mut self, bytes_read: Option<u64>) {
+        self.bytes_read = bytes_read;
+    }
+
+    // Judges the source once every window. Returns what changed if it's
+    // become unhealthy or recovered since last time.
+    pub fn check(&mut self, config: &SourceHealth) -> Option<HealthChange> {
+        let elapsed = self.window_started.elapsed();
+
+        if elapsed < Duration::from_secs(config.window) {
+            return None;
+        }
+
+        let seconds = secs(elapsed);
+        let total = self.packets + self.bad_packets;
+
+        // a source that's gone quiet is still waiting for its next packet:
+        let longest_gap = cmp::max(self.longest_gap, self.last_packet.elapsed());
+
+        let stats = SourceStats {
+            speed: self.audio / seconds,
+            longest_gap: millis(longest_gap),
+            decode_errors: if total == 0 { 0.0 } else { self.bad_packets as f64 / total as f64 },
+            kilobitrate: match (self.bytes_at_start, self.bytes_read) {
+                (Some(start), Some(now)) => Some(((now - start) as f64 * 8.0 / 1000.0 / seconds) as u32),
+                _ => None,
+            },
+        };
+
+        // the gap since the last packet carries on into the next window:
+        *self = Monitor {
+            healthy: self.healthy,
+            last_packet: self.last_packet,
+            ..Monitor::new(self.bytes_read)
+        };
+
+        let problems = problems(config, &stats);
+        let healthy = problems.is_empty();
+
+        if healthy == self.healthy {
+            return None;
+        }
+
+        self.healthy = healthy;
+
+        Some(HealthChange { healthy, problems, stats })
+    }
+}
+
+fn problems(config: &SourceHealth, stats: &SourceStats) -> Vec<String> {
+    let mut problems = Vec::new();
+
+    if stats.speed < config.min_speed {
+        problems.push(format!("underrunning, sending {:.0}% of real time", stats.speed * 100.0));
+    }
+
+    if stats.longest_gap > config.max_gap {
+        problems.push(format!("stuttering, {} ms without audio", stats.longest_gap));
+    }
+
+    if stats.decode_errors > config.max_decode_errors {
+        problems.push(format!("{:.1}% of packets couldn't be decoded", stats.decode_errors * 100.0));
+    }
+
+    if let (Some(min), Some(kilobitrate)) = (config.min_bitrate, stats.kilobitrate) {
+        if kilobitrate < min {
+            problems.push(format!("sending {} kbps, under {} kbps", kilobitrate, min));
+        }
+    }
+
+    problems
+}
+
+pub fn run(rustcast: Arc<Rustcast>) {
+    while !rustcast.is_shutdown() {
+        thread::sleep(POLL_INTERVAL);
+
+        let config = rustcast.config();
+
+        let settings = match config.source_health {
+            Some(ref settings) => settings,
+            None => continue,
+        };
+
+        for (mountpoint, stream) in rustcast.live_streams() {
+            let change = match stream.check_health(settings) {
+                Some(change) => change,
+                None => continue,
+            };
+
+            if change.healthy {
+                rustcast.log.info(&format!("Source for stream {} on {} is healthy again",
+                    stream.uuid, mountpoint));
+            } else {
+                rustcast.log.error(&format!("Source for stream {} on {} is unhealthy: {}",
+                    stream.uuid, mountpoint, change.problems.join(", ")));
+            }
+
+            rustcast.publish_event(Event::SourceHealth {
+                mountpoint,
+                uuid: stream.uuid,
+                healthy: change.healthy,
+                problems: change.problems,
+                stats: change.stats,
+            });
+        }
+    }
+}
@@ -0,0 +1,324 @@
+// Historical stats kept in an SQLite database, for stations that want to
+// know how their shows did without running anything else: every stream and
+// listener session, and every track played. Fed from the event bus.
+
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use chrono::{DateTime, Utc};
+use rusqlite::{self, Connection};
+use uuid::Uuid;
+
+use events::{Event, EventSink};
+use log::Log;
+
+const SCHEMA: &'static str = "
+    CREATE TABLE IF NOT EXISTS stream_sessions (
+        uuid TEXT PRIMARY KEY,
+        mountpoint TEXT NOT NULL,
+        started_at TEXT NOT NULL,
+        ended_at TEXT,
+        peak_listeners INTEGER NOT NULL DEFAULT 0,
+        duration INTEGER
+    );
+
+    CREATE TABLE IF NOT EXISTS listener_sessions (
+        id INTEGER PRIMARY KEY,
+        mountpoint TEXT NOT NULL,
+        ip TEXT NOT NULL,
+        joined_at TEXT NOT NULL,
+        left_at TEXT,
+        user_agent TEXT,
+        player TEXT,
+        session TEXT,
+        peak_buffer INTEGER,
+        dropped INTEGER
+    );
+
+    CREATE INDEX IF NOT EXISTS listener_sessions_open
+        ON listener_sessions (mountpoint, left_at);
+
+    CREATE TABLE IF NOT EXISTS tracks (
+        id INTEGER PRIMARY KEY,
+        mountpoint TEXT NOT NULL,
+        stream_uuid TEXT NOT NULL,
+        artist TEXT,
+        title TEXT,
+        album TEXT,
+        started_at TEXT NOT NULL
+    );
+";
+
+// timestamps are stored in SQLite's own format, in UTC, so its date
+// functions work on them:
+fn now() -> String {
+    timestamp(Utc::now())
+}
+
+fn timestamp(at: DateTime<Utc>) -> String {
+    at.format("%Y-%m-%d %H:%M:%S").to_string()
+}
+
+// Adds a column to databases made before it existed.
+fn add_column(conn: &Connection, table: &str, column: &str, definition: &str) -> rusqlite::Result<()> {
+    let mut statement = conn.prepare(&format!("PRAGMA table_info({})", table))?;
+
+    let exists = statement.query_map(&[], |row| row.get::<_, String>(1))?
+        .collect::<rusqlite::Result<Vec<_>>>()?
+        .iter()
+        .any(|name| name == column);
+
+    if !exists {
+        conn.execute_batch(&format!("ALTER TABLE {} ADD COLUMN {} {}", table, column, definition))?;
+    }
+
+    Ok(())
+}
+
+// Opens the database for this run of rustcast, identified by run.
+fn open(path: &Path, run: &str) -> rusqlite::Result<Connection> {
+    let conn = Connection::open(path)?;
+    conn.execute_batch(SCHEMA)?;
+
+    add_column(&conn, "listener_sessions", "user_agent", "TEXT")?;
+    add_column(&conn, "listener_sessions", "player", "TEXT")?;
+    add_column(&conn, "listener_sessions", "session", "TEXT")?;
+    add_column(&conn, "listener_sessions", "peak_buffer", "INTEGER")?;
+    add_column(&conn, "listener_sessions", "dropped", "INTEGER")?;
+    add_column(&conn, "stream_sessions", "duration", "INTEGER")?;
+    add_column(&conn, "listener_sessions", "run", "TEXT")?;
+    add_column(&conn, "stream_sessions", "run", "TEXT")?;
+
+    // sessions still open from an earlier run are left over from rustcast
+    // stopping without ending them, so they're taken to have ended now.
+    // This run's own are still going, when the database is opened again
+    // after being changed in the config and back:
+    let now = now();
+    conn.execute("UPDATE stream_sessions SET ended_at = ?1 WHERE ended_at IS NULL AND run IS NOT ?2", &[&now, &run])?;
+    conn.execute("UPDATE listener_sessions SET left_at = ?1 WHERE left_at IS NULL AND run IS NOT ?2", &[&now, &run])?;
+
+    Ok(conn)
+}
+
+#[derive(Serialize)]
+pub struct StreamSession {
+    pub uuid: String,
+    pub mountpoint: String,
+    pub started_at: String,
+    pub ended_at: Option<String>,
+    // seconds of audio the source sent, once it's ended:
+    pub duration: Option<i64>,
+    pub peak_listeners: i64,
+    pub listener_hours: f64,
+}
+
+// A track as played on a mountpoint, with its actual total performances:
+// how many listeners heard at least some of it.
+#[derive(Serialize)]
+pub struct TrackPlay {
+    pub mountpoint: String,
+    pub artist: Option<String>,
+    pub title: Option<String>,
+    pub album: Option<String>,
+    pub started_at: String,
+    pub ended_at: String,
+    pub performances: i64,
+}
+
+#[derive(Serialize)]
+pub struct ListenerHours {
+    pub mountpoint: String,
+    pub listener_hours: f64,
+}
+
+pub struct StatsDb {
+    // None until the first event, and after the database is changed in the
+    // config:
+    conn: Mutex<Option<(PathBuf, Connection)>>,
+    // sessions are marked with the run of rustcast they were opened in:
+    run: String,
+}
+
+impl StatsDb {
+    pub fn new() -> StatsDb {
+        StatsDb {
+            conn: Mutex::new(None),
+            run: Uuid::new_v4().hyphenated().to_string(),
+        }
+    }
+
+    // Runs f with a connection to the database at path, opening it first if
+    // need be.
+    pub fn with_conn<F, T>(&self, path: &Path, f: F) -> rusqlite::Result<T>
+        where F: FnOnce(&Connection) -> rusqlite::Result<T>
+    {
+        let mut conn = self.conn.lock().expect("lock on stats database");
+
+        let stale = match *conn {
+            Some((ref open_path, _)) => open_path != path,
+            None => true,
+        };
+
+        if stale {
+            *conn = None;
+            *conn = Some((path.to_owned(), open(path, &self.run)?));
+        }
+
+        f(&conn.as_ref().expect("connection just opened").1)
+    }
+
+    fn record(&self, conn: &Connection, event: &Event) -> rusqlite::Result<()> {
+        match *event {
+            Event::StreamStarted { ref mountpoint, ref uuid } => {
+                conn.execute("INSERT OR REPLACE INTO stream_sessions (uuid, mountpoint, started_at, run) VALUES (?1, ?2, ?3, ?4)",
+                    &[&uuid.hyphenated().to_string(), mountpoint, &now(), &self.run])?;
+            }
+            Event::StreamEnded { ref uuid, duration, .. } => {
+                conn.execute("UPDATE stream_sessions SET ended_at = ?1, duration = ?2 WHERE uuid = ?3",
+                    &[&now(), &(duration as i64), &uuid.hyphenated().to_string()])?;
+            }
+            Event::MetadataChanged { ref mountpoint, ref uuid, ref metadata } => {
+                conn.execute("INSERT INTO tracks (mountpoint, stream_uuid, artist, title, album, started_at) VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+                    &[mountpoint, &uuid.hyphenated().to_string(),
+                        &metadata.artist, &metadata.title, &metadata.album, &now()])?;
+            }
+            Event::ListenerJoined { ref mountpoint, ip, ref user_agent, player, ref session } => {
+                conn.execute("INSERT INTO listener_sessions (mountpoint, ip, joined_at, user_agent, player, session, run) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+                    &[mountpoint, &ip.to_string(), &now(), user_agent, &player, session, &self.run])?;
+
+                conn.execute("UPDATE stream_sessions SET peak_listeners = MAX(peak_listeners,
+                        (SELECT COUNT(*) FROM listener_sessions WHERE mountpoint = ?1 AND left_at IS NULL))
+                    WHERE mountpoint = ?1 AND ended_at IS NULL",
+                    &[mountpoint])?;
+            }
+            Event::ListenerLeft { ref mountpoint, ip, ref session, peak_buffer, dropped, left, .. } => {
+                // the same address may be listening more than once, any of
+                // its sessions will do, preferring one with the same cookie:
+                conn.execute("UPDATE listener_sessions SET left_at = ?1, peak_buffer = ?5, dropped = ?6 WHERE id =
+                        (SELECT id FROM listener_sessions WHERE mountpoint = ?2 AND ip = ?3 AND left_at IS NULL
+                            ORDER BY session IS NOT ?4, id LIMIT 1)",
+                    &[&timestamp(left), mountpoint, &ip.to_string(), session, &(peak_buffer as i64), &(dropped as i64)])?;
+            }
+            _ => (),
+        }
+
+        Ok(())
+    }
+
+    // The most recent stream sessions, with how many listener hours were
+    // spent on the mountpoint while each was live.
+    pub fn stream_sessions(&self, path: &Path, limit: u32) -> rusqlite::Result<Vec<StreamSession>> {
+        self.with_conn(path, |conn| {
+            let mut statement = conn.prepare("
+                SELECT s.uuid, s.mountpoint, s.started_at, s.ended_at, s.duration, s.peak_listeners,
+                    COALESCE(SUM(
+                        MAX(0, julianday(MIN(COALESCE(l.left_at, datetime('now')), COALESCE(s.ended_at, datetime('now'))))
+                            - julianday(MAX(l.joined_at, s.started_at)))
+                    ) * 24, 0.0)
+                FROM stream_sessions s
+                LEFT JOIN listener_sessions l ON l.mountpoint = s.mountpoint
+                    AND l.joined_at <= COALESCE(s.ended_at, datetime('now'))
+                    AND COALESCE(l.left_at, datetime('now')) >= s.started_at
+                GROUP BY s.uuid
+                ORDER BY s.started_at DESC
+                LIMIT ?1")?;
+
+            let sessions = statement.query_map(&[&(limit as i64)], |row| {
+                StreamSession {
+                    uuid: row.get(0),
+                    mountpoint: row.get(1),
+                    started_at: row.get(2),
+                    ended_at: row.get(3),
+                    duration: row.get(4),
+                    peak_listeners: row.get(5),
+                    listener_hours: row.get(6),
+                }
+            })?;
+
+            sessions.collect()
+        })
+    }
+
+    // Tracks that started in [from, to), oldest first. A track ends when the
+    // next one on its stream starts, or when the stream ends.
+    pub fn track_plays(&self, path: &Path, from: &str, to: &str) -> rusqlite::Result<Vec<TrackPlay>> {
+        self.with_conn(path, |conn| {
+            let mut statement = conn.prepare("
+                WITH plays AS (
+                    SELECT t.mountpoint, t.artist, t.title, t.album, t.started_at,
+                        COALESCE(
+                            (SELECT MIN(n.started_at) FROM tracks n WHERE n.stream_uuid = t.stream_uuid AND n.id > t.id),
+                            (SELECT s.ended_at FROM stream_sessions s WHERE s.uuid = t.stream_uuid),
+                            datetime('now')) AS ended_at
+                    FROM tracks t
+                    WHERE t.started_at >= ?1 AND t.started_at < ?2
+                )
+                SELECT p.mountpoint, p.artist, p.title, p.album, p.started_at, p.ended_at,
+                    (SELECT COUNT(*) FROM listener_sessions l
+                        WHERE l.mountpoint = p.mountpoint
+                            AND l.joined_at < p.ended_at
+                            AND COALESCE(l.left_at, datetime('now')) > p.started_at)
+                FROM plays p
+                ORDER BY p.started_at")?;
+
+            let plays = statement.query_map(&[&from, &to], |row| {
+                TrackPlay {
+                    mountpoint: row.get(0),
+                    artist: row.get(1),
+                    title: row.get(2),
+                    album: row.get(3),
+                    started_at: row.get(4),
+                    ended_at: row.get(5),
+                    performances: row.get(6),
+                }
+            })?;
+
+            plays.collect()
+        })
+    }
+
+    // Hours spent listening to each mountpoint in [from, to), counting only
+    // the part of each listener session inside it.
+    pub fn listener_hours(&self, path: &Path, from: &str, to: &str) -> rusqlite::Result<Vec<ListenerHours>> {
+        self.with_conn(path, |conn| {
+            let mut statement = conn.prepare("
+                SELECT mountpoint,
+                    SUM(MAX(0, julianday(MIN(COALESCE(left_at, datetime('now')), ?2)) - julianday(MAX(joined_at, ?1)))) * 24
+                FROM listener_sessions
+                WHERE joined_at < ?2 AND COALESCE(left_at, datetime('now')) > ?1
+                GROUP BY mountpoint
+                ORDER BY mountpoint")?;
+
+            let hours = statement.query_map(&[&from, &to], |row| {
+                ListenerHours {
+                    mountpoint: row.get(0),
+                    listener_hours: row.get(1),
+                }
+            })?;
+
+            hours.collect()
+        })
+    }
+}
+
+pub struct StatsSink {
+    db: Arc<StatsDb>,
+    path: PathBuf,
+}
+
+impl StatsSink {
+    pub fn new(db: Arc<StatsDb>, path: PathBuf) -> StatsSink {
+        StatsSink { db, path }
+    }
+}
+
+impl EventSink for StatsSink {
+    fn publish(&self, event: &Event) {
+        let result = self.db.with_conn(&self.path, |conn| self.db.record(conn, event));
+
+        if let Err(e) = result {
+            Log::new().error(&format!("Couldn't record {:?} in stats database {}: {}",
+                event, self.path.display(), e));
+        }
+    }
+}
@@ -0,0 +1,108 @@
+// Pushes listener counts, bytes sent and active sources to a statsd server
+// every so often, for shops graphing with Graphite rather than Prometheus.
+
+use std::net::UdpSocket;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use config::Statsd;
+use log::Log;
+use server::Rustcast;
+
+// how often to check whether it's time to push, and whether statsd has been
+// configured or changed:
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+// Turns a mountpoint into a statsd key segment, "/dj/alice" is "dj.alice"
+// and "/" is "root".
+fn key(mountpoint: &str) -> String {
+    let key = mountpoint.trim_matches('/')
+        .chars()
+        .map(|c| match c {
+            '/' => '.',
+            c if c.is_alphanumeric() || c == '-' || c == '_' => c,
+            _ => '_',
+        })
+        .collect::<String>();
+
+    if key.is_empty() { "root".to_owned() } else { key }
+}
+
+// Statsd lines for the current state of rustcast. Bytes sent is a counter of
+// what's been sent since the last push, given the totals then.
+fn lines(rustcast: &Rustcast, prefix: &str, last_sent: &mut Option<u64>) -> Vec<String> {
+    let mut lines = Vec::new();
+    let streams = rustcast.live_streams();
+
+    // mirrors aren't sources of their own:
+    let sources = streams.iter().filter(|&&(_, ref stream)| !stream.is_mirror()).count();
+    lines.push(format!("{}.sources:{}|g", prefix, sources));
+
+    let mut listeners = 0;
+
+    for &(ref mountpoint, ref stream) in &streams {
+        let count = stream.listeners();
+        listeners += count;
+        lines.push(format!("{}.mounts.{}.listeners:{}|g", prefix, key(mountpoint), count));
+    }
+
+    lines.push(format!("{}.listeners:{}|g", prefix, listeners));
+
+    let total = rustcast.transfer.stats().total;
+
+    // the first push only sets the baseline, there's nothing to compare to:
+    if let Some(last) = *last_sent {
+        lines.push(format!("{}.bytes_sent:{}|c", prefix, total.saturating_sub(last)));
+    }
+
+    *last_sent = Some(total);
+
+    lines
+}
+
+fn push(socket: &UdpSocket, config: &Statsd, lines: &[String]) {
+    // one metric per datagram keeps clear of any MTU trouble:
+    for line in lines {
+        if let Err(e) = socket.send_to(line.as_bytes(), config.host.as_str()) {
+            Log::new().error(&format!("Couldn't send metrics to statsd at {}: {}", config.host, e));
+            return;
+        }
+    }
+}
+
+pub fn run(rustcast: Arc<Rustcast>) {
+    let socket = match UdpSocket::bind("0.0.0.0:0") {
+        Ok(socket) => socket,
+        Err(e) => {
+            Log::new().error(&format!("Couldn't open socket for statsd: {}", e));
+            return;
+        }
+    };
+
+    let mut last_push: Option<Instant> = None;
+    let mut last_sent = None;
+
+    while !rustcast.is_shutdown() {
+        thread::sleep(POLL_INTERVAL);
+
+        let config = rustcast.config();
+
+        let statsd = match config.statsd {
+            Some(ref statsd) => statsd,
+            None => continue,
+        };
+
+        let due = last_push.map(|at| at.elapsed() >= Duration::from_secs(statsd.interval))
+            .unwrap_or(true);
+
+        if !due {
+            continue;
+        }
+
+        last_push = Some(Instant::now());
+
+        let lines = lines(&rustcast, &statsd.prefix, &mut last_sent);
+        push(&socket, statsd, &lines);
+    }
+}
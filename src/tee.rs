@@ -0,0 +1,304 @@
+// Fans decoded audio from a source out to any number of sinks, each doing
+// its own thing with it: encoding it for listeners, recording it, and so on.
+// New output formats are added as sinks, without touching the ingest loop.
+//
+// Each sink runs on its own thread behind a bounded queue, so a slow encoder
+// loses audio rather than holding up reads from the source. Dropping the Tee
+// waits for every sink to finish what's queued, so everything encoded has
+// been published, and stream dumps closed, before the stream ends.
+
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::mpsc::{self, SyncSender, TrySendError};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use archive::Archive;
+use audio::PcmData;
+use config::{Config, OutputCodec};
+use encoder::{EncoderSettings, OutputEncoder};
+use server::{self, Stream, StreamData};
+
+pub trait PcmSink {
+    // What the sink is, for logs.
+    fn name(&self) -> String;
+
+    // The source has changed sample rate or channels. The sink should
+    // finish off anything it has in the old format and carry on in the new.
+    fn reformat(&mut self, sample_rate: u32, channels: u8) -> Result<(), String>;
+
+    // A packet of audio, one Vec of samples per channel.
+    fn write(&mut self, packet: &PcmData) -> Result<(), String>;
+}
+
+// packets of PCM that can be queued up for a sink before they're dropped.
+// a packet is usually a few tens of milliseconds of audio:
+const PCM_QUEUE: usize = 64;
+
+enum Work {
+    Reformat(u32, u8),
+    Audio(PcmData),
+}
+
+// A sink running on its own thread. If the sink fails or panics the thread
+// stops, and the failure is given back on the next thing sent to it.
+struct Worker {
+    name: String,
+    tx: SyncSender<Work>,
+    thread: thread::JoinHandle<()>,
+    error: Arc<Mutex<Option<String>>>,
+    dropped: usize,
+}
+
+impl Worker {
+    fn spawn(mut sink: Box<PcmSink + Send>) -> Worker {
+        let (tx, rx) = mpsc::sync_channel(PCM_QUEUE);
+        let error = Arc::new(Mutex::new(None));
+        let name = sink.name();
+
+        let thread = {
+            let error = Arc::clone(&error);
+
+            thread::spawn(move || {
+                let result = panic::catch_unwind(AssertUnwindSafe(move || {
+                    for work in rx {
+                        match work {
+                            Work::Reformat(sample_rate, channels) => sink.reformat(sample_rate, channels)?,
+                            Work::Audio(packet) => sink.write(&packet)?,
+                        }
+                    }
+
+                    Ok(())
+                }));
+
+                let failure = match result {
+                    Ok(Ok(())) => return,
+                    Ok(Err(e)) => e,
+                    Err(panic) => format!("crashed: {}", server::panic_message(&*panic)),
+                };
+
+                *error.lock().expect("lock on worker error") = Some(failure);
+            })
+        };
+
+        Worker { name, tx, thread, error, dropped: 0 }
+    }
+
+    // Waits for the sink to finish with everything queued.
+    fn finish(self) {
+        let Worker { tx, thread, .. } = self;
+
+        // the sink's thread stops once the queue's empty and closed:
+        drop(tx);
+        let _ = thread.join();
+    }
+
+    fn stopped(&self) -> String {
+        self.error.lock()
+            .expect("lock on worker error")
+            .clone()
+            .unwrap_or_else(|| "worker stopped".to_owned())
+    }
+
+    // A format change mustn't be lost, so waits for room in the queue.
+    fn reformat(&mut self, sample_rate: u32, channels: u8) -> Result<(), String> {
+        self.tx.send(Work::Reformat(sample_rate, channels))
+            .map_err(|_| self.stopped())
+    }
+
+    fn write(&mut self, packet: &PcmData) -> Result<(), String> {
+        match self.tx.try_send(Work::Audio(packet.clone())) {
+            Ok(()) => Ok(()),
+            Err(TrySendError::Full(_)) => {
+                self.dropped += 1;
+                Ok(())
+            }
+            Err(TrySendError::Disconnected(_)) => Err(self.stopped()),
+        }
+    }
+}
+
+pub struct Tee {
+    workers: Vec<Worker>,
+}
+
+impl Tee {
+    pub fn new() -> Tee {
+        Tee {
+            workers: Vec::new(),
+        }
+    }
+
+    pub fn add(&mut self, sink: Box<PcmSink + Send>) {
+        self.workers.push(Worker::spawn(sink));
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.workers.is_empty()
+    }
+
+    // Packets dropped so far because a sink was falling behind.
+    pub fn dropped(&self) -> usize {
+        self.workers.iter().map(|worker| worker.dropped).sum()
+    }
+
+    // Runs f on every worker, dropping those that have failed, and returns
+    // what went wrong with each one that did.
+    fn each<F>(&mut self, mut f: F) -> Vec<String> where F: FnMut(&mut Worker) -> Result<(), String> {
+        let mut errors = Vec::new();
+        let mut i = 0;
+
+        while i < self.workers.len() {
+            match f(&mut self.workers[i]) {
+                Ok(()) => i += 1,
+                Err(e) => {
+                    errors.push(format!("{}: {}", self.workers[i].name, e));
+                    self.workers.remove(i).finish();
+                }
+            }
+        }
+
+        errors
+    }
+
+    pub fn reformat(&mut self, sample_rate: u32, channels: u8) -> Vec<String> {
+        self.each(|worker| worker.reformat(sample_rate, channels))
+    }
+
+    pub fn write(&mut self, packet: &PcmData) -> Vec<String> {
+        self.each(|worker| worker.write(packet))
+    }
+}
+
+impl Drop for Tee {
+    fn drop(&mut self) {
+        for worker in self.workers.drain(..) {
+            worker.finish();
+        }
+    }
+}
+
+// Encodes audio for a stream's listeners, and its stream dump if it has one.
+pub struct EncoderSink {
+    stream: Arc<Stream>,
+    config: Arc<Config>,
+    mountpoint: String,
+    codec: OutputCodec,
+    kilobitrate: i32,
+    sample_rate: u32,
+    channels: u8,
+    encoder: OutputEncoder,
+    archive: Option<Archive>,
+    // skips encoding while nobody's listening:
+    lazy: bool,
+    idle: bool,
+}
+
+impl EncoderSink {
+    pub fn new(stream: Arc<Stream>, config: Arc<Config>, mountpoint: &str, codec: OutputCodec, kilobitrate: i32, sample_rate: u32, channels: u8, archive: Option<Archive>) -> Result<EncoderSink, String> {
+        let settings = EncoderSettings::new(sample_rate, channels, kilobitrate, config.mount(mountpoint));
+
+        let encoder = OutputEncoder::new(codec, &settings)
+            .map_err(|e| format!("couldn't start encoder: {:?}", e))?;
+
+        // a stream dump or timeshift needs everything encoded, listened to
+        // or not:
+        let lazy = archive.is_none() && config.mount(mountpoint)
+            .map(|mount| mount.lazy_encoding && mount.timeshift.is_none())
+            .unwrap_or(false);
+
+        Ok(EncoderSink {
+            stream,
+            config,
+            mountpoint: mountpoint.to_owned(),
+            codec,
+            kilobitrate,
+            sample_rate,
+            channels,
+            encoder,
+            archive,
+            lazy,
+            idle: false,
+        })
+    }
+
+    fn restart(&mut self) -> Result<(), String> {
+        let settings = EncoderSettings::new(self.sample_rate, self.channels, self.kilobitrate,
+            self.config.mount(&self.mountpoint));
+
+        self.encoder = OutputEncoder::new(self.codec, &settings)
+            .map_err(|e| format!("couldn't restart encoder: {:?}", e))?;
+
+        Ok(())
+    }
+
+    fn publish(&mut self, data: Vec<u8>) {
+        let data: StreamData = Arc::new(data.into_boxed_slice());
+
+        if let Some(archive) = self.archive.as_mut() {
+            archive.set_metadata(&self.stream.metadata.read().unwrap());
+            archive.write(&data);
+        }
+
+        self.stream.publish(data);
+    }
+}
+
+impl PcmSink for EncoderSink {
+    fn name(&self) -> String {
+        format!("{} {} kbps", self.codec.name(), self.kilobitrate)
+    }
+
+    fn reformat(&mut self, sample_rate: u32, channels: u8) -> Result<(), String> {
+        self.sample_rate = sample_rate;
+        self.channels = channels;
+
+        // an idle encoder is restarted in the new format when it's needed:
+        if self.idle {
+            return Ok(());
+        }
+
+        // finish off what the old encoder has, so nothing's lost. if that
+        // fails, only the end of the old format is:
+        if let Ok(flushed) = self.encoder.flush() {
+            self.publish(flushed);
+        }
+
+        self.restart()
+    }
+
+    fn write(&mut self, packet: &PcmData) -> Result<(), String> {
+        if self.lazy {
+            let listened = self.stream.has_listeners();
+
+            if !listened && !self.idle {
+                // what's buffered would be out of date by the time anyone
+                // gets it:
+                self.idle = true;
+                self.stream.clear_burst();
+            }
+
+            if listened && self.idle {
+                // the old encoder still has the end of the audio from
+                // before, which would play out of place:
+                self.idle = false;
+                self.restart()?;
+            }
+
+            if self.idle {
+                return Ok(());
+            }
+        }
+
+        let (left, right) = match packet.len() {
+            1     => (&packet[0], &packet[0]),
+            2 | _ => (&packet[0], &packet[1]),
+        };
+
+        let encoded = self.encoder.encode(left, right)
+            .map_err(|e| format!("encoder error: {:?}", e))?;
+
+        self.publish(encoded);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,194 @@
+// The last so many minutes of a stream, for mounts with timeshift set, so a
+// listener who paused can reconnect with ?offset=N and carry on N seconds
+// behind live from where they left off. Kept in memory, so a long timeshift
+// on a high bitrate mount costs as much RAM as it sounds like it would.
+
+use std::cmp;
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+use server::StreamData;
+
+struct Packet {
+    published: Instant,
+    data: StreamData,
+}
+
+struct State {
+    // how long packets are kept for, nothing's kept if zero:
+    length: Duration,
+    packets: VecDeque<Packet>,
+    // the number of the packet at the front of packets, counting from the
+    // first ever published:
+    first: u64,
+    ended: bool,
+    // bumped to close every reader there is now:
+    closed: u64,
+}
+
+pub struct Timeshift {
+    state: Mutex<State>,
+    published: Condvar,
+    readers: AtomicUsize,
+}
+
+impl Timeshift {
+    pub fn new() -> Timeshift {
+        Timeshift {
+            state: Mutex::new(State {
+                length: Duration::from_secs(0),
+                packets: VecDeque::new(),
+                first: 0,
+                ended: false,
+                closed: 0,
+            }),
+            published: Condvar::new(),
+            readers: AtomicUsize::new(0),
+        }
+    }
+
+    fn state(&self) -> MutexGuard<State> {
+        self.state.lock().expect("lock on timeshift")
+    }
+
+    pub fn set_length(&self, length: Duration) {
+        let mut state = self.state();
+        state.length = length;
+        state.trim(Instant::now());
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.state().length > Duration::from_secs(0)
+    }
+
+    pub fn push(&self, data: StreamData) {
+        let mut state = self.state();
+
+        if state.length == Duration::from_secs(0) {
+            return;
+        }
+
+        let now = Instant::now();
+        state.packets.push_back(Packet { published: now, data });
+        state.trim(now);
+
+        self.published.notify_all();
+    }
+
+    // The source has gone. Readers play out what's left, then stop.
+    pub fn end(&self) {
+        self.state().ended = true;
+        self.published.notify_all();
+    }
+
+    // Stops every reader, including those waiting for a packet to be due.
+    // Returns how many there were.
+    pub fn close_readers(&self) -> usize {
+        self.state().closed += 1;
+        self.published.notify_all();
+        self.readers()
+    }
+
+    pub fn readers(&self) -> usize {
+        self.readers.load(Ordering::SeqCst)
+    }
+
+    // A reader delay behind live, starting with the packet published delay
+    // ago. A delay longer than what's kept is cut down to the oldest packet
+    // there is, so it starts playing straight away.
+    pub fn read(timeshift: &Arc<Timeshift>, delay: Duration) -> Reader {
+        let state = timeshift.state();
+        let now = Instant::now();
+
+        let oldest = state.packets.front()
+            .map(|packet| now.duration_since(packet.published))
+            .unwrap_or(Duration::from_secs(0));
+
+        let delay = cmp::min(delay, cmp::min(state.length, oldest));
+
+        let skip = state.packets.iter()
+            .take_while(|packet| now.duration_since(packet.published) > delay)
+            .count();
+
+        timeshift.readers.fetch_add(1, Ordering::SeqCst);
+
+        Reader {
+            timeshift: Arc::clone(timeshift),
+            next: state.first + skip as u64,
+            closed: state.closed,
+            delay,
+        }
+    }
+}
+
+impl State {
+    fn trim(&mut self, now: Instant) {
+        while self.packets.front().map(|packet| now.duration_since(packet.published) > self.length).unwrap_or(false) {
+            self.packets.pop_front();
+            self.first += 1;
+        }
+    }
+}
+
+pub struct Reader {
+    timeshift: Arc<Timeshift>,
+    // the number of the packet to send next:
+    next: u64,
+    closed: u64,
+    delay: Duration,
+}
+
+impl Reader {
+    // Waits until the next packet is due, delay after it was published.
+    // None once the stream has ended and everything's been played out, or
+    // the reader's been closed.
+    pub fn recv(&mut self) -> Option<StreamData> {
+        let mut state = self.timeshift.state();
+
+        loop {
+            if state.closed != self.closed {
+                return None;
+            }
+
+            // fell so far behind its packets were thrown away, so carries
+            // on from the oldest:
+            if self.next < state.first {
+                self.next = state.first;
+            }
+
+            let index = (self.next - state.first) as usize;
+
+            let due = match state.packets.get(index) {
+                Some(packet) => packet.published + self.delay,
+                None if state.ended => return None,
+                None => {
+                    state = self.timeshift.published.wait(state)
+                        .expect("lock on timeshift");
+                    continue;
+                }
+            };
+
+            let now = Instant::now();
+
+            if due <= now {
+                let data = state.packets[index].data.clone();
+                self.next += 1;
+                return Some(data);
+            }
+
+            // woken early by every packet published, and by close_readers,
+            // so a kicked listener doesn't wait out its delay first:
+            state = self.timeshift.published.wait_timeout(state, due - now)
+                .expect("lock on timeshift")
+                .0;
+        }
+    }
+}
+
+impl Drop for Reader {
+    fn drop(&mut self) {
+        self.timeshift.readers.fetch_sub(1, Ordering::SeqCst);
+    }
+}
@@ -0,0 +1,213 @@
+// Bytes sent to listeners, per mountpoint and overall, for stats and for
+// transfer caps on capped hosting. Like History this outlives individual
+// streams, but it's only kept in memory so starts over on restart. Each
+// connection counts what it sends on its own, and run adds those counts up
+// once a second, so sending audio never waits on the totals.
+
+use std::collections::HashMap;
+use std::io::{self, Write};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use chrono::{Datelike, Local, NaiveDate};
+
+use config::MountConfig;
+use server::Rustcast;
+
+const BYTES_PER_MEGABYTE: u64 = 1_000_000;
+
+// how often connections' counts are added to the totals:
+const COLLECT_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Serialize, Clone, Default)]
+pub struct MountTransfer {
+    pub total: u64,
+    pub today: u64,
+    pub this_month: u64,
+    // the day and month today and this_month were counted over:
+    #[serde(skip)]
+    day: Option<NaiveDate>,
+    #[serde(skip)]
+    month: Option<(i32, u32)>,
+}
+
+impl MountTransfer {
+    // starts the daily and monthly counts over when their period has passed:
+    fn roll_over(&mut self, date: NaiveDate) {
+        if self.day != Some(date) {
+            self.day = Some(date);
+            self.today = 0;
+        }
+
+        let month = (date.year(), date.month());
+
+        if self.month != Some(month) {
+            self.month = Some(month);
+            self.this_month = 0;
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct TransferStats {
+    pub total: u64,
+    pub mounts: HashMap<String, MountTransfer>,
+}
+
+impl TransferStats {
+    fn record(&mut self, mountpoint: &str, bytes: usize, date: NaiveDate) {
+        self.total += bytes as u64;
+
+        let mount = self.mounts.entry(mountpoint.to_owned())
+            .or_insert_with(MountTransfer::default);
+
+        mount.roll_over(date);
+        mount.total += bytes as u64;
+        mount.today += bytes as u64;
+        mount.this_month += bytes as u64;
+    }
+}
+
+// What one connection has sent since its count was last collected.
+struct Connection {
+    mountpoint: String,
+    sent: Arc<AtomicUsize>,
+}
+
+pub struct Transfer {
+    stats: Mutex<TransferStats>,
+    connections: Mutex<Vec<Connection>>,
+}
+
+impl Transfer {
+    pub fn new() -> Transfer {
+        Transfer {
+            stats: Mutex::new(TransferStats {
+                total: 0,
+                mounts: HashMap::new(),
+            }),
+            connections: Mutex::new(Vec::new()),
+        }
+    }
+
+    // Starts counting a new connection to mountpoint.
+    fn connect(&self, mountpoint: &str) -> Arc<AtomicUsize> {
+        let sent = Arc::new(AtomicUsize::new(0));
+
+        self.connections.lock().expect("lock on transfer connections")
+            .push(Connection { mountpoint: mountpoint.to_owned(), sent: Arc::clone(&sent) });
+
+        sent
+    }
+
+    // Adds what every connection has sent to the totals, and forgets those
+    // that have closed.
+    fn collect(&self) {
+        let mut connections = self.connections.lock().expect("lock on transfer connections");
+        let mut sent = Vec::new();
+
+        connections.retain(|connection| {
+            // a closed connection can't send any more, so once it's been
+            // seen closed its last count is final:
+            let open = Arc::strong_count(&connection.sent) > 1;
+            let bytes = connection.sent.swap(0, Ordering::Relaxed);
+
+            if bytes > 0 {
+                sent.push((connection.mountpoint.clone(), bytes));
+            }
+
+            open
+        });
+
+        drop(connections);
+
+        if sent.is_empty() {
+            return;
+        }
+
+        let mut stats = self.stats.lock().expect("lock on transfer stats");
+        let today = Local::today().naive_local();
+
+        for (mountpoint, bytes) in sent {
+            stats.record(&mountpoint, bytes, today);
+        }
+    }
+
+    pub fn mount(&self, mountpoint: &str) -> MountTransfer {
+        self.collect();
+
+        let mut stats = self.stats.lock().expect("lock on transfer stats");
+
+        match stats.mounts.get_mut(mountpoint) {
+            Some(mount) => {
+                mount.roll_over(Local::today().naive_local());
+                mount.clone()
+            }
+            None => MountTransfer::default(),
+        }
+    }
+
+    pub fn stats(&self) -> TransferStats {
+        self.collect();
+
+        let mut stats = self.stats.lock().expect("lock on transfer stats");
+        let today = Local::today().naive_local();
+
+        for mount in stats.mounts.values_mut() {
+            mount.roll_over(today);
+        }
+
+        TransferStats {
+            total: stats.total,
+            mounts: stats.mounts.clone(),
+        }
+    }
+
+    // Whether mountpoint has used up its daily or monthly transfer cap, in
+    // which case it shouldn't take on any more listeners.
+    pub fn over_cap(&self, mountpoint: &str, mount: &MountConfig) -> bool {
+        let transfer = self.mount(mountpoint);
+
+        let over = |cap: Option<u64>, used: u64| {
+            cap.map(|cap| used >= cap * BYTES_PER_MEGABYTE).unwrap_or(false)
+        };
+
+        over(mount.daily_transfer_cap, transfer.today)
+            || over(mount.monthly_transfer_cap, transfer.this_month)
+    }
+}
+
+pub fn run(rustcast: Arc<Rustcast>) {
+    while !rustcast.is_shutdown() {
+        rustcast.sleep(COLLECT_INTERVAL);
+        rustcast.transfer.collect();
+    }
+}
+
+// Counts everything written through it towards a mountpoint's transfer.
+pub struct CountingWriter {
+    inner: Box<Write + Send>,
+    sent: Arc<AtomicUsize>,
+}
+
+impl CountingWriter {
+    pub fn new(inner: Box<Write + Send>, transfer: Arc<Transfer>, mountpoint: &str) -> CountingWriter {
+        CountingWriter {
+            inner,
+            sent: transfer.connect(mountpoint),
+        }
+    }
+}
+
+impl Write for CountingWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let len = self.inner.write(buf)?;
+        self.sent.fetch_add(len, Ordering::Relaxed);
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
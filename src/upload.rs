@@ -0,0 +1,154 @@
+extern crate hmac;
+extern crate sha2;
+
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::path::Path;
+
+use chrono::Utc;
+use reqwest::{self, Client, Url};
+use reqwest::header::Headers;
+
+use self::hmac::{Hmac, Mac};
+use self::sha2::{Digest, Sha256};
+
+use config::{Config, Upload};
+use log::Log;
+
+#[derive(Debug)]
+pub enum UploadError {
+    Io(io::Error),
+    Url(reqwest::UrlError),
+    Http(reqwest::Error),
+    Status(reqwest::StatusCode),
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+pub fn hmac_sha256(key: &[u8], data: &str) -> Vec<u8> {
+    let mut mac = Hmac::<Sha256>::new(key).expect("hmac accepts any key length");
+    mac.input(data.as_bytes());
+    mac.result().code().to_vec()
+}
+
+fn sha256_file(path: &str) -> io::Result<String> {
+    let mut file = File::open(path)?;
+    let mut hasher = Sha256::default();
+    let mut buff = [0u8; 64 * 1024];
+
+    loop {
+        let sz = file.read(&mut buff)?;
+
+        if sz == 0 {
+            break;
+        }
+
+        hasher.input(&buff[..sz]);
+    }
+
+    Ok(hex(&hasher.result()))
+}
+
+// percent encodes everything except unreserved characters and path
+// separators, as SigV4 expects of the canonical URI:
+fn uri_encode(path: &str) -> String {
+    let mut encoded = String::new();
+
+    for byte in path.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b'/' => {
+                encoded.push(byte as char);
+            }
+            _ => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+
+    encoded
+}
+
+fn put_object(upload: &Upload, key: &str, path: &str) -> Result<String, UploadError> {
+    let canonical_uri = uri_encode(&format!("/{}/{}", upload.bucket, key));
+
+    let url = Url::parse(&format!("{}{}", upload.endpoint.trim_end_matches('/'), canonical_uri))
+        .map_err(UploadError::Url)?;
+
+    let host = match (url.host_str(), url.port()) {
+        (Some(host), Some(port)) => format!("{}:{}", host, port),
+        (Some(host), None) => host.to_owned(),
+        (None, _) => return Err(UploadError::Url(reqwest::UrlError::EmptyHost)),
+    };
+
+    let now = Utc::now();
+    let amz_date = format!("{}", now.format("%Y%m%dT%H%M%SZ"));
+    let date = format!("{}", now.format("%Y%m%d"));
+    let scope = format!("{}/{}/s3/aws4_request", date, upload.region);
+
+    let payload_hash = sha256_file(path).map_err(UploadError::Io)?;
+
+    let canonical_request = format!(
+        "PUT\n{}\n\nhost:{}\nx-amz-content-sha256:{}\nx-amz-date:{}\n\nhost;x-amz-content-sha256;x-amz-date\n{}",
+        canonical_uri, host, payload_hash, amz_date, payload_hash);
+
+    let string_to_sign = format!("AWS4-HMAC-SHA256\n{}\n{}\n{}",
+        amz_date, scope, hex(&Sha256::digest(canonical_request.as_bytes())));
+
+    let signing_key = {
+        let key = hmac_sha256(format!("AWS4{}", upload.secret_key).as_bytes(), &date);
+        let key = hmac_sha256(&key, &upload.region);
+        let key = hmac_sha256(&key, "s3");
+        hmac_sha256(&key, "aws4_request")
+    };
+
+    let signature = hex(&hmac_sha256(&signing_key, &string_to_sign));
+
+    let mut headers = Headers::new();
+    headers.set_raw("x-amz-date", amz_date);
+    headers.set_raw("x-amz-content-sha256", payload_hash);
+    headers.set_raw("Authorization", format!(
+        "AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders=host;x-amz-content-sha256;x-amz-date, Signature={}",
+        upload.access_key, scope, signature));
+
+    let file = File::open(path).map_err(UploadError::Io)?;
+
+    let response = Client::new()
+        .put(url.clone())
+        .headers(headers)
+        .body(file)
+        .send()
+        .map_err(UploadError::Http)?;
+
+    if !response.status().is_success() {
+        return Err(UploadError::Status(response.status()));
+    }
+
+    Ok(url.into_string())
+}
+
+// Uploads a finished dump file to object storage if that's configured,
+// returning the URL it was uploaded to.
+pub fn upload(config: &Config, path: &str) -> Result<Option<String>, UploadError> {
+    let upload = match config.upload.as_ref() {
+        Some(upload) => upload,
+        None => return Ok(None),
+    };
+
+    let file_name = Path::new(path).file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_else(|| path.to_owned());
+
+    let key = format!("{}{}", upload.prefix, file_name);
+
+    let url = put_object(upload, &key, path)?;
+
+    // the file's uploaded either way, so a copy left behind is only worth
+    // a mention:
+    if upload.delete_local {
+        if let Err(e) = fs::remove_file(path) {
+            Log::new().error(&format!("Uploaded {} to {}, but couldn't delete it: {}", path, url, e));
+        }
+    }
+
+    Ok(Some(url))
+}
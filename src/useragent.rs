@@ -0,0 +1,42 @@
+// Sorts listener User-Agents into the players they come from, so stations
+// can see what their audience listens with. Anything not recognised is
+// "other", and listeners sending no User-Agent at all are "unknown".
+
+// checked in order, so more specific names come before anything they'd also
+// match (Edge and Chrome both claim to be Safari):
+const PLAYERS: &'static [(&'static str, &'static str)] = &[
+    ("vlc", "vlc"),
+    ("libvlc", "vlc"),
+    ("sonos", "sonos"),
+    ("itunes", "itunes"),
+    ("applecoremedia", "apple"),
+    ("winamp", "winamp"),
+    ("foobar2000", "foobar2000"),
+    ("mplayer", "mplayer"),
+    ("mpv", "mpv"),
+    ("lavf", "ffmpeg"),
+    ("ffmpeg", "ffmpeg"),
+    ("gstreamer", "gstreamer"),
+    ("exoplayer", "android"),
+    ("stagefright", "android"),
+    ("tunein", "tunein"),
+    ("alexa", "alexa"),
+    ("curl", "curl"),
+    ("wget", "wget"),
+    ("edg/", "edge"),
+    ("firefox", "firefox"),
+    ("chrome", "chrome"),
+    ("safari", "safari"),
+];
+
+pub fn classify(user_agent: Option<&str>) -> &'static str {
+    let user_agent = match user_agent {
+        Some(user_agent) if !user_agent.trim().is_empty() => user_agent.to_lowercase(),
+        _ => return "unknown",
+    };
+
+    PLAYERS.iter()
+        .find(|&&(pattern, _)| user_agent.contains(pattern))
+        .map(|&(_, player)| player)
+        .unwrap_or("other")
+}
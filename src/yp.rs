@@ -0,0 +1,244 @@
+// Announces public mountpoints to Icecast YP directory servers such as
+// dir.xiph.org, keeping the listings up to date with listener counts and
+// what's playing.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use reqwest::{self, Client, Response};
+use uuid::Uuid;
+
+use config::{Config, MountConfig};
+use log::Log;
+use server::{Rustcast, Stream};
+
+// how often to look for listings to add, touch or remove:
+const POLL_INTERVAL: Duration = Duration::from_secs(10);
+
+// how often to touch a listing until the directory says otherwise, and how
+// long to wait before retrying a failed add:
+const DEFAULT_TOUCH_FREQ: Duration = Duration::from_secs(300);
+
+#[derive(Debug)]
+pub enum YpError {
+    Http(reqwest::Error),
+    Status(reqwest::StatusCode),
+    Rejected(String),
+}
+
+struct Listing {
+    uuid: Uuid,
+    // None if the directory hasn't accepted the listing yet:
+    sid: Option<String>,
+    touch_freq: Duration,
+    last_contact: Option<Instant>,
+}
+
+fn header(response: &Response, name: &str) -> Option<String> {
+    response.headers().get_raw(name)
+        .and_then(|raw| raw.one())
+        .map(|value| String::from_utf8_lossy(value).trim().to_owned())
+}
+
+fn call(yp_url: &str, params: &[(&str, String)]) -> Result<Response, YpError> {
+    let response = Client::new()
+        .post(yp_url)
+        .form(&params)
+        .send()
+        .map_err(YpError::Http)?;
+
+    if !response.status().is_success() {
+        return Err(YpError::Status(response.status()));
+    }
+
+    if header(&response, "YPResponse").as_ref().map(String::as_str) != Some("1") {
+        let message = header(&response, "YPMessage")
+            .unwrap_or_else(|| "no message".to_owned());
+
+        return Err(YpError::Rejected(message));
+    }
+
+    Ok(response)
+}
+
+fn song_title(stream: &Stream) -> String {
+    let metadata = stream.metadata.read().unwrap();
+
+    match (metadata.artist.as_ref(), metadata.title.as_ref()) {
+        (Some(artist), Some(title)) => format!("{} - {}", artist, title),
+        (None, Some(title)) => title.clone(),
+        (Some(artist), None) => artist.clone(),
+        (None, None) => String::new(),
+    }
+}
+
+fn add(yp_url: &str, mountpoint: &str, mount: &MountConfig, listen_url: String) -> Result<(String, Duration), YpError> {
+    let mut params = vec![
+        ("action", "add".to_owned()),
+        ("sn", mount.name.clone().unwrap_or_else(|| mountpoint.to_owned())),
+        ("genre", mount.genre.clone().unwrap_or_default()),
+        ("desc", mount.description.clone().unwrap_or_default()),
+        ("url", mount.url.clone().unwrap_or_default()),
+        ("listenurl", listen_url),
+        ("type", mount.content_type().to_owned()),
+        ("stype", "rustcast".to_owned()),
+    ];
+
+    if let Some(bitrate) = mount.bitrate {
+        params.push(("b", format!("{}", bitrate)));
+    }
+
+    let response = call(yp_url, &params)?;
+
+    let sid = match header(&response, "SID") {
+        Some(sid) => sid,
+        None => return Err(YpError::Rejected("no SID in response".to_owned())),
+    };
+
+    let touch_freq = header(&response, "TouchFreq")
+        .and_then(|freq| freq.parse().ok())
+        .map(Duration::from_secs)
+        .unwrap_or(DEFAULT_TOUCH_FREQ);
+
+    Ok((sid, touch_freq))
+}
+
+fn touch(yp_url: &str, sid: &str, stream: &Stream) -> Result<(), YpError> {
+    let params = [
+        ("action", "touch".to_owned()),
+        ("sid", sid.to_owned()),
+        ("st", song_title(stream)),
+        ("listeners", format!("{}", stream.listeners())),
+    ];
+
+    call(yp_url, &params).map(|_| ())
+}
+
+fn remove(yp_url: &str, sid: &str) -> Result<(), YpError> {
+    let params = [
+        ("action", "remove".to_owned()),
+        ("sid", sid.to_owned()),
+    ];
+
+    call(yp_url, &params).map(|_| ())
+}
+
+// mountpoints to list, with their settings and public stream URLs:
+fn listable(config: &Config, rustcast: &Rustcast) -> Vec<(String, Arc<Stream>, String)> {
+    rustcast.public_streams().into_iter()
+        .filter(|&(ref mountpoint, _)| {
+            config.mount(mountpoint).map(|mount| mount.public).unwrap_or(false)
+        })
+        .filter_map(|(mountpoint, stream)| {
+            config.public_stream_url(&mountpoint)
+                .map(|url| (mountpoint, stream, url))
+        })
+        .collect()
+}
+
+pub fn run(rustcast: Arc<Rustcast>) {
+    let log = Log::new();
+
+    // keyed by directory URL and mountpoint:
+    let mut listings = HashMap::<(String, String), Listing>::new();
+
+    let mut warned_no_public_url = false;
+
+    while !rustcast.is_shutdown() {
+        rustcast.sleep(POLL_INTERVAL);
+
+        let config = rustcast.config();
+
+        if !config.yp_urls.is_empty() && config.public_url.is_none() {
+            if !warned_no_public_url {
+                log.error("YP directories configured without public_url, not announcing");
+                warned_no_public_url = true;
+            }
+        } else {
+            warned_no_public_url = false;
+        }
+
+        let streams = listable(&config, &rustcast);
+
+        // take down listings for streams that have ended or shouldn't be
+        // listed any more:
+        let stale = listings.iter()
+            .filter(|&(&(ref yp_url, ref mountpoint), listing)| {
+                !config.yp_urls.contains(yp_url) || !streams.iter().any(|&(ref live, ref stream, _)| {
+                    live == mountpoint && stream.uuid == listing.uuid
+                })
+            })
+            .map(|(key, _)| key.clone())
+            .collect::<Vec<_>>();
+
+        for key in stale {
+            let listing = listings.remove(&key).expect("stale listing to exist");
+
+            if let Some(sid) = listing.sid {
+                if let Err(e) = remove(&key.0, &sid) {
+                    log.error(&format!("Couldn't remove {} from YP directory {}: {:?}",
+                        key.1, key.0, e));
+                }
+            }
+        }
+
+        for yp_url in config.yp_urls.iter() {
+            for &(ref mountpoint, ref stream, ref listen_url) in streams.iter() {
+                let key = (yp_url.clone(), mountpoint.clone());
+
+                let listing = listings.entry(key).or_insert_with(|| Listing {
+                    uuid: stream.uuid,
+                    sid: None,
+                    touch_freq: DEFAULT_TOUCH_FREQ,
+                    last_contact: None,
+                });
+
+                if let Some(last_contact) = listing.last_contact {
+                    if last_contact.elapsed() < listing.touch_freq {
+                        continue;
+                    }
+                }
+
+                listing.last_contact = Some(Instant::now());
+
+                let sid = match listing.sid.clone() {
+                    Some(sid) => sid,
+                    None => {
+                        let mount = config.mount(mountpoint).expect("public mount to be configured");
+
+                        match add(yp_url, mountpoint, mount, listen_url.clone()) {
+                            Ok((sid, touch_freq)) => {
+                                log.info(&format!("Listed {} in YP directory {}", mountpoint, yp_url));
+                                listing.sid = Some(sid);
+                                listing.touch_freq = touch_freq;
+                            }
+                            Err(e) => {
+                                log.error(&format!("Couldn't list {} in YP directory {}: {:?}",
+                                    mountpoint, yp_url, e));
+                            }
+                        }
+
+                        continue;
+                    }
+                };
+
+                if let Err(e) = touch(yp_url, &sid, stream) {
+                    log.error(&format!("Couldn't update {} in YP directory {}: {:?}",
+                        mountpoint, yp_url, e));
+
+                    // the directory may have dropped the listing, so add it
+                    // again next time around:
+                    listing.sid = None;
+                }
+            }
+        }
+    }
+
+    // take everything down on the way out:
+    for ((yp_url, _), listing) in listings {
+        if let Some(sid) = listing.sid {
+            let _ = remove(&yp_url, &sid);
+        }
+    }
+}
@@ -0,0 +1,137 @@
+// End to end tests of a real server, with sources and listeners connecting
+// over HTTP. Run with `cargo test`.
+
+extern crate base64;
+extern crate rustcast;
+
+mod support;
+
+use std::io::Write;
+
+use support::{FakeSource, Listener, Server, FIXTURE_ARTIST, FIXTURE_TITLE, SOURCE_PASSWORD};
+
+#[test]
+fn source_with_wrong_password_is_unauthorized() {
+    let server = Server::start("");
+
+    let mut source = FakeSource::connect(&server, "/live", "wrong", "application/ogg");
+
+    assert_eq!(source.status(), 401);
+}
+
+#[test]
+fn source_with_unsupported_content_type_is_refused() {
+    let server = Server::start("");
+
+    let mut source = FakeSource::connect(&server, "/live", SOURCE_PASSWORD, "video/mp4");
+
+    assert_eq!(source.status(), 415);
+}
+
+#[test]
+fn second_source_on_live_mountpoint_is_refused() {
+    let server = Server::start("");
+
+    let mut first = FakeSource::connect(&server, "/live", SOURCE_PASSWORD, "application/ogg");
+    assert_eq!(first.status(), 200);
+    first.send_fixture();
+    server.wait_for_live("/live");
+
+    let mut second = FakeSource::connect(&server, "/live", SOURCE_PASSWORD, "application/ogg");
+    assert_eq!(second.status(), 403);
+}
+
+#[test]
+fn metadata_updates_reach_mount_json() {
+    let server = Server::start("");
+
+    let mut source = FakeSource::connect(&server, "/live", SOURCE_PASSWORD, "application/ogg");
+    assert_eq!(source.status(), 200);
+    source.send_fixture();
+
+    // the fixture's own tags come first:
+    server.wait_for_body("/live.json", FIXTURE_ARTIST);
+    server.wait_for_body("/live.json", FIXTURE_TITLE);
+
+    // changes have to be POSTed:
+    let (status, _) = server.get("/admin/metadata?mount=/live&song=Artist%20-%20Title");
+    assert_eq!(status, 405);
+
+    let (status, _) = server.post("/admin/metadata?mount=/live&song=Artist%20-%20Title");
+    assert_eq!(status, 200);
+
+    let (status, body) = server.get("/live.json");
+    assert_eq!(status, 200);
+    assert!(body.contains(r#""artist":"Artist""#), "unexpected /live.json: {}", body);
+    assert!(body.contains(r#""title":"Title""#), "unexpected /live.json: {}", body);
+}
+
+#[test]
+fn listener_on_mountpoint_without_source_is_not_found() {
+    let server = Server::start("");
+
+    let listener = Listener::connect(&server, "/nothing");
+
+    assert_eq!(listener.status, 404);
+}
+
+#[test]
+fn audio_reaches_every_listener() {
+    let server = Server::start("");
+
+    let mut source = FakeSource::connect(&server, "/live", SOURCE_PASSWORD, "application/ogg");
+    assert_eq!(source.status(), 200);
+    server.wait_for_live("/live");
+
+    let mut listeners = (0..3)
+        .map(|_| Listener::connect(&server, "/live"))
+        .collect::<Vec<_>>();
+
+    server.wait_for_listeners("/live", listeners.len());
+
+    source.send_fixture();
+
+    // everyone gets the same MP3, encoded from the source's Vorbis:
+    let heard = listeners.iter_mut()
+        .map(|listener| {
+            assert_eq!(listener.status, 200);
+            listener.read_audio(4096)
+        })
+        .collect::<Vec<_>>();
+
+    assert!(heard.iter().all(|audio| *audio == heard[0]), "listeners heard different audio");
+}
+
+#[test]
+fn source_connecting_with_put_is_heard() {
+    let server = Server::start("");
+
+    let mut source = FakeSource::connect_put(&server, "/live", SOURCE_PASSWORD, "application/ogg");
+    assert_eq!(source.status(), 100);
+    assert_eq!(source.status(), 200);
+    server.wait_for_live("/live");
+
+    let mut listener = Listener::connect(&server, "/live");
+    server.wait_for_listeners("/live", 1);
+
+    source.send_fixture();
+
+    assert_eq!(listener.status, 200);
+    assert_eq!(listener.read_audio(4096).len(), 4096);
+}
+
+#[test]
+fn in_process_mp3_reaches_listeners_as_written() {
+    let server = Server::start("");
+
+    let mut source = server.handle().create_mp3_source("/mp3").expect("start mp3 source");
+
+    let mut listener = Listener::connect(&server, "/mp3");
+    server.wait_for_listeners("/mp3", 1);
+
+    let audio = (0..4096).map(|i| i as u8).collect::<Vec<u8>>();
+    source.write_all(&audio).expect("write audio");
+
+    assert_eq!(listener.status, 200);
+    assert_eq!(listener.read_audio(audio.len()), audio);
+}
@@ -0,0 +1,258 @@
+// A rustcast server to test against, with a fake source client and
+// listeners that talk to it over HTTP like the real things would. The fake
+// source sends fixtures/silence.ogg: a few seconds of stereo Vorbis
+// silence, tagged with FIXTURE_ARTIST and FIXTURE_TITLE.
+
+#![allow(dead_code)]
+
+use std::env;
+use std::fs;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::TcpStream;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use base64;
+use rustcast::{self, config, Handle, StreamEntry};
+
+pub const SOURCE_PASSWORD: &'static str = "hackme";
+pub const API_KEY: &'static str = "test-key";
+
+pub const FIXTURE_ARTIST: &'static str = "Fixture Artist";
+pub const FIXTURE_TITLE: &'static str = "Fixture Title";
+
+// how long to wait for the server to get round to something before failing:
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+static NEXT_CONFIG: AtomicUsize = AtomicUsize::new(0);
+
+pub struct Server {
+    handle: Option<Handle>,
+    pub addr: String,
+    config_path: PathBuf,
+}
+
+impl Server {
+    // Starts a server on a free port, with extra appended to its config.
+    pub fn start(extra: &str) -> Server {
+        let config_path = env::temp_dir().join(format!("rustcast-test-{}-{}.toml",
+            std::process::id(), NEXT_CONFIG.fetch_add(1, Ordering::SeqCst)));
+
+        fs::write(&config_path, format!(r#"
+listen = "127.0.0.1:0"
+
+[mounts."/live"]
+password = "{}"
+
+[[api_keys]]
+key = "{}"
+mounts = ["/"]
+
+{}
+"#, SOURCE_PASSWORD, API_KEY, extra)).expect("write test config");
+
+        // the port is picked by the system as the server binds it, so no
+        // other test can take it in the meantime:
+        let config = config::open(&config_path).expect("open test config");
+        let handle = rustcast::start(config_path.clone(), config).expect("start server");
+        let addr = format!("{}", handle.local_addr());
+
+        Server { handle: Some(handle), addr, config_path }
+    }
+
+    pub fn handle(&self) -> &Handle {
+        self.handle.as_ref().expect("server running")
+    }
+
+    // Waits until mountpoint has at least count listeners.
+    pub fn wait_for_listeners(&self, mountpoint: &str, count: usize) {
+        wait_until(&format!("{} listeners on {}", count, mountpoint), || {
+            match self.handle().rustcast().get_stream(mountpoint) {
+                Some(StreamEntry::Live(stream)) => stream.listeners() >= count,
+                _ => false,
+            }
+        });
+    }
+
+    // Waits until mountpoint is live.
+    pub fn wait_for_live(&self, mountpoint: &str) {
+        wait_until(&format!("{} to go live", mountpoint), || {
+            match self.handle().rustcast().get_stream(mountpoint) {
+                Some(StreamEntry::Live(_)) => true,
+                _ => false,
+            }
+        });
+    }
+
+    // Waits until a GET of path has needle in its body.
+    pub fn wait_for_body(&self, path: &str, needle: &str) {
+        wait_until(&format!("{:?} in {}", needle, path), || self.get(path).1.contains(needle));
+    }
+
+    // A GET request, returning the status and body.
+    pub fn get(&self, path: &str) -> (u16, String) {
+        self.request("GET", path)
+    }
+
+    pub fn post(&self, path: &str) -> (u16, String) {
+        self.request("POST", path)
+    }
+
+    fn request(&self, method: &str, path: &str) -> (u16, String) {
+        let mut conn = connect(&self.addr);
+
+        write!(conn, "{} {} HTTP/1.0\r\nHost: {}\r\nAuthorization: Bearer {}\r\nContent-Length: 0\r\n\r\n",
+            method, path, self.addr, API_KEY).expect("send request");
+
+        let mut reader = BufReader::new(conn);
+        let (status, _) = read_head(&mut reader).expect("read response");
+
+        let mut body = String::new();
+        reader.read_to_string(&mut body).expect("read body");
+
+        (status, body)
+    }
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        if let Some(handle) = self.handle.take() {
+            handle.shutdown();
+        }
+
+        let _ = fs::remove_file(&self.config_path);
+    }
+}
+
+// A source client, connected with the Icecast SOURCE method or PUT.
+pub struct FakeSource {
+    conn: TcpStream,
+    reader: BufReader<TcpStream>,
+}
+
+impl FakeSource {
+    pub fn connect(server: &Server, mountpoint: &str, password: &str, content_type: &str) -> FakeSource {
+        FakeSource::open(server, &format!("SOURCE {} HTTP/1.0", mountpoint), password, content_type, "")
+    }
+
+    // Connects the way newer source clients do, with PUT, waiting to be
+    // told to go ahead before sending audio.
+    pub fn connect_put(server: &Server, mountpoint: &str, password: &str, content_type: &str) -> FakeSource {
+        FakeSource::open(server, &format!("PUT {} HTTP/1.1", mountpoint), password, content_type,
+            "Expect: 100-continue\r\n")
+    }
+
+    fn open(server: &Server, request_line: &str, password: &str, content_type: &str, extra_headers: &str) -> FakeSource {
+        let mut conn = connect(&server.addr);
+
+        write!(conn, "{}\r\nHost: {}\r\nAuthorization: Basic {}\r\nContent-Type: {}\r\nIce-Name: test\r\n{}\r\n",
+            request_line, server.addr, basic_auth("source", password), content_type, extra_headers)
+            .expect("send source request");
+
+        let reader = BufReader::new(conn.try_clone().expect("clone source connection"));
+
+        FakeSource { conn, reader }
+    }
+
+    // The status of the next response the server answers with, once it
+    // has. PUT sources get an interim 100 first.
+    pub fn status(&mut self) -> u16 {
+        read_head(&mut self.reader).expect("read source response").0
+    }
+
+    // Sends audio, as a canned file or anything else.
+    pub fn send<R: Read>(&mut self, audio: &mut R) -> io::Result<u64> {
+        io::copy(audio, &mut self.conn)
+    }
+
+    // Sends fixtures/silence.ogg.
+    pub fn send_fixture(&mut self) {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/silence.ogg");
+        let mut fixture = fs::File::open(path).expect("open fixture");
+        self.send(&mut fixture).expect("send fixture");
+    }
+}
+
+// A listener on a mountpoint, which has had its response headers.
+pub struct Listener {
+    reader: BufReader<TcpStream>,
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+}
+
+impl Listener {
+    pub fn connect(server: &Server, path: &str) -> Listener {
+        let mut conn = connect(&server.addr);
+
+        write!(conn, "GET {} HTTP/1.0\r\nHost: {}\r\nUser-Agent: rustcast-test\r\n\r\n",
+            path, server.addr).expect("send listener request");
+
+        let mut reader = BufReader::new(conn);
+        let (status, headers) = read_head(&mut reader).expect("read listener response");
+
+        Listener { reader, status, headers }
+    }
+
+    // Reads exactly len bytes of audio.
+    pub fn read_audio(&mut self, len: usize) -> Vec<u8> {
+        let mut audio = vec![0u8; len];
+        self.reader.read_exact(&mut audio).expect("read audio");
+        audio
+    }
+}
+
+fn connect(addr: &str) -> TcpStream {
+    let conn = TcpStream::connect(addr).expect("connect to server");
+    conn.set_read_timeout(Some(TIMEOUT)).expect("set read timeout");
+    conn
+}
+
+fn wait_until<F: FnMut() -> bool>(what: &str, mut f: F) {
+    let started = Instant::now();
+
+    while !f() {
+        if started.elapsed() > TIMEOUT {
+            panic!("timed out waiting for {}", what);
+        }
+
+        thread::sleep(Duration::from_millis(10));
+    }
+}
+
+// The status and headers of a response, leaving reader at its body.
+fn read_head<R: BufRead>(reader: &mut R) -> io::Result<(u16, Vec<(String, String)>)> {
+    let mut line = String::new();
+    reader.read_line(&mut line)?;
+
+    let status = line.split_whitespace()
+        .nth(1)
+        .and_then(|status| status.parse().ok())
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, format!("bad status line {:?}", line)))?;
+
+    let mut headers = Vec::new();
+
+    loop {
+        line.clear();
+        reader.read_line(&mut line)?;
+
+        let line = line.trim_end();
+
+        if line.is_empty() {
+            break;
+        }
+
+        let mut parts = line.splitn(2, ':');
+
+        if let (Some(name), Some(value)) = (parts.next(), parts.next()) {
+            headers.push((name.to_lowercase(), value.trim().to_owned()));
+        }
+    }
+
+    Ok((status, headers))
+}
+
+fn basic_auth(user: &str, password: &str) -> String {
+    base64::encode(format!("{}:{}", user, password).as_bytes())
+}